@@ -36,6 +36,7 @@ use bp_message_lane::{
 	source_chain::{LaneMessageVerifier, MessageDeliveryAndDispatchPayment, TargetHeaderChain},
 	target_chain::{DispatchMessage, MessageDispatch, ProvedLaneMessages, ProvedMessages, SourceHeaderChain},
 	InboundLaneData, LaneId, MessageData, MessageKey, MessageNonce, MessagePayload, OutboundLaneData,
+	UnrewardedRelayersState,
 };
 use codec::{Decode, Encode};
 use frame_support::{
@@ -435,6 +436,11 @@ impl<T: Trait<I>, I: Instance> Module<T, I> {
 		OutboundMessages::<T, I>::get(MessageKey { lane_id: lane, nonce }).map(|message_data| message_data.payload)
 	}
 
+	/// Get payload and delivery and dispatch fee of given outbound message.
+	pub fn outbound_message_data(lane: LaneId, nonce: MessageNonce) -> Option<MessageData<T::OutboundMessageFee>> {
+		OutboundMessages::<T, I>::get(MessageKey { lane_id: lane, nonce })
+	}
+
 	/// Get nonce of latest generated message at given outbound lane.
 	pub fn outbound_latest_generated_nonce(lane: LaneId) -> MessageNonce {
 		OutboundLanes::<I>::get(&lane).latest_generated_nonce
@@ -454,6 +460,16 @@ impl<T: Trait<I>, I: Instance> Module<T, I> {
 	pub fn inbound_latest_confirmed_nonce(lane: LaneId) -> MessageNonce {
 		InboundLanes::<T, I>::get(&lane).latest_confirmed_nonce
 	}
+
+	/// Get state of unrewarded relayers set at given inbound lane.
+	pub fn inbound_unrewarded_relayers_state(lane: LaneId) -> UnrewardedRelayersState {
+		let relayers = InboundLanes::<T, I>::get(&lane).relayers;
+		UnrewardedRelayersState {
+			unrewarded_relayer_entries: relayers.len() as MessageNonce,
+			messages_in_oldest_entry: relayers.front().map(|(begin, end, _)| 1 + end - begin).unwrap_or(0),
+			total_messages: relayers.iter().map(|(begin, end, _)| 1 + end - begin).sum(),
+		}
+	}
 }
 
 /// Getting storage keys for messages and lanes states. These keys are normally used when building
@@ -906,6 +922,31 @@ mod tests {
 		});
 	}
 
+	#[test]
+	fn inbound_unrewarded_relayers_state_is_computed_properly() {
+		run_test(|| {
+			InboundLanes::<TestRuntime, DefaultInstance>::insert(
+				TEST_LANE_ID,
+				InboundLaneData {
+					latest_confirmed_nonce: 8,
+					latest_received_nonce: 13,
+					relayers: vec![(9, 10, TEST_RELAYER_A), (11, 13, TEST_RELAYER_B)]
+						.into_iter()
+						.collect(),
+				},
+			);
+
+			assert_eq!(
+				Module::<TestRuntime>::inbound_unrewarded_relayers_state(TEST_LANE_ID),
+				UnrewardedRelayersState {
+					unrewarded_relayer_entries: 2,
+					messages_in_oldest_entry: 2,
+					total_messages: 5,
+				},
+			);
+		});
+	}
+
 	#[test]
 	fn receive_messages_proof_rejects_invalid_dispatch_weight() {
 		run_test(|| {
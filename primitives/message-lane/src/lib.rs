@@ -23,7 +23,7 @@
 #![allow(clippy::unnecessary_mut_passed)]
 
 use codec::{Decode, Encode};
-use frame_support::RuntimeDebug;
+use frame_support::{Parameter, RuntimeDebug};
 use sp_api::decl_runtime_apis;
 use sp_std::{collections::vec_deque::VecDeque, prelude::*};
 
@@ -103,6 +103,42 @@ impl<RelayerId> Default for InboundLaneData<RelayerId> {
 	}
 }
 
+/// Gist of `InboundLaneData::relayers` field used by runtime APIs.
+#[derive(Clone, Default, Encode, Decode, RuntimeDebug, PartialEq, Eq)]
+pub struct UnrewardedRelayersState {
+	/// Number of entries in the `InboundLaneData::relayers` set.
+	pub unrewarded_relayer_entries: MessageNonce,
+	/// Number of messages in the oldest entry of `InboundLaneData::relayers`. This is the
+	/// minimal number of reward proofs required to push out this entry from the set.
+	pub messages_in_oldest_entry: MessageNonce,
+	/// Total number of messages in the relayers vector.
+	pub total_messages: MessageNonce,
+}
+
+/// Parameters of the inbound lane that are limiting messages delivery, used by runtime APIs.
+#[derive(Clone, Encode, Decode, RuntimeDebug, PartialEq, Eq)]
+pub struct InboundLaneParameters {
+	/// Maximal number of messages in the single delivery transaction.
+	pub max_messages_in_single_batch: MessageNonce,
+	/// Maximal cumulative dispatch weight of messages in the single delivery transaction.
+	pub max_messages_weight_in_single_batch: Weight,
+	/// Maximal number of delivered, but not yet confirmed messages at the inbound lane.
+	pub max_unconfirmed_messages: MessageNonce,
+}
+
+/// Details of the outbound message, used by runtime APIs.
+#[derive(Clone, Encode, Decode, RuntimeDebug, PartialEq, Eq)]
+pub struct MessageDetails<OutboundMessageFee> {
+	/// Nonce assigned to the message.
+	pub nonce: MessageNonce,
+	/// Message dispatch weight, declared by the submitter.
+	pub dispatch_weight: Weight,
+	/// Size of the encoded message payload.
+	pub size: u32,
+	/// Delivery and dispatch fee, paid by the message submitter.
+	pub delivery_and_dispatch_fee: OutboundMessageFee,
+}
+
 /// Outbound lane data.
 #[derive(Encode, Decode, Clone, RuntimeDebug, PartialEq, Eq)]
 pub struct OutboundLaneData {
@@ -128,7 +164,8 @@ impl Default for OutboundLaneData {
 
 decl_runtime_apis! {
 	/// Outbound message lane API.
-	pub trait OutboundLaneApi {
+	#[api_version(2)]
+	pub trait OutboundLaneApi<OutboundMessageFee: Parameter> {
 		/// Returns dispatch weight of all messages in given inclusive range.
 		///
 		/// If some (or all) messages are missing from the storage, they'll also will
@@ -138,6 +175,16 @@ decl_runtime_apis! {
 			begin: MessageNonce,
 			end: MessageNonce,
 		) -> Vec<(MessageNonce, Weight)>;
+		/// Returns details of all messages in given inclusive range. This method has been added
+		/// in version 2 of the API.
+		///
+		/// If some (or all) messages are missing from the storage, they'll also will
+		/// be missing from the resulting vector. The vector is ordered by the nonce.
+		fn message_details(
+			lane: LaneId,
+			begin: MessageNonce,
+			end: MessageNonce,
+		) -> Vec<MessageDetails<OutboundMessageFee>>;
 		/// Returns nonce of the latest message, received by bridged chain.
 		fn latest_received_nonce(lane: LaneId) -> MessageNonce;
 		/// Returns nonce of the latest message, generated by given lane.
@@ -145,10 +192,16 @@ decl_runtime_apis! {
 	}
 
 	/// Inbound message lane API.
+	#[api_version(2)]
 	pub trait InboundLaneApi {
 		/// Returns nonce of the latest message, received by given lane.
 		fn latest_received_nonce(lane: LaneId) -> MessageNonce;
 		/// Nonce of latest message that has been confirmed to the bridged chain.
 		fn latest_confirmed_nonce(lane: LaneId) -> MessageNonce;
+		/// State of the unrewarded relayers set at given lane.
+		fn unrewarded_relayers_state(lane: LaneId) -> UnrewardedRelayersState;
+		/// Returns parameters of given lane that are limiting messages delivery. This method has
+		/// been added in version 2 of the API.
+		fn lane_parameters(lane: LaneId) -> InboundLaneParameters;
 	}
 }
@@ -22,15 +22,20 @@
 //! target chain to the source chain.
 
 // required for futures::select!
-#![recursion_limit = "1024"]
+#![recursion_limit = "4096"]
 #![warn(missing_docs)]
 
+mod metered_client;
 mod metrics;
 
 pub mod message_lane;
 pub mod message_lane_loop;
+pub mod rate_limited_client;
 
 mod message_race_delivery;
 mod message_race_loop;
 mod message_race_receiving;
 mod message_race_strategy;
+
+#[cfg(any(test, feature = "test-helpers"))]
+pub mod testing;
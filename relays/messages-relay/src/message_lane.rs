@@ -23,7 +23,7 @@ use relay_utils::{BlockNumberBase, HeaderId};
 use std::fmt::Debug;
 
 /// One-way message lane.
-pub trait MessageLane: Clone + Send + Sync {
+pub trait MessageLane: 'static + Clone + Send + Sync {
 	/// Name of the messages source.
 	const SOURCE_NAME: &'static str;
 	/// Name of the messages target.
@@ -37,12 +37,29 @@ pub trait MessageLane: Clone + Send + Sync {
 	/// Number of the source header.
 	type SourceHeaderNumber: BlockNumberBase;
 	/// Hash of the source header.
-	type SourceHeaderHash: Clone + Debug + Default + PartialEq + Send + Sync;
+	type SourceHeaderHash: 'static + Clone + Debug + Default + PartialEq + Send + Sync;
 
 	/// Number of the target header.
 	type TargetHeaderNumber: BlockNumberBase;
 	/// Hash of the target header.
-	type TargetHeaderHash: Clone + Debug + Default + PartialEq + Send + Sync;
+	type TargetHeaderHash: 'static + Clone + Debug + Default + PartialEq + Send + Sync;
+
+	/// Balance of the source chain, used to pay for message delivery and dispatch.
+	type SourceChainBalance: 'static
+		+ Clone
+		+ Copy
+		+ Debug
+		+ Default
+		+ Ord
+		+ Send
+		+ Sync
+		+ num_traits::Saturating
+		+ num_traits::Zero;
+
+	/// Returns size of the messages proof (in bytes).
+	fn messages_proof_size(proof: &Self::MessagesProof) -> usize;
+	/// Returns size of the messages receiving proof (in bytes).
+	fn messages_receiving_proof_size(proof: &Self::MessagesReceivingProof) -> usize;
 }
 
 /// Source header id within given one-way message lane.
@@ -26,22 +26,41 @@
 
 use crate::message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf};
 use crate::message_race_delivery::run as run_message_delivery_race;
+use crate::message_race_loop::{random_delay, RaceParams};
 use crate::message_race_receiving::run as run_message_receiving_race;
 use crate::metrics::MessageLaneLoopMetrics;
+use crate::rate_limited_client::RateLimitedClient;
+
+pub use crate::message_race_delivery::{DeliveryLimits, MaxSizeConstraint, MaxWeightConstraint};
+pub use crate::message_race_loop::{
+	BalanceGuardParams, BlockRef, DeliveryCondition, DryRunOutcome, ProofSizeLimit, RaceCommand, RaceStateSnapshot,
+	RangeFailureKind, StrategyMetricsSnapshot, SubmitOptions, SubmitPolicy, TargetLaneParameters,
+};
+pub use crate::message_race_receiving::LazyConfirmationsParams;
+pub use crate::message_race_strategy::{
+	BasicStrategy, BatchingParams, CompositeStrategy, MaxNoncesConstraint, QueueOverflowPolicy, RaceCheckpoint,
+	RangeFailureAction, SelectionConstraint, SelectorError, SnapshotError, StrategySnapshot, STRATEGY_SNAPSHOT_VERSION,
+};
+pub use crate::rate_limited_client::RateLimitParams;
 
 use async_trait::async_trait;
-use bp_message_lane::{LaneId, MessageNonce, Weight};
-use futures::{channel::mpsc::unbounded, future::FutureExt, stream::StreamExt};
+use bp_message_lane::{LaneId, MessageNonce, UnrewardedRelayersState, Weight};
+use futures::{
+	channel::mpsc::{unbounded, UnboundedSender},
+	future::FutureExt,
+	stream::StreamExt,
+};
 use relay_utils::{
 	interval,
 	metrics::{start as metrics_start, GlobalMetrics, MetricsParams},
-	process_future_result, retry_backoff, FailedClient, MaybeConnectionError,
+	process_future_result, FailedClient, MaybeFatalError, MaybeRejectedTransactionError, MaybeThrottledError,
+	RelayError, RetryBackoffParams,
 };
-use std::{collections::BTreeMap, fmt::Debug, future::Future, ops::RangeInclusive, time::Duration};
+use std::{collections::BTreeMap, fmt::Debug, future::Future, ops::RangeInclusive, sync::Arc, time::Duration};
 
 /// Message lane loop configuration params.
 #[derive(Debug, Clone)]
-pub struct Params {
+pub struct Params<SourceChainBalance> {
 	/// Id of lane this loop is servicing.
 	pub lane: LaneId,
 	/// Interval at which we ask target node about its updates.
@@ -50,28 +69,232 @@ pub struct Params {
 	pub target_tick: Duration,
 	/// Delay between moments when connection error happens and our reconnect attempt.
 	pub reconnect_delay: Duration,
-	/// The loop will auto-restart if there has been no updates during this period.
-	pub stall_timeout: Duration,
+	/// The loop will auto-restart if source client has not provided any updates during this period.
+	pub source_stall_timeout: Duration,
+	/// The loop will auto-restart if target client has not accepted any updates during this period.
+	pub target_stall_timeout: Duration,
+	/// Submitted proof is regenerated and resubmitted if it has not been confirmed during this period.
+	pub resubmit_timeout: Duration,
+	/// Parameters of retries after source client errors.
+	pub source_retry_backoff: RetryBackoffParams,
+	/// Parameters of retries after target client errors.
+	pub target_retry_backoff: RetryBackoffParams,
+	/// If true, the loop never submits any transactions. Instead, it only logs proofs that it
+	/// would submit. Both races act as if every generated proof has been delivered immediately.
+	/// The delivery race still respects the `max_unconfirmed_nonces_at_target` limit, so it stops
+	/// once the limit is reached.
+	pub dry_run: bool,
+	/// If set, the loop only delivers messages (and confirmations) up to this nonce. The loop
+	/// exits once delivery of this nonce is confirmed to the source node.
+	pub final_nonce: Option<MessageNonce>,
+	/// If set, races read nonces from both nodes at least once per this interval, even if node
+	/// states are not updated.
+	pub nonces_refresh_interval: Option<Duration>,
+	/// If set, snapshots of the message delivery race state are sent to this channel.
+	pub delivery_race_state_notifier: Option<UnboundedSender<RaceStateSnapshot>>,
+	/// If set, snapshots of the receiving confirmations race state are sent to this channel.
+	pub receiving_race_state_notifier: Option<UnboundedSender<RaceStateSnapshot>>,
+	/// If true, messages proofs are verified by the target client before submission.
+	pub verify_messages_proofs: bool,
+	/// If true, submission of messages proofs is dry run at the target node before the delivery
+	/// transaction is submitted. Proofs that would fail are not submitted.
+	pub dry_run_messages_before_submit: bool,
+	/// Maximal number of submitted, but not yet confirmed transactions in every race.
+	pub max_in_flight_submissions: usize,
+	/// Maximal number of proofs that are submitted in a single transaction, if the race target
+	/// client supports batch submission.
+	pub max_proofs_per_batch: usize,
+	/// If true, proofs that are waiting for submission are regenerated when the target node
+	/// knows source header that is `reselect_threshold` blocks ahead of the proof header.
+	pub reselect_on_newer_target_state: bool,
+	/// Number of blocks for the `reselect_on_newer_target_state` option.
+	pub reselect_threshold: u64,
+	/// If set, races stop reading nonces from their source nodes while more than this number of
+	/// nonces is waiting for delivery. `None` means that the number is unlimited.
+	pub max_queued_nonces: Option<MessageNonce>,
+	/// If set, races wait for random delay (up to this value) before generating proofs, so that
+	/// relayers that are serving the same lane are not generating the same proofs at once.
+	pub competition_delay: Option<Duration>,
+	/// If set, races cancel proof generation that takes longer than this and select nonces again.
+	pub proof_generation_timeout: Option<Duration>,
+	/// If set, the message delivery race shrinks selected range of messages until the estimated
+	/// size of messages proof fits the limit.
+	pub proof_size_limit: Option<ProofSizeLimit>,
+	/// Timeout of nonces requests to both clients. Requests that take longer are treated as
+	/// connection errors.
+	pub rpc_timeout: Duration,
+	/// If true, source nonces are read at the best source header known to the target node.
+	pub nonces_at_target_known_header: bool,
+	/// Lane source header where the message delivery race reads nonces.
+	pub delivery_source_nonces_at: BlockRef,
+	/// Lane target header where the receiving confirmations race reads nonces.
+	pub receiving_source_nonces_at: BlockRef,
+	/// Number of the latest source headers, known to the target node, that are treated as
+	/// unstable by the message delivery race. Messages are never delivered from these headers.
+	pub source_confirmation_depth: u32,
+	/// If true, proofs are generated at the newest header where delivered messages (or
+	/// confirmations) have been seen, instead of the best header known to the other node.
+	pub proof_at_queued_header: bool,
+	/// If set, races log warning when the best header of their source node, known to their target
+	/// node, lags behind by more than this number of blocks while messages (or confirmations)
+	/// are waiting for delivery.
+	pub max_source_header_lag: Option<u64>,
+	/// If set, races are restarted once any client has returned this number of non-connection
+	/// errors in a row.
+	pub max_consecutive_failures: Option<u32>,
+	/// Number of attempts to reconnect race clients after connection errors, before races are
+	/// restarted. If zero, races are restarted on the first connection error.
+	pub race_reconnect_attempts: u32,
+	/// If set, rate of requests to the source node is limited. Throttled requests are treated
+	/// as connection errors.
+	pub source_rate_limit: Option<RateLimitParams>,
+	/// If set, rate of requests to the target node is limited. Throttled requests are treated
+	/// as connection errors.
+	pub target_rate_limit: Option<RateLimitParams>,
+	/// If set, new messages are only delivered while this condition allows it. The delivery race
+	/// should be configured to refresh nonces periodically (see `nonces_refresh_interval`), so
+	/// that delivery is resumed soon after the condition allows it again.
+	pub delivery_condition: Option<Arc<dyn DeliveryCondition>>,
+	/// If set, new messages are not delivered while the relayer balance at the target node is low.
+	pub balance_guard: Option<BalanceGuardParams>,
 	/// Message delivery race parameters.
-	pub delivery_params: MessageDeliveryParams,
+	pub delivery_params: MessageDeliveryParams<SourceChainBalance>,
+	/// If set, receiving confirmations are only delivered when it is worthwhile. The races should
+	/// be configured to refresh nonces periodically (see `nonces_refresh_interval`), so that
+	/// confirmations are delivered after `LazyConfirmationsParams::max_confirmation_delay` even
+	/// if there are no new messages.
+	pub lazy_confirmations: Option<LazyConfirmationsParams>,
 }
 
 /// Message delivery race parameters.
 #[derive(Debug, Clone)]
-pub struct MessageDeliveryParams {
+pub struct MessageDeliveryParams<SourceChainBalance> {
 	/// Message delivery race will stop delivering messages if there are `max_unconfirmed_nonces_at_target`
 	/// unconfirmed nonces on the target node. The race would continue once they're confirmed by the
 	/// receiving race.
 	pub max_unconfirmed_nonces_at_target: MessageNonce,
-	/// Maximal cumulative dispatch weight of relayed messages in single delivery transaction.
-	pub max_messages_weight_in_single_batch: Weight,
+	/// Message delivery race will stop delivering messages if there are `max_unrewarded_relayer_entries_at_target`
+	/// unrewarded relayer entries at the target node and the outbound lane state proof (that would prune these
+	/// entries) can't be included into the delivery transaction. The race would continue once new confirmations
+	/// are delivered to the source node by the receiving race.
+	pub max_unrewarded_relayer_entries_at_target: MessageNonce,
+	/// Limits of the single delivery transaction: number of messages, their cumulative dispatch
+	/// weight and size.
+	pub limits: DeliveryLimits,
+	/// If true, messages are delivered again if the target node reports lower latest received
+	/// nonce than it has reported before (i.e. if target chain has been reverted).
+	pub redeliver_after_target_rollback: bool,
+	/// If set, messages are delivered in batches. The race should be configured to refresh nonces
+	/// periodically (see `nonces_refresh_interval`), so that the batch is delivered after
+	/// `BatchingParams::max_wait` even if there are no new messages.
+	pub batching: Option<BatchingParams>,
+	/// If set, at most this number of messages is delivered per minute. It may be used to drain
+	/// large backlog without saturating target chain blocks. The race should be configured to refresh
+	/// nonces periodically (see `nonces_refresh_interval`), so that delivery continues once the limit
+	/// allows it.
+	pub max_messages_per_minute: Option<MessageNonce>,
+	/// If set, messages are only delivered if their fees are paying for the delivery transaction.
+	pub profitability: Option<MessageDeliveryProfitability<SourceChainBalance>>,
+	/// If set, selected messages are only delivered if their fees are paying for the delivery
+	/// transaction, which cost is estimated by the target client right before the proof is
+	/// generated.
+	pub target_cost_profitability: Option<TargetCostProfitability>,
+	/// If set, the race doesn't always deliver the longest prefix of messages that fits into the
+	/// delivery transaction limits. Instead, it selects the prefix that maximizes the difference
+	/// between cumulative messages fees and the estimated delivery cost.
+	pub batch_optimizer: Option<DeliveryBatchOptimizer<SourceChainBalance>>,
+	/// If set, messages that are refused by the filter are never relayed. Messages are delivered
+	/// in order, so refused message blocks delivery of all following messages by this relayer.
+	pub nonce_filter: Option<DeliveryNonceFilter>,
+	/// If set, the race assumes that messages up to this nonce are already delivered to the
+	/// target node (e.g. it is known from relayer records). So messages are read from the source
+	/// node, starting from the next nonce. The nonce, reported by the target node, is preferred
+	/// if it is different.
+	pub initial_target_nonce: Option<MessageNonce>,
+	/// Policy that selects tip and mortality of messages delivery transactions.
+	pub submit_policy: SubmitPolicy,
+}
+
+/// Estimates cost of the delivery transaction (in source chain tokens), given delivered nonces,
+/// cumulative dispatch weight and cumulative size of delivered messages.
+pub type DeliveryCostEstimator<SourceChainBalance> =
+	Arc<dyn Fn(&RangeInclusive<MessageNonce>, Weight, u32) -> SourceChainBalance + Send + Sync>;
+
+/// Message delivery profitability parameters.
+#[derive(Clone)]
+pub struct MessageDeliveryProfitability<SourceChainBalance> {
+	/// Delivery transaction cost estimator.
+	pub estimate_delivery_cost: DeliveryCostEstimator<SourceChainBalance>,
+	/// Messages are delivered in order, so unprofitable message blocks delivery of all following
+	/// messages. If it is blocking the lane for this long, it is delivered anyway.
+	pub relay_unprofitable_after: Duration,
+}
+
+impl<SourceChainBalance> std::fmt::Debug for MessageDeliveryProfitability<SourceChainBalance> {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		fmt.debug_struct("MessageDeliveryProfitability")
+			.field("relay_unprofitable_after", &self.relay_unprofitable_after)
+			.finish()
+	}
+}
+
+/// Parameters of the message delivery profitability check, that is using delivery transaction
+/// cost estimated by the target client.
+#[derive(Debug, Clone)]
+pub struct TargetCostProfitability {
+	/// Messages are delivered in order, so unprofitable message blocks delivery of all following
+	/// messages. If it is blocking the lane for this long, it is delivered anyway.
+	pub relay_unprofitable_after: Duration,
 }
 
-/// Messages weights map.
-pub type MessageWeightsMap = BTreeMap<MessageNonce, Weight>;
+/// Message delivery batch optimizer parameters.
+///
+/// Every delivery transaction has some fixed overhead (signature, proof envelope, header check),
+/// so larger batches are usually cheaper per message. But if cost of delivering message exceeds
+/// its fee, it may be better to deliver it later, together with following messages.
+#[derive(Clone)]
+pub struct DeliveryBatchOptimizer<SourceChainBalance> {
+	/// Delivery transaction cost estimator.
+	pub estimate_delivery_cost: DeliveryCostEstimator<SourceChainBalance>,
+}
+
+impl<SourceChainBalance> std::fmt::Debug for DeliveryBatchOptimizer<SourceChainBalance> {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		fmt.debug_struct("DeliveryBatchOptimizer").finish()
+	}
+}
+
+/// Filter of messages that must not be relayed (e.g. known spam messages).
+#[derive(Clone)]
+pub struct DeliveryNonceFilter {
+	/// Returns true if message with given nonce must not be relayed.
+	pub is_refused: Arc<dyn Fn(MessageNonce) -> bool + Send + Sync>,
+}
+
+impl std::fmt::Debug for DeliveryNonceFilter {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		fmt.debug_struct("DeliveryNonceFilter").finish()
+	}
+}
+
+/// Message details.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageDetails<SourceChainBalance> {
+	/// Dispatch weight of the message.
+	pub dispatch_weight: Weight,
+	/// Size of the encoded message payload.
+	pub size: u32,
+	/// Delivery and dispatch fee, paid by the message submitter. This is what relayer gets
+	/// for delivering the message.
+	pub reward: SourceChainBalance,
+}
+
+/// Messages details map.
+pub type MessageDetailsMap<SourceChainBalance> = BTreeMap<MessageNonce, MessageDetails<SourceChainBalance>>;
 
 /// Message delivery race proof parameters.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MessageProofParameters {
 	/// Include outbound lane state proof?
 	pub outbound_state_proof_required: bool,
@@ -79,17 +302,45 @@ pub struct MessageProofParameters {
 	pub dispatch_weight: Weight,
 }
 
+/// Status of the transaction, submitted by the relay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrackedTransactionStatus {
+	/// Transaction has been lost (i.e. it has been dropped from the pool or invalidated) and
+	/// it will never be included into the chain.
+	Lost,
+	/// Transaction has been finalized.
+	Finalized,
+}
+
+/// Tracker of the transaction, submitted by the relay.
+#[async_trait]
+pub trait TransactionTracker: Send {
+	/// Wait until transaction is either finalized or lost.
+	async fn wait(self) -> TrackedTransactionStatus;
+}
+
 /// Source client trait.
 #[async_trait]
 pub trait SourceClient<P: MessageLane>: Clone + Send + Sync {
 	/// Type of error this clients returns.
-	type Error: std::fmt::Debug + MaybeConnectionError;
+	type Error: std::fmt::Debug + RelayError + MaybeRejectedTransactionError + MaybeFatalError + MaybeThrottledError;
+	/// Type of the tracker of transactions, submitted to the source node.
+	type TransactionTracker: TransactionTracker;
 
 	/// Try to reconnect to source node.
 	async fn reconnect(self) -> Result<Self, Self::Error>;
 
 	/// Returns state of the client.
 	async fn state(&self) -> Result<SourceClientState<P>, Self::Error>;
+	/// Returns spec version of the source node runtime, or `None` if the client is unable to
+	/// tell it. By default, the version is unknown.
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(None)
+	}
+	/// Called when the source node runtime has been upgraded. The client should forget everything
+	/// that depends on the runtime (e.g. cached transaction formats). By default, nothing is
+	/// cached.
+	fn on_runtime_upgrade(&self) {}
 
 	/// Get nonce of instance of latest generated message.
 	async fn latest_generated_nonce(
@@ -102,12 +353,12 @@ pub trait SourceClient<P: MessageLane>: Clone + Send + Sync {
 		id: SourceHeaderIdOf<P>,
 	) -> Result<(SourceHeaderIdOf<P>, MessageNonce), Self::Error>;
 
-	/// Returns mapping of message nonces, generated on this client, to their weights.
-	async fn generated_messages_weights(
+	/// Returns mapping of message nonces, generated on this client, to their details.
+	async fn generated_messages_details(
 		&self,
 		id: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
-	) -> Result<MessageWeightsMap, Self::Error>;
+	) -> Result<MessageDetailsMap<P::SourceChainBalance>, Self::Error>;
 
 	/// Prove messages in inclusive range [begin; end].
 	async fn prove_messages(
@@ -117,25 +368,58 @@ pub trait SourceClient<P: MessageLane>: Clone + Send + Sync {
 		proof_parameters: MessageProofParameters,
 	) -> Result<(SourceHeaderIdOf<P>, RangeInclusive<MessageNonce>, P::MessagesProof), Self::Error>;
 
-	/// Submit messages receiving proof.
+	/// Estimate size (in bytes) of the proof of messages in inclusive range [begin; end]. By
+	/// default, messages are proved and the proof is measured.
+	async fn estimate_messages_proof_size(
+		&self,
+		id: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: MessageProofParameters,
+	) -> Result<u32, Self::Error> {
+		let (_, _, proof) = self.prove_messages(id, nonces, proof_parameters).await?;
+		Ok(P::messages_proof_size(&proof) as u32)
+	}
+
+	/// Submit messages receiving proof, using given transaction options.
 	async fn submit_messages_receiving_proof(
 		&self,
 		generated_at_block: TargetHeaderIdOf<P>,
 		proof: P::MessagesReceivingProof,
-	) -> Result<(), Self::Error>;
+		options: SubmitOptions,
+	) -> Result<Self::TransactionTracker, Self::Error>;
+
+	/// Called when the receiving confirmations race can't make progress until the source node
+	/// knows given target header. Client may then ask headers relay to relay this header. By
+	/// default, this is ignored.
+	fn require_target_header_on_source(&self, _id: TargetHeaderIdOf<P>) {}
 }
 
 /// Target client trait.
 #[async_trait]
 pub trait TargetClient<P: MessageLane>: Clone + Send + Sync {
 	/// Type of error this clients returns.
-	type Error: std::fmt::Debug + MaybeConnectionError;
+	type Error: std::fmt::Debug + RelayError + MaybeRejectedTransactionError + MaybeFatalError + MaybeThrottledError;
+	/// Type of the tracker of transactions, submitted to the target node.
+	type TransactionTracker: TransactionTracker;
 
 	/// Try to reconnect to source node.
 	async fn reconnect(self) -> Result<Self, Self::Error>;
 
 	/// Returns state of the client.
 	async fn state(&self) -> Result<TargetClientState<P>, Self::Error>;
+	/// Returns spec version of the target node runtime, or `None` if the client is unable to
+	/// tell it. By default, the version is unknown.
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(None)
+	}
+	/// Called when the target node runtime has been upgraded. The client should forget everything
+	/// that depends on the runtime (e.g. cached transaction formats). By default, nothing is
+	/// cached.
+	fn on_runtime_upgrade(&self) {}
+	/// Returns parameters of the lane at the target node. They are read when the loop is started
+	/// and after every target node runtime upgrade. Parameters replace limits of the message
+	/// delivery race (`MessageDeliveryParams::limits` and `max_unconfirmed_nonces_at_target`).
+	async fn lane_parameters(&self) -> Result<TargetLaneParameters, Self::Error>;
 
 	/// Get nonce of latest received message.
 	async fn latest_received_nonce(
@@ -148,6 +432,11 @@ pub trait TargetClient<P: MessageLane>: Clone + Send + Sync {
 		&self,
 		id: TargetHeaderIdOf<P>,
 	) -> Result<(TargetHeaderIdOf<P>, MessageNonce), Self::Error>;
+	/// Get state of unrewarded relayers set at the inbound lane.
+	async fn unrewarded_relayers_state(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, UnrewardedRelayersState), Self::Error>;
 
 	/// Prove messages receiving at given block.
 	async fn prove_messages_receiving(
@@ -155,13 +444,63 @@ pub trait TargetClient<P: MessageLane>: Clone + Send + Sync {
 		id: TargetHeaderIdOf<P>,
 	) -> Result<(TargetHeaderIdOf<P>, P::MessagesReceivingProof), Self::Error>;
 
-	/// Submit messages proof.
+	/// Verify messages proof before submitting it. Returns error if the proof is known to be
+	/// rejected by the target node. By default, any proof is accepted.
+	async fn verify_messages_proof(
+		&self,
+		_generated_at_header: SourceHeaderIdOf<P>,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &P::MessagesProof,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
+
+	/// Dry run submission of messages proof at the best target header, without paying for the
+	/// transaction. By default, the submission is assumed to succeed.
+	async fn dry_run_messages_proof(
+		&self,
+		_generated_at_header: SourceHeaderIdOf<P>,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &P::MessagesProof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		Ok(DryRunOutcome::Success)
+	}
+
+	/// Returns the earliest source header that the target node needs to know to accept messages
+	/// proofs, given the `current_best` source header known to the target node. E.g. the headers
+	/// pallet may be missing some mandatory source header. Returns `None` if the target node is
+	/// up to date or if the client doesn't track source headers. By default, source headers
+	/// aren't tracked.
+	async fn required_source_header_at_target(
+		&self,
+		_current_best: SourceHeaderIdOf<P>,
+	) -> Result<Option<SourceHeaderIdOf<P>>, Self::Error> {
+		Ok(None)
+	}
+
+	/// Submit messages proof, using given transaction options.
 	async fn submit_messages_proof(
 		&self,
 		generated_at_header: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
 		proof: P::MessagesProof,
-	) -> Result<RangeInclusive<MessageNonce>, Self::Error>;
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error>;
+
+	/// Estimate cost of the messages delivery transaction (in source chain tokens), given
+	/// delivered nonces, cumulative dispatch weight and cumulative size of delivered messages.
+	/// It is only called if `MessageDeliveryParams::target_cost_profitability` is set.
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<P::SourceChainBalance, Self::Error>;
+
+	/// Called when the message delivery race can't make progress until the target node knows
+	/// given source header. Client may then ask headers relay to relay this header. By default,
+	/// this is ignored.
+	fn require_source_header_on_target(&self, _id: SourceHeaderIdOf<P>) {}
 }
 
 /// State of the client.
@@ -169,6 +508,8 @@ pub trait TargetClient<P: MessageLane>: Clone + Send + Sync {
 pub struct ClientState<SelfHeaderId, PeerHeaderId> {
 	/// Best header id of this chain.
 	pub best_self: SelfHeaderId,
+	/// Best finalized header id of this chain.
+	pub best_finalized_self: SelfHeaderId,
 	/// Best header id of the peer chain.
 	pub best_peer: PeerHeaderId,
 }
@@ -190,12 +531,14 @@ pub struct ClientsState<P: MessageLane> {
 
 /// Run message lane service loop.
 pub fn run<P: MessageLane>(
-	params: Params,
-	mut source_client: impl SourceClient<P>,
-	mut target_client: impl TargetClient<P>,
+	params: Params<P::SourceChainBalance>,
+	source_client: impl SourceClient<P>,
+	target_client: impl TargetClient<P>,
 	metrics_params: Option<MetricsParams>,
 	exit_signal: impl Future<Output = ()>,
 ) {
+	let mut source_client = RateLimitedClient::new(source_client, params.source_rate_limit);
+	let mut target_client = RateLimitedClient::new(target_client, params.target_rate_limit);
 	let mut local_pool = futures::executor::LocalPool::new();
 	let exit_signal = exit_signal.shared();
 
@@ -203,6 +546,9 @@ pub fn run<P: MessageLane>(
 		let mut metrics_global = GlobalMetrics::default();
 		let metrics_msg = MessageLaneLoopMetrics::default();
 		let metrics_enabled = metrics_params.is_some();
+		// races are restarted from the last checkpoints after reconnect
+		let mut delivery_race_checkpoint = None;
+		let mut receiving_race_checkpoint = None;
 		metrics_start(
 			format!(
 				"{}_to_{}_MessageLoop/{}",
@@ -230,47 +576,31 @@ pub fn run<P: MessageLane>(
 				} else {
 					None
 				},
+				&mut delivery_race_checkpoint,
+				&mut receiving_race_checkpoint,
 				exit_signal.clone(),
 			)
 			.await;
 
 			match result {
 				Ok(()) => break,
-				Err(failed_client) => loop {
-					async_std::task::sleep(params.reconnect_delay).await;
-					if failed_client == FailedClient::Both || failed_client == FailedClient::Source {
-						source_client = match source_client.clone().reconnect().await {
-							Ok(source_client) => source_client,
-							Err(error) => {
-								log::warn!(
-									target: "bridge",
-									"Failed to reconnect {}. Going to retry in {}s: {:?}",
-									P::SOURCE_NAME,
-									params.reconnect_delay.as_secs(),
-									error,
-								);
-								continue;
-							}
-						}
+				Err(failed_client) => {
+					// we may be waiting for the node for a long time, so the exit signal is not
+					// ignored while clients are reconnecting
+					let reconnect = reconnect_failed_client(
+						failed_client,
+						params.reconnect_delay,
+						&mut source_client,
+						&mut target_client,
+					)
+					.fuse();
+					let exit_signal = exit_signal.clone().fuse();
+					futures::pin_mut!(reconnect, exit_signal);
+					futures::select! {
+						() = reconnect => (),
+						() = exit_signal => break,
 					}
-					if failed_client == FailedClient::Both || failed_client == FailedClient::Target {
-						target_client = match target_client.clone().reconnect().await {
-							Ok(target_client) => target_client,
-							Err(error) => {
-								log::warn!(
-									target: "bridge",
-									"Failed to reconnect {}. Going to retry in {}s: {:?}",
-									P::TARGET_NAME,
-									params.reconnect_delay.as_secs(),
-									error,
-								);
-								continue;
-							}
-						}
-					}
-
-					break;
-				},
+				}
 			}
 
 			log::debug!(
@@ -283,41 +613,167 @@ pub fn run<P: MessageLane>(
 	});
 }
 
+/// Reconnect failed client(s), retrying every `reconnect_delay` until the reconnect succeeds.
+async fn reconnect_failed_client<P: MessageLane>(
+	failed_client: FailedClient,
+	reconnect_delay: Duration,
+	source_client: &mut impl SourceClient<P>,
+	target_client: &mut impl TargetClient<P>,
+) {
+	loop {
+		async_std::task::sleep(reconnect_delay).await;
+		if failed_client == FailedClient::Both || failed_client == FailedClient::Source {
+			*source_client = match source_client.clone().reconnect().await {
+				Ok(source_client) => source_client,
+				Err(error) => {
+					log::warn!(
+						target: "bridge",
+						"Failed to reconnect {}. Going to retry in {}s: {:?}",
+						P::SOURCE_NAME,
+						reconnect_delay.as_secs(),
+						error,
+					);
+					continue;
+				}
+			}
+		}
+		if failed_client == FailedClient::Both || failed_client == FailedClient::Target {
+			*target_client = match target_client.clone().reconnect().await {
+				Ok(target_client) => target_client,
+				Err(error) => {
+					log::warn!(
+						target: "bridge",
+						"Failed to reconnect {}. Going to retry in {}s: {:?}",
+						P::TARGET_NAME,
+						reconnect_delay.as_secs(),
+						error,
+					);
+					continue;
+				}
+			}
+		}
+
+		return;
+	}
+}
+
 /// Run one-way message delivery loop until connection with target or source node is lost, or exit signal is received.
+#[allow(clippy::too_many_arguments)]
 async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: TargetClient<P>>(
-	params: Params,
+	params: Params<P::SourceChainBalance>,
 	source_client: SC,
 	target_client: TC,
 	mut metrics_global: Option<&mut GlobalMetrics>,
 	metrics_msg: Option<MessageLaneLoopMetrics>,
-	exit_signal: impl Future<Output = ()>,
+	delivery_race_checkpoint: &mut Option<
+		RaceCheckpoint<SourceHeaderIdOf<P>, MessageDetailsMap<P::SourceChainBalance>>,
+	>,
+	receiving_race_checkpoint: &mut Option<RaceCheckpoint<TargetHeaderIdOf<P>, RangeInclusive<MessageNonce>>>,
+	exit_signal: impl Future<Output = ()> + Clone,
 ) -> Result<(), FailedClient> {
-	let mut source_retry_backoff = retry_backoff();
+	let mut source_retry_backoff = params.source_retry_backoff.backoff();
 	let mut source_client_is_online = false;
 	let mut source_state_required = true;
 	let source_state = source_client.state().fuse();
 	let source_go_offline_future = futures::future::Fuse::terminated();
 	let source_tick_stream = interval(params.source_tick).fuse();
 
-	let mut target_retry_backoff = retry_backoff();
+	let mut target_retry_backoff = params.target_retry_backoff.backoff();
 	let mut target_client_is_online = false;
 	let mut target_state_required = true;
 	let target_state = target_client.state().fuse();
 	let target_go_offline_future = futures::future::Fuse::terminated();
 	let target_tick_stream = interval(params.target_tick).fuse();
+	let mut target_lane_parameters_required = true;
+	let target_lane_parameters = futures::future::Fuse::terminated();
+
+	let delivery_race_params = RaceParams {
+		source_stall_timeout: params.source_stall_timeout,
+		target_stall_timeout: params.target_stall_timeout,
+		resubmit_timeout: params.resubmit_timeout,
+		source_retry_backoff: params.source_retry_backoff.clone(),
+		target_retry_backoff: params.target_retry_backoff.clone(),
+		dry_run: params.dry_run,
+		final_nonce: params.final_nonce,
+		refresh_interval: params.nonces_refresh_interval,
+		state_notifier: params.delivery_race_state_notifier.clone(),
+		verify_proofs: params.verify_messages_proofs,
+		dry_run_before_submit: params.dry_run_messages_before_submit,
+		reselect_on_newer_target_state: params.reselect_on_newer_target_state,
+		reselect_threshold: params.reselect_threshold,
+		lane_name: hex::encode(params.lane),
+		max_in_flight: params.max_in_flight_submissions,
+		max_proofs_per_batch: params.max_proofs_per_batch,
+		max_queued_nonces: params.max_queued_nonces,
+		competition_delay: params.competition_delay,
+		random_delay,
+		proof_generation_timeout: params.proof_generation_timeout,
+		proof_size_limit: params.proof_size_limit,
+		rpc_timeout: params.rpc_timeout,
+		nonces_at_target_known_header: params.nonces_at_target_known_header,
+		source_nonces_at: params.delivery_source_nonces_at,
+		source_confirmation_depth: params.source_confirmation_depth,
+		proof_at_queued_header: params.proof_at_queued_header,
+		// delivery transaction may be resubmitted if it is lost in the target chain reorg
+		require_target_finality: false,
+		max_source_header_lag: params.max_source_header_lag,
+		max_consecutive_failures: params.max_consecutive_failures,
+		max_reconnect_attempts: params.race_reconnect_attempts,
+		delivery_condition: params.delivery_condition.clone(),
+		balance_guard: params.balance_guard.clone(),
+		submit_policy: params.delivery_params.submit_policy.clone(),
+	};
+	// source of the receiving race is the target of the lane (and vice versa)
+	let receiving_race_params = RaceParams {
+		source_stall_timeout: params.target_stall_timeout,
+		target_stall_timeout: params.source_stall_timeout,
+		source_retry_backoff: params.target_retry_backoff.clone(),
+		target_retry_backoff: params.source_retry_backoff.clone(),
+		state_notifier: params.receiving_race_state_notifier.clone(),
+		source_nonces_at: params.receiving_source_nonces_at,
+		// source client is unable to verify messages receiving proofs
+		verify_proofs: false,
+		dry_run_before_submit: false,
+		// `source_confirmation_depth` is the number of lane source headers
+		source_confirmation_depth: 0,
+		// confirmations are never deferred - they're only making delivery possible
+		delivery_condition: None,
+		// tip is configured in the lane target chain tokens
+		submit_policy: SubmitPolicy::default(),
+		// target of the receiving race is the lane source, where relayer has another account
+		balance_guard: None,
+		// confirmations proofs are small
+		proof_size_limit: None,
+		// confirmations at non-finalized lane source headers may be reverted
+		require_target_finality: true,
+		..delivery_race_params.clone()
+	};
 
 	let (
 		(delivery_source_state_sender, delivery_source_state_receiver),
 		(delivery_target_state_sender, delivery_target_state_receiver),
 	) = (unbounded(), unbounded());
+	let (delivery_control_sender, delivery_control_receiver) = unbounded();
+	let (target_runtime_upgrade_sender, mut target_runtime_upgrade_receiver) = unbounded();
 	let delivery_race_loop = run_message_delivery_race(
 		source_client.clone(),
 		delivery_source_state_receiver,
 		target_client.clone(),
 		delivery_target_state_receiver,
-		params.stall_timeout,
+		delivery_race_params,
 		metrics_msg.clone(),
 		params.delivery_params,
+		delivery_control_receiver,
+		delivery_race_checkpoint.take(),
+		move |checkpoint| *delivery_race_checkpoint = Some(checkpoint),
+		{
+			let target_client = target_client.clone();
+			move |id| target_client.require_source_header_on_target(id)
+		},
+		Arc::new(move || {
+			let _ = target_runtime_upgrade_sender.unbounded_send(());
+		}),
+		exit_signal.clone(),
 	)
 	.fuse();
 
@@ -330,13 +786,19 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 		receiving_source_state_receiver,
 		target_client.clone(),
 		receiving_target_state_receiver,
-		params.stall_timeout,
+		receiving_race_params,
+		params.lazy_confirmations,
 		metrics_msg.clone(),
+		receiving_race_checkpoint.take(),
+		move |checkpoint| *receiving_race_checkpoint = Some(checkpoint),
+		{
+			let source_client = source_client.clone();
+			move |id| source_client.require_target_header_on_source(id)
+		},
+		exit_signal,
 	)
 	.fuse();
 
-	let exit_signal = exit_signal.fuse();
-
 	futures::pin_mut!(
 		source_state,
 		source_go_offline_future,
@@ -344,11 +806,15 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 		target_state,
 		target_go_offline_future,
 		target_tick_stream,
+		target_lane_parameters,
 		delivery_race_loop,
 		receiving_race_loop,
-		exit_signal
 	);
 
+	// races are receiving exit signal themselves, so we only exit when both races have finished
+	let mut is_delivery_race_finished = false;
+	let mut is_receiving_race_finished = false;
+
 	loop {
 		futures::select! {
 			new_source_state = source_state => {
@@ -372,7 +838,7 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 						}
 					},
 					&mut source_go_offline_future,
-					|delay| async_std::task::sleep(delay),
+					async_std::task::sleep,
 					|| format!("Error retrieving state from {} node", P::SOURCE_NAME),
 				).fail_if_connection_error(FailedClient::Source)?;
 			},
@@ -403,7 +869,7 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 						}
 					},
 					&mut target_go_offline_future,
-					|delay| async_std::task::sleep(delay),
+					async_std::task::sleep,
 					|| format!("Error retrieving state from {} node", P::TARGET_NAME),
 				).fail_if_connection_error(FailedClient::Target)?;
 			},
@@ -413,23 +879,85 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 			_ = target_tick_stream.next() => {
 				target_state_required = true;
 			},
+			new_target_lane_parameters = target_lane_parameters => {
+				target_client_is_online = process_future_result(
+					new_target_lane_parameters,
+					&mut target_retry_backoff,
+					|new_target_lane_parameters| {
+						log::debug!(
+							target: "bridge",
+							"Received lane parameters from {} node: {:?}",
+							P::TARGET_NAME,
+							new_target_lane_parameters,
+						);
+						let _ = delivery_control_sender
+							.unbounded_send(RaceCommand::SetLaneParameters(new_target_lane_parameters));
+					},
+					&mut target_go_offline_future,
+					async_std::task::sleep,
+					|| format!("Error retrieving lane parameters from {} node", P::TARGET_NAME),
+				).fail_if_connection_error(FailedClient::Target)?;
+				// parameters are read again once the client is back online
+				target_lane_parameters_required = !target_client_is_online;
+			},
+			_ = target_runtime_upgrade_receiver.next() => {
+				target_lane_parameters_required = true;
+			},
 
-			delivery_error = delivery_race_loop => {
-				match delivery_error {
-					Ok(_) => unreachable!("only ends with error; qed"),
-					Err(err) => return Err(err),
+			delivery_result = delivery_race_loop => {
+				match delivery_result {
+					Ok(summary) => {
+						log::info!(
+							target: "bridge",
+							"Message delivery race {} -> {} has finished: {}",
+							P::SOURCE_NAME,
+							P::TARGET_NAME,
+							summary,
+						);
+						is_delivery_race_finished = true;
+					}
+					Err(err) => {
+						log::error!(
+							target: "bridge",
+							"Message delivery race {} -> {} has failed: {}. Before failure it has {}",
+							P::SOURCE_NAME,
+							P::TARGET_NAME,
+							err,
+							err.summary,
+						);
+						return Err(err.failed_client);
+					}
 				}
 			},
-			receiving_error = receiving_race_loop => {
-				match receiving_error {
-					Ok(_) => unreachable!("only ends with error; qed"),
-					Err(err) => return Err(err),
+			receiving_result = receiving_race_loop => {
+				match receiving_result {
+					Ok(summary) => {
+						log::info!(
+							target: "bridge",
+							"Message receiving race {} -> {} has finished: {}",
+							P::TARGET_NAME,
+							P::SOURCE_NAME,
+							summary,
+						);
+						is_receiving_race_finished = true;
+					}
+					Err(err) => {
+						log::error!(
+							target: "bridge",
+							"Message receiving race {} -> {} has failed: {}. Before failure it has {}",
+							P::TARGET_NAME,
+							P::SOURCE_NAME,
+							err,
+							err.summary,
+						);
+						return Err(err.failed_client);
+					}
 				}
 			},
+		}
 
-			() = exit_signal => {
-				return Ok(());
-			}
+		if is_delivery_race_finished && is_receiving_race_finished {
+			return Ok(());
 		}
 
 		if let Some(metrics_global) = metrics_global.as_mut() {
@@ -447,292 +975,30 @@ async fn run_until_connection_lost<P: MessageLane, SC: SourceClient<P>, TC: Targ
 			target_state.set(target_client.state().fuse());
 			target_client_is_online = false;
 		}
+
+		if target_client_is_online && target_lane_parameters_required {
+			log::debug!(target: "bridge", "Asking {} node about lane parameters", P::TARGET_NAME);
+			target_lane_parameters.set(target_client.lane_parameters().fuse());
+			target_lane_parameters_required = false;
+			target_client_is_online = false;
+		}
 	}
 }
 
 #[cfg(test)]
-pub(crate) mod tests {
+mod tests {
 	use super::*;
+	use crate::testing::{run_race_scenario, test_params, TestClientData, TestClientTick};
 	use futures::stream::StreamExt;
-	use parking_lot::Mutex;
 	use relay_utils::HeaderId;
-	use std::sync::Arc;
-
-	pub fn header_id(number: TestSourceHeaderNumber) -> TestSourceHeaderId {
-		HeaderId(number, number)
-	}
-
-	pub type TestSourceHeaderId = HeaderId<TestSourceHeaderNumber, TestSourceHeaderHash>;
-	pub type TestTargetHeaderId = HeaderId<TestTargetHeaderNumber, TestTargetHeaderHash>;
-
-	pub type TestMessagesProof = (RangeInclusive<MessageNonce>, Option<MessageNonce>);
-	pub type TestMessagesReceivingProof = MessageNonce;
-
-	pub type TestSourceHeaderNumber = u64;
-	pub type TestSourceHeaderHash = u64;
-
-	pub type TestTargetHeaderNumber = u64;
-	pub type TestTargetHeaderHash = u64;
-
-	#[derive(Debug)]
-	pub struct TestError;
-
-	impl MaybeConnectionError for TestError {
-		fn is_connection_error(&self) -> bool {
-			true
-		}
-	}
-
-	#[derive(Clone)]
-	pub struct TestMessageLane;
-
-	impl MessageLane for TestMessageLane {
-		const SOURCE_NAME: &'static str = "TestSource";
-		const TARGET_NAME: &'static str = "TestTarget";
-
-		type MessagesProof = TestMessagesProof;
-		type MessagesReceivingProof = TestMessagesReceivingProof;
-
-		type SourceHeaderNumber = TestSourceHeaderNumber;
-		type SourceHeaderHash = TestSourceHeaderHash;
-
-		type TargetHeaderNumber = TestTargetHeaderNumber;
-		type TargetHeaderHash = TestTargetHeaderHash;
-	}
-
-	#[derive(Debug, Default, Clone)]
-	pub struct TestClientData {
-		is_source_fails: bool,
-		is_source_reconnected: bool,
-		source_state: SourceClientState<TestMessageLane>,
-		source_latest_generated_nonce: MessageNonce,
-		source_latest_confirmed_received_nonce: MessageNonce,
-		submitted_messages_receiving_proofs: Vec<TestMessagesReceivingProof>,
-		is_target_fails: bool,
-		is_target_reconnected: bool,
-		target_state: SourceClientState<TestMessageLane>,
-		target_latest_received_nonce: MessageNonce,
-		target_latest_confirmed_received_nonce: MessageNonce,
-		submitted_messages_proofs: Vec<TestMessagesProof>,
-	}
-
-	#[derive(Clone)]
-	pub struct TestSourceClient {
-		data: Arc<Mutex<TestClientData>>,
-		tick: Arc<dyn Fn(&mut TestClientData) + Send + Sync>,
-	}
-
-	#[async_trait]
-	impl SourceClient<TestMessageLane> for TestSourceClient {
-		type Error = TestError;
-
-		async fn reconnect(self) -> Result<Self, Self::Error> {
-			{
-				let mut data = self.data.lock();
-				(self.tick)(&mut *data);
-				data.is_source_reconnected = true;
-			}
-			Ok(self)
-		}
-
-		async fn state(&self) -> Result<SourceClientState<TestMessageLane>, Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_source_fails {
-				return Err(TestError);
-			}
-			Ok(data.source_state.clone())
-		}
-
-		async fn latest_generated_nonce(
-			&self,
-			id: SourceHeaderIdOf<TestMessageLane>,
-		) -> Result<(SourceHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_source_fails {
-				return Err(TestError);
-			}
-			Ok((id, data.source_latest_generated_nonce))
-		}
-
-		async fn latest_confirmed_received_nonce(
-			&self,
-			id: SourceHeaderIdOf<TestMessageLane>,
-		) -> Result<(SourceHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			Ok((id, data.source_latest_confirmed_received_nonce))
-		}
-
-		async fn generated_messages_weights(
-			&self,
-			_id: SourceHeaderIdOf<TestMessageLane>,
-			nonces: RangeInclusive<MessageNonce>,
-		) -> Result<MessageWeightsMap, Self::Error> {
-			Ok(nonces.map(|nonce| (nonce, 1)).collect())
-		}
-
-		async fn prove_messages(
-			&self,
-			id: SourceHeaderIdOf<TestMessageLane>,
-			nonces: RangeInclusive<MessageNonce>,
-			proof_parameters: MessageProofParameters,
-		) -> Result<
-			(
-				SourceHeaderIdOf<TestMessageLane>,
-				RangeInclusive<MessageNonce>,
-				TestMessagesProof,
-			),
-			Self::Error,
-		> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			Ok((
-				id,
-				nonces.clone(),
-				(
-					nonces,
-					if proof_parameters.outbound_state_proof_required {
-						Some(data.source_latest_confirmed_received_nonce)
-					} else {
-						None
-					},
-				),
-			))
-		}
-
-		async fn submit_messages_receiving_proof(
-			&self,
-			_generated_at_block: TargetHeaderIdOf<TestMessageLane>,
-			proof: TestMessagesReceivingProof,
-		) -> Result<(), Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			data.submitted_messages_receiving_proofs.push(proof);
-			data.source_latest_confirmed_received_nonce = proof;
-			Ok(())
-		}
-	}
-
-	#[derive(Clone)]
-	pub struct TestTargetClient {
-		data: Arc<Mutex<TestClientData>>,
-		tick: Arc<dyn Fn(&mut TestClientData) + Send + Sync>,
-	}
-
-	#[async_trait]
-	impl TargetClient<TestMessageLane> for TestTargetClient {
-		type Error = TestError;
-
-		async fn reconnect(self) -> Result<Self, Self::Error> {
-			{
-				let mut data = self.data.lock();
-				(self.tick)(&mut *data);
-				data.is_target_reconnected = true;
-			}
-			Ok(self)
-		}
-
-		async fn state(&self) -> Result<TargetClientState<TestMessageLane>, Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_target_fails {
-				return Err(TestError);
-			}
-			Ok(data.target_state.clone())
-		}
-
-		async fn latest_received_nonce(
-			&self,
-			id: TargetHeaderIdOf<TestMessageLane>,
-		) -> Result<(TargetHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_target_fails {
-				return Err(TestError);
-			}
-			Ok((id, data.target_latest_received_nonce))
-		}
-
-		async fn latest_confirmed_received_nonce(
-			&self,
-			id: TargetHeaderIdOf<TestMessageLane>,
-		) -> Result<(TargetHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_target_fails {
-				return Err(TestError);
-			}
-			Ok((id, data.target_latest_confirmed_received_nonce))
-		}
-
-		async fn prove_messages_receiving(
-			&self,
-			id: TargetHeaderIdOf<TestMessageLane>,
-		) -> Result<(TargetHeaderIdOf<TestMessageLane>, TestMessagesReceivingProof), Self::Error> {
-			Ok((id, self.data.lock().target_latest_received_nonce))
-		}
-
-		async fn submit_messages_proof(
-			&self,
-			_generated_at_header: SourceHeaderIdOf<TestMessageLane>,
-			nonces: RangeInclusive<MessageNonce>,
-			proof: TestMessagesProof,
-		) -> Result<RangeInclusive<MessageNonce>, Self::Error> {
-			let mut data = self.data.lock();
-			(self.tick)(&mut *data);
-			if data.is_target_fails {
-				return Err(TestError);
-			}
-			data.target_state.best_self =
-				HeaderId(data.target_state.best_self.0 + 1, data.target_state.best_self.1 + 1);
-			data.target_latest_received_nonce = *proof.0.end();
-			if let Some(target_latest_confirmed_received_nonce) = proof.1 {
-				data.target_latest_confirmed_received_nonce = target_latest_confirmed_received_nonce;
-			}
-			data.submitted_messages_proofs.push(proof);
-			Ok(nonces)
-		}
-	}
 
 	fn run_loop_test(
 		data: TestClientData,
-		source_tick: Arc<dyn Fn(&mut TestClientData) + Send + Sync>,
-		target_tick: Arc<dyn Fn(&mut TestClientData) + Send + Sync>,
+		source_tick: TestClientTick,
+		target_tick: TestClientTick,
 		exit_signal: impl Future<Output = ()>,
 	) -> TestClientData {
-		async_std::task::block_on(async {
-			let data = Arc::new(Mutex::new(data));
-
-			let source_client = TestSourceClient {
-				data: data.clone(),
-				tick: source_tick,
-			};
-			let target_client = TestTargetClient {
-				data: data.clone(),
-				tick: target_tick,
-			};
-			run(
-				Params {
-					lane: [0, 0, 0, 0],
-					source_tick: Duration::from_millis(100),
-					target_tick: Duration::from_millis(100),
-					reconnect_delay: Duration::from_millis(0),
-					stall_timeout: Duration::from_millis(60 * 1000),
-					delivery_params: MessageDeliveryParams {
-						max_unconfirmed_nonces_at_target: 4,
-						max_messages_weight_in_single_batch: 4,
-					},
-				},
-				source_client,
-				target_client,
-				None,
-				exit_signal,
-			);
-			let result = data.lock().clone();
-			result
-		})
+		run_race_scenario(test_params(), data, source_tick, target_tick, exit_signal)
 	}
 
 	#[test]
@@ -740,17 +1006,19 @@ pub(crate) mod tests {
 		// with this configuration, source client will return Err, making source client
 		// reconnect. Then the target client will fail with Err + reconnect. Then we finally
 		// able to deliver messages.
-		let (exit_sender, exit_receiver) = unbounded();
+		let (exit_sender, mut exit_receiver) = unbounded();
 		let result = run_loop_test(
 			TestClientData {
 				is_source_fails: true,
 				source_state: ClientState {
 					best_self: HeaderId(0, 0),
+					best_finalized_self: HeaderId(0, 0),
 					best_peer: HeaderId(0, 0),
 				},
 				source_latest_generated_nonce: 1,
 				target_state: ClientState {
 					best_self: HeaderId(0, 0),
+					best_finalized_self: HeaderId(0, 0),
 					best_peer: HeaderId(0, 0),
 				},
 				target_latest_received_nonce: 0,
@@ -774,7 +1042,9 @@ pub(crate) mod tests {
 					exit_sender.unbounded_send(()).unwrap();
 				}
 			}),
-			exit_receiver.into_future().map(|(_, _)| ()),
+			// the receiver outlives the loop, because the client may tick while the race is finishing
+			// in-flight submissions after the exit signal
+			exit_receiver.next().map(|_| ()),
 		);
 
 		assert_eq!(result.submitted_messages_proofs, vec![(1..=1, None)],);
@@ -782,16 +1052,18 @@ pub(crate) mod tests {
 
 	#[test]
 	fn message_lane_loop_works() {
-		let (exit_sender, exit_receiver) = unbounded();
+		let (exit_sender, mut exit_receiver) = unbounded();
 		let result = run_loop_test(
 			TestClientData {
 				source_state: ClientState {
 					best_self: HeaderId(10, 10),
+					best_finalized_self: HeaderId(10, 10),
 					best_peer: HeaderId(0, 0),
 				},
 				source_latest_generated_nonce: 10,
 				target_state: ClientState {
 					best_self: HeaderId(0, 0),
+					best_finalized_self: HeaderId(0, 0),
 					best_peer: HeaderId(0, 0),
 				},
 				target_latest_received_nonce: 0,
@@ -822,7 +1094,9 @@ pub(crate) mod tests {
 					exit_sender.unbounded_send(()).unwrap();
 				}
 			}),
-			exit_receiver.into_future().map(|(_, _)| ()),
+			// the receiver outlives the loop, because the client may tick while the race is finishing
+			// in-flight submissions after the exit signal
+			exit_receiver.next().map(|_| ()),
 		);
 
 		// there are no strict restrictions on when reward confirmation should come
@@ -834,4 +1108,145 @@ pub(crate) mod tests {
 		assert_eq!(result.submitted_messages_proofs[2].0, 9..=10);
 		assert!(!result.submitted_messages_receiving_proofs.is_empty());
 	}
+
+	#[test]
+	fn message_lane_loop_refreshes_lane_parameters_after_target_runtime_upgrade() {
+		let (exit_sender, exit_receiver) = unbounded();
+		let params = test_params();
+		let result = run_race_scenario(
+			Params {
+				race_reconnect_attempts: 1,
+				delivery_params: MessageDeliveryParams {
+					max_unconfirmed_nonces_at_target: 10,
+					limits: DeliveryLimits {
+						max_nonces: Some(1),
+						max_weight: 10,
+						max_size: 1024 * 1024,
+					},
+					..params.delivery_params.clone()
+				},
+				..params
+			},
+			TestClientData {
+				source_state: ClientState {
+					best_self: HeaderId(10, 10),
+					best_finalized_self: HeaderId(10, 10),
+					best_peer: HeaderId(0, 0),
+				},
+				source_latest_generated_nonce: 10,
+				target_runtime_version: Some(1),
+				target_lane_parameters: Some(TargetLaneParameters {
+					max_messages_in_single_batch: 1,
+					max_messages_weight_in_single_batch: 10,
+					max_unconfirmed_messages: 10,
+				}),
+				..Default::default()
+			},
+			Arc::new(|_: &mut TestClientData| {}),
+			Arc::new(move |data: &mut TestClientData| {
+				if data.target_state.best_peer.0 < data.source_state.best_self.0 {
+					data.target_state.best_peer = data.source_state.best_self;
+				}
+				if data.is_target_reconnected {
+					data.is_target_submission_fails = false;
+				}
+				// after two messages are delivered, the target runtime is upgraded and the lane
+				// parameters are changed. The connection is lost, so the race reads runtime version
+				// again after reconnect
+				if data.target_latest_received_nonce >= 2 && data.target_runtime_version == Some(1) {
+					data.target_runtime_version = Some(2);
+					data.target_lane_parameters = Some(TargetLaneParameters {
+						max_messages_in_single_batch: 10,
+						max_messages_weight_in_single_batch: 10,
+						max_unconfirmed_messages: 10,
+					});
+					data.is_target_submission_fails = true;
+				}
+				if data.target_latest_received_nonce == 10 {
+					let _ = exit_sender.unbounded_send(());
+				}
+			}),
+			exit_receiver.into_future().map(|(_, _)| ()),
+		);
+
+		// parameters are read at startup and after the runtime upgrade (and also after every reconnect,
+		// so there may be more requests if the failed submission has caused the connection to be restarted)
+		assert!(result.target_lane_parameters_requests >= 2);
+		assert_eq!(result.submitted_messages_proofs[0].0, 1..=1);
+		assert_eq!(result.submitted_messages_proofs[1].0, 2..=2);
+		let last_proof = &result.submitted_messages_proofs.last().unwrap().0;
+		assert_eq!(*last_proof.end(), 10);
+		assert!(last_proof.start() < last_proof.end());
+	}
+
+	#[test]
+	fn message_lane_loop_delivers_messages_despite_latency_and_injected_failures() {
+		let (exit_sender, exit_receiver) = unbounded();
+		let result = run_loop_test(
+			TestClientData {
+				source_latency: Duration::from_millis(5),
+				source_state: ClientState {
+					best_self: HeaderId(10, 10),
+					best_finalized_self: HeaderId(10, 10),
+					best_peer: HeaderId(0, 0),
+				},
+				source_generated_nonces: vec![1, 2, 3].into_iter().collect(),
+				target_latency: Duration::from_millis(5),
+				target_failures: 2,
+				target_state: ClientState {
+					best_self: HeaderId(0, 0),
+					best_finalized_self: HeaderId(0, 0),
+					best_peer: HeaderId(10, 10),
+				},
+				..Default::default()
+			},
+			Arc::new(|_: &mut TestClientData| {}),
+			Arc::new(move |data: &mut TestClientData| {
+				if data.source_state.best_peer.0 < data.target_state.best_self.0 {
+					data.source_state.best_peer = data.target_state.best_self;
+				}
+				if data.target_latest_received_nonce == 3 {
+					let _ = exit_sender.unbounded_send(());
+				}
+			}),
+			exit_receiver.into_future().map(|(_, _)| ()),
+		);
+
+		// injected failures are connection errors, so the loop has reconnected to the target node
+		assert!(result.is_target_reconnected);
+		assert_eq!(result.target_failures, 0);
+		assert!(result.source_generated_nonces.is_empty());
+		assert_eq!(result.target_latest_received_nonce, 3);
+	}
+
+	#[test]
+	fn message_lane_loop_exits_while_waiting_for_reconnect() {
+		// the source client always fails, so the loop is waiting for the (very long) reconnect
+		// delay when the exit signal is received
+		let (exit_sender, exit_receiver) = unbounded();
+		let (result_sender, result_receiver) = std::sync::mpsc::channel();
+		std::thread::spawn(move || {
+			let result = run_race_scenario(
+				Params {
+					reconnect_delay: Duration::from_secs(60 * 60),
+					..test_params()
+				},
+				TestClientData {
+					is_source_fails: true,
+					..Default::default()
+				},
+				Arc::new(move |_: &mut TestClientData| {
+					let _ = exit_sender.unbounded_send(());
+				}),
+				Arc::new(|_: &mut TestClientData| {}),
+				exit_receiver.into_future().map(|(_, _)| ()),
+			);
+			let _ = result_sender.send(result);
+		});
+
+		let result = result_receiver
+			.recv_timeout(Duration::from_secs(10))
+			.expect("loop has not exited while waiting for reconnect");
+		assert!(!result.is_source_reconnected);
+	}
 }
@@ -15,52 +15,115 @@
 
 use crate::message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf};
 use crate::message_lane_loop::{
-	MessageDeliveryParams, MessageProofParameters, MessageWeightsMap, SourceClient as MessageLaneSourceClient,
-	SourceClientState, TargetClient as MessageLaneTargetClient, TargetClientState,
+	DeliveryBatchOptimizer, DeliveryNonceFilter, MessageDeliveryParams, MessageDeliveryProfitability, MessageDetails,
+	MessageDetailsMap, MessageProofParameters, SourceClient as MessageLaneSourceClient, SourceClientState,
+	TargetClient as MessageLaneTargetClient, TargetClientState, TargetCostProfitability,
 };
 use crate::message_race_loop::{
-	MessageRace, NoncesRange, RaceState, RaceStrategy, SourceClient, SourceClientNonces, TargetClient,
-	TargetClientNonces,
+	DryRunOutcome, MessageRace, NoncesRange, PrefixProofParameters, RaceCommand, RaceContext, RaceError,
+	RaceErrorReason, RaceParams, RaceState, RaceStrategy, RaceSummary, RangeFailureKind, ReconnectError, SourceClient,
+	SourceClientNonces, StrategyMetricsSnapshot, SubmitOptions, TargetClient, TargetClientNonces, TargetNonces,
+};
+use crate::message_race_strategy::{
+	BasicStrategy, BatchingStrategy, MaxNoncesConstraint, RaceCheckpoint, RateLimitedStrategy, SelectionConstraint,
 };
-use crate::message_race_strategy::BasicStrategy;
 use crate::metrics::MessageLaneLoopMetrics;
 
 use async_trait::async_trait;
-use bp_message_lane::{MessageNonce, Weight};
+use bp_message_lane::{MessageNonce, UnrewardedRelayersState, Weight};
 use futures::stream::FusedStream;
-use relay_utils::FailedClient;
-use std::{collections::BTreeMap, marker::PhantomData, ops::RangeInclusive, time::Duration};
+use num_traits::{Saturating, Zero};
+use std::{
+	collections::BTreeMap,
+	future::Future,
+	marker::PhantomData,
+	ops::RangeInclusive,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/// Estimated size of the messages proof, excluding messages payloads. It accounts storage proof
+/// nodes, lane state and the delivery transaction itself.
+const MESSAGES_PROOF_OVERHEAD: u32 = 2 * 1024;
 
 /// Run message delivery race.
+#[allow(clippy::too_many_arguments)]
 pub async fn run<P: MessageLane>(
 	source_client: impl MessageLaneSourceClient<P>,
 	source_state_updates: impl FusedStream<Item = SourceClientState<P>>,
 	target_client: impl MessageLaneTargetClient<P>,
 	target_state_updates: impl FusedStream<Item = TargetClientState<P>>,
-	stall_timeout: Duration,
+	race_params: RaceParams,
 	metrics_msg: Option<MessageLaneLoopMetrics>,
-	params: MessageDeliveryParams,
-) -> Result<(), FailedClient> {
+	params: MessageDeliveryParams<P::SourceChainBalance>,
+	control: impl FusedStream<Item = RaceCommand>,
+	checkpoint: Option<RaceCheckpoint<SourceHeaderIdOf<P>, MessageDetailsMap<P::SourceChainBalance>>>,
+	on_checkpoint: impl FnMut(RaceCheckpoint<SourceHeaderIdOf<P>, MessageDetailsMap<P::SourceChainBalance>>),
+	on_required_source_header: impl FnMut(SourceHeaderIdOf<P>),
+	on_target_runtime_upgrade: Arc<dyn Fn() + Send + Sync>,
+	exit_signal: impl Future<Output = ()>,
+) -> Result<RaceSummary, RaceError> {
+	let final_nonce = race_params.final_nonce;
+	let source_confirmation_depth = race_params.source_confirmation_depth;
+	let proof_at_queued_header = race_params.proof_at_queued_header;
+	let require_target_finality = race_params.require_target_finality;
+	let race_metrics = metrics_msg
+		.as_ref()
+		.map(|metrics_msg| metrics_msg.delivery_race().clone());
 	crate::message_race_loop::run(
 		MessageDeliveryRaceSource {
 			client: source_client,
 			metrics_msg: metrics_msg.clone(),
 			_phantom: Default::default(),
 		},
-		source_state_updates,
 		MessageDeliveryRaceTarget {
 			client: target_client,
 			metrics_msg,
+			on_runtime_upgrade: on_target_runtime_upgrade,
 			_phantom: Default::default(),
 		},
-		target_state_updates,
-		stall_timeout,
-		MessageDeliveryStrategy::<P> {
-			max_unconfirmed_nonces_at_target: params.max_unconfirmed_nonces_at_target,
-			max_messages_weight_in_single_batch: params.max_messages_weight_in_single_batch,
-			latest_confirmed_nonce_at_source: None,
-			target_nonces: None,
-			strategy: BasicStrategy::new(),
+		race_params,
+		RateLimitedStrategy::new(
+			BatchingStrategy::new(
+				MessageDeliveryStrategy::<P> {
+					max_unconfirmed_nonces_at_target: params.max_unconfirmed_nonces_at_target,
+					max_unrewarded_relayer_entries_at_target: params.max_unrewarded_relayer_entries_at_target,
+					limits: params.limits,
+					profitability: params.profitability,
+					target_cost_profitability: params.target_cost_profitability,
+					latest_selection: None,
+					latest_selected_weights: Vec::new(),
+					batch_optimizer: params.batch_optimizer,
+					nonce_filter: params.nonce_filter,
+					unprofitable_since: None,
+					latest_confirmed_nonce_at_source: None,
+					target_nonces: None,
+					strategy: match (checkpoint, params.initial_target_nonce) {
+						(Some(checkpoint), _) => BasicStrategy::from_checkpoint(checkpoint),
+						(None, Some(initial_target_nonce)) => {
+							BasicStrategy::new_with_target_nonce(initial_target_nonce)
+						}
+						(None, None) => BasicStrategy::new(),
+					}
+					.with_target_nonce_rollback(params.redeliver_after_target_rollback)
+					.with_final_nonce(final_nonce)
+					.with_source_confirmation_depth(source_confirmation_depth)
+					.with_proof_at_queued_header(proof_at_queued_header)
+					.with_target_finality_required(require_target_finality),
+				},
+				params.batching,
+			),
+			params.max_messages_per_minute.unwrap_or(MessageNonce::MAX),
+		),
+		RaceContext {
+			source_updated: source_state_updates,
+			target_updated: target_state_updates,
+			control,
+			on_checkpoint,
+			on_required_source_header,
+			observer: (),
+			metrics: race_metrics,
+			exit_signal,
 		},
 	)
 	.await
@@ -83,6 +146,14 @@ impl<P: MessageLane> MessageRace for MessageDeliveryRace<P> {
 	fn target_name() -> String {
 		format!("{}::MessagesDelivery", P::TARGET_NAME)
 	}
+
+	fn race_kind() -> String {
+		"Messages delivery".into()
+	}
+
+	fn proof_size(proof: &P::MessagesProof) -> usize {
+		P::messages_proof_size(proof)
+	}
 }
 
 /// Message delivery race source, which is a source of the lane.
@@ -99,28 +170,42 @@ where
 	C: MessageLaneSourceClient<P>,
 {
 	type Error = C::Error;
-	type NoncesRange = MessageWeightsMap;
+	type NoncesRange = MessageDetailsMap<P::SourceChainBalance>;
 	type ProofParameters = MessageProofParameters;
 
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		self.client = self.client.clone().reconnect().await.map_err(ReconnectError::Failed)?;
+		Ok(())
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.client.runtime_version().await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
 	async fn nonces(
 		&self,
 		at_block: SourceHeaderIdOf<P>,
-		prev_latest_nonce: MessageNonce,
+		prev_latest_nonce: Option<MessageNonce>,
 	) -> Result<(SourceHeaderIdOf<P>, SourceClientNonces<Self::NoncesRange>), Self::Error> {
 		let (at_block, latest_generated_nonce) = self.client.latest_generated_nonce(at_block).await?;
 		let (at_block, latest_confirmed_nonce) = self.client.latest_confirmed_received_nonce(at_block).await?;
 
 		if let Some(metrics_msg) = self.metrics_msg.as_ref() {
-			metrics_msg.update_source_latest_generated_nonce::<P>(latest_generated_nonce);
-			metrics_msg.update_source_latest_confirmed_nonce::<P>(latest_confirmed_nonce);
+			metrics_msg.update_source_latest_generated_nonce(latest_generated_nonce);
+			metrics_msg.update_source_latest_confirmed_nonce(latest_confirmed_nonce);
 		}
 
+		let prev_latest_nonce = prev_latest_nonce.unwrap_or(0);
 		let new_nonces = if latest_generated_nonce > prev_latest_nonce {
 			self.client
-				.generated_messages_weights(at_block.clone(), prev_latest_nonce + 1..=latest_generated_nonce)
+				.generated_messages_details(at_block.clone(), prev_latest_nonce + 1..=latest_generated_nonce)
 				.await?
 		} else {
-			MessageWeightsMap::new()
+			MessageDetailsMap::new()
 		};
 
 		Ok((
@@ -128,6 +213,7 @@ where
 			SourceClientNonces {
 				new_nonces,
 				confirmed_nonce: Some(latest_confirmed_nonce),
+				unrewarded_relayer_entries: None,
 			},
 		))
 	}
@@ -140,15 +226,56 @@ where
 	) -> Result<(SourceHeaderIdOf<P>, RangeInclusive<MessageNonce>, P::MessagesProof), Self::Error> {
 		self.client.prove_messages(at_block, nonces, proof_parameters).await
 	}
+
+	async fn estimate_proof_size(
+		&self,
+		at_block: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> Result<u32, Self::Error> {
+		self.client
+			.estimate_messages_proof_size(at_block, nonces, proof_parameters)
+			.await
+	}
 }
 
 /// Message delivery race target, which is a target of the lane.
 struct MessageDeliveryRaceTarget<P: MessageLane, C> {
 	client: C,
 	metrics_msg: Option<MessageLaneLoopMetrics>,
+	/// Called when the target node runtime has been upgraded, so that lane parameters may be
+	/// read again.
+	on_runtime_upgrade: Arc<dyn Fn() + Send + Sync>,
 	_phantom: PhantomData<P>,
 }
 
+impl<P, C> MessageDeliveryRaceTarget<P, C>
+where
+	P: MessageLane,
+	C: MessageLaneTargetClient<P>,
+{
+	/// Read nonces at given target header.
+	async fn nonces_at(
+		&self,
+		at_block: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, TargetClientNonces<DeliveryRaceTargetNoncesData>), C::Error> {
+		let (at_block, latest_received_nonce) = self.client.latest_received_nonce(at_block).await?;
+		let (at_block, latest_confirmed_nonce) = self.client.latest_confirmed_received_nonce(at_block).await?;
+		let (at_block, unrewarded_relayers) = self.client.unrewarded_relayers_state(at_block).await?;
+
+		Ok((
+			at_block,
+			TargetClientNonces {
+				latest_nonce: latest_received_nonce,
+				nonces_data: DeliveryRaceTargetNoncesData {
+					confirmed_nonce: latest_confirmed_nonce,
+					unrewarded_relayers,
+				},
+			},
+		))
+	}
+}
+
 #[async_trait]
 impl<P, C> TargetClient<MessageDeliveryRace<P>> for MessageDeliveryRaceTarget<P, C>
 where
@@ -156,50 +283,146 @@ where
 	C: MessageLaneTargetClient<P>,
 {
 	type Error = C::Error;
+	type TransactionTracker = C::TransactionTracker;
+	type Cost = P::SourceChainBalance;
+	type TargetNoncesData = DeliveryRaceTargetNoncesData;
+
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		self.client = self.client.clone().reconnect().await.map_err(ReconnectError::Failed)?;
+		Ok(())
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.client.runtime_version().await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade();
+		(self.on_runtime_upgrade)();
+	}
 
 	async fn nonces(
 		&self,
-		at_block: TargetHeaderIdOf<P>,
-	) -> Result<(TargetHeaderIdOf<P>, TargetClientNonces), Self::Error> {
-		let (at_block, latest_received_nonce) = self.client.latest_received_nonce(at_block).await?;
-		let (at_block, latest_confirmed_nonce) = self.client.latest_confirmed_received_nonce(at_block).await?;
+		at_best: TargetHeaderIdOf<P>,
+		at_finalized: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, TargetNonces<DeliveryRaceTargetNoncesData>), Self::Error> {
+		let is_best_finalized = at_best == at_finalized;
+		let (at_best, nonces_at_best) = self.nonces_at(at_best).await?;
+		let nonces_at_finalized = if is_best_finalized {
+			nonces_at_best.clone()
+		} else {
+			self.nonces_at(at_finalized).await?.1
+		};
 
 		if let Some(metrics_msg) = self.metrics_msg.as_ref() {
-			metrics_msg.update_target_latest_received_nonce::<P>(latest_received_nonce);
-			metrics_msg.update_target_latest_confirmed_nonce::<P>(latest_confirmed_nonce);
+			metrics_msg.update_target_latest_received_nonce(nonces_at_best.latest_nonce);
+			metrics_msg.update_target_latest_confirmed_nonce(nonces_at_best.nonces_data.confirmed_nonce);
 		}
 
 		Ok((
-			at_block,
-			TargetClientNonces {
-				latest_nonce: latest_received_nonce,
-				confirmed_nonce: Some(latest_confirmed_nonce),
+			at_best,
+			TargetNonces {
+				at_best: nonces_at_best,
+				at_finalized: nonces_at_finalized,
 			},
 		))
 	}
 
+	async fn verify_proof(
+		&self,
+		generated_at_block: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<(), Self::Error> {
+		self.client
+			.verify_messages_proof(generated_at_block, nonces, proof)
+			.await
+	}
+
+	async fn dry_run_proof(
+		&self,
+		generated_at_block: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		self.client
+			.dry_run_messages_proof(generated_at_block, nonces, proof)
+			.await
+	}
+
+	async fn required_source_header_at_target(
+		&self,
+		current_best: SourceHeaderIdOf<P>,
+	) -> Result<Option<SourceHeaderIdOf<P>>, Self::Error> {
+		self.client.required_source_header_at_target(current_best).await
+	}
+
 	async fn submit_proof(
 		&self,
 		generated_at_block: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
-		proof: P::MessagesProof,
-	) -> Result<RangeInclusive<MessageNonce>, Self::Error> {
+		proof: Arc<P::MessagesProof>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		// lane client needs owned proof, so it is copied here unless the race has already dropped it
+		let proof = Arc::try_unwrap(proof).unwrap_or_else(|proof| (*proof).clone());
+		self.client
+			.submit_messages_proof(generated_at_block, nonces, proof, options)
+			.await
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<P::SourceChainBalance, Self::Error> {
 		self.client
-			.submit_messages_proof(generated_at_block, nonces, proof)
+			.estimate_delivery_cost(nonces, total_dispatch_weight, total_size)
 			.await
 	}
 }
 
+/// Additional nonces data from the target client used by message delivery race.
+#[derive(Debug, Clone, Default)]
+struct DeliveryRaceTargetNoncesData {
+	/// Latest nonce that we know: (1) has been delivered to us (2) has been confirmed
+	/// back to the source node (by confirmations race) and (3) relayer has received
+	/// reward for (and this has been confirmed by the message delivery race).
+	confirmed_nonce: MessageNonce,
+	/// State of the unrewarded relayers set at the target node.
+	unrewarded_relayers: UnrewardedRelayersState,
+}
+
 /// Messages delivery strategy.
 struct MessageDeliveryStrategy<P: MessageLane> {
 	/// Maximal unconfirmed nonces at target client.
 	max_unconfirmed_nonces_at_target: MessageNonce,
-	/// Maximal cumulative messages weight in the single delivery transaction.
-	max_messages_weight_in_single_batch: Weight,
+	/// Maximal unrewarded relayer entries at target client.
+	max_unrewarded_relayer_entries_at_target: MessageNonce,
+	/// Limits of the single delivery transaction.
+	limits: DeliveryLimits,
+	/// If set, only messages that are paying for their delivery are selected.
+	profitability: Option<MessageDeliveryProfitability<P::SourceChainBalance>>,
+	/// If set, only messages that are paying for their delivery (according to the cost, estimated
+	/// by the target client) are delivered.
+	target_cost_profitability: Option<TargetCostProfitability>,
+	/// Nonces, cumulative dispatch weight, size and reward of the latest selected messages. Only
+	/// tracked if `target_cost_profitability` is set.
+	latest_selection: Option<(RangeInclusive<MessageNonce>, Weight, u32, P::SourceChainBalance)>,
+	/// Nonces and dispatch weights of the latest selected messages. They're used to compute
+	/// parameters of the proof if the selected range is shrunk.
+	latest_selected_weights: Vec<(MessageNonce, Weight)>,
+	/// If set, the prefix of selected messages that maximizes net reward is delivered.
+	batch_optimizer: Option<DeliveryBatchOptimizer<P::SourceChainBalance>>,
+	/// If set, refused messages (and all following messages) are never selected.
+	nonce_filter: Option<DeliveryNonceFilter>,
+	/// First nonce of the unprofitable batch and the moment when it has been found unprofitable.
+	unprofitable_since: Option<(MessageNonce, Instant)>,
 	/// Latest confirmed nonce at the source client.
 	latest_confirmed_nonce_at_source: Option<MessageNonce>,
 	/// Target nonces from the source client.
-	target_nonces: Option<TargetClientNonces>,
+	target_nonces: Option<TargetClientNonces<DeliveryRaceTargetNoncesData>>,
 	/// Basic delivery strategy.
 	strategy: MessageDeliveryStrategyBase<P>,
 }
@@ -209,20 +432,35 @@ type MessageDeliveryStrategyBase<P> = BasicStrategy<
 	<P as MessageLane>::SourceHeaderHash,
 	<P as MessageLane>::TargetHeaderNumber,
 	<P as MessageLane>::TargetHeaderHash,
-	MessageWeightsMap,
+	MessageDetailsMap<<P as MessageLane>::SourceChainBalance>,
 	<P as MessageLane>::MessagesProof,
 >;
 
 impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::MessagesProof>
 	for MessageDeliveryStrategy<P>
 {
-	type SourceNoncesRange = MessageWeightsMap;
+	type SourceNoncesRange = MessageDetailsMap<P::SourceChainBalance>;
 	type ProofParameters = MessageProofParameters;
+	type Checkpoint = RaceCheckpoint<SourceHeaderIdOf<P>, MessageDetailsMap<P::SourceChainBalance>>;
+	type Cost = P::SourceChainBalance;
+	type TargetNoncesData = DeliveryRaceTargetNoncesData;
 
 	fn is_empty(&self) -> bool {
 		self.strategy.is_empty()
 	}
 
+	fn is_source_queue_full(&self) -> bool {
+		self.strategy.is_source_queue_full()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		self.strategy.queue_summary()
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		self.strategy.metrics()
+	}
+
 	fn best_at_source(&self) -> MessageNonce {
 		self.strategy.best_at_source()
 	}
@@ -231,6 +469,18 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 		self.strategy.best_at_target()
 	}
 
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_source_opt()
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_target_opt()
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.strategy.requires_target_finality()
+	}
+
 	fn source_nonces_updated(
 		&mut self,
 		at_block: SourceHeaderIdOf<P>,
@@ -240,24 +490,34 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 		self.strategy.source_nonces_updated(at_block, nonces)
 	}
 
+	fn source_reorged(&mut self, new_best: &SourceHeaderIdOf<P>) {
+		self.strategy.source_reorged(new_best)
+	}
+
 	fn target_nonces_updated(
 		&mut self,
-		nonces: TargetClientNonces,
+		nonces: TargetClientNonces<DeliveryRaceTargetNoncesData>,
 		race_state: &mut RaceState<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::MessagesProof>,
 	) {
-		self.target_nonces = Some(nonces.clone());
-		self.strategy.target_nonces_updated(nonces, race_state)
+		let latest_nonce = nonces.latest_nonce;
+		self.target_nonces = Some(nonces);
+		self.strategy.target_nonces_updated(
+			TargetClientNonces {
+				latest_nonce,
+				nonces_data: (),
+			},
+			race_state,
+		)
 	}
 
 	fn select_nonces_to_deliver(
 		&mut self,
 		race_state: &RaceState<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::MessagesProof>,
-	) -> Option<(RangeInclusive<MessageNonce>, Self::ProofParameters)> {
-		const CONFIRMED_NONCE_PROOF: &str = "\
-			ClientNonces are crafted by MessageDeliveryRace(Source|Target);\
-			MessageDeliveryRace(Source|Target) always fills confirmed_nonce field;\
-			qed";
-
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<SourceHeaderIdOf<P>>,
+	)> {
 		let latest_confirmed_nonce_at_source = self.latest_confirmed_nonce_at_source?;
 		let target_nonces = self.target_nonces.as_ref()?;
 
@@ -271,14 +531,21 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 
 		// The receiving race is responsible to deliver confirmations back to the source chain. So if
 		// there's a lot of unconfirmed messages, let's wait until it'll be able to do its job.
-		let latest_received_nonce_at_target = target_nonces.latest_nonce;
+		//
+		// Nonces that we have already submitted (or queued for submission), are treated as received
+		// here - otherwise the next (pipelined) batch may exceed the unconfirmed messages limit.
+		let latest_received_nonce_at_target = race_state
+			.best_pending_nonce()
+			.map(|best_pending_nonce| std::cmp::max(best_pending_nonce, target_nonces.latest_nonce))
+			.unwrap_or(target_nonces.latest_nonce);
 		let confirmations_missing = latest_received_nonce_at_target.checked_sub(latest_confirmed_nonce_at_source);
 		match confirmations_missing {
 			Some(confirmations_missing) if confirmations_missing >= self.max_unconfirmed_nonces_at_target => {
 				log::debug!(
 					target: "bridge",
 					"Cannot deliver any more messages from {} to {}. Too many unconfirmed nonces \
-					at target: target.latest_received={:?}, source.latest_confirmed={:?}, max={:?}",
+					at target: target.latest_received={:?}, source.latest_confirmed={:?}, max={:?}. \
+					Waiting for confirmations",
 					MessageDeliveryRace::<P>::source_name(),
 					MessageDeliveryRace::<P>::target_name(),
 					latest_received_nonce_at_target,
@@ -297,9 +564,31 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 		//
 		// Important note: we're including outbound state lane proof whenever there are unconfirmed nonces
 		// on the target chain. Other strategy is to include it only if it's absolutely necessary.
-		let latest_confirmed_nonce_at_target = target_nonces.confirmed_nonce.expect(CONFIRMED_NONCE_PROOF);
+		let latest_confirmed_nonce_at_target = target_nonces.nonces_data.confirmed_nonce;
 		let outbound_state_proof_required = latest_confirmed_nonce_at_target < latest_confirmed_nonce_at_source;
 
+		// Target would also reject messages if there are too many unrewarded relayer entries at the inbound
+		// lane. The only exception is when the delivery transaction brings updated outbound lane state, which
+		// allows target to prune some of these entries. So if there's nothing to bring, let's wait until the
+		// receiving race will deliver new confirmations to the source node.
+		let unrewarded_relayer_entries_at_target =
+			target_nonces.nonces_data.unrewarded_relayers.unrewarded_relayer_entries;
+		if unrewarded_relayer_entries_at_target >= self.max_unrewarded_relayer_entries_at_target
+			&& !outbound_state_proof_required
+		{
+			log::debug!(
+				target: "bridge",
+				"Cannot deliver any more messages from {} to {}. Too many unrewarded relayer entries \
+				at target: target.unrewarded_relayer_entries={:?}, max={:?}. Waiting for confirmations",
+				MessageDeliveryRace::<P>::source_name(),
+				MessageDeliveryRace::<P>::target_name(),
+				unrewarded_relayer_entries_at_target,
+				self.max_unrewarded_relayer_entries_at_target,
+			);
+
+			return None;
+		}
+
 		// If we're here, then the confirmations race did its job && sending side now knows that messages
 		// have been delivered. Now let's select nonces that we want to deliver.
 		//
@@ -320,21 +609,62 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 			.checked_sub(future_confirmed_nonce_at_target)
 			.and_then(|diff| self.max_unconfirmed_nonces_at_target.checked_sub(diff))
 			.unwrap_or_default();
-		let max_messages_weight_in_single_batch = self.max_messages_weight_in_single_batch;
+		let max_nonces = std::cmp::min(max_nonces, self.limits.max_nonces.unwrap_or(MessageNonce::MAX));
+		let max_messages_weight_in_single_batch = self.limits.max_weight;
+		let max_messages_size_in_single_batch = self.limits.max_size;
+		let nonce_filter = self.nonce_filter.as_ref();
 		let mut selected_weight: Weight = 0;
+		let mut selected_size: u32 = MESSAGES_PROOF_OVERHEAD;
 		let mut selected_count: MessageNonce = 0;
+		let mut selected_messages = Vec::new();
 
 		let selected_nonces = self
 			.strategy
 			.select_nonces_to_deliver_with_selector(race_state, |range| {
 				let to_requeue = range
 					.into_iter()
-					.skip_while(|(_, weight)| {
-						// limit messages in the batch by weight
-						let new_selected_weight = match selected_weight.checked_add(*weight) {
+					.skip_while(|(nonce, details)| {
+						// refused message is never delivered, so it blocks all following messages
+						let is_refused = nonce_filter
+							.map(|nonce_filter| (nonce_filter.is_refused)(*nonce))
+							.unwrap_or(false);
+						if is_refused {
+							log::warn!(
+								target: "bridge",
+								"Message {} from {} to {} is refused by the filter. It blocks delivery of all following messages",
+								nonce,
+								MessageDeliveryRace::<P>::source_name(),
+								MessageDeliveryRace::<P>::target_name(),
+							);
+							return false;
+						}
+
+						// limit messages in the batch by weight. Message that is heavier than the limit
+						// is delivered alone - otherwise the race would never deliver it
+						let new_selected_weight = match selected_weight.checked_add(details.dispatch_weight) {
 							Some(new_selected_weight) if new_selected_weight <= max_messages_weight_in_single_batch => {
 								new_selected_weight
 							}
+							_ if selected_count == 0 => details.dispatch_weight,
+							_ => return false,
+						};
+
+						// limit messages in the batch by size. Oversized message is also delivered alone,
+						// but the transaction is likely to be rejected by the target node
+						let new_selected_size = match selected_size.checked_add(details.size) {
+							Some(new_selected_size) if new_selected_size <= max_messages_size_in_single_batch => {
+								new_selected_size
+							}
+							_ if selected_count == 0 => {
+								log::warn!(
+									target: "bridge",
+									"Size of message {} ({} bytes) exceeds the limit of {} bytes. It is delivered alone",
+									nonce,
+									details.size,
+									max_messages_size_in_single_batch,
+								);
+								selected_size.saturating_add(details.size)
+							}
 							_ => return false,
 						};
 
@@ -345,7 +675,9 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 						}
 
 						selected_weight = new_selected_weight;
+						selected_size = new_selected_size;
 						selected_count = new_selected_count;
+						selected_messages.push((*nonce, *details));
 						true
 					})
 					.collect::<BTreeMap<_, _>>();
@@ -354,19 +686,386 @@ impl<P: MessageLane> RaceStrategy<SourceHeaderIdOf<P>, TargetHeaderIdOf<P>, P::M
 				} else {
 					Some(to_requeue)
 				}
-			})?;
+			});
+		let selected_nonces = match selected_nonces {
+			Ok(selected_nonces) => selected_nonces?,
+			Err(error) => {
+				log::error!(target: "bridge", "{}. Selecting nothing", error);
+				return None;
+			}
+		};
 
+		// if we care about profitability, only select messages that are paying for the delivery
+		let selected_count = match self.profitability.clone() {
+			Some(profitability) => {
+				let profitable_count = profitable_messages_count(&selected_messages, &profitability);
+				Some(self.select_profitable_messages(
+					&selected_nonces,
+					profitable_count,
+					profitability.relay_unprofitable_after,
+				)?)
+			}
+			None => None,
+		};
+
+		// if we have batch optimizer, select the most rewarding prefix of messages
+		let selected_count = match self.batch_optimizer {
+			Some(ref batch_optimizer) => Some(most_rewarding_messages_count(
+				&selected_messages[..selected_count.unwrap_or(selected_messages.len())],
+				batch_optimizer,
+			)),
+			None => selected_count,
+		};
+
+		let (selected_nonces, selected_weight) = match selected_count {
+			Some(selected_count) if selected_count != selected_messages.len() => {
+				let selected_messages = &selected_messages[..selected_count];
+				(
+					*selected_nonces.start()..=selected_messages[selected_messages.len() - 1].0,
+					selected_messages.iter().fold(0 as Weight, |weight, (_, details)| {
+						weight.saturating_add(details.dispatch_weight)
+					}),
+				)
+			}
+			_ => (selected_nonces, selected_weight),
+		};
+
+		// remember what we have selected, so that we could check delivery cost and compute parameters
+		// of the shrunk range later
+		let selected_messages = &selected_messages[..selected_count.unwrap_or(selected_messages.len())];
+		self.latest_selected_weights = selected_messages
+			.iter()
+			.map(|(nonce, details)| (*nonce, details.dispatch_weight))
+			.collect();
+		if self.target_cost_profitability.is_some() {
+			let (selected_size, selected_reward) =
+				selected_messages
+					.iter()
+					.fold((0u32, P::SourceChainBalance::zero()), |(size, reward), (_, details)| {
+						(size.saturating_add(details.size), reward.saturating_add(details.reward))
+					});
+			self.latest_selection = Some((selected_nonces.clone(), selected_weight, selected_size, selected_reward));
+		}
+
+		let proof_header = self.strategy.proof_header(&selected_nonces);
 		Some((
 			selected_nonces,
 			MessageProofParameters {
 				outbound_state_proof_required,
 				dispatch_weight: selected_weight,
 			},
+			proof_header,
 		))
 	}
+
+	fn delivery_cost_required(&self, nonces: &RangeInclusive<MessageNonce>) -> Option<(Weight, u32)> {
+		self.target_cost_profitability.as_ref()?;
+		match self.latest_selection {
+			Some((ref selected_nonces, selected_weight, selected_size, _)) if selected_nonces == nonces => {
+				Some((selected_weight, selected_size))
+			}
+			_ => None,
+		}
+	}
+
+	fn delivery_cost_estimated(&mut self, nonces: &RangeInclusive<MessageNonce>, cost: P::SourceChainBalance) -> bool {
+		let relay_unprofitable_after = match self.target_cost_profitability {
+			Some(ref target_cost_profitability) => target_cost_profitability.relay_unprofitable_after,
+			None => return true,
+		};
+		let selected_reward = match self.latest_selection {
+			Some((ref selected_nonces, _, _, selected_reward)) if selected_nonces == nonces => selected_reward,
+			_ => return true,
+		};
+
+		let selected_count = (nonces.end() - nonces.start() + 1) as usize;
+		let profitable_count = if selected_reward >= cost { selected_count } else { 0 };
+		self.select_profitable_messages(nonces, profitable_count, relay_unprofitable_after)
+			.is_some()
+	}
+
+	fn required_source_header_at_target(&self, current_best: &SourceHeaderIdOf<P>) -> Option<SourceHeaderIdOf<P>> {
+		self.strategy.required_source_header_at_target(current_best)
+	}
+
+	fn prefix_proof_parameters(
+		&self,
+		nonces: &RangeInclusive<MessageNonce>,
+		proof_parameters: MessageProofParameters,
+	) -> PrefixProofParameters<MessageProofParameters> {
+		let is_latest_selection = self.latest_selected_weights.first().map(|(nonce, _)| nonce) == Some(nonces.start())
+			&& self.latest_selected_weights.last().map(|(nonce, _)| nonce) == Some(nonces.end());
+		if !is_latest_selection {
+			return Box::new(move |_| proof_parameters.clone());
+		}
+
+		// dispatch weight of the shrunk range only includes weights of messages from the range
+		let selected_weights = self.latest_selected_weights.clone();
+		Box::new(move |prefix| MessageProofParameters {
+			outbound_state_proof_required: proof_parameters.outbound_state_proof_required,
+			dispatch_weight: selected_weights
+				.iter()
+				.take_while(|(nonce, _)| nonce <= prefix.end())
+				.fold(0, |dispatch_weight, (_, weight)| {
+					dispatch_weight.saturating_add(*weight)
+				}),
+		})
+	}
+
+	fn apply_command(&mut self, command: RaceCommand) {
+		match command {
+			RaceCommand::SetMaxWeightPerProof(max_weight) => self.limits.max_weight = max_weight,
+			RaceCommand::SetMaxNoncesPerProof(max_nonces) => self.limits.max_nonces = Some(max_nonces as MessageNonce),
+			// all limits are replaced at once, so the selection never mixes old and new parameters
+			RaceCommand::SetLaneParameters(parameters) => {
+				self.limits = DeliveryLimits {
+					max_nonces: Some(parameters.max_messages_in_single_batch),
+					max_weight: parameters.max_messages_weight_in_single_batch,
+					..self.limits
+				};
+				self.max_unconfirmed_nonces_at_target = parameters.max_unconfirmed_messages;
+			}
+			_ => (),
+		}
+		self.strategy.apply_command(command)
+	}
+
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		self.strategy.range_failed(nonces, kind)
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		self.strategy.checkpoint()
+	}
+}
+
+impl<P: MessageLane> MessageDeliveryStrategy<P> {
+	/// Select profitable prefix of the batch. Returns number of selected messages.
+	///
+	/// If there's no profitable prefix, the whole batch is selected only when it has been unprofitable
+	/// for at least `relay_unprofitable_after`. Otherwise nothing is selected and the batch is
+	/// reevaluated later - e.g. when more messages could be delivered in the same transaction.
+	fn select_profitable_messages(
+		&mut self,
+		selected_nonces: &RangeInclusive<MessageNonce>,
+		profitable_count: usize,
+		relay_unprofitable_after: Duration,
+	) -> Option<usize> {
+		let first_nonce = *selected_nonces.start();
+		if profitable_count != 0 {
+			self.unprofitable_since = None;
+			return Some(profitable_count);
+		}
+
+		let now = Instant::now();
+		let unprofitable_since = match self.unprofitable_since {
+			Some((nonce, unprofitable_since)) if nonce == first_nonce => unprofitable_since,
+			_ => {
+				self.unprofitable_since = Some((first_nonce, now));
+				now
+			}
+		};
+		let unprofitable_for = now.duration_since(unprofitable_since);
+		if unprofitable_for < relay_unprofitable_after {
+			log::debug!(
+				target: "bridge",
+				"Not delivering unprofitable messages {:?} from {} to {}. They're unprofitable for {}s",
+				selected_nonces,
+				MessageDeliveryRace::<P>::source_name(),
+				MessageDeliveryRace::<P>::target_name(),
+				unprofitable_for.as_secs(),
+			);
+			return None;
+		}
+
+		log::warn!(
+			target: "bridge",
+			"Delivering unprofitable messages {:?} from {} to {}. They have been blocking the lane for {}s",
+			selected_nonces,
+			MessageDeliveryRace::<P>::source_name(),
+			MessageDeliveryRace::<P>::target_name(),
+			unprofitable_for.as_secs(),
+		);
+		Some((selected_nonces.end() - selected_nonces.start() + 1) as usize)
+	}
+}
+
+/// Returns number of messages in the prefix of selected messages, which maximizes difference between
+/// cumulative reward and estimated delivery cost. At least one message is always selected (if there
+/// are any), and the longest prefix is preferred if several prefixes are equally rewarding.
+fn most_rewarding_messages_count<SourceChainBalance>(
+	selected_messages: &[(MessageNonce, MessageDetails<SourceChainBalance>)],
+	batch_optimizer: &DeliveryBatchOptimizer<SourceChainBalance>,
+) -> usize
+where
+	SourceChainBalance: Copy + Ord + Saturating + Zero,
+{
+	let first_nonce = match selected_messages.first() {
+		Some((first_nonce, _)) => *first_nonce,
+		None => return 0,
+	};
+
+	let mut total_weight: Weight = 0;
+	let mut total_size: u32 = 0;
+	let mut total_reward = SourceChainBalance::zero();
+	let mut best: Option<(usize, SourceChainBalance, SourceChainBalance)> = None;
+	for (index, (nonce, details)) in selected_messages.iter().enumerate() {
+		total_weight = total_weight.saturating_add(details.dispatch_weight);
+		total_size = total_size.saturating_add(details.size);
+		total_reward = total_reward.saturating_add(details.reward);
+
+		let delivery_cost = (batch_optimizer.estimate_delivery_cost)(&(first_nonce..=*nonce), total_weight, total_size);
+		// balance may be unsigned, so instead of comparing `reward - cost` differences, we're comparing
+		// `reward + best_cost` with `best_reward + cost`
+		let is_better = match best {
+			Some((_, best_reward, best_cost)) => {
+				total_reward.saturating_add(best_cost) >= best_reward.saturating_add(delivery_cost)
+			}
+			None => true,
+		};
+		if is_better {
+			best = Some((index + 1, total_reward, delivery_cost));
+		}
+	}
+
+	best.map(|(count, _, _)| count).unwrap_or(0)
+}
+
+/// Returns number of messages in the longest prefix of selected messages, which cumulative reward
+/// exceeds estimated delivery cost.
+fn profitable_messages_count<SourceChainBalance>(
+	selected_messages: &[(MessageNonce, MessageDetails<SourceChainBalance>)],
+	profitability: &MessageDeliveryProfitability<SourceChainBalance>,
+) -> usize
+where
+	SourceChainBalance: Copy + Ord + Saturating + Zero,
+{
+	let first_nonce = match selected_messages.first() {
+		Some((first_nonce, _)) => *first_nonce,
+		None => return 0,
+	};
+
+	let mut total_weight: Weight = 0;
+	let mut total_size: u32 = 0;
+	let mut total_reward = SourceChainBalance::zero();
+	let mut profitable_count = 0;
+	for (index, (nonce, details)) in selected_messages.iter().enumerate() {
+		total_weight = total_weight.saturating_add(details.dispatch_weight);
+		total_size = total_size.saturating_add(details.size);
+		total_reward = total_reward.saturating_add(details.reward);
+
+		let delivery_cost = (profitability.estimate_delivery_cost)(&(first_nonce..=*nonce), total_weight, total_size);
+		if total_reward > delivery_cost {
+			profitable_count = index + 1;
+		}
+	}
+
+	profitable_count
+}
+
+/// Limits of the single message delivery transaction.
+///
+/// All limits are applied together when selecting messages for the delivery transaction, so the
+/// selected prefix is cut at the first message that would break any of them. The first message is
+/// always selected, even if it alone exceeds the weight or size limit - otherwise the lane would be
+/// blocked forever.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeliveryLimits {
+	/// If set, at most this number of messages is delivered in single transaction.
+	pub max_nonces: Option<MessageNonce>,
+	/// Maximal cumulative dispatch weight of messages in single transaction.
+	pub max_weight: Weight,
+	/// Maximal cumulative size of messages (including the proof overhead) in single transaction.
+	pub max_size: u32,
+}
+
+impl<SourceChainBalance> SelectionConstraint<MessageDetailsMap<SourceChainBalance>> for DeliveryLimits {
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		queued: &[MessageDetailsMap<SourceChainBalance>],
+	) -> Option<RangeInclusive<MessageNonce>> {
+		let nonces = match self.max_nonces {
+			Some(max_nonces) => MaxNoncesConstraint(max_nonces).restrict(nonces, queued)?,
+			None => nonces,
+		};
+
+		// weight and size limits may only be applied if we know details of selected messages.
+		// Otherwise only the number of messages is limited
+		let restrict_by_details = |nonces: RangeInclusive<MessageNonce>| {
+			let nonces =
+				restrict_by_cumulative_value(nonces, queued, self.max_weight, |details| details.dispatch_weight)?;
+			restrict_by_cumulative_value(
+				nonces,
+				queued,
+				self.max_size.saturating_sub(MESSAGES_PROOF_OVERHEAD) as u64,
+				|details| details.size as u64,
+			)
+		};
+		Some(restrict_by_details(nonces.clone()).unwrap_or(nonces))
+	}
+}
+
+/// Selection constraint that limits cumulative dispatch weight of selected messages. Message that
+/// is heavier than the limit is selected alone.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxWeightConstraint(pub Weight);
+
+impl<SourceChainBalance> SelectionConstraint<MessageDetailsMap<SourceChainBalance>> for MaxWeightConstraint {
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		queued: &[MessageDetailsMap<SourceChainBalance>],
+	) -> Option<RangeInclusive<MessageNonce>> {
+		restrict_by_cumulative_value(nonces, queued, self.0, |details| details.dispatch_weight)
+	}
+}
+
+/// Selection constraint that limits cumulative size of selected messages. Message that is larger
+/// than the limit is selected alone.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxSizeConstraint(pub u32);
+
+impl<SourceChainBalance> SelectionConstraint<MessageDetailsMap<SourceChainBalance>> for MaxSizeConstraint {
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		queued: &[MessageDetailsMap<SourceChainBalance>],
+	) -> Option<RangeInclusive<MessageNonce>> {
+		restrict_by_cumulative_value(nonces, queued, self.0 as u64, |details| details.size as u64)
+	}
+}
+
+/// Select prefix of `nonces` range, so that cumulative value of selected messages doesn't exceed
+/// the `limit`. First message is always selected.
+fn restrict_by_cumulative_value<SourceChainBalance>(
+	nonces: RangeInclusive<MessageNonce>,
+	queued: &[MessageDetailsMap<SourceChainBalance>],
+	limit: u64,
+	value: impl Fn(&MessageDetails<SourceChainBalance>) -> u64,
+) -> Option<RangeInclusive<MessageNonce>> {
+	let mut total_value: u64 = 0;
+	let mut selected_end = None;
+	let messages = queued
+		.iter()
+		.flat_map(|queued_range| queued_range.range(nonces.clone()));
+	for (nonce, details) in messages {
+		total_value = match total_value.checked_add(value(details)) {
+			Some(new_total_value) if new_total_value <= limit => new_total_value,
+			_ if selected_end.is_none() => value(details),
+			_ => break,
+		};
+		selected_end = Some(*nonce);
+	}
+
+	selected_end.map(|selected_end| *nonces.start()..=selected_end)
 }
 
-impl NoncesRange for MessageWeightsMap {
+impl<SourceChainBalance: Clone + std::fmt::Debug> NoncesRange for MessageDetailsMap<SourceChainBalance> {
 	fn begin(&self) -> MessageNonce {
 		self.keys().next().cloned().unwrap_or_default()
 	}
@@ -376,47 +1075,131 @@ impl NoncesRange for MessageWeightsMap {
 	}
 
 	fn greater_than(mut self, nonce: MessageNonce) -> Option<Self> {
-		let gte = self.split_off(&(nonce + 1));
+		// there are no nonces after the `MessageNonce::MAX`
+		let gte = self.split_off(&nonce.checked_add(1)?);
 		if gte.is_empty() {
 			None
 		} else {
 			Some(gte)
 		}
 	}
+
+	fn not_greater_than(mut self, nonce: MessageNonce) -> Option<Self> {
+		if let Some(next_nonce) = nonce.checked_add(1) {
+			self.split_off(&next_nonce);
+		}
+		if self.is_empty() {
+			None
+		} else {
+			Some(self)
+		}
+	}
+
+	fn merge_with_next(&mut self, mut next: Self) {
+		self.append(&mut next);
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
-	use crate::message_lane_loop::{
-		tests::{header_id, TestMessageLane, TestMessagesProof, TestSourceHeaderId, TestTargetHeaderId},
-		ClientState,
+	use crate::message_lane_loop::{ClientState, TargetLaneParameters};
+	use crate::message_race_strategy::{tests::SelectNonces, CompositeStrategy, MaxNoncesConstraint};
+	use crate::testing::{
+		header_id, TestMessageLane, TestMessagesProof, TestSourceChainBalance, TestSourceHeaderId, TestTargetHeaderId,
 	};
 
 	type TestRaceState = RaceState<TestSourceHeaderId, TestTargetHeaderId, TestMessagesProof>;
 	type TestStrategy = MessageDeliveryStrategy<TestMessageLane>;
 
 	fn prepare_strategy() -> (TestRaceState, TestStrategy) {
+		prepare_strategy_with_weights(&[1, 1, 1, 1])
+	}
+
+	fn prepare_strategy_with_weights(weights: &[Weight]) -> (TestRaceState, TestStrategy) {
+		prepare_strategy_with_details(&weights.iter().map(|weight| (*weight, 1)).collect::<Vec<_>>())
+	}
+
+	fn prepare_strategy_with_sizes(sizes: &[u32]) -> (TestRaceState, TestStrategy) {
+		prepare_strategy_with_details(&sizes.iter().map(|size| (1, *size)).collect::<Vec<_>>())
+	}
+
+	fn prepare_strategy_with_details(details: &[(Weight, u32)]) -> (TestRaceState, TestStrategy) {
+		prepare_strategy_with_messages(
+			&details
+				.iter()
+				.map(|(dispatch_weight, size)| MessageDetails {
+					dispatch_weight: *dispatch_weight,
+					size: *size,
+					reward: 1,
+				})
+				.collect::<Vec<_>>(),
+		)
+	}
+
+	fn prepare_strategy_with_rewards(
+		rewards: &[TestSourceChainBalance],
+		relay_unprofitable_after: Duration,
+	) -> (TestRaceState, TestStrategy) {
+		let (race_state, mut race_strategy) = prepare_strategy_with_messages(
+			&rewards
+				.iter()
+				.map(|reward| MessageDetails {
+					dispatch_weight: 1,
+					size: 1,
+					reward: *reward,
+				})
+				.collect::<Vec<_>>(),
+		);
+		// every delivery transaction costs 10 + 1 for every message
+		race_strategy.profitability = Some(MessageDeliveryProfitability {
+			estimate_delivery_cost: Arc::new(|nonces, _, _| 10 + nonces.end() - nonces.start() + 1),
+			relay_unprofitable_after,
+		});
+		(race_state, race_strategy)
+	}
+
+	fn prepare_strategy_with_messages(
+		messages: &[MessageDetails<TestSourceChainBalance>],
+	) -> (TestRaceState, TestStrategy) {
 		let mut race_state = RaceState {
 			source_state: Some(ClientState {
 				best_self: header_id(1),
+				best_finalized_self: header_id(1),
 				best_peer: header_id(1),
 			}),
 			target_state: Some(ClientState {
 				best_self: header_id(1),
+				best_finalized_self: header_id(1),
 				best_peer: header_id(1),
 			}),
 			nonces_to_submit: None,
-			nonces_submitted: None,
+			proofs_to_submit: Default::default(),
+			nonces_submitted: Default::default(),
 		};
 
 		let mut race_strategy = TestStrategy {
 			max_unconfirmed_nonces_at_target: 4,
-			max_messages_weight_in_single_batch: 4,
+			max_unrewarded_relayer_entries_at_target: 4,
+			limits: DeliveryLimits {
+				max_nonces: None,
+				max_weight: 4,
+				max_size: MESSAGES_PROOF_OVERHEAD + 4,
+			},
+			profitability: None,
+			batch_optimizer: None,
+			nonce_filter: None,
+			target_cost_profitability: None,
+			latest_selection: None,
+			latest_selected_weights: Vec::new(),
+			unprofitable_since: None,
 			latest_confirmed_nonce_at_source: Some(19),
 			target_nonces: Some(TargetClientNonces {
 				latest_nonce: 19,
-				confirmed_nonce: Some(19),
+				nonces_data: DeliveryRaceTargetNoncesData {
+					confirmed_nonce: 19,
+					unrewarded_relayers: Default::default(),
+				},
 			}),
 			strategy: BasicStrategy::new(),
 		};
@@ -424,17 +1207,30 @@ mod tests {
 		race_strategy.strategy.source_nonces_updated(
 			header_id(1),
 			SourceClientNonces {
-				new_nonces: vec![(20, 1), (21, 1), (22, 1), (23, 1)].into_iter().collect(),
+				new_nonces: (20..).zip(messages.iter().cloned()).collect(),
 				confirmed_nonce: Some(19),
+				unrewarded_relayer_entries: None,
 			},
 		);
-		race_strategy
-			.strategy
-			.target_nonces_updated(race_strategy.target_nonces.clone().unwrap(), &mut race_state);
+		race_strategy.strategy.target_nonces_updated(
+			TargetClientNonces {
+				latest_nonce: 19,
+				nonces_data: (),
+			},
+			&mut race_state,
+		);
 
 		(race_state, race_strategy)
 	}
 
+	fn details(dispatch_weight: Weight) -> MessageDetails<TestSourceChainBalance> {
+		MessageDetails {
+			dispatch_weight,
+			size: 1,
+			reward: 1,
+		}
+	}
+
 	fn proof_parameters(state_required: bool, weight: Weight) -> MessageProofParameters {
 		MessageProofParameters {
 			outbound_state_proof_required: state_required,
@@ -443,9 +1239,9 @@ mod tests {
 	}
 
 	#[test]
-	fn weights_map_works_as_nonces_range() {
-		fn build_map(range: RangeInclusive<MessageNonce>) -> MessageWeightsMap {
-			range.map(|idx| (idx, idx)).collect()
+	fn details_map_works_as_nonces_range() {
+		fn build_map(range: RangeInclusive<MessageNonce>) -> MessageDetailsMap<TestSourceChainBalance> {
+			range.map(|idx| (idx, details(idx))).collect()
 		}
 
 		let map = build_map(20..=30);
@@ -457,7 +1253,25 @@ mod tests {
 		assert_eq!(map.clone().greater_than(20), Some(build_map(21..=30)));
 		assert_eq!(map.clone().greater_than(25), Some(build_map(26..=30)));
 		assert_eq!(map.clone().greater_than(29), Some(build_map(30..=30)));
-		assert_eq!(map.greater_than(30), None);
+		assert_eq!(map.clone().greater_than(30), None);
+		assert_eq!(map.clone().not_greater_than(19), None);
+		assert_eq!(map.clone().not_greater_than(20), Some(build_map(20..=20)));
+		assert_eq!(map.clone().not_greater_than(25), Some(build_map(20..=25)));
+		assert_eq!(map.clone().not_greater_than(30), Some(build_map(20..=30)));
+		assert_eq!(map.clone().not_greater_than(40), Some(build_map(20..=30)));
+		assert_eq!(map.clone().greater_than(MessageNonce::MAX), None);
+		assert_eq!(
+			map.clone().not_greater_than(MessageNonce::MAX),
+			Some(build_map(20..=30))
+		);
+
+		let empty_map = MessageDetailsMap::<TestSourceChainBalance>::new();
+		assert_eq!(empty_map.clone().greater_than(10), None);
+		assert_eq!(empty_map.not_greater_than(10), None);
+
+		let mut merged_map = map;
+		merged_map.merge_with_next(build_map(31..=40));
+		assert_eq!(merged_map, build_map(20..=40));
 	}
 
 	#[test]
@@ -466,11 +1280,26 @@ mod tests {
 
 		// both sides are ready to relay new messages
 		assert_eq!(
-			strategy.select_nonces_to_deliver(&state),
+			strategy.select_nonces(&state),
 			Some(((20..=23), proof_parameters(false, 4)))
 		);
 	}
 
+	#[test]
+	fn message_delivery_strategy_computes_proof_parameters_of_shrunk_range() {
+		let (state, mut strategy) = prepare_strategy_with_weights(&[1, 2, 4, 8]);
+		strategy.limits.max_weight = 15;
+
+		let (nonces, selected_proof_parameters) = strategy.select_nonces(&state).unwrap();
+		assert_eq!(nonces, 20..=23);
+		assert_eq!(selected_proof_parameters, proof_parameters(false, 15));
+
+		let prefix_proof_parameters = strategy.prefix_proof_parameters(&nonces, selected_proof_parameters);
+		assert_eq!(prefix_proof_parameters(&(20..=20)), proof_parameters(false, 1));
+		assert_eq!(prefix_proof_parameters(&(20..=22)), proof_parameters(false, 7));
+		assert_eq!(prefix_proof_parameters(&(20..=23)), proof_parameters(false, 15));
+	}
+
 	#[test]
 	fn message_delivery_strategy_selects_nothing_if_too_many_confirmations_missing() {
 		let (state, mut strategy) = prepare_strategy();
@@ -479,35 +1308,274 @@ mod tests {
 		// we need to wait until confirmations will be delivered by receiving race
 		strategy.latest_confirmed_nonce_at_source =
 			Some(strategy.target_nonces.as_ref().unwrap().latest_nonce - strategy.max_unconfirmed_nonces_at_target);
-		assert_eq!(strategy.select_nonces_to_deliver(&state), None);
+		assert_eq!(strategy.select_nonces(&state), None);
 	}
 
 	#[test]
-	fn message_delivery_strategy_includes_outbound_state_proof_when_new_nonces_are_available() {
+	fn message_delivery_strategy_limits_batch_by_max_unconfirmed_nonces_at_target() {
 		let (state, mut strategy) = prepare_strategy();
+		let set_confirmed_nonce = |strategy: &mut TestStrategy, confirmed_nonce| {
+			strategy.latest_confirmed_nonce_at_source = Some(confirmed_nonce);
+			strategy.target_nonces.as_mut().unwrap().nonces_data.confirmed_nonce = confirmed_nonce;
+		};
 
-		// if there are new confirmed nonces on source, we want to relay this information
-		// to target to prune rewards queue
-		let prev_confirmed_nonce_at_source = strategy.latest_confirmed_nonce_at_source.unwrap();
-		strategy.target_nonces.as_mut().unwrap().confirmed_nonce = Some(prev_confirmed_nonce_at_source - 1);
+		// 2 messages are unconfirmed => we may deliver 2 more
+		set_confirmed_nonce(&mut strategy, 17);
 		assert_eq!(
-			strategy.select_nonces_to_deliver(&state),
-			Some(((20..=23), proof_parameters(true, 4)))
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+
+		// 3 messages are unconfirmed => we may deliver 1 more
+		set_confirmed_nonce(&mut strategy, 16);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
 		);
+
+		// 4 messages are unconfirmed => delivery is blocked
+		set_confirmed_nonce(&mut strategy, 15);
+		assert_eq!(strategy.select_nonces(&state), None);
 	}
 
 	#[test]
-	fn message_delivery_strategy_limits_batch_by_messages_weight() {
+	fn message_delivery_strategy_is_unblocked_when_confirmations_are_delivered() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.latest_confirmed_nonce_at_source = Some(15);
+		strategy.target_nonces.as_mut().unwrap().nonces_data.confirmed_nonce = 15;
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// receiving race has delivered confirmations to the source node => we may deliver messages
+		// along with updated outbound lane state
+		strategy.source_nonces_updated(
+			header_id(1),
+			SourceClientNonces {
+				new_nonces: MessageDetailsMap::new(),
+				confirmed_nonce: Some(19),
+				unrewarded_relayer_entries: None,
+			},
+		);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(true, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_nothing_if_too_many_unrewarded_relayer_entries() {
+		let (state, mut strategy) = prepare_strategy();
+
+		// if there are already `max_unrewarded_relayer_entries_at_target` entries on target,
+		// we need to wait until confirmations will be delivered by receiving race
+		strategy
+			.target_nonces
+			.as_mut()
+			.unwrap()
+			.nonces_data
+			.unrewarded_relayers
+			.unrewarded_relayer_entries = strategy.max_unrewarded_relayer_entries_at_target;
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// one entry less => delivery is allowed
+		strategy
+			.target_nonces
+			.as_mut()
+			.unwrap()
+			.nonces_data
+			.unrewarded_relayers
+			.unrewarded_relayer_entries = strategy.max_unrewarded_relayer_entries_at_target - 1;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_uses_target_nonces_data() {
+		let (mut state, mut strategy) = prepare_strategy();
+		let unrewarded_relayers = UnrewardedRelayersState {
+			unrewarded_relayer_entries: 2,
+			messages_in_oldest_entry: 1,
+			total_messages: 2,
+		};
+		strategy.target_nonces_updated(
+			TargetClientNonces {
+				latest_nonce: 21,
+				nonces_data: DeliveryRaceTargetNoncesData {
+					confirmed_nonce: 19,
+					unrewarded_relayers: unrewarded_relayers.clone(),
+				},
+			},
+			&mut state,
+		);
+		assert_eq!(strategy.best_at_target(), 21);
+		assert_eq!(
+			strategy
+				.target_nonces
+				.as_ref()
+				.map(|nonces| nonces.nonces_data.unrewarded_relayers.clone()),
+			Some(unrewarded_relayers),
+		);
+
+		// two nonces are not yet confirmed at target => only two more nonces may be delivered
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((22..=23), proof_parameters(false, 2)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_is_unblocked_when_unrewarded_relayer_entries_may_be_pruned() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.latest_confirmed_nonce_at_source = Some(17);
+		strategy.target_nonces.as_mut().unwrap().nonces_data.confirmed_nonce = 17;
+		strategy
+			.target_nonces
+			.as_mut()
+			.unwrap()
+			.nonces_data
+			.unrewarded_relayers
+			.unrewarded_relayer_entries = strategy.max_unrewarded_relayer_entries_at_target;
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// receiving race has delivered confirmations to the source node => we may deliver messages
+		// along with updated outbound lane state, which will prune unrewarded relayer entries
+		strategy.source_nonces_updated(
+			header_id(1),
+			SourceClientNonces {
+				new_nonces: MessageDetailsMap::new(),
+				confirmed_nonce: Some(19),
+				unrewarded_relayer_entries: None,
+			},
+		);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(true, 4)))
+		);
+
+		// once entries are pruned at target, we may deliver messages without outbound lane state
+		strategy.target_nonces = Some(TargetClientNonces {
+			latest_nonce: 19,
+			nonces_data: DeliveryRaceTargetNoncesData {
+				confirmed_nonce: 19,
+				unrewarded_relayers: Default::default(),
+			},
+		});
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_includes_outbound_state_proof_when_new_nonces_are_available() {
+		let (state, mut strategy) = prepare_strategy();
+
+		// if there are new confirmed nonces on source, we want to relay this information
+		// to target to prune rewards queue
+		let prev_confirmed_nonce_at_source = strategy.latest_confirmed_nonce_at_source.unwrap();
+		strategy.target_nonces.as_mut().unwrap().nonces_data.confirmed_nonce = prev_confirmed_nonce_at_source - 1;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(true, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_limits_batch_by_messages_weight() {
 		let (state, mut strategy) = prepare_strategy();
 
 		// not all queued messages may fit in the batch, because batch has max weight
-		strategy.max_messages_weight_in_single_batch = 3;
+		strategy.limits.max_weight = 3;
 		assert_eq!(
-			strategy.select_nonces_to_deliver(&state),
+			strategy.select_nonces(&state),
 			Some(((20..=22), proof_parameters(false, 3)))
 		);
 	}
 
+	#[test]
+	fn message_delivery_strategy_limits_batch_by_mixed_messages_weights() {
+		let (state, mut strategy) = prepare_strategy_with_weights(&[1, 2, 3, 1]);
+
+		// 1 + 2 + 3 > 4, so only the first two messages are selected
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 3)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_single_message_heavier_than_limit() {
+		let (state, mut strategy) = prepare_strategy_with_weights(&[5, 1, 1, 1]);
+
+		// otherwise the race would stall forever
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 5)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_does_not_append_heavy_message_to_batch() {
+		let (state, mut strategy) = prepare_strategy_with_weights(&[1, 5, 1, 1]);
+
+		// heavy message will be delivered alone in the next batch
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_limits_batch_by_messages_size() {
+		let (state, mut strategy) = prepare_strategy_with_sizes(&[1, 2, 1, 1]);
+
+		// proof overhead + 1 + 2 + 1 = limit
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=22), proof_parameters(false, 3)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_single_message_larger_than_limit() {
+		let (state, mut strategy) = prepare_strategy_with_sizes(&[100, 1, 1, 1]);
+
+		// otherwise the race would stall forever
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_does_not_append_large_message_to_batch() {
+		let (state, mut strategy) = prepare_strategy_with_sizes(&[1, 100, 1, 1]);
+
+		// large message will be delivered alone in the next batch
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_limits_batch_by_max_weight_per_proof_command() {
+		let (state, mut strategy) = prepare_strategy_with_weights(&[1, 2, 1, 1]);
+		strategy.limits.max_weight = 10;
+
+		strategy.apply_command(RaceCommand::SetMaxWeightPerProof(4));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=22), proof_parameters(false, 4)))
+		);
+		strategy.apply_command(RaceCommand::SetMaxWeightPerProof(2));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
 	#[test]
 	fn message_delivery_strategy_limits_batch_by_messages_count() {
 		let (state, mut strategy) = prepare_strategy();
@@ -516,10 +1584,494 @@ mod tests {
 		// relay 3 new messages
 		let prev_confirmed_nonce_at_source = strategy.latest_confirmed_nonce_at_source.unwrap();
 		strategy.latest_confirmed_nonce_at_source = Some(prev_confirmed_nonce_at_source - 1);
-		strategy.target_nonces.as_mut().unwrap().confirmed_nonce = Some(prev_confirmed_nonce_at_source - 1);
+		strategy.target_nonces.as_mut().unwrap().nonces_data.confirmed_nonce = prev_confirmed_nonce_at_source - 1;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=22), proof_parameters(false, 3)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_limits_batch_by_max_nonces_per_proof_command() {
+		let (state, mut strategy) = prepare_strategy();
+
+		// weight of messages that don't fit into the proof is not accounted
+		strategy.apply_command(RaceCommand::SetMaxNoncesPerProof(2));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_replaces_all_limits_by_lane_parameters_command() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.limits.max_weight = 10;
+		strategy.max_unconfirmed_nonces_at_target = 10;
+
+		strategy.apply_command(RaceCommand::SetLaneParameters(TargetLaneParameters {
+			max_messages_in_single_batch: 3,
+			max_messages_weight_in_single_batch: 10,
+			max_unconfirmed_messages: 10,
+		}));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=22), proof_parameters(false, 3)))
+		);
+		strategy.apply_command(RaceCommand::SetLaneParameters(TargetLaneParameters {
+			max_messages_in_single_batch: 10,
+			max_messages_weight_in_single_batch: 2,
+			max_unconfirmed_messages: 10,
+		}));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+		strategy.apply_command(RaceCommand::SetLaneParameters(TargetLaneParameters {
+			max_messages_in_single_batch: 10,
+			max_messages_weight_in_single_batch: 10,
+			max_unconfirmed_messages: 1,
+		}));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_accounts_submitted_nonces_when_limiting_batch() {
+		let (mut state, mut strategy) = prepare_strategy();
+
+		// nonces 20..=21 are submitted, but not yet confirmed by target => we may only
+		// relay 2 new messages in the next batch
+		strategy.limits.max_weight = 2;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+		state.nonces_submitted.push_back(20..=21);
+		strategy.limits.max_weight = 4;
+		strategy.source_nonces_updated(
+			header_id(1),
+			SourceClientNonces {
+				new_nonces: vec![(24, details(1)), (25, details(1))].into_iter().collect(),
+				confirmed_nonce: Some(19),
+				unrewarded_relayer_entries: None,
+			},
+		);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((22..=23), proof_parameters(false, 2)))
+		);
+	}
+
+	fn select_with_limits(
+		details: &[(Weight, u32)],
+		limits: DeliveryLimits,
+	) -> Option<(RangeInclusive<MessageNonce>, MessageProofParameters)> {
+		let (state, mut strategy) = prepare_strategy_with_details(details);
+		strategy.limits = limits;
+		strategy.select_nonces(&state)
+	}
+
+	#[test]
+	fn delivery_limits_are_bound_by_messages_count() {
+		assert_eq!(
+			select_with_limits(
+				&[(1, 1), (1, 1), (1, 1), (1, 1)],
+				DeliveryLimits {
+					max_nonces: Some(2),
+					max_weight: 4,
+					max_size: MESSAGES_PROOF_OVERHEAD + 4,
+				},
+			),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+	}
+
+	#[test]
+	fn delivery_limits_are_bound_by_messages_weight() {
+		assert_eq!(
+			select_with_limits(
+				&[(1, 1), (2, 1), (1, 1), (1, 1)],
+				DeliveryLimits {
+					max_nonces: Some(4),
+					max_weight: 3,
+					max_size: MESSAGES_PROOF_OVERHEAD + 4,
+				},
+			),
+			Some(((20..=21), proof_parameters(false, 3)))
+		);
+	}
+
+	#[test]
+	fn delivery_limits_are_bound_by_messages_size() {
+		assert_eq!(
+			select_with_limits(
+				&[(1, 2), (1, 2), (1, 1), (1, 1)],
+				DeliveryLimits {
+					max_nonces: Some(4),
+					max_weight: 4,
+					max_size: MESSAGES_PROOF_OVERHEAD + 3,
+				},
+			),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn delivery_limits_select_all_messages_if_limits_are_generous() {
+		// only `max_unconfirmed_nonces_at_target` limits the batch here
+		assert_eq!(
+			select_with_limits(
+				&[(1, 1), (1, 1), (1, 1), (1, 1)],
+				DeliveryLimits {
+					max_nonces: None,
+					max_weight: 100,
+					max_size: MESSAGES_PROOF_OVERHEAD + 100,
+				},
+			),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn delivery_limits_select_single_message_exceeding_all_limits() {
+		assert_eq!(
+			select_with_limits(
+				&[(10, 10), (1, 1), (1, 1), (1, 1)],
+				DeliveryLimits {
+					max_nonces: Some(4),
+					max_weight: 4,
+					max_size: MESSAGES_PROOF_OVERHEAD + 4,
+				},
+			),
+			Some(((20..=20), proof_parameters(false, 10)))
+		);
+	}
+
+	#[test]
+	fn delivery_limits_constraint_falls_back_to_count_only_without_message_details() {
+		let limits = DeliveryLimits {
+			max_nonces: Some(3),
+			max_weight: 1,
+			max_size: MESSAGES_PROOF_OVERHEAD + 1,
+		};
+		let queued: Vec<MessageDetailsMap<TestSourceChainBalance>> =
+			vec![(20..=30).map(|nonce| (nonce, details(1))).collect()];
+		let no_details: Vec<MessageDetailsMap<TestSourceChainBalance>> = Vec::new();
+
+		assert_eq!({ limits }.restrict(20..=30, &queued), Some(20..=20));
+		assert_eq!({ limits }.restrict(20..=30, &no_details), Some(20..=22));
+		assert_eq!(
+			DeliveryLimits {
+				max_nonces: None,
+				..limits
+			}
+			.restrict(20..=30, &no_details),
+			Some(20..=30)
+		);
+		assert_eq!(
+			DeliveryLimits {
+				max_nonces: Some(0),
+				..limits
+			}
+			.restrict(20..=30, &no_details),
+			None
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_profitable_messages() {
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[20, 1, 1, 1], Duration::from_secs(60));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_profitable_prefix_of_messages() {
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[14, 0, 0, 0], Duration::from_secs(60));
 		assert_eq!(
-			strategy.select_nonces_to_deliver(&state),
+			strategy.select_nonces(&state),
 			Some(((20..=22), proof_parameters(false, 3)))
 		);
 	}
+
+	#[test]
+	fn message_delivery_strategy_selects_nothing_if_messages_are_unprofitable() {
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[1, 1, 1, 1], Duration::from_secs(60));
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert_eq!(strategy.unprofitable_since.map(|(nonce, _)| nonce), Some(20));
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_unprofitable_messages_once_they_may_be_batched() {
+		// every single message is unprofitable, but the batch of 4 messages is profitable
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[4, 4, 4, 4], Duration::from_secs(60));
+		strategy.limits.max_weight = 3;
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		strategy.limits.max_weight = 4;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+		assert_eq!(strategy.unprofitable_since, None);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_unprofitable_messages_if_they_are_blocking_lane() {
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[1, 1, 1, 1], Duration::from_secs(60));
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// if other message has been blocking the lane for a long time, we still wait
+		let long_ago = Instant::now() - Duration::from_secs(120);
+		strategy.unprofitable_since = Some((19, long_ago));
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// but once the same message is blocking the lane for too long, it is delivered
+		strategy.unprofitable_since = Some((20, long_ago));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn message_delivery_strategy_selects_unprofitable_messages_immediately_if_allowed() {
+		let (state, mut strategy) = prepare_strategy_with_rewards(&[1, 1, 1, 1], Duration::from_secs(0));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	fn prepare_strategy_with_target_cost_profitability(
+		rewards: &[TestSourceChainBalance],
+		relay_unprofitable_after: Duration,
+	) -> (TestRaceState, TestStrategy) {
+		let (race_state, mut race_strategy) = prepare_strategy_with_messages(
+			&rewards
+				.iter()
+				.map(|reward| MessageDetails {
+					dispatch_weight: 1,
+					size: 1,
+					reward: *reward,
+				})
+				.collect::<Vec<_>>(),
+		);
+		race_strategy.target_cost_profitability = Some(TargetCostProfitability {
+			relay_unprofitable_after,
+		});
+		(race_state, race_strategy)
+	}
+
+	#[test]
+	fn message_delivery_strategy_requires_delivery_cost_of_selected_messages() {
+		let (state, mut strategy) =
+			prepare_strategy_with_target_cost_profitability(&[1, 2, 3, 4], Duration::from_secs(60));
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+		assert_eq!(strategy.delivery_cost_required(&(20..=23)), Some((4, 4)));
+		assert_eq!(strategy.delivery_cost_required(&(20..=22)), None);
+
+		strategy.target_cost_profitability = None;
+		assert_eq!(strategy.delivery_cost_required(&(20..=23)), None);
+	}
+
+	#[test]
+	fn message_delivery_strategy_declines_delivery_if_estimated_cost_exceeds_reward() {
+		let (state, mut strategy) =
+			prepare_strategy_with_target_cost_profitability(&[1, 2, 3, 4], Duration::from_secs(60));
+		strategy.select_nonces(&state);
+		assert!(!strategy.delivery_cost_estimated(&(20..=23), 11));
+		assert_eq!(strategy.unprofitable_since.map(|(nonce, _)| nonce), Some(20));
+
+		assert!(strategy.delivery_cost_estimated(&(20..=23), 10));
+		assert_eq!(strategy.unprofitable_since, None);
+	}
+
+	#[test]
+	fn message_delivery_strategy_approves_expensive_delivery_if_messages_are_blocking_lane() {
+		let (state, mut strategy) =
+			prepare_strategy_with_target_cost_profitability(&[1, 2, 3, 4], Duration::from_secs(60));
+		strategy.select_nonces(&state);
+		strategy.unprofitable_since = Some((20, Instant::now() - Duration::from_secs(120)));
+		assert!(strategy.delivery_cost_estimated(&(20..=23), 11));
+	}
+
+	fn prepare_strategy_with_batch_optimizer(rewards: &[TestSourceChainBalance]) -> (TestRaceState, TestStrategy) {
+		let (race_state, mut race_strategy) = prepare_strategy_with_rewards(rewards, Duration::from_secs(0));
+		// every delivery transaction costs 10 + 1 for every message
+		race_strategy.batch_optimizer = Some(DeliveryBatchOptimizer {
+			estimate_delivery_cost: race_strategy.profitability.take().unwrap().estimate_delivery_cost,
+		});
+		(race_state, race_strategy)
+	}
+
+	#[test]
+	fn batch_optimizer_selects_most_rewarding_prefix_of_messages() {
+		// net rewards of prefixes are: -1, 8, 7, 6
+		let (state, mut strategy) = prepare_strategy_with_batch_optimizer(&[10, 10, 0, 0]);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+	}
+
+	#[test]
+	fn batch_optimizer_is_not_used_if_not_configured() {
+		let (state, mut strategy) = prepare_strategy_with_batch_optimizer(&[10, 10, 0, 0]);
+		strategy.batch_optimizer = None;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn batch_optimizer_respects_delivery_limits() {
+		let (state, mut strategy) = prepare_strategy_with_batch_optimizer(&[10, 10, 0, 0]);
+		strategy.limits.max_nonces = Some(1);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=20), proof_parameters(false, 1)))
+		);
+	}
+
+	#[test]
+	fn batch_optimizer_prefers_longest_of_equally_rewarding_prefixes() {
+		// every delivery transaction costs 2 + square of messages count, so net rewards of
+		// prefixes are: 0, 0, -2, -6
+		let batch_optimizer = DeliveryBatchOptimizer {
+			estimate_delivery_cost: Arc::new(|nonces: &RangeInclusive<MessageNonce>, _, _| {
+				let count = nonces.end() - nonces.start() + 1;
+				2 + count * count
+			}),
+		};
+		let selected_messages = (20..=23)
+			.map(|nonce| {
+				(
+					nonce,
+					MessageDetails {
+						dispatch_weight: 1,
+						size: 1,
+						reward: 3,
+					},
+				)
+			})
+			.collect::<Vec<_>>();
+
+		assert_eq!(most_rewarding_messages_count(&selected_messages, &batch_optimizer), 2);
+		assert_eq!(
+			most_rewarding_messages_count(&selected_messages[..1], &batch_optimizer),
+			1
+		);
+		assert_eq!(most_rewarding_messages_count(&[], &batch_optimizer), 0);
+	}
+
+	fn refuse_nonce(refused_nonce: MessageNonce) -> Option<DeliveryNonceFilter> {
+		Some(DeliveryNonceFilter {
+			is_refused: Arc::new(move |nonce| nonce == refused_nonce),
+		})
+	}
+
+	#[test]
+	fn nonce_filter_truncates_selection_before_refused_nonce() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.nonce_filter = refuse_nonce(22);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=21), proof_parameters(false, 2)))
+		);
+	}
+
+	#[test]
+	fn nonce_filter_blocks_selection_if_first_nonce_is_refused() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.nonce_filter = refuse_nonce(20);
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn nonce_filter_does_not_affect_selection_if_nonce_is_not_queued() {
+		let (state, mut strategy) = prepare_strategy();
+		strategy.nonce_filter = refuse_nonce(30);
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+
+		let (state, mut strategy) = prepare_strategy();
+		strategy.nonce_filter = None;
+		assert_eq!(
+			strategy.select_nonces(&state),
+			Some(((20..=23), proof_parameters(false, 4)))
+		);
+	}
+
+	#[test]
+	fn composite_strategy_restricts_selection_by_nonces_weight_and_size() {
+		let (state, _) = prepare_strategy();
+		let mut strategy = CompositeStrategy::new(MessageDeliveryStrategyBase::<TestMessageLane>::new());
+		strategy.source_nonces_updated(
+			header_id(1),
+			SourceClientNonces {
+				new_nonces: (20..)
+					.zip([(1, 1), (2, 1), (1, 1), (1, 1), (1, 10), (1, 1)].iter())
+					.map(|(nonce, (dispatch_weight, size))| {
+						(
+							nonce,
+							MessageDetails {
+								dispatch_weight: *dispatch_weight,
+								size: *size,
+								reward: 1,
+							},
+						)
+					})
+					.collect(),
+				confirmed_nonce: Some(19),
+				unrewarded_relayer_entries: None,
+			},
+		);
+		strategy.target_nonces_updated(
+			TargetClientNonces {
+				latest_nonce: 19,
+				nonces_data: (),
+			},
+			&mut TestRaceState::default(),
+		);
+
+		let mut strategy = strategy.with_constraint(MaxNoncesConstraint(5));
+		assert_eq!(strategy.select_nonces(&state), Some((20..=24, ())));
+
+		let mut strategy = strategy.with_constraint(MaxSizeConstraint(10));
+		assert_eq!(strategy.select_nonces(&state), Some((20..=23, ())));
+
+		let mut strategy = strategy.with_constraint(MaxWeightConstraint(4));
+		assert_eq!(strategy.select_nonces(&state), Some((20..=22, ())));
+	}
+
+	#[test]
+	fn weight_and_size_constraints_select_single_oversized_message() {
+		let queued: Vec<MessageDetailsMap<TestSourceChainBalance>> = vec![(20..=21)
+			.map(|nonce| {
+				(
+					nonce,
+					MessageDetails {
+						dispatch_weight: 100,
+						size: 100,
+						reward: 1,
+					},
+				)
+			})
+			.collect()];
+
+		assert_eq!(MaxWeightConstraint(10).restrict(20..=21, &queued), Some(20..=20));
+		assert_eq!(MaxSizeConstraint(10).restrict(20..=21, &queued), Some(20..=20));
+	}
 }
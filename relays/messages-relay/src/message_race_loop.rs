@@ -20,37 +20,92 @@
 //! associated data - like messages, lane state, etc) to the target node by
 //! generating and submitting proof.
 
-use crate::message_lane_loop::ClientState;
+mod client_requests;
+mod proof_generation;
+mod relayer_balance;
+mod stall_tracker;
+mod submission;
+
+use self::{
+	client_requests::ClientRequests, proof_generation::ProofGeneration, relayer_balance::RelayerBalance,
+	stall_tracker::StallTracker, submission::Submission,
+};
+use crate::message_lane_loop::{ClientState, TrackedTransactionStatus, TransactionTracker};
+use crate::metered_client::{MeteredSourceClient, MeteredTargetClient};
+use crate::metrics::MessageRaceMetrics;
 
 use async_trait::async_trait;
-use bp_message_lane::MessageNonce;
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use bp_message_lane::{MessageNonce, Weight};
 use futures::{
-	future::FutureExt,
-	stream::{FusedStream, StreamExt},
+	channel::mpsc::UnboundedSender,
+	future::{FusedFuture, FutureExt},
+	stream::{FusedStream, FuturesUnordered, StreamExt},
 };
-use relay_utils::{process_future_result, retry_backoff, FailedClient, MaybeConnectionError};
+use rand::Rng;
+use relay_utils::{
+	process_future_result, with_timeout, AsyncStdTimer, ErrorClassification, FailedClient, HeaderId, MaybeFatalError,
+	MaybeRejectedTransactionError, RelayError, RetryBackoffParams, TimeoutError, Timer, CONNECTION_ERROR_DELAY,
+};
+use serde::Serialize;
 use std::{
+	collections::{BTreeMap, VecDeque},
 	fmt::Debug,
+	future::Future,
 	ops::RangeInclusive,
+	panic::AssertUnwindSafe,
+	pin::Pin,
+	sync::Arc,
 	time::{Duration, Instant},
 };
+use tracing::Instrument;
 
 /// One of races within lane.
-pub trait MessageRace {
+pub trait MessageRace: 'static {
 	/// Header id of the race source.
-	type SourceHeaderId: Debug + Clone + PartialEq;
+	type SourceHeaderId: Debug + Clone + PartialEq + Send + Sync + ReorgAwareHeaderId + NumberedHeaderId;
 	/// Header id of the race source.
 	type TargetHeaderId: Debug + Clone + PartialEq;
 
 	/// Message nonce used in the race.
 	type MessageNonce: Debug + Clone;
 	/// Proof that is generated and delivered in this race.
-	type Proof: Clone;
+	type Proof: Send + Sync;
 
 	/// Name of the race source.
 	fn source_name() -> String;
 	/// Name of the race target.
 	fn target_name() -> String;
+	/// Kind of the race (e.g. messages delivery or delivery confirmations).
+	fn race_kind() -> String;
+
+	/// Returns size of the proof (in bytes).
+	fn proof_size(proof: &Self::Proof) -> usize;
+}
+
+/// Header id that allows to detect chain reorganizations.
+pub trait ReorgAwareHeaderId {
+	/// Returns true if this header can't be an ancestor of the `new_best` header, i.e. if it is
+	/// orphaned by the reorganization.
+	fn is_orphaned_by(&self, new_best: &Self) -> bool;
+}
+
+impl<Hash: PartialEq, Number: PartialOrd> ReorgAwareHeaderId for HeaderId<Hash, Number> {
+	fn is_orphaned_by(&self, new_best: &Self) -> bool {
+		new_best.0 < self.0 || (new_best.0 == self.0 && new_best.1 != self.1)
+	}
+}
+
+/// Header id that knows number of the header.
+pub trait NumberedHeaderId {
+	/// Returns number of the header.
+	fn number(&self) -> u64;
+}
+
+impl<Hash, Number: Copy + Into<u64>> NumberedHeaderId for HeaderId<Hash, Number> {
+	fn number(&self) -> u64 {
+		self.0.into()
+	}
 }
 
 /// State of race source client.
@@ -60,7 +115,7 @@ type SourceClientState<P> = ClientState<<P as MessageRace>::SourceHeaderId, <P a
 type TargetClientState<P> = ClientState<<P as MessageRace>::TargetHeaderId, <P as MessageRace>::SourceHeaderId>;
 
 /// Inclusive nonces range.
-pub trait NoncesRange: Debug + Sized {
+pub trait NoncesRange: Clone + Debug + Sized {
 	/// Get begin of the range.
 	fn begin(&self) -> MessageNonce;
 	/// Get end of the range.
@@ -68,6 +123,11 @@ pub trait NoncesRange: Debug + Sized {
 	/// Returns new range with current range nonces that are greater than the passed `nonce`.
 	/// If there are no such nonces, `None` is returned.
 	fn greater_than(self, nonce: MessageNonce) -> Option<Self>;
+	/// Returns new range with current range nonces that are not greater than the passed `nonce`.
+	/// If there are no such nonces, `None` is returned.
+	fn not_greater_than(self, nonce: MessageNonce) -> Option<Self>;
+	/// Merge `next` range into this range. The `next` range must begin right after this range ends.
+	fn merge_with_next(&mut self, next: Self);
 }
 
 /// Nonces on the race source client.
@@ -79,59 +139,417 @@ pub struct SourceClientNonces<NoncesRange> {
 	/// Latest nonce that is confirmed to the bridged client. This nonce only makes
 	/// sense in some races. In other races it is `None`.
 	pub confirmed_nonce: Option<MessageNonce>,
+	/// Number of unrewarded relayer entries at the source client. This number only makes
+	/// sense in some races. In other races it is `None`.
+	pub unrewarded_relayer_entries: Option<MessageNonce>,
 }
 
 /// Nonces on the race target client.
 #[derive(Debug, Clone)]
-pub struct TargetClientNonces {
+pub struct TargetClientNonces<TargetNoncesData> {
 	/// Latest nonce that is known to the target client.
 	pub latest_nonce: MessageNonce,
-	/// Latest nonce that is confirmed to the bridged client. This nonce only makes
-	/// sense in some races. In other races it is `None`.
-	pub confirmed_nonce: Option<MessageNonce>,
+	/// Additional data from target node that may be used by the race.
+	pub nonces_data: TargetNoncesData,
+}
+
+/// Nonces at the best and at the best finalized headers of the race target client.
+#[derive(Debug, Clone)]
+pub struct TargetNonces<TargetNoncesData> {
+	/// Nonces at the best target header.
+	pub at_best: TargetClientNonces<TargetNoncesData>,
+	/// Nonces at the best finalized target header.
+	pub at_finalized: TargetClientNonces<TargetNoncesData>,
 }
 
 /// One of message lane clients, which is source client for the race.
 #[async_trait]
-pub trait SourceClient<P: MessageRace> {
+pub trait SourceClient<P: MessageRace>: Send + Sync {
 	/// Type of error this clients returns.
-	type Error: std::fmt::Debug + MaybeConnectionError;
+	type Error: std::fmt::Debug + RelayError + MaybeFatalError;
 	/// Type of nonces range returned by the source client.
 	type NoncesRange: NoncesRange;
 	/// Additional proof parameters required to generate proof.
-	type ProofParameters;
+	type ProofParameters: Clone + Send + 'static;
 
+	/// Reconnect to the source node after connection error. The race only calls it if
+	/// `RaceParams::max_reconnect_attempts` is non-zero. No client requests are in flight while
+	/// the client is reconnecting. By default, reconnection is unsupported and the race fails.
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		Err(ReconnectError::Unsupported)
+	}
+	/// Return spec version of the source node runtime, or `None` if the client is unable to
+	/// tell it. By default, the version is unknown.
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(None)
+	}
+	/// Called when the race has noticed that the source node runtime has been upgraded. The
+	/// client should forget everything that depends on the runtime. By default, nothing is
+	/// cached.
+	fn on_runtime_upgrade(&self) {}
 	/// Return nonces that are known to the source client.
+	///
+	/// The `prev_latest_nonce` is the best nonce that is already known to the race. It is `None`
+	/// if the race knows nothing about nonces yet (e.g. right after start), so all nonces should
+	/// be returned.
 	async fn nonces(
 		&self,
 		at_block: P::SourceHeaderId,
-		prev_latest_nonce: MessageNonce,
+		prev_latest_nonce: Option<MessageNonce>,
 	) -> Result<(P::SourceHeaderId, SourceClientNonces<Self::NoncesRange>), Self::Error>;
-	/// Generate proof for delivering to the target client.
+	/// Generate proof for delivering to the target client. The returned future may be dropped
+	/// before completion (e.g. if proof generation takes too long), so the client must be ready
+	/// to serve other requests after that.
 	async fn generate_proof(
 		&self,
 		at_block: P::SourceHeaderId,
 		nonces: RangeInclusive<MessageNonce>,
 		proof_parameters: Self::ProofParameters,
 	) -> Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>, P::Proof), Self::Error>;
+	/// Estimate size (in bytes) of the proof of given nonces. By default, the proof is generated
+	/// and measured.
+	async fn estimate_proof_size(
+		&self,
+		at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> Result<u32, Self::Error> {
+		let (_, _, proof) = self.generate_proof(at_block, nonces, proof_parameters).await?;
+		Ok(P::proof_size(&proof) as u32)
+	}
+	/// Trim generated proof, so that it only proves given subrange of originally proved nonces.
+	/// Returns `None` if the client is unable to trim proofs. Then the proof is discarded and
+	/// nonces are selected again.
+	fn trim_proof(&self, _proof: &P::Proof, _nonces: RangeInclusive<MessageNonce>) -> Option<P::Proof> {
+		None
+	}
 }
 
 /// One of message lane clients, which is target client for the race.
 #[async_trait]
-pub trait TargetClient<P: MessageRace> {
+pub trait TargetClient<P: MessageRace>: Send + Sync {
 	/// Type of error this clients returns.
-	type Error: std::fmt::Debug + MaybeConnectionError;
+	type Error: std::fmt::Debug + RelayError + MaybeRejectedTransactionError + MaybeFatalError;
+	/// Type of the tracker of submitted transactions.
+	type TransactionTracker: TransactionTracker;
+	/// Cost of the delivery transaction.
+	type Cost: Debug + Clone + Ord + std::ops::Add<Output = Self::Cost> + Send;
+	/// Additional data from target node that may be used by the race. In dry-run mode, the
+	/// `Default` value is used until the target node reports its nonces.
+	type TargetNoncesData: Debug + Clone + Default + Send;
 
-	/// Return nonces that are known to the target client.
-	async fn nonces(&self, at_block: P::TargetHeaderId)
-		-> Result<(P::TargetHeaderId, TargetClientNonces), Self::Error>;
-	/// Submit proof to the target client.
+	/// Reconnect to the target node after connection error. The race only calls it if
+	/// `RaceParams::max_reconnect_attempts` is non-zero. No client requests are in flight while
+	/// the client is reconnecting. By default, reconnection is unsupported and the race fails.
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		Err(ReconnectError::Unsupported)
+	}
+	/// Return spec version of the target node runtime, or `None` if the client is unable to
+	/// tell it. By default, the version is unknown.
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(None)
+	}
+	/// Called when the race has noticed that the target node runtime has been upgraded. The
+	/// client should forget everything that depends on the runtime (e.g. cached transaction
+	/// formats). By default, nothing is cached.
+	fn on_runtime_upgrade(&self) {}
+	/// Return nonces that are known to the target client at the best and at the best finalized
+	/// target headers. If both headers are the same, the client may only read nonces once.
+	async fn nonces(
+		&self,
+		at_best: P::TargetHeaderId,
+		at_finalized: P::TargetHeaderId,
+	) -> Result<(P::TargetHeaderId, TargetNonces<Self::TargetNoncesData>), Self::Error>;
+	/// Verify proof before submitting it to the target client. Returns error if the proof is
+	/// known to be rejected by the target client. By default, any proof is accepted.
+	async fn verify_proof(
+		&self,
+		_generated_at_block: P::SourceHeaderId,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &P::Proof,
+	) -> Result<(), Self::Error> {
+		Ok(())
+	}
+	/// Dry run submission of the proof at the best target header, without paying for the
+	/// transaction. By default, the submission is assumed to succeed.
+	async fn dry_run_proof(
+		&self,
+		_generated_at_block: P::SourceHeaderId,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &P::Proof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		Ok(DryRunOutcome::Success)
+	}
+	/// Returns the earliest source header that the target node needs to know (e.g. mandatory
+	/// header that is still missing in its headers pallet), given the `current_best` source header
+	/// that is known to the target node. Returns `None` if the target node is up to date, or if
+	/// the client doesn't track source headers. Then the race relies on the strategy answer. By
+	/// default, source headers aren't tracked.
+	async fn required_source_header_at_target(
+		&self,
+		_current_best: P::SourceHeaderId,
+	) -> Result<Option<P::SourceHeaderId>, Self::Error> {
+		Ok(None)
+	}
+	/// Submit proof to the target client, using given transaction options. Returns tracker of the
+	/// submitted transaction.
 	async fn submit_proof(
 		&self,
 		generated_at_block: P::SourceHeaderId,
 		nonces: RangeInclusive<MessageNonce>,
-		proof: P::Proof,
-	) -> Result<RangeInclusive<MessageNonce>, Self::Error>;
+		proof: Arc<P::Proof>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error>;
+	/// Returns true if the client is able to submit several proofs in a single transaction (see
+	/// `submit_proofs`). Then ready proofs are queued while the client is busy, instead of being
+	/// submitted one by one. By default, batch submission is unsupported.
+	fn supports_batch_submission(&self) -> bool {
+		false
+	}
+	/// Submit proofs of consecutive nonces ranges, using given transaction options. Returns the
+	/// range of all submitted nonces and tracker of the submitted transaction. By default, proofs
+	/// are submitted one by one and the tracker of the last transaction is returned.
+	#[allow(clippy::type_complexity)]
+	async fn submit_proofs(
+		&self,
+		batch: Vec<(P::SourceHeaderId, RangeInclusive<MessageNonce>, Arc<P::Proof>)>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		let mut submitted: Option<(RangeInclusive<MessageNonce>, Self::TransactionTracker)> = None;
+		for (generated_at_block, nonces, proof) in batch {
+			let (nonces, tx_tracker) = self.submit_proof(generated_at_block, nonces, proof, options).await?;
+			let nonces_begin = submitted
+				.map(|(submitted_nonces, _)| *submitted_nonces.start())
+				.unwrap_or(*nonces.start());
+			submitted = Some((nonces_begin..=*nonces.end(), tx_tracker));
+		}
+
+		Ok(submitted.expect("the race never submits empty batches; qed"))
+	}
+	/// Estimate cost of the transaction that delivers given nonces, given their cumulative
+	/// dispatch weight and cumulative size.
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<Self::Cost, Self::Error>;
+}
+
+/// Observer of the race events. All methods are no-op by default.
+pub trait RaceObserver<P: MessageRace> {
+	/// Called when nonces are selected for delivery.
+	fn on_nonces_selected(&mut self, _nonces: &RangeInclusive<MessageNonce>) {}
+	/// Called when proof of nonces is generated by the source client.
+	fn on_proof_generated(&mut self, _at_block: &P::SourceHeaderId, _nonces: &RangeInclusive<MessageNonce>) {}
+	/// Called when proof of nonces is submitted to the target client.
+	fn on_proof_submitted(&mut self, _nonces: &RangeInclusive<MessageNonce>) {}
+	/// Called when submitted nonces are confirmed by the target client.
+	fn on_range_confirmed(&mut self, _nonces: &RangeInclusive<MessageNonce>) {}
+}
+
+impl<P: MessageRace> RaceObserver<P> for () {}
+
+/// External condition that may defer delivery (e.g. while target chain fees are spiking).
+///
+/// The race consults the condition before selecting nonces and generating their proof. While it
+/// doesn't allow delivery, nothing is selected and stall timeouts of both clients are suspended.
+/// Already generated proofs are still submitted. The condition is only checked when the race
+/// wakes up, so the race should be configured to refresh nonces periodically (see
+/// `RaceParams::refresh_interval`) to resume delivery without waiting for client updates.
+pub trait DeliveryCondition: Debug + Send + Sync {
+	/// Returns true if new nonces may be delivered now.
+	fn can_deliver_now(&self) -> bool;
+}
+
+impl DeliveryCondition for std::sync::atomic::AtomicBool {
+	fn can_deliver_now(&self) -> bool {
+		self.load(std::sync::atomic::Ordering::SeqCst)
+	}
+}
+
+#[cfg(feature = "tokio-watch")]
+impl DeliveryCondition for tokio::sync::watch::Receiver<bool> {
+	fn can_deliver_now(&self) -> bool {
+		*self.borrow()
+	}
+}
+
+/// Limit of the proof size, which is checked before the proof is generated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProofSizeLimit {
+	/// Maximal size (in bytes) of the single proof.
+	pub max_proof_size: u32,
+	/// Maximal number of `SourceClient::estimate_proof_size` calls before the single proof is
+	/// generated. If the estimated size still exceeds the limit after that, the proof of the last
+	/// (shrunk) range is generated anyway.
+	pub max_estimations: u32,
+}
+
+/// Future that resolves into balance of the relayer account at the target node.
+pub type RelayerBalanceFuture = Pin<Box<dyn Future<Output = Result<u128, String>> + Send>>;
+
+/// Parameters of the guard that stops delivery while the relayer account balance is low, so
+/// that the relayer doesn't submit transactions that would fail anyway.
+#[derive(Clone)]
+pub struct BalanceGuardParams {
+	/// Reads balance of the relayer account at the target node. It is called at most once per
+	/// target block.
+	pub relayer_balance: Arc<dyn Fn() -> RelayerBalanceFuture + Send + Sync>,
+	/// Nonces are not selected for delivery while the relayer balance is below this threshold.
+	pub min_balance: u128,
+}
+
+impl std::fmt::Debug for BalanceGuardParams {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		fmt.debug_struct("BalanceGuardParams")
+			.field("min_balance", &self.min_balance)
+			.finish()
+	}
+}
+
+/// Options of the transaction that submits proof to the target node.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SubmitOptions {
+	/// Tip that is paid to the target chain block author (in target chain base units).
+	pub tip: u128,
+	/// Number of target chain blocks the transaction stays valid for. If `None`, the target client
+	/// uses its default mortality.
+	pub mortality: Option<u32>,
+}
+
+/// Reference to the header of the race source node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockRef {
+	/// Best header of the node. It may be reverted by the chain reorganization.
+	Best,
+	/// Best finalized header of the node.
+	Finalized,
+}
+
+/// Predicted outcome of the proof submission, reported by the target client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DryRunOutcome {
+	/// Submission would succeed.
+	Success,
+	/// Submission would fail, because all proved nonces are already delivered (e.g. by other
+	/// relayer).
+	AlreadyDelivered,
+	/// Submission would fail for other reason.
+	Failure,
+}
+
+/// Error of the race client reconnection.
+pub enum ReconnectError<E> {
+	/// The client is unable to reconnect.
+	Unsupported,
+	/// The reconnection has failed.
+	Failed(E),
+}
+
+impl<E: Debug> Debug for ReconnectError<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		// client errors are printed as is, so wrapping them doesn't change error messages
+		match *self {
+			ReconnectError::Unsupported => write!(f, "Reconnection is not supported by the client"),
+			ReconnectError::Failed(ref error) => error.fmt(f),
+		}
+	}
+}
+
+impl<E: RelayError> RelayError for ReconnectError<E> {
+	fn classification(&self) -> ErrorClassification {
+		match *self {
+			ReconnectError::Unsupported => ErrorClassification::Fatal,
+			ReconnectError::Failed(ref error) => error.classification(),
+		}
+	}
+}
+
+impl<E: MaybeFatalError> MaybeFatalError for ReconnectError<E> {
+	fn is_fatal(&self) -> bool {
+		match *self {
+			ReconnectError::Unsupported => true,
+			ReconnectError::Failed(ref error) => error.is_fatal(),
+		}
+	}
+}
+
+/// Policy that selects options of proof submission transactions. By default, no tip is paid and
+/// the default mortality of the target client is used.
+#[derive(Clone)]
+pub struct SubmitPolicy {
+	/// Returns tip of the `attempt`-th submission of the same nonces. Attempts are counted from
+	/// `1`, so the tip may be escalated when the transaction is lost or is not confirmed in time.
+	pub tip: Arc<dyn Fn(u32) -> u128 + Send + Sync>,
+	/// Mortality of submitted transactions.
+	pub mortality: Option<u32>,
+}
+
+impl SubmitPolicy {
+	/// Returns options of the `attempt`-th submission of the same nonces.
+	pub fn submit_options(&self, attempt: u32) -> SubmitOptions {
+		SubmitOptions {
+			tip: (self.tip)(attempt),
+			mortality: self.mortality,
+		}
+	}
+}
+
+impl Default for SubmitPolicy {
+	fn default() -> Self {
+		SubmitPolicy {
+			tip: Arc::new(|_| 0),
+			mortality: None,
+		}
+	}
+}
+
+impl std::fmt::Debug for SubmitPolicy {
+	fn fmt(&self, fmt: &mut std::fmt::Formatter) -> std::fmt::Result {
+		fmt.debug_struct("SubmitPolicy")
+			.field("mortality", &self.mortality)
+			.finish()
+	}
+}
+
+/// Command that changes race behavior while the race is running.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaceCommand {
+	/// Never select more than given number of nonces for the single proof.
+	SetMaxNoncesPerProof(u32),
+	/// Never select messages with cumulative dispatch weight larger than given for the single
+	/// proof. Only the messages delivery race is aware of messages weights.
+	SetMaxWeightPerProof(Weight),
+	/// Stop generating and submitting proofs. Client states and nonces are still read, so the
+	/// race is able to continue from the current state when resumed. Stall and resubmit
+	/// timeouts are suspended while the race is paused.
+	Pause,
+	/// Resume generating and submitting proofs after `Pause` command.
+	Resume,
+	/// Replace all limits of the single delivery proof with parameters of the target lane. Only
+	/// the messages delivery race is aware of these parameters.
+	SetLaneParameters(TargetLaneParameters),
+}
+
+/// Parameters of the lane at the target node, that are limiting messages delivery.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetLaneParameters {
+	/// Maximal number of messages in the single delivery transaction.
+	pub max_messages_in_single_batch: MessageNonce,
+	/// Maximal cumulative dispatch weight of messages in the single delivery transaction.
+	pub max_messages_weight_in_single_batch: Weight,
+	/// Maximal number of delivered, but not yet confirmed messages at the target node.
+	pub max_unconfirmed_messages: MessageNonce,
+}
+
+/// Kind of failure that has happened when the race has been delivering nonces range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeFailureKind {
+	/// Source client has failed to generate proof of nonces (or generation has timed out).
+	ProofGeneration,
+	/// Target client has rejected transaction with proof of nonces.
+	ProofSubmission,
 }
 
 /// Race strategy.
@@ -140,31 +558,126 @@ pub trait RaceStrategy<SourceHeaderId, TargetHeaderId, Proof> {
 	type SourceNoncesRange: NoncesRange;
 	/// Additional proof parameters required to generate proof.
 	type ProofParameters;
+	/// Checkpoint of the strategy state.
+	type Checkpoint;
+	/// Cost of the delivery transaction, estimated by the target client.
+	type Cost;
+	/// Additional data expected from the target client.
+	type TargetNoncesData;
 
 	/// Should return true if nothing has to be synced.
 	fn is_empty(&self) -> bool;
+	/// Should return true if the strategy refuses to queue new source nonces. The race stops
+	/// reading source nonces while it returns true. By default, the queue is never full.
+	fn is_source_queue_full(&self) -> bool {
+		false
+	}
+	/// Return human-readable summary of nonces queued by the strategy. It is included in the
+	/// periodic race progress message. By default, there's no summary.
+	fn queue_summary(&self) -> Option<String> {
+		None
+	}
+	/// Return metrics of the strategy. They are published along with the periodic race progress
+	/// message. By default, no metrics are supported.
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		StrategyMetricsSnapshot::default()
+	}
 	/// Return best nonce at source node.
 	fn best_at_source(&self) -> MessageNonce;
 	/// Return best nonce at target node.
 	fn best_at_target(&self) -> MessageNonce;
+	/// Return best nonce at source node, or `None` if the source node has not yet reported its
+	/// nonces. By default, the `best_at_source` is always known.
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		Some(self.best_at_source())
+	}
+	/// Return best nonce at target node, or `None` if the target node has not yet reported its
+	/// nonces. By default, the `best_at_target` is always known.
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		Some(self.best_at_target())
+	}
+	/// Return true if the strategy only trusts nonces at the best finalized target header.
+	/// Otherwise, nonces at the best target header are passed to the `target_nonces_updated`.
+	/// By default, nonces at the best target header are used.
+	fn requires_target_finality(&self) -> bool {
+		false
+	}
 
 	/// Called when nonces are updated at source node of the race.
 	fn source_nonces_updated(&mut self, at_block: SourceHeaderId, nonces: SourceClientNonces<Self::SourceNoncesRange>);
+	/// Called when source chain of the race is reorganized. All nonces, that have been seen at
+	/// headers orphaned by the `new_best` header, must be forgotten.
+	fn source_reorged(&mut self, new_best: &SourceHeaderId);
 	/// Called when nonces are updated at target node of the race.
 	fn target_nonces_updated(
 		&mut self,
-		nonces: TargetClientNonces,
+		nonces: TargetClientNonces<Self::TargetNoncesData>,
 		race_state: &mut RaceState<SourceHeaderId, TargetHeaderId, Proof>,
 	);
 	/// Should return `Some(nonces)` if we need to deliver proof of `nonces` (and associated
 	/// data) from source to target node.
-	/// Additionally, parameters required to generate proof are returned.
+	/// Additionally, parameters required to generate proof are returned, along with the source
+	/// header where the proof should be generated. If header is not returned, the proof is
+	/// generated at the best source header known to the target node.
 	fn select_nonces_to_deliver(
 		&mut self,
 		race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
-	) -> Option<(RangeInclusive<MessageNonce>, Self::ProofParameters)>;
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<SourceHeaderId>,
+	)>;
+	/// Should return `Some(header)` if the race can't make progress until the target node knows
+	/// the source `header`, which is newer than the `current_best` source header known to the
+	/// target node.
+	fn required_source_header_at_target(&self, current_best: &SourceHeaderId) -> Option<SourceHeaderId>;
+	/// Returns function that computes parameters of the proof of any prefix of `nonces`, which
+	/// have been selected with given `proof_parameters`. It is used to prove the shrunk range if
+	/// the proof of selected range exceeds the size limit. By default, parameters of the whole
+	/// range are used.
+	fn prefix_proof_parameters(
+		&self,
+		_nonces: &RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> PrefixProofParameters<Self::ProofParameters>
+	where
+		Self::ProofParameters: Clone + Send + 'static,
+	{
+		Box::new(move |_| proof_parameters.clone())
+	}
+	/// Called right before the proof of selected `nonces` is generated. If the strategy needs to
+	/// know the delivery cost of these nonces, it should return their cumulative dispatch weight
+	/// and size. The cost is then estimated by the target client and passed to the
+	/// `delivery_cost_estimated`. By default, the cost is never estimated.
+	fn delivery_cost_required(&self, _nonces: &RangeInclusive<MessageNonce>) -> Option<(Weight, u32)> {
+		None
+	}
+	/// Called with estimated delivery cost of selected `nonces`. Should return true if the proof
+	/// of these nonces may be generated. Otherwise, nonces are selected again later.
+	fn delivery_cost_estimated(&mut self, _nonces: &RangeInclusive<MessageNonce>, _cost: Self::Cost) -> bool {
+		true
+	}
+	/// Called when the race receives control command. Strategy may change its parameters
+	/// accordingly. By default, all commands are ignored.
+	fn apply_command(&mut self, _command: RaceCommand) {}
+	/// Called when the race has failed to generate or submit proof of `nonces`. Connection errors
+	/// are not reported. Strategy may withhold the range, or return error if the lane can't make
+	/// any progress. By default, failures are ignored.
+	fn range_failed(
+		&mut self,
+		_nonces: RangeInclusive<MessageNonce>,
+		_kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		Ok(())
+	}
+	/// Return checkpoint of the strategy state. It may be used to restore the strategy state
+	/// after restart.
+	fn checkpoint(&self) -> Self::Checkpoint;
 }
 
+/// Function that computes parameters of the proof of the prefix of selected nonces.
+pub type PrefixProofParameters<ProofParameters> = Box<dyn Fn(&RangeInclusive<MessageNonce>) -> ProofParameters + Send>;
+
 /// State of the race.
 #[derive(Debug)]
 pub struct RaceState<SourceHeaderId, TargetHeaderId, Proof> {
@@ -172,292 +685,7839 @@ pub struct RaceState<SourceHeaderId, TargetHeaderId, Proof> {
 	pub source_state: Option<ClientState<SourceHeaderId, TargetHeaderId>>,
 	/// Target state, if known.
 	pub target_state: Option<ClientState<TargetHeaderId, SourceHeaderId>>,
-	/// Range of nonces that we have selected to submit.
-	pub nonces_to_submit: Option<(SourceHeaderId, RangeInclusive<MessageNonce>, Proof)>,
-	/// Range of nonces that is currently submitted.
-	pub nonces_submitted: Option<RangeInclusive<MessageNonce>>,
+	/// Range of nonces that we have selected to submit. If there are too many submitted ranges
+	/// in `nonces_submitted`, these nonces are waiting until submitted nonces are confirmed by
+	/// the target node. The proof is shared with the submission future, so it is never copied
+	/// when submission is retried.
+	pub nonces_to_submit: Option<(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>)>,
+	/// Proofs that are ready to be submitted, but are waiting until the target client is able to
+	/// submit them all in a single batch. Ranges are ordered by nonces and they're all preceding
+	/// `nonces_to_submit`. Proofs are only queued if the target client supports batch submission
+	/// and the queue never holds more than `RaceParams::max_proofs_per_batch - 1` proofs.
+	pub proofs_to_submit: VecDeque<(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>)>,
+	/// Ranges of nonces that are currently submitted, but not yet confirmed by the target node.
+	/// Ranges are ordered by nonces - the front range is the first to be confirmed.
+	pub nonces_submitted: VecDeque<RangeInclusive<MessageNonce>>,
+}
+
+/// Snapshot of the race state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RaceStateSnapshot {
+	/// Best nonce at the source node.
+	pub best_at_source: MessageNonce,
+	/// Best nonce at the target node.
+	pub best_at_target: MessageNonce,
+	/// Nonces that we're going to submit to the target node.
+	pub nonces_to_submit: Option<RangeInclusive<MessageNonce>>,
+	/// Nonces that have been submitted to the target node, but are not yet confirmed.
+	pub nonces_submitted: Vec<RangeInclusive<MessageNonce>>,
+	/// Number of generated proofs that have been discarded, because their nonces have been
+	/// delivered by other relayers.
+	pub discarded_proofs: u64,
+	/// True if the best source header known to the target node lags too far behind the best
+	/// source header, while there are nonces waiting for delivery.
+	pub is_source_header_lagging: bool,
+}
+
+impl RaceStateSnapshot {
+	/// Create snapshot of the given race state.
+	fn new<SourceHeaderId, TargetHeaderId, Proof>(
+		race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+		strategy: &impl RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+		discarded_proofs: u64,
+		is_source_header_lagging: bool,
+	) -> Self {
+		RaceStateSnapshot {
+			best_at_source: strategy.best_at_source(),
+			best_at_target: strategy.best_at_target(),
+			nonces_to_submit: race_state
+				.nonces_to_submit
+				.as_ref()
+				.map(|(_, nonces_range, _)| nonces_range.clone()),
+			nonces_submitted: race_state.nonces_submitted.iter().cloned().collect(),
+			discarded_proofs,
+			is_source_header_lagging,
+		}
+	}
 }
 
-/// Run race loop until connection with target or source node is lost.
-pub async fn run<P: MessageRace, SC: SourceClient<P>>(
+/// Metrics of the race strategy. Metrics that are not supported by the strategy are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StrategyMetricsSnapshot {
+	/// Number of queued nonces ranges.
+	pub queued_ranges: Option<u64>,
+	/// Total number of queued nonces.
+	pub queued_nonces: Option<MessageNonce>,
+	/// Number of the source block where the oldest queued range has been seen. It is `None` if
+	/// nothing is queued.
+	pub oldest_queued_at: Option<u64>,
+	/// Number of source nonces updates that have been refused, because they haven't started
+	/// right after the best known source nonce.
+	pub source_nonce_gaps: Option<u64>,
+}
+
+/// Race loop parameters.
+#[derive(Debug, Clone)]
+pub struct RaceParams {
+	/// The race fails with `FailedClient::Source` if source client has not provided any nonces
+	/// or proofs during this period.
+	pub source_stall_timeout: Duration,
+	/// The race fails with `FailedClient::Target` if target client has not accepted any proofs
+	/// (and target nonces have not been advanced) during this period.
+	pub target_stall_timeout: Duration,
+	/// If submitted nonces are not confirmed by the target node during this period, the proof is
+	/// regenerated and submitted again.
+	pub resubmit_timeout: Duration,
+	/// Parameters of retries after source client errors.
+	pub source_retry_backoff: RetryBackoffParams,
+	/// Parameters of retries after target client errors.
+	pub target_retry_backoff: RetryBackoffParams,
+	/// If true, generated proofs are never submitted to the target node. Instead, the race acts
+	/// as if they have been delivered immediately.
+	pub dry_run: bool,
+	/// If set, the race never delivers nonces after this nonce and exits successfully once this
+	/// nonce is received by the target node. The strategy must never select nonces after this
+	/// nonce either.
+	pub final_nonce: Option<MessageNonce>,
+	/// If set, nonces are read from both clients at least once per this interval, even if client
+	/// states are not updated.
+	pub refresh_interval: Option<Duration>,
+	/// If set, snapshot of the race state is sent to this channel whenever it changes.
+	pub state_notifier: Option<UnboundedSender<RaceStateSnapshot>>,
+	/// If true, every generated proof is verified by the target client before submission. Proofs
+	/// that fail verification are never submitted - new proof is generated instead.
+	pub verify_proofs: bool,
+	/// If true, submission of every proof is dry run at the target client before the transaction
+	/// is submitted. Proofs of already delivered nonces are dropped and proofs that would fail for
+	/// other reasons are generated again. Proofs that follow unconfirmed submissions are not dry
+	/// run, because the target node doesn't know about previous nonces yet.
+	pub dry_run_before_submit: bool,
+	/// If true, the proof that is waiting for submission is dropped (and nonces are selected
+	/// again) when the best source header known to the target node is advanced by more than
+	/// `reselect_threshold` blocks since the proof has been generated. Larger range of nonces
+	/// may be delivered then. Nonces are selected again at most once per pending proof.
+	pub reselect_on_newer_target_state: bool,
+	/// Number of source blocks for the `reselect_on_newer_target_state` option.
+	pub reselect_threshold: u64,
+	/// Name of the lane, used in log messages.
+	pub lane_name: String,
+	/// Maximal number of submitted, but not yet confirmed proofs. Proofs are always submitted in
+	/// order, so the next proof is only submitted when the previous submission has completed.
+	pub max_in_flight: usize,
+	/// Maximal number of proofs that are submitted in a single transaction, if the target client
+	/// supports batch submission. While the target client is unable to submit the ready proof
+	/// (e.g. because of `max_in_flight` limit), the proof is queued and next nonces are proved.
+	/// The whole queue is submitted once the client is ready.
+	pub max_proofs_per_batch: usize,
+	/// If set, source nonces are not read while the number of nonces that are known to the
+	/// source node, but not yet delivered to the target node, exceeds this limit. Reading
+	/// continues once the target node catches up. `None` means that the queue is unlimited.
+	pub max_queued_nonces: Option<MessageNonce>,
+	/// If set, the race waits for random delay in `[0, competition_delay]` range before
+	/// generating proof of selected nonces. Then nonces are read from the target node again and
+	/// the proof is only generated if nonces have not been delivered by other relayers.
+	pub competition_delay: Option<Duration>,
+	/// Returns random delay in `[0, max_delay]` range for the `competition_delay` option.
+	pub random_delay: fn(Duration) -> Duration,
+	/// If set, proof generation is cancelled if it hasn't completed during this period. The
+	/// source client is then treated as failed (and is retried after backoff) and nonces are
+	/// selected again, probably at the newer source header.
+	pub proof_generation_timeout: Option<Duration>,
+	/// If set, selected range is shrunk until the estimated size of its proof fits the limit.
+	/// Parameters of the proof of the shrunk range are computed by the strategy (see
+	/// `RaceStrategy::prefix_proof_parameters`).
+	pub proof_size_limit: Option<ProofSizeLimit>,
+	/// Every nonces request to both clients must complete during this period. Otherwise it is
+	/// treated as a connection error.
+	pub rpc_timeout: Duration,
+	/// If true, source nonces are read at the best source header that is known to the target
+	/// node (if it is older than the best source header). So all read nonces may be delivered
+	/// immediately. Otherwise nonces are read at the best source header.
+	pub nonces_at_target_known_header: bool,
+	/// Source header where nonces are read. Nonces that are read at the best (non-finalized)
+	/// header may be orphaned later, so their proofs would be generated again.
+	pub source_nonces_at: BlockRef,
+	/// Number of the latest source headers, known to the target node, that are treated as
+	/// unstable. The strategy must never select nonces that have been queued at these headers.
+	pub source_confirmation_depth: u32,
+	/// If true, the strategy asks to generate proofs at the newest source header where selected
+	/// nonces have been queued, instead of the best source header known to the target node.
+	/// Such proofs are smaller and are not affected by reorgs of the recent source headers.
+	pub proof_at_queued_header: bool,
+	/// If true, the strategy only trusts nonces at the best finalized target header. Otherwise
+	/// nonces at the best target header are used.
+	pub require_target_finality: bool,
+	/// If set, warning is logged when the best source header known to the target node lags
+	/// behind the best source header by more than this number of blocks, while there are nonces
+	/// waiting for delivery. The warning is logged once and is cleared when the lag recovers.
+	pub max_source_header_lag: Option<u64>,
+	/// If set, the race fails once any client has returned this number of non-connection errors
+	/// in a row. Such errors (e.g. undecodable responses) are otherwise retried forever.
+	pub max_consecutive_failures: Option<u32>,
+	/// Number of attempts to reconnect the client after connection error, before the race fails.
+	/// Strategy and race state are preserved while the client is reconnecting, but in-flight
+	/// requests are dropped. If zero, the race fails on the first connection error.
+	pub max_reconnect_attempts: u32,
+	/// If set, new nonces are only selected for delivery while this condition allows it.
+	pub delivery_condition: Option<Arc<dyn DeliveryCondition>>,
+	/// If set, new nonces are not selected for delivery while the relayer balance at the target
+	/// node is low. Stall timeouts are suspended during that time.
+	pub balance_guard: Option<BalanceGuardParams>,
+	/// Policy that selects tip and mortality of proof submission transactions.
+	pub submit_policy: SubmitPolicy,
+}
+
+/// Returns random delay in `[0, max_delay]` range.
+pub fn random_delay(max_delay: Duration) -> Duration {
+	Duration::from_millis(rand::thread_rng().gen_range(0, max_delay.as_millis() as u64 + 1))
+}
+
+/// Summary of the race, returned when the race loop exits.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RaceSummary {
+	/// Number of nonces that we have submitted and that have been confirmed by the target node.
+	pub delivered_nonces: MessageNonce,
+	/// Number of proofs generated by the source client.
+	pub generated_proofs: u64,
+	/// Number of proofs submitted to the target client.
+	pub submitted_proofs: u64,
+	/// Number of times when client requests have been retried after failure.
+	pub retries: u64,
+	/// Total time the race has been running.
+	pub runtime: Duration,
+}
+
+impl std::fmt::Display for RaceSummary {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(
+			f,
+			"delivered {} nonces, generated {} proofs, submitted {} proofs, retried {} requests in {}s",
+			self.delivered_nonces,
+			self.generated_proofs,
+			self.submitted_proofs,
+			self.retries,
+			self.runtime.as_secs(),
+		)
+	}
+}
+
+/// Race loop error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaceError {
+	/// Client that has caused the error.
+	pub failed_client: FailedClient,
+	/// Why the race has failed.
+	pub reason: RaceErrorReason,
+	/// Summary of the race before it has failed.
+	pub summary: RaceSummary,
+}
+
+/// Reason of the race failure.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RaceErrorReason {
+	/// Client has returned connection error. Formatted client error is attached.
+	Connection(String),
+	/// The race has not been making any progress for too long.
+	Stall {
+		/// Last formatted error of the stalled client(s), if there have been any.
+		last_error: Option<String>,
+		/// Time since last successful response of the source client.
+		since_source_success: Duration,
+		/// Time since last successful response of the target client.
+		since_target_success: Duration,
+	},
+	/// Client has returned fatal error. Formatted client error is attached.
+	Fatal(String),
+	/// Client has returned too many non-connection errors in a row.
+	ConsecutiveFailures {
+		/// Number of consecutive failures.
+		failures: u32,
+		/// Last formatted client error.
+		last_error: String,
+	},
+	/// Race strategy has panicked. Panic message is attached. Strategy state may be corrupted,
+	/// so the race can't continue.
+	StrategyPanic(String),
+	/// Nonces range, starting at given nonce, has failed to be delivered too many times in a row.
+	/// Nonces are delivered in order, so the lane can't make any progress.
+	LaneBlocked {
+		/// First nonce of the failed range.
+		nonce: MessageNonce,
+		/// Number of consecutive failures.
+		failures: u32,
+		/// Kind of the last failure.
+		last_failure: RangeFailureKind,
+	},
+}
+
+impl std::fmt::Display for RaceError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self.reason {
+			RaceErrorReason::Connection(ref error) => {
+				write!(
+					f,
+					"{:?} client has failed with connection error: {}",
+					self.failed_client, error
+				)
+			}
+			RaceErrorReason::Fatal(ref error) => {
+				write!(
+					f,
+					"{:?} client has failed with fatal error: {}",
+					self.failed_client, error
+				)
+			}
+			RaceErrorReason::StrategyPanic(ref error) => write!(f, "Race strategy has panicked: {}", error),
+			RaceErrorReason::LaneBlocked {
+				nonce,
+				failures,
+				last_failure,
+			} => write!(
+				f,
+				"Lane blocked at nonce {}: delivery has failed {} times in a row. Last failure: {:?}",
+				nonce, failures, last_failure,
+			),
+			RaceErrorReason::ConsecutiveFailures {
+				failures,
+				ref last_error,
+			} => write!(
+				f,
+				"{:?} client has failed {} times in a row. Last error: {}",
+				self.failed_client, failures, last_error,
+			),
+			RaceErrorReason::Stall {
+				ref last_error,
+				since_source_success,
+				since_target_success,
+			} => write!(
+				f,
+				"{:?} client has stalled. Last error: {}. Last successful responses: {}s ago from source, {}s ago from target",
+				self.failed_client,
+				last_error.as_deref().unwrap_or("<none>"),
+				since_source_success.as_secs(),
+				since_target_success.as_secs(),
+			),
+		}
+	}
+}
+
+/// Updates of the race clients states, commands and callbacks that connect the race loop with the
+/// rest of the message lane loop.
+pub struct RaceContext<
+	SourceUpdated,
+	TargetUpdated,
+	Control,
+	OnCheckpoint,
+	OnRequiredSourceHeader,
+	Observer,
+	ExitSignal,
+> {
+	/// Stream of the race source client states.
+	pub source_updated: SourceUpdated,
+	/// Stream of the race target client states.
+	pub target_updated: TargetUpdated,
+	/// Stream of commands that change race behavior while the race is running.
+	pub control: Control,
+	/// Receives checkpoint of the strategy state whenever the best nonce at the target node is
+	/// changed. Checkpoints are never produced in dry-run mode.
+	pub on_checkpoint: OnCheckpoint,
+	/// Receives source header that the target node needs to know to unblock the race, whenever
+	/// this header is changed. It may be used to relay only source headers that are required for
+	/// delivery.
+	pub on_required_source_header: OnRequiredSourceHeader,
+	/// Observer of the race events.
+	pub observer: Observer,
+	/// If set, best nonces at both sides of the race and all calls to race clients are exposed
+	/// using these metrics.
+	pub metrics: Option<MessageRaceMetrics>,
+	/// The race stops once this future resolves.
+	pub exit_signal: ExitSignal,
+}
+
+/// Run race loop until connection with target or source node is lost, or exit signal is received.
+///
+/// If `RaceParams::max_reconnect_attempts` is non-zero, the client that has lost connection is
+/// reconnected in place and the race continues. The race only fails if all attempts have failed.
+///
+/// When exit signal is received, the loop stops starting new requests, waits until in-flight
+/// proof submission (if any) is completed and returns summary of the race. The summary is also
+/// attached to the race error.
+pub async fn run<P, SC, TC, S>(
 	race_source: SC,
-	race_source_updated: impl FusedStream<Item = SourceClientState<P>>,
-	race_target: impl TargetClient<P>,
-	race_target_updated: impl FusedStream<Item = TargetClientState<P>>,
-	stall_timeout: Duration,
-	mut strategy: impl RaceStrategy<
+	race_target: TC,
+	params: RaceParams,
+	strategy: S,
+	context: RaceContext<
+		impl FusedStream<Item = SourceClientState<P>>,
+		impl FusedStream<Item = TargetClientState<P>>,
+		impl FusedStream<Item = RaceCommand>,
+		impl FnMut(S::Checkpoint),
+		impl FnMut(P::SourceHeaderId),
+		impl RaceObserver<P>,
+		impl Future<Output = ()>,
+	>,
+) -> Result<RaceSummary, RaceError>
+where
+	P: MessageRace + Send + Sync,
+	P::SourceHeaderId: Send + Sync,
+	P::TargetHeaderId: Send,
+	P::Proof: Send + Sync,
+	SC: SourceClient<P> + Send + Sync,
+	TC: TargetClient<P> + Send + Sync,
+	S: RaceStrategy<
 		P::SourceHeaderId,
 		P::TargetHeaderId,
 		P::Proof,
 		SourceNoncesRange = SC::NoncesRange,
 		ProofParameters = SC::ProofParameters,
+		Cost = TC::Cost,
+		TargetNoncesData = TC::TargetNoncesData,
 	>,
-) -> Result<(), FailedClient> {
-	let mut progress_context = Instant::now();
-	let mut race_state = RaceState::default();
-	let mut stall_countdown = Instant::now();
-
-	let mut source_retry_backoff = retry_backoff();
-	let mut source_client_is_online = true;
-	let mut source_nonces_required = false;
-	let source_nonces = futures::future::Fuse::terminated();
-	let source_generate_proof = futures::future::Fuse::terminated();
-	let source_go_offline_future = futures::future::Fuse::terminated();
-
-	let mut target_retry_backoff = retry_backoff();
-	let mut target_client_is_online = true;
-	let mut target_nonces_required = false;
-	let target_nonces = futures::future::Fuse::terminated();
-	let target_submit_proof = futures::future::Fuse::terminated();
-	let target_go_offline_future = futures::future::Fuse::terminated();
+{
+	run_with_timer(race_source, race_target, params, strategy, context, AsyncStdTimer).await
+}
 
-	futures::pin_mut!(
-		race_source_updated,
-		source_nonces,
-		source_generate_proof,
-		source_go_offline_future,
-		race_target_updated,
-		target_nonces,
-		target_submit_proof,
-		target_go_offline_future,
+/// Same as `run`, but all delays and timeouts of the race are awaited using given timer. So the
+/// race may be started on any async executor.
+pub async fn run_with_timer<P, SC, TC, S>(
+	race_source: SC,
+	race_target: TC,
+	params: RaceParams,
+	strategy: S,
+	context: RaceContext<
+		impl FusedStream<Item = SourceClientState<P>>,
+		impl FusedStream<Item = TargetClientState<P>>,
+		impl FusedStream<Item = RaceCommand>,
+		impl FnMut(S::Checkpoint),
+		impl FnMut(P::SourceHeaderId),
+		impl RaceObserver<P>,
+		impl Future<Output = ()>,
+	>,
+	timer: impl Timer,
+) -> Result<RaceSummary, RaceError>
+where
+	P: MessageRace + Send + Sync,
+	P::SourceHeaderId: Send + Sync,
+	P::TargetHeaderId: Send,
+	P::Proof: Send + Sync,
+	SC: SourceClient<P> + Send + Sync,
+	TC: TargetClient<P> + Send + Sync,
+	S: RaceStrategy<
+		P::SourceHeaderId,
+		P::TargetHeaderId,
+		P::Proof,
+		SourceNoncesRange = SC::NoncesRange,
+		ProofParameters = SC::ProofParameters,
+		Cost = TC::Cost,
+		TargetNoncesData = TC::TargetNoncesData,
+	>,
+{
+	let started_at = timer.now();
+	let RaceContext {
+		source_updated,
+		target_updated,
+		control,
+		on_checkpoint,
+		on_required_source_header,
+		observer,
+		metrics,
+		exit_signal,
+	} = context;
+	let race_source = MeteredSourceClient::new(race_source, params.lane_name.clone(), metrics.clone());
+	let race_target = MeteredTargetClient::new(race_target, params.lane_name.clone(), metrics.clone());
+	let mut race_loop = RaceLoop::new(
+		params,
+		strategy,
+		on_checkpoint,
+		on_required_source_header,
+		observer,
+		metrics,
+		timer.clone(),
 	);
+	let result = run_race_loop(
+		&mut race_loop,
+		race_source,
+		race_target,
+		source_updated,
+		target_updated,
+		control,
+		exit_signal,
+	)
+	.await;
+	let mut summary = race_loop.summary;
+	summary.runtime = timer.now().saturating_duration_since(started_at);
 
-	loop {
-		futures::select! {
-			// when headers ids are updated
-			source_state = race_source_updated.next() => {
-				if let Some(source_state) = source_state {
-					if race_state.source_state.as_ref() != Some(&source_state) {
-						source_nonces_required = true;
-						race_state.source_state = Some(source_state);
-					}
-				}
-			},
-			target_state = race_target_updated.next() => {
-				if let Some(target_state) = target_state {
-					if race_state.target_state.as_ref() != Some(&target_state) {
-						target_nonces_required = true;
-						race_state.target_state = Some(target_state);
-					}
-				}
-			},
+	match result {
+		Ok(()) => Ok(summary),
+		Err(error) => Err(RaceError { summary, ..error }),
+	}
+}
+
+/// Run race loop until it exits. Summary of the race is updated while the race makes progress.
+///
+/// This function only owns futures of client requests and streams of the race. Results of
+/// requests are processed by the `RaceLoop`, which also decides what to request next.
+async fn run_race_loop<P, SC, TC, S, OC, OR, O, T>(
+	race_loop: &mut RaceLoop<P, SC, TC, S, OC, OR, O, T>,
+	mut race_source: SC,
+	mut race_target: TC,
+	source_updated: impl FusedStream<Item = SourceClientState<P>>,
+	target_updated: impl FusedStream<Item = TargetClientState<P>>,
+	control: impl FusedStream<Item = RaceCommand>,
+	exit_signal: impl Future<Output = ()>,
+) -> Result<(), RaceError>
+where
+	P: MessageRace,
+	SC: SourceClient<P>,
+	TC: TargetClient<P>,
+	S: RaceStrategy<
+		P::SourceHeaderId,
+		P::TargetHeaderId,
+		P::Proof,
+		SourceNoncesRange = SC::NoncesRange,
+		ProofParameters = SC::ProofParameters,
+		Cost = TC::Cost,
+		TargetNoncesData = TC::TargetNoncesData,
+	>,
+	OC: FnMut(S::Checkpoint),
+	OR: FnMut(P::SourceHeaderId),
+	O: RaceObserver<P>,
+	T: Timer,
+{
+	// futures of client requests may outlive any borrow of the race loop
+	let timer = race_loop.timer.clone();
+	let lane_name = race_loop.params.lane_name.clone();
+	let rpc_timeout = race_loop.params.rpc_timeout;
+	let proof_size_limit = race_loop.params.proof_size_limit;
+	let exit_signal = exit_signal.fuse();
+	let refresh_tick_stream = match race_loop.params.refresh_interval {
+		Some(refresh_interval) => timer.interval(refresh_interval).left_stream(),
+		None => futures::stream::pending().right_stream(),
+	}
+	.fuse();
+	// transactions are tracked even if the target client is reconnected
+	let mut target_tx_trackers = FuturesUnordered::new();
 
-			// when nonces are updated
-			nonces = source_nonces => {
-				source_nonces_required = false;
+	futures::pin_mut!(
+		source_updated,
+		target_updated,
+		refresh_tick_stream,
+		control,
+		exit_signal
+	);
 
-				source_client_is_online = process_future_result(
-					nonces,
-					&mut source_retry_backoff,
-					|(at_block, nonces)| {
-						log::debug!(
-							target: "bridge",
-							"Received nonces from {}: {:?}",
-							P::source_name(),
-							nonces,
-						);
+	loop {
+		// futures of client requests are borrowing clients, so they only live until the client
+		// needs to be reconnected
+		let reconnect_error = {
+			let source_nonces = futures::future::Fuse::terminated();
+			let source_generate_proof = futures::future::Fuse::terminated();
+			let target_nonces = futures::future::Fuse::terminated();
+			let target_verify_proof = futures::future::Fuse::terminated();
+			let target_dry_run_proof = futures::future::Fuse::terminated();
+			let target_read_required_source_header = futures::future::Fuse::terminated();
+			let target_submit_proof = futures::future::Fuse::terminated();
+			let target_estimate_delivery_cost = futures::future::Fuse::terminated();
+			let read_runtime_versions = futures::future::Fuse::terminated();
+			futures::pin_mut!(
+				source_nonces,
+				source_generate_proof,
+				target_nonces,
+				target_verify_proof,
+				target_dry_run_proof,
+				target_read_required_source_header,
+				target_submit_proof,
+				target_estimate_delivery_cost,
+				read_runtime_versions,
+			);
+			race_loop.on_batch_submission_support(race_target.supports_batch_submission());
+			loop {
+				let mut is_submitted_tx_lost = false;
 
-						strategy.source_nonces_updated(at_block, nonces);
+				futures::select! {
+					// when headers ids are updated
+					source_state = source_updated.next() => {
+						// there may be several queued states (e.g. if source produces blocks faster than we
+						// are processing them). We're processing all of them here, so nonces are only read
+						// at the latest state
+						let queued_source_states = std::iter::from_fn(|| source_updated.next().now_or_never().flatten());
+						let is_source_reorged = race_loop.on_source_states(source_state.into_iter().chain(queued_source_states));
+						if is_source_reorged && race_loop.cancel_proof_generation(
+							!source_generate_proof.is_terminated(),
+							!target_estimate_delivery_cost.is_terminated(),
+						) {
+							source_generate_proof.set(futures::future::Fuse::terminated());
+							target_estimate_delivery_cost.set(futures::future::Fuse::terminated());
+						}
+						race_loop.ensure_state_stream_alive(source_updated.is_terminated(), FailedClient::Source)?;
+					},
+					target_state = target_updated.next() => {
+						let queued_target_states = std::iter::from_fn(|| target_updated.next().now_or_never().flatten());
+						race_loop.on_target_states(target_state.into_iter().chain(queued_target_states));
+						race_loop.ensure_state_stream_alive(target_updated.is_terminated(), FailedClient::Target)?;
 					},
-					&mut source_go_offline_future,
-					|delay| async_std::task::sleep(delay),
-					|| format!("Error retrieving nonces from {}", P::source_name()),
-				).fail_if_connection_error(FailedClient::Source)?;
-			},
-			nonces = target_nonces => {
-				target_nonces_required = false;
-
-				target_client_is_online = process_future_result(
-					nonces,
-					&mut target_retry_backoff,
-					|(_, nonces)| {
-						log::debug!(
-							target: "bridge",
-							"Received nonces from {}: {:?}",
-							P::target_name(),
-							nonces,
-						);
 
-						strategy.target_nonces_updated(nonces, &mut race_state);
+					// when nonces are updated
+					nonces = source_nonces => race_loop.on_source_nonces(nonces)?,
+					nonces = target_nonces => {
+						// dropping the future cancels the request
+						if race_loop.on_target_nonces(nonces, !source_generate_proof.is_terminated())? {
+							source_generate_proof.set(futures::future::Fuse::terminated());
+						}
 					},
-					&mut target_go_offline_future,
-					|delay| async_std::task::sleep(delay),
-					|| format!("Error retrieving nonces from {}", P::target_name()),
-				).fail_if_connection_error(FailedClient::Target)?;
-			},
-
-			// proof generation and submission
-			proof = source_generate_proof => {
-				source_client_is_online = process_future_result(
-					proof,
-					&mut source_retry_backoff,
-					|(at_block, nonces_range, proof)| {
-						log::debug!(
-							target: "bridge",
-							"Received proof for nonces in range {:?} from {}",
-							nonces_range,
-							P::source_name(),
-						);
 
-						race_state.nonces_to_submit = Some((at_block, nonces_range, proof));
+					// proof generation and submission
+					proof = source_generate_proof => race_loop.on_proof_generated(&race_source, proof)?,
+					_ = &mut race_loop.proofs.deadline => {
+						// the deadline may outlive the proof generation, if it has been restarted
+						if !source_generate_proof.is_terminated() {
+							// dropping the future cancels the request
+							source_generate_proof.set(futures::future::Fuse::terminated());
+							race_loop.on_proof_generation_timeout()?;
+						}
+					},
+					verify_result = target_verify_proof => race_loop.on_proof_verified(verify_result)?,
+					dry_run_result = target_dry_run_proof => race_loop.on_proof_dry_run(dry_run_result)?,
+					required_source_header = target_read_required_source_header => {
+						race_loop.on_required_source_header_read(required_source_header)?;
+					},
+					submit_result = target_submit_proof => {
+						if let Some((nonces_range, tx_tracker)) = race_loop.on_proof_submitted(submit_result)? {
+							target_tx_trackers.push(tx_tracker.wait().map(move |tx_status| (nonces_range, tx_status)));
+						}
+					},
+					(tracked_nonces, tx_status) = target_tx_trackers.select_next_some() => {
+						is_submitted_tx_lost = race_loop.on_transaction_status(tracked_nonces, tx_status);
 					},
-					&mut source_go_offline_future,
-					|delay| async_std::task::sleep(delay),
-					|| format!("Error generating proof at {}", P::source_name()),
-				).fail_if_connection_error(FailedClient::Source)?;
-			},
-			proof_submit_result = target_submit_proof => {
-				target_client_is_online = process_future_result(
-					proof_submit_result,
-					&mut target_retry_backoff,
-					|nonces_range| {
-						log::debug!(
-							target: "bridge",
-							"Successfully submitted proof of nonces {:?} to {}",
-							nonces_range,
-							P::target_name(),
-						);
 
-						race_state.nonces_to_submit = None;
-						race_state.nonces_submitted = Some(nonces_range);
+					// when delivery transaction cost is estimated by the target node
+					cost = target_estimate_delivery_cost => race_loop.on_delivery_cost_estimated(cost)?,
+					runtime_versions = read_runtime_versions => {
+						race_loop.on_runtime_versions(&race_source, &race_target, runtime_versions);
 					},
-					&mut target_go_offline_future,
-					|delay| async_std::task::sleep(delay),
-					|| format!("Error submitting proof {}", P::target_name()),
-				).fail_if_connection_error(FailedClient::Target)?;
-			}
-		}
+					balance = &mut race_loop.relayer_balance.read => race_loop.on_relayer_balance(balance),
+					_ = &mut race_loop.relayer_balance.retry => race_loop.relayer_balance.checked_at = None,
 
-		progress_context = print_race_progress::<P, _>(progress_context, &strategy);
+					// when we need to read nonces again, even if states are not updated
+					_ = refresh_tick_stream.next() => race_loop.on_refresh_tick(),
 
-		if stall_countdown.elapsed() > stall_timeout {
-			return Err(FailedClient::Both);
-		} else if race_state.nonces_to_submit.is_none() && race_state.nonces_submitted.is_none() && strategy.is_empty()
-		{
-			stall_countdown = Instant::now();
-		}
+					// when competition delay has elapsed, we're reading target nonces again to check if
+					// selected nonces have been delivered by other relayers
+					_ = &mut race_loop.proofs.competition_delay => race_loop.on_competition_delay_elapsed(),
 
-		if source_client_is_online {
-			source_client_is_online = false;
+					// when we're ready to retry request
+					_ = &mut race_loop.source.go_offline_future => race_loop.on_retry(FailedClient::Source),
+					_ = &mut race_loop.target.go_offline_future => race_loop.on_retry(FailedClient::Target),
 
-			let nonces_to_deliver = select_nonces_to_deliver(&race_state, &mut strategy);
+					// when we're asked to change race behavior
+					command = control.next() => race_loop.on_command(command),
 
-			if let Some((at_block, nonces_range, proof_parameters)) = nonces_to_deliver {
-				log::debug!(
-					target: "bridge",
-					"Asking {} to prove nonces in range {:?} at block {:?}",
-					P::source_name(),
-					nonces_range,
-					at_block,
-				);
-				source_generate_proof.set(
-					race_source
-						.generate_proof(at_block, nonces_range, proof_parameters)
-						.fuse(),
-				);
-			} else if source_nonces_required {
-				log::debug!(target: "bridge", "Asking {} about message nonces", P::source_name());
-				let at_block = race_state
-					.source_state
-					.as_ref()
-					.expect("source_nonces_required is only true when source_state is Some; qed")
-					.best_self
-					.clone();
-				source_nonces.set(race_source.nonces(at_block, strategy.best_at_source()).fuse());
-			} else {
-				source_client_is_online = true;
-			}
-		}
+					// when we're asked to stop
+					() = exit_signal => race_loop.on_exit_signal(),
+				}
 
-		if target_client_is_online {
-			target_client_is_online = false;
+				// the client is reconnected once all futures that are borrowing it are dropped
+				if let Some(reconnect_error) = race_loop.reconnect_error.take() {
+					if race_loop.exit_requested {
+						return Err(reconnect_error);
+					}
 
-			if let Some((at_block, nonces_range, proof)) = race_state.nonces_to_submit.as_ref() {
-				log::debug!(
-					target: "bridge",
-					"Going to submit proof of messages in range {:?} to {} node",
-					nonces_range,
-					P::target_name(),
-				);
-				target_submit_proof.set(
-					race_target
-						.submit_proof(at_block.clone(), nonces_range.clone(), proof.clone())
-						.fuse(),
-				);
-			}
-			if target_nonces_required {
-				log::debug!(target: "bridge", "Asking {} about message nonces", P::target_name());
-				let at_block = race_state
-					.target_state
-					.as_ref()
-					.expect("target_nonces_required is only true when target_state is Some; qed")
-					.best_self
-					.clone();
-				target_nonces.set(race_target.nonces(at_block).fuse());
-			} else {
-				target_client_is_online = true;
-			}
-		}
-	}
-}
+					break reconnect_error;
+				}
 
-impl<SourceHeaderId, TargetHeaderId, Proof> Default for RaceState<SourceHeaderId, TargetHeaderId, Proof> {
-	fn default() -> Self {
-		RaceState {
-			source_state: None,
-			target_state: None,
-			nonces_to_submit: None,
-			nonces_submitted: None,
-		}
-	}
-}
+				// we never drop in-flight proof submission, so we're only exiting when there's no such
+				if race_loop.exit_requested {
+					if target_submit_proof.is_terminated() {
+						return Ok(());
+					}
 
-/// Print race progress.
-fn print_race_progress<P, S>(prev_time: Instant, strategy: &S) -> Instant
-where
-	P: MessageRace,
-	S: RaceStrategy<P::SourceHeaderId, P::TargetHeaderId, P::Proof>,
-{
-	let now_time = Instant::now();
+					continue;
+				}
 
-	let need_update = now_time.saturating_duration_since(prev_time) > Duration::from_secs(10);
-	if !need_update {
-		return prev_time;
-	}
+				race_loop.apply_delivery_conditions();
+				if race_loop.prepare_resubmission(is_submitted_tx_lost) {
+					target_tx_trackers = FuturesUnordered::new();
+					if race_loop.cancel_proof_generation(
+						!source_generate_proof.is_terminated(),
+						!target_estimate_delivery_cost.is_terminated(),
+					) {
+						source_generate_proof.set(futures::future::Fuse::terminated());
+						target_estimate_delivery_cost.set(futures::future::Fuse::terminated());
+					}
+				}
+				race_loop.report_progress();
+				if race_loop.is_race_completed(target_submit_proof.is_terminated()) {
+					return Ok(());
+				}
+				race_loop.check_stall()?;
+				race_loop.reselect_outdated_nonces(target_submit_proof.is_terminated());
+				race_loop.prove_refreshed_competing_nonces();
 
-	let now_best_nonce_at_source = strategy.best_at_source();
-	let now_best_nonce_at_target = strategy.best_at_target();
-	log::info!(
-		target: "bridge",
-		"Synced {:?} of {:?} nonces in {} -> {} race",
-		now_best_nonce_at_target,
-		now_best_nonce_at_source,
-		P::source_name(),
-		P::target_name(),
+				race_loop.queue_ready_proof(target_submit_proof.is_terminated());
+				if let Some((at_block, prev_latest_nonce)) = race_loop.next_source_request()? {
+					source_nonces
+						.set(with_timeout(&timer, rpc_timeout, race_source.nonces(at_block, prev_latest_nonce)).fuse());
+				}
+
+				if let Some((at_block, nonces_range, proof_parameters)) = race_loop.next_proof_to_generate() {
+					let nonces_span = race_loop.nonces_spans.span(&nonces_range);
+					let prefix_proof_parameters = race_loop
+						.strategy
+						.prefix_proof_parameters(&nonces_range, proof_parameters.clone());
+					source_generate_proof.set(
+						generate_proof_within_size_limit::<P, _>(
+							&race_source,
+							&lane_name,
+							proof_size_limit,
+							at_block,
+							nonces_range,
+							proof_parameters,
+							prefix_proof_parameters,
+						)
+						.instrument(nonces_span)
+						.fuse(),
+					);
+				}
+
+				match race_loop.next_target_request(target_submit_proof.is_terminated()) {
+					Some(TargetRequest::RuntimeVersions) => {
+						read_runtime_versions
+							.set(read_runtime_versions_of::<P, _, _>(&race_source, &race_target, &lane_name).fuse());
+					}
+					Some(TargetRequest::VerifyProof(at_block, nonces_range, proof)) => {
+						let race_target = &race_target;
+						let nonces_span = race_loop.nonces_spans.span(&nonces_range);
+						target_verify_proof.set(
+							async move {
+								race_target
+									.verify_proof(at_block.clone(), nonces_range.clone(), &proof)
+									.await
+									.map(|_| (at_block, nonces_range))
+							}
+							.instrument(nonces_span)
+							.fuse(),
+						);
+					}
+					Some(TargetRequest::DryRunProof(at_block, nonces_range, proof)) => {
+						let race_target = &race_target;
+						let nonces_span = race_loop.nonces_spans.span(&nonces_range);
+						target_dry_run_proof.set(
+							async move {
+								race_target
+									.dry_run_proof(at_block.clone(), nonces_range.clone(), &proof)
+									.await
+									.map(|outcome| (at_block, nonces_range, outcome))
+							}
+							.instrument(nonces_span)
+							.fuse(),
+						);
+					}
+					Some(TargetRequest::SubmitProof(at_block, nonces_range, proof, submit_options)) => {
+						let nonces_span = race_loop.nonces_spans.span(&nonces_range);
+						target_submit_proof.set(
+							race_target
+								.submit_proof(at_block, nonces_range, proof, submit_options)
+								.instrument(nonces_span)
+								.fuse(),
+						);
+					}
+					Some(TargetRequest::SubmitProofs(batch, submit_options)) => {
+						let nonces_span = race_loop.nonces_spans.span(&batch[batch.len() - 1].1);
+						target_submit_proof.set(
+							race_target
+								.submit_proofs(batch, submit_options)
+								.instrument(nonces_span)
+								.fuse(),
+						);
+					}
+					Some(TargetRequest::EstimateDeliveryCost(nonces_range, total_dispatch_weight, total_size)) => {
+						target_estimate_delivery_cost.set(
+							with_timeout(
+								&timer,
+								rpc_timeout,
+								race_target.estimate_delivery_cost(nonces_range, total_dispatch_weight, total_size),
+							)
+							.fuse(),
+						);
+					}
+					Some(TargetRequest::Nonces(at_best, at_finalized)) => {
+						target_nonces
+							.set(with_timeout(&timer, rpc_timeout, race_target.nonces(at_best, at_finalized)).fuse());
+					}
+					Some(TargetRequest::RequiredSourceHeader(current_best)) => {
+						let race_target = &race_target;
+						target_read_required_source_header.set(
+							async move {
+								race_target
+									.required_source_header_at_target(current_best.clone())
+									.await
+									.map(|required_source_header| (current_best, required_source_header))
+							}
+							.fuse(),
+						);
+					}
+					None => (),
+				}
+			}
+		};
+
+		let is_reconnected = reconnect_failed_client(
+			&mut race_source,
+			&mut race_target,
+			&race_loop.params,
+			&timer,
+			&mut exit_signal,
+			reconnect_error,
+		)
+		.await?;
+		if !is_reconnected {
+			return Ok(());
+		}
+
+		race_loop.on_reconnected();
+	}
+}
+
+/// Reconnect the race client that has failed with connection error.
+///
+/// The client is reconnected after backoff, at most `RaceParams::max_reconnect_attempts` times.
+/// Fatal errors (e.g. if the client doesn't support reconnection) are not retried. Returns
+/// `Ok(false)` if the exit signal has been received while the race has been waiting for the
+/// reconnection.
+async fn reconnect_failed_client<P, SC, TC>(
+	race_source: &mut SC,
+	race_target: &mut TC,
+	params: &RaceParams,
+	timer: &impl Timer,
+	mut exit_signal: impl FusedFuture<Output = ()> + Unpin,
+	reconnect_error: RaceError,
+) -> Result<bool, RaceError>
+where
+	P: MessageRace,
+	SC: SourceClient<P>,
+	TC: TargetClient<P>,
+{
+	let mut reconnect_backoff = match reconnect_error.failed_client {
+		FailedClient::Source => params.source_retry_backoff.backoff(),
+		FailedClient::Target | FailedClient::Both => params.target_retry_backoff.backoff(),
+	};
+	let mut reconnect_attempt = 0;
+	// when both clients have failed, we only reconnect the target client after the source client
+	// has been reconnected
+	let mut failed_client = reconnect_error.failed_client;
+	loop {
+		reconnect_attempt += 1;
+		let reconnect_delay = reconnect_backoff.next_backoff().unwrap_or(CONNECTION_ERROR_DELAY);
+		log::warn!(
+			target: "bridge",
+			"[lane {}] {}. Reconnecting in {}s (attempt {} of {})",
+			params.lane_name,
+			reconnect_error,
+			reconnect_delay.as_secs_f64(),
+			reconnect_attempt,
+			params.max_reconnect_attempts,
+		);
+
+		futures::select! {
+			_ = timer.sleep(reconnect_delay).fuse() => (),
+			() = exit_signal => return Ok(false),
+		}
+
+		let reconnect_result = match failed_client {
+			FailedClient::Source => race_source
+				.reconnect()
+				.await
+				.map_err(|error| (error.is_fatal(), format!("{:?}", error))),
+			FailedClient::Target => race_target
+				.reconnect()
+				.await
+				.map_err(|error| (error.is_fatal(), format!("{:?}", error))),
+			FailedClient::Both => match race_source.reconnect().await {
+				Ok(()) => {
+					failed_client = FailedClient::Target;
+					race_target
+						.reconnect()
+						.await
+						.map_err(|error| (error.is_fatal(), format!("{:?}", error)))
+				}
+				Err(error) => Err((error.is_fatal(), format!("{:?}", error))),
+			},
+		};
+		match reconnect_result {
+			Ok(()) => break,
+			Err((is_fatal, error)) => {
+				log::warn!(
+					target: "bridge",
+					"[lane {}] Failed to reconnect {:?} client: {}",
+					params.lane_name,
+					failed_client,
+					error,
+				);
+				if is_fatal || reconnect_attempt >= params.max_reconnect_attempts {
+					return Err(reconnect_error);
+				}
+			}
+		}
+	}
+
+	log::info!(
+		target: "bridge",
+		"[lane {}] {:?} client has been reconnected",
+		params.lane_name,
+		reconnect_error.failed_client,
 	);
-	now_time
+
+	Ok(true)
+}
+
+/// Request to the race target client.
+enum TargetRequest<SourceHeaderId, TargetHeaderId, Proof> {
+	/// Read runtime versions of both race nodes.
+	RuntimeVersions,
+	/// Verify proof of nonces, generated at given source header.
+	VerifyProof(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>),
+	/// Dry run submission of the proof of nonces, generated at given source header.
+	DryRunProof(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>),
+	/// Submit proof of nonces, generated at given source header.
+	SubmitProof(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>, SubmitOptions),
+	/// Submit multiple proofs of consecutive nonces in a single transaction.
+	#[allow(clippy::type_complexity)]
+	SubmitProofs(
+		Vec<(SourceHeaderId, RangeInclusive<MessageNonce>, Arc<Proof>)>,
+		SubmitOptions,
+	),
+	/// Estimate cost of delivering nonces with given total dispatch weight and size.
+	EstimateDeliveryCost(RangeInclusive<MessageNonce>, Weight, u32),
+	/// Read nonces at the best and at the best finalized target headers.
+	Nonces(TargetHeaderId, TargetHeaderId),
+	/// Ask which source header is required, given the best source header known to the target.
+	RequiredSourceHeader(SourceHeaderId),
+}
+
+/// State of the race loop. It is preserved when race clients are reconnected.
+///
+/// The race loop processes responses of race clients and decides which requests should be issued
+/// next. Requests themselves are issued by the `run_race_loop`.
+struct RaceLoop<P: MessageRace, SC: SourceClient<P>, TC: TargetClient<P>, S, OC, OR, O, T> {
+	/// Race parameters.
+	params: RaceParams,
+	/// Race strategy.
+	strategy: S,
+	/// Receives checkpoint of the strategy state.
+	on_checkpoint: OC,
+	/// Receives source header that is required by the target node.
+	on_required_source_header: OR,
+	/// Observer of the race events.
+	observer: O,
+	/// Race metrics.
+	metrics: Option<MessageRaceMetrics>,
+	/// Race timer.
+	timer: T,
+	/// Summary of the race.
+	summary: RaceSummary,
+	/// State of the race.
+	race_state: RaceState<P::SourceHeaderId, P::TargetHeaderId, P::Proof>,
+	/// Requests to the source client.
+	source: ClientRequests,
+	/// Requests to the target client.
+	target: ClientRequests,
+	/// Stall detection.
+	stall: StallTracker,
+	/// Race progress reporting.
+	progress: ProgressTracker,
+	/// Preparation and generation of proofs.
+	proofs: ProofGeneration<P::SourceHeaderId, P::TargetHeaderId, SC::ProofParameters, TC::Cost>,
+	/// Submission of proofs.
+	submission: Submission<P::SourceHeaderId>,
+	/// Relayer balance at the target node.
+	relayer_balance: RelayerBalance<P::TargetHeaderId>,
+	/// Tracing spans of nonces that are being delivered.
+	nonces_spans: NoncesSpans,
+	/// Connection error of the client that needs to be reconnected.
+	reconnect_error: Option<RaceError>,
+	/// Spec versions of the source and target nodes runtimes. They're read when the race starts
+	/// and after every submission failure that isn't a connection error.
+	runtime_versions: (Option<u32>, Option<u32>),
+	/// True if runtime versions need to be read.
+	is_runtime_versions_read_required: bool,
+	/// Nonces at the target node, simulated in dry-run mode.
+	dry_run_target_nonces: Option<TargetClientNonces<TC::TargetNoncesData>>,
+	/// Nonces are not selected until target nonces are read after the dry run has reported that
+	/// selected nonces are already delivered.
+	is_target_nonces_outdated: bool,
+	/// Nonces are only selected when something that affects selection has changed.
+	is_selection_required: bool,
+	/// The target client is asked which source header it needs once per best source header known
+	/// to the target node. This is the best source header, known to the target node, when the
+	/// target client needs to be asked.
+	required_source_header_request: Option<P::SourceHeaderId>,
+	/// Best source header, known to the target node, when the target client has been asked.
+	required_source_header_asked_at: Option<P::SourceHeaderId>,
+	/// Answer of the target client: best source header known to the target node and the source
+	/// header that it needs. If it needs some header, its answer takes precedence over the
+	/// strategy answer.
+	target_required_source_header: Option<(P::SourceHeaderId, Option<P::SourceHeaderId>)>,
+	/// Required source header that has been reported to the callback.
+	prev_required_source_header: Option<P::SourceHeaderId>,
+	/// Snapshot of the race state that has been sent to the state notifier.
+	prev_state_snapshot: Option<RaceStateSnapshot>,
+	/// Best nonces that have been exposed using metrics.
+	prev_best_nonces: Option<(MessageNonce, MessageNonce)>,
+	/// True if the best source header known to the target node lags too far behind.
+	is_source_header_lagging: bool,
+	/// After source reorganization, this is the new best source header, until the target node
+	/// sees it.
+	source_reorged_to: Option<P::SourceHeaderId>,
+	/// True if the race is paused by the control command.
+	is_paused: bool,
+	/// True if the delivery is deferred by the external condition.
+	is_delivery_deferred: bool,
+	/// True if the exit signal has been received.
+	exit_requested: bool,
 }
 
-fn select_nonces_to_deliver<SourceHeaderId, TargetHeaderId, Proof, Strategy>(
-	race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
-	strategy: &mut Strategy,
-) -> Option<(SourceHeaderId, RangeInclusive<MessageNonce>, Strategy::ProofParameters)>
-where
-	SourceHeaderId: Clone,
-	Strategy: RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
-{
-	race_state.target_state.as_ref().and_then(|target_state| {
-		strategy
-			.select_nonces_to_deliver(&race_state)
-			.map(|(nonces_range, proof_parameters)| (target_state.best_peer.clone(), nonces_range, proof_parameters))
-	})
-}
+impl<P, SC, TC, S, OC, OR, O, T> RaceLoop<P, SC, TC, S, OC, OR, O, T>
+where
+	P: MessageRace,
+	SC: SourceClient<P>,
+	TC: TargetClient<P>,
+	S: RaceStrategy<
+		P::SourceHeaderId,
+		P::TargetHeaderId,
+		P::Proof,
+		SourceNoncesRange = SC::NoncesRange,
+		ProofParameters = SC::ProofParameters,
+		Cost = TC::Cost,
+		TargetNoncesData = TC::TargetNoncesData,
+	>,
+	OC: FnMut(S::Checkpoint),
+	OR: FnMut(P::SourceHeaderId),
+	O: RaceObserver<P>,
+	T: Timer,
+{
+	/// Create state of the race that has just started.
+	fn new(
+		params: RaceParams,
+		strategy: S,
+		on_checkpoint: OC,
+		on_required_source_header: OR,
+		observer: O,
+		metrics: Option<MessageRaceMetrics>,
+		timer: T,
+	) -> Self {
+		let now = timer.now();
+		RaceLoop {
+			source: ClientRequests::new(FailedClient::Source, &params.source_retry_backoff, now),
+			target: ClientRequests::new(FailedClient::Target, &params.target_retry_backoff, now),
+			stall: StallTracker::new(now),
+			progress: ProgressTracker::new(now),
+			proofs: ProofGeneration::new(now),
+			submission: Submission::new(now),
+			relayer_balance: RelayerBalance::new(&params),
+			params,
+			strategy,
+			on_checkpoint,
+			on_required_source_header,
+			observer,
+			metrics,
+			timer,
+			summary: RaceSummary::default(),
+			race_state: RaceState::default(),
+			nonces_spans: NoncesSpans::new::<P>(),
+			reconnect_error: None,
+			runtime_versions: (None, None),
+			is_runtime_versions_read_required: true,
+			dry_run_target_nonces: None,
+			is_target_nonces_outdated: false,
+			is_selection_required: true,
+			required_source_header_request: None,
+			required_source_header_asked_at: None,
+			target_required_source_header: None,
+			prev_required_source_header: None,
+			prev_state_snapshot: None,
+			prev_best_nonces: None,
+			is_source_header_lagging: false,
+			source_reorged_to: None,
+			is_paused: false,
+			is_delivery_deferred: false,
+			exit_requested: false,
+		}
+	}
+
+	/// Process updated states of the source client. Returns true if the source chain has been
+	/// reorganized. Then the proof generation needs to be cancelled.
+	fn on_source_states(&mut self, source_states: impl Iterator<Item = SourceClientState<P>>) -> bool {
+		let mut is_source_reorged = false;
+		for source_state in source_states {
+			if self.race_state.source_state.as_ref() == Some(&source_state) {
+				continue;
+			}
+
+			let is_reorged = self
+				.race_state
+				.source_state
+				.as_ref()
+				.map(|prev_source_state| prev_source_state.best_self.is_orphaned_by(&source_state.best_self))
+				.unwrap_or(false);
+			if is_reorged {
+				log::warn!(
+					target: "bridge",
+					"[lane {}] {} has been reorganized. New best header: {:?}",
+					self.params.lane_name,
+					P::source_name(),
+					source_state.best_self,
+				);
+
+				// forget about nonces and proofs that are seen at orphaned headers
+				self.strategy.source_reorged(&source_state.best_self);
+				self.is_selection_required = true;
+				let is_proof_orphaned = self
+					.race_state
+					.nonces_to_submit
+					.as_ref()
+					.map(|(at_block, _, _)| at_block.is_orphaned_by(&source_state.best_self))
+					.unwrap_or(false);
+				if is_proof_orphaned {
+					self.race_state.nonces_to_submit = None;
+				}
+				// queued proofs are submitted together with the next proof, so the whole batch is
+				// regenerated if any of them is orphaned
+				let is_queued_proof_orphaned = self
+					.race_state
+					.proofs_to_submit
+					.iter()
+					.any(|(at_block, _, _)| at_block.is_orphaned_by(&source_state.best_self));
+				if is_queued_proof_orphaned {
+					self.race_state.proofs_to_submit.clear();
+					self.race_state.nonces_to_submit = None;
+				}
+
+				self.source_reorged_to = Some(source_state.best_self.clone());
+				is_source_reorged = true;
+			}
+
+			self.source.nonces_required = true;
+			self.race_state.source_state = Some(source_state);
+		}
+		is_source_reorged
+	}
+
+	/// Process updated states of the target client.
+	fn on_target_states(&mut self, target_states: impl Iterator<Item = TargetClientState<P>>) {
+		for target_state in target_states {
+			if self.race_state.target_state.as_ref() == Some(&target_state) {
+				continue;
+			}
+
+			// nonces that are read at the best source header known to the target node are outdated
+			// once the target node sees the new source header
+			let is_best_peer_changed = self
+				.race_state
+				.target_state
+				.as_ref()
+				.map(|prev_target_state| prev_target_state.best_peer != target_state.best_peer)
+				.unwrap_or(true);
+			if self.params.nonces_at_target_known_header
+				&& is_best_peer_changed
+				&& self.race_state.source_state.is_some()
+			{
+				self.source.nonces_required = true;
+			}
+
+			self.target.nonces_required = true;
+			self.is_selection_required = true;
+			self.race_state.target_state = Some(target_state);
+		}
+	}
+
+	/// Returns error if the stream of client states has terminated. We'll never receive new
+	/// states, so the race can't make any progress.
+	fn ensure_state_stream_alive(&self, is_terminated: bool, failed_client: FailedClient) -> Result<(), RaceError> {
+		if !is_terminated || self.exit_requested {
+			return Ok(());
+		}
+
+		let client_name = match failed_client {
+			FailedClient::Source => P::source_name(),
+			FailedClient::Target | FailedClient::Both => P::target_name(),
+		};
+		Err(RaceError {
+			failed_client,
+			reason: RaceErrorReason::Connection(format!("{} state stream has terminated", client_name)),
+			summary: RaceSummary::default(),
+		})
+	}
+
+	/// Cancel preparation and generation of the proof. Returns true if anything has been
+	/// cancelled. Then in-flight proof generation and delivery cost estimation requests must be
+	/// dropped.
+	fn cancel_proof_generation(&mut self, is_generating_proof: bool, is_estimating_cost: bool) -> bool {
+		if !is_generating_proof && !self.proofs.is_preparing() {
+			return false;
+		}
+
+		if is_estimating_cost {
+			self.target.is_online = true;
+		}
+		self.proofs.cancel_preparation();
+		self.source.is_online = true;
+		true
+	}
+
+	/// Process nonces, received from the source client.
+	#[allow(clippy::type_complexity)]
+	fn on_source_nonces(
+		&mut self,
+		nonces: Result<(P::SourceHeaderId, SourceClientNonces<SC::NoncesRange>), TimeoutError<SC::Error>>,
+	) -> Result<(), RaceError> {
+		self.source.nonces_required = false;
+		self.is_selection_required = true;
+
+		let nonces = self.source.process_result(
+			nonces,
+			|| format!("Error retrieving nonces from {}", P::source_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some((at_block, nonces)) = nonces {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Received nonces from {}: {:?}",
+				self.params.lane_name,
+				P::source_name(),
+				nonces,
+			);
+
+			self.strategy.source_nonces_updated(at_block, nonces);
+			self.stall.on_source_progress(self.timer.now());
+		}
+
+		Ok(())
+	}
+
+	/// Process nonces, received from the target client. Returns true if all nonces that we're
+	/// proving have been delivered by other relayer. Then there's no need to wait for the proof
+	/// and the proof generation request must be dropped. Partially delivered nonces are trimmed
+	/// when the proof is generated.
+	#[allow(clippy::type_complexity)]
+	fn on_target_nonces(
+		&mut self,
+		nonces: Result<(P::TargetHeaderId, TargetNonces<TC::TargetNoncesData>), TimeoutError<TC::Error>>,
+		is_generating_proof: bool,
+	) -> Result<bool, RaceError> {
+		self.target.nonces_required = false;
+		self.is_selection_required = true;
+
+		let nonces = self.target.process_result(
+			nonces,
+			|| format!("Error retrieving nonces from {}", P::target_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some((_, nonces)) = nonces {
+			self.on_target_nonces_received(nonces);
+		}
+
+		let is_generated_proof_obsolete = is_generating_proof
+			&& self
+				.proofs
+				.nonces
+				.as_ref()
+				.map(|nonces_range| *nonces_range.end() <= self.strategy.best_at_target())
+				.unwrap_or(false);
+		if is_generated_proof_obsolete {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Nonces {:?} have been delivered to {} by other relayer. Cancelling proof generation",
+				self.params.lane_name,
+				self.proofs.nonces,
+				P::target_name(),
+			);
+			if let Some(proof_span) = self.proofs.span.take() {
+				tracing::debug!(parent: &proof_span, "Proof generation has been cancelled");
+			}
+
+			self.proofs.nonces = None;
+			self.source.is_online = true;
+		}
+
+		Ok(is_generated_proof_obsolete)
+	}
+
+	/// Update the strategy with nonces, received from the target client.
+	fn on_target_nonces_received(&mut self, nonces: TargetNonces<TC::TargetNoncesData>) {
+		let mut nonces = if self.strategy.requires_target_finality() {
+			nonces.at_finalized
+		} else {
+			nonces.at_best
+		};
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Received nonces from {}: {:?}",
+			self.params.lane_name,
+			P::target_name(),
+			nonces,
+		);
+
+		// in dry-run mode, target never receives our proofs, so we are keeping the simulated nonce
+		if self.params.dry_run {
+			if let Some(ref dry_run_target_nonces) = self.dry_run_target_nonces {
+				nonces.latest_nonce = std::cmp::max(nonces.latest_nonce, dry_run_target_nonces.latest_nonce);
+			}
+			self.dry_run_target_nonces = Some(nonces.clone());
+		}
+
+		let confirmed_nonces = self
+			.race_state
+			.nonces_submitted
+			.iter()
+			.take_while(|nonces_submitted| *nonces_submitted.end() <= nonces.latest_nonce)
+			.cloned()
+			.collect::<Vec<_>>();
+		let prev_best_at_target = self.strategy.best_at_target();
+		self.is_target_nonces_outdated = false;
+		self.strategy.target_nonces_updated(nonces, &mut self.race_state);
+		let best_at_target = self.strategy.best_at_target();
+		self.nonces_spans.close_delivered(best_at_target);
+		self.submission.on_delivered(best_at_target);
+		for nonces in &confirmed_nonces {
+			self.summary.delivered_nonces += nonces.end() - nonces.start() + 1;
+			self.observer.on_range_confirmed(nonces);
+		}
+		if let Some(competing_nonces) = self.proofs.competing_nonces.as_mut() {
+			if competing_nonces.stage == CompetingNoncesStage::RefreshRequested {
+				competing_nonces.stage = CompetingNoncesStage::Refreshed;
+			}
+		}
+
+		// the race is making progress if target has received new nonces. The oldest submission
+		// that is still not confirmed is now the next one
+		if best_at_target > prev_best_at_target {
+			let now = self.timer.now();
+			self.stall.on_target_progress(now);
+			self.submission.restart_resubmit_timeout(now);
+		}
+		if best_at_target != prev_best_at_target {
+			(self.on_checkpoint)(self.strategy.checkpoint());
+		}
+	}
+
+	/// Process proof, generated by the source client.
+	#[allow(clippy::type_complexity)]
+	fn on_proof_generated(
+		&mut self,
+		race_source: &SC,
+		proof: Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>, P::Proof), SC::Error>,
+	) -> Result<(), RaceError> {
+		self.is_selection_required = true;
+		let proof_span = self.proofs.span.take();
+		let is_proof_generation_failed = proof.is_err();
+		let is_range_failed = match proof {
+			Ok(_) => false,
+			Err(ref error) => error.classification() != ErrorClassification::Reconnect,
+		};
+		if let Some(metrics) = self.metrics.as_ref() {
+			metrics.update_proof_generation::<P, _, _>(
+				&self.params.lane_name,
+				self.timer.now().saturating_duration_since(self.proofs.started_at),
+				&proof,
+			);
+		}
+
+		let proof = self.source.process_result(
+			proof,
+			|| format!("Error generating proof at {}", P::source_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some((at_block, nonces_range, proof)) = proof {
+			self.on_proof_received(race_source, at_block, nonces_range, proof);
+		}
+
+		if let (true, Some(proof_span)) = (is_proof_generation_failed, proof_span) {
+			tracing::debug!(parent: &proof_span, "Proof generation has failed. Going to retry");
+		}
+		if is_range_failed {
+			if let Some(nonces_range) = self.proofs.nonces.take() {
+				report_range_failure(
+					&mut self.strategy,
+					nonces_range,
+					RangeFailureKind::ProofGeneration,
+					FailedClient::Source,
+				)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Remember proof, generated by the source client, so that it is submitted to the target
+	/// client. In dry-run mode, the proof is never submitted - the race acts as if it has been
+	/// delivered instead.
+	fn on_proof_received(
+		&mut self,
+		race_source: &SC,
+		at_block: P::SourceHeaderId,
+		mut nonces_range: RangeInclusive<MessageNonce>,
+		mut proof: P::Proof,
+	) {
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Received proof for nonces in range {:?} from {}",
+			self.params.lane_name,
+			nonces_range,
+			P::source_name(),
+		);
+
+		self.stall.on_source_progress(self.timer.now());
+		self.summary.generated_proofs += 1;
+		self.observer.on_proof_generated(&at_block, &nonces_range);
+		let nonces_span = self.nonces_spans.span(&nonces_range);
+		tracing::debug!(parent: &nonces_span, at_block = ?at_block, "Proof has been generated");
+
+		// other relayer may have delivered (some of) our nonces while the proof has been generated
+		let best_at_target = self.strategy.best_at_target();
+		if best_at_target >= *nonces_range.start() {
+			let undelivered_nonces = best_at_target + 1..=*nonces_range.end();
+			let trimmed_proof = if undelivered_nonces.is_empty() {
+				None
+			} else {
+				race_source.trim_proof(&proof, undelivered_nonces.clone())
+			};
+			match trimmed_proof {
+				Some(trimmed_proof) => {
+					log::debug!(
+						target: "bridge",
+						"[lane {}] Nonces up to {} have been delivered to {} by other relayer. \
+						Trimmed proof to nonces in range {:?}",
+						self.params.lane_name,
+						best_at_target,
+						P::target_name(),
+						undelivered_nonces,
+					);
+					tracing::debug!(
+						parent: &nonces_span,
+						range = ?undelivered_nonces,
+						"Proof has been trimmed"
+					);
+					nonces_range = undelivered_nonces;
+					proof = trimmed_proof;
+				}
+				None => {
+					log::debug!(
+						target: "bridge",
+						"[lane {}] Nonces up to {} have been delivered to {} by other relayer. \
+						Discarding proof of nonces in range {:?}",
+						self.params.lane_name,
+						best_at_target,
+						P::target_name(),
+						nonces_range,
+					);
+					tracing::debug!(parent: &nonces_span, "Proof has been discarded");
+					self.proofs.discarded_proofs += 1;
+					return;
+				}
+			}
+		}
+
+		if !self.params.dry_run {
+			self.race_state.nonces_to_submit = Some((at_block, nonces_range, Arc::new(proof)));
+			self.submission.proof_runtime_versions = self.runtime_versions;
+			return;
+		}
+
+		log::info!(
+			target: "bridge",
+			"[lane {}] Dry run: not submitting proof of nonces {:?} ({} bytes) to {}",
+			self.params.lane_name,
+			nonces_range,
+			P::proof_size(&proof),
+			P::target_name(),
+		);
+
+		// act as if the proof has been delivered
+		let simulated_target_nonces = TargetClientNonces {
+			latest_nonce: *nonces_range.end(),
+			nonces_data: self
+				.dry_run_target_nonces
+				.as_ref()
+				.map(|nonces| nonces.nonces_data.clone())
+				.unwrap_or_default(),
+		};
+		let prev_best_at_target = self.strategy.best_at_target();
+		self.strategy
+			.target_nonces_updated(simulated_target_nonces.clone(), &mut self.race_state);
+		self.nonces_spans.close_delivered(self.strategy.best_at_target());
+		if self.strategy.best_at_target() > prev_best_at_target {
+			self.stall.on_target_progress(self.timer.now());
+		}
+		self.dry_run_target_nonces = Some(simulated_target_nonces);
+	}
+
+	/// Process timeout of the proof generation. The source client is treated as failed and
+	/// nonces are selected again.
+	fn on_proof_generation_timeout(&mut self) -> Result<(), RaceError> {
+		self.is_selection_required = true;
+		if let Some(proof_span) = self.proofs.span.take() {
+			tracing::debug!(parent: &proof_span, "Proof generation has timed out. Going to retry");
+		}
+
+		let error = format!("Proof generation at {} has timed out", P::source_name());
+		self.source
+			.retry_after_error(error, &self.params.lane_name, &self.timer);
+
+		if let Some(nonces_range) = self.proofs.nonces.take() {
+			report_range_failure(
+				&mut self.strategy,
+				nonces_range,
+				RangeFailureKind::ProofGeneration,
+				FailedClient::Source,
+			)?;
+		}
+
+		Ok(())
+	}
+
+	/// Process result of the proof verification by the target client.
+	fn on_proof_verified(
+		&mut self,
+		verify_result: Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>), TC::Error>,
+	) -> Result<(), RaceError> {
+		self.is_selection_required = true;
+		let is_proof_invalid = match verify_result {
+			Ok(_) => false,
+			Err(ref error) => error.classification() != ErrorClassification::Reconnect,
+		};
+
+		let verified_proof = self.target.process_result(
+			verify_result,
+			|| format!("Error verifying proof at {}", P::target_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some(verified_proof) = verified_proof {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Proof of nonces {:?} has been verified by {}",
+				self.params.lane_name,
+				verified_proof.1,
+				P::target_name(),
+			);
+
+			self.submission.verified_proof = Some(verified_proof);
+		}
+
+		// we're selecting nonces again and generating new proof (at the newer block, if there's
+		// any), once we know the latest state of the target node
+		if is_proof_invalid {
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Proof of nonces {:?} has failed verification at {}. Going to regenerate proof",
+				self.params.lane_name,
+				self.race_state.nonces_to_submit.as_ref().map(|(_, nonces_range, _)| nonces_range),
+				P::target_name(),
+			);
+
+			if let Some((_, nonces_range, _)) = self.race_state.nonces_to_submit.as_ref() {
+				tracing::debug!(
+					parent: &self.nonces_spans.span(nonces_range),
+					"Proof has failed verification. Going to regenerate",
+				);
+			}
+			self.race_state.nonces_to_submit = None;
+			self.target.nonces_required = true;
+		}
+
+		Ok(())
+	}
+
+	/// Process result of the proof submission dry run by the target client.
+	fn on_proof_dry_run(
+		&mut self,
+		dry_run_result: Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>, DryRunOutcome), TC::Error>,
+	) -> Result<(), RaceError> {
+		self.is_selection_required = true;
+
+		let dry_run_result = self.target.process_result(
+			dry_run_result,
+			|| format!("Error dry running proof at {}", P::target_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		let dry_run_outcome = dry_run_result.map(|(at_block, nonces_range, outcome)| {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Dry run of proof of nonces {:?} at {}: {:?}",
+				self.params.lane_name,
+				nonces_range,
+				P::target_name(),
+				outcome,
+			);
+
+			if outcome == DryRunOutcome::Success {
+				self.submission.dry_run_passed_proof = Some((at_block, nonces_range));
+			}
+			outcome
+		});
+
+		// in both cases we're selecting nonces again, once we know the latest state of the target
+		// node. Already delivered nonces are never selected again
+		let dry_run_failure = match dry_run_outcome {
+			Some(DryRunOutcome::AlreadyDelivered) => {
+				self.is_target_nonces_outdated = true;
+				Some("Nonces are already delivered. Skipping submission")
+			}
+			Some(DryRunOutcome::Failure) => Some("Dry run has failed. Going to regenerate proof"),
+			Some(DryRunOutcome::Success) | None => None,
+		};
+		if let Some(dry_run_failure) = dry_run_failure {
+			if let Some((_, nonces_range, _)) = self.race_state.nonces_to_submit.take() {
+				log::warn!(
+					target: "bridge",
+					"[lane {}] Proof of nonces {:?} would fail at {}. {}",
+					self.params.lane_name,
+					nonces_range,
+					P::target_name(),
+					dry_run_failure,
+				);
+				tracing::debug!(parent: &self.nonces_spans.span(&nonces_range), "{}", dry_run_failure);
+			}
+			self.target.nonces_required = true;
+		}
+
+		Ok(())
+	}
+
+	/// Process source header that is required by the target node, reported by the target client.
+	#[allow(clippy::type_complexity)]
+	fn on_required_source_header_read(
+		&mut self,
+		required_source_header: Result<(P::SourceHeaderId, Option<P::SourceHeaderId>), TC::Error>,
+	) -> Result<(), RaceError> {
+		let required_source_header = self.target.process_result(
+			required_source_header,
+			|| {
+				format!(
+					"Error reading required {} header from {}",
+					P::source_name(),
+					P::target_name()
+				)
+			},
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some((current_best, required_source_header)) = required_source_header {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] {} has reported required {} header at {:?}: {:?}",
+				self.params.lane_name,
+				P::target_name(),
+				P::source_name(),
+				current_best,
+				required_source_header,
+			);
+
+			self.target_required_source_header = Some((current_best, required_source_header));
+		}
+
+		Ok(())
+	}
+
+	/// Process result of the proof submission. Returns submitted nonces and tracker of the
+	/// submitted transaction, if the proof has been submitted.
+	#[allow(clippy::type_complexity)]
+	fn on_proof_submitted(
+		&mut self,
+		submit_result: Result<(RangeInclusive<MessageNonce>, TC::TransactionTracker), TC::Error>,
+	) -> Result<Option<(RangeInclusive<MessageNonce>, TC::TransactionTracker)>, RaceError> {
+		self.is_selection_required = true;
+		let is_proof_rejected = match submit_result {
+			Ok(_) => false,
+			Err(ref error) => TC::Error::is_rejected_transaction(error),
+		};
+		// the submission may have failed because the runtime has been upgraded
+		if let Err(ref error) = submit_result {
+			if error.classification() != ErrorClassification::Reconnect {
+				self.is_runtime_versions_read_required = true;
+			}
+		}
+		if let Some(metrics) = self.metrics.as_ref() {
+			metrics.update_proof_submission::<P, _, _>(
+				&self.params.lane_name,
+				self.timer.now().saturating_duration_since(self.submission.started_at),
+				&submit_result,
+			);
+		}
+
+		let submitted = self.target.process_result(
+			submit_result,
+			|| format!("Error submitting proof {}", P::target_name()),
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		let submitted_nonces = self.submission.nonces.take();
+		if let Some((ref nonces_range, _)) = submitted {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Successfully submitted proof of nonces {:?} to {}",
+				self.params.lane_name,
+				nonces_range,
+				P::target_name(),
+			);
+
+			let now = self.timer.now();
+			let is_oldest_unconfirmed = self.race_state.nonces_submitted.is_empty();
+			if self.submission.on_submitted(nonces_range, is_oldest_unconfirmed, now) {
+				self.stall.on_target_progress(now);
+			}
+			self.summary.submitted_proofs += 1;
+			self.observer.on_proof_submitted(nonces_range);
+			tracing::debug!(parent: &self.nonces_spans.span(nonces_range), "Proof has been submitted");
+			if let Some(ref submitted_nonces) = submitted_nonces {
+				self.forget_proofs_to_submit(submitted_nonces);
+			}
+			self.race_state.nonces_submitted.push_back(nonces_range.clone());
+		}
+
+		// submitting the same proof again would fail too, so we're selecting nonces again and
+		// generating new proof, once we know the latest state of the target node
+		if is_proof_rejected {
+			if let Some(ref submitted_nonces) = submitted_nonces {
+				tracing::debug!(
+					parent: &self.nonces_spans.span(submitted_nonces),
+					"Proof has been rejected. Going to regenerate",
+				);
+			}
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Proof of nonces {:?} has been rejected by {}. Going to regenerate proof",
+				self.params.lane_name,
+				submitted_nonces,
+				P::target_name(),
+			);
+
+			if let Some(submitted_nonces) = submitted_nonces {
+				if self.forget_proofs_to_submit(&submitted_nonces) {
+					report_range_failure(
+						&mut self.strategy,
+						submitted_nonces,
+						RangeFailureKind::ProofSubmission,
+						FailedClient::Target,
+					)?;
+				}
+			}
+			self.target.nonces_required = true;
+		}
+
+		Ok(submitted)
+	}
+
+	/// Forget queued proofs and the proof that is waiting for submission, if their nonces have
+	/// been submitted. Returns true if any proof has been forgotten.
+	fn forget_proofs_to_submit(&mut self, submitted_nonces: &RangeInclusive<MessageNonce>) -> bool {
+		let is_submitted = |nonces_range: &RangeInclusive<MessageNonce>| nonces_range.end() <= submitted_nonces.end();
+		let queued_proofs = self.race_state.proofs_to_submit.len();
+		self.race_state
+			.proofs_to_submit
+			.retain(|(_, nonces_range, _)| !is_submitted(nonces_range));
+		let is_queued_proof_forgotten = self.race_state.proofs_to_submit.len() != queued_proofs;
+		let is_proof_forgotten = match self.race_state.nonces_to_submit {
+			Some((_, ref nonces_range, _)) if is_submitted(nonces_range) => {
+				self.race_state.nonces_to_submit = None;
+				true
+			}
+			_ => false,
+		};
+		is_queued_proof_forgotten || is_proof_forgotten
+	}
+
+	/// Process status of the submitted transaction. Returns true if the transaction has been lost
+	/// and its nonces need to be resubmitted.
+	fn on_transaction_status(
+		&mut self,
+		tracked_nonces: RangeInclusive<MessageNonce>,
+		tx_status: TrackedTransactionStatus,
+	) -> bool {
+		self.is_selection_required = true;
+		match tx_status {
+			TrackedTransactionStatus::Finalized => {
+				log::debug!(
+					target: "bridge",
+					"[lane {}] Transaction with proof of nonces {:?} has been finalized at {}",
+					self.params.lane_name,
+					tracked_nonces,
+					P::target_name(),
+				);
+				false
+			}
+			TrackedTransactionStatus::Lost if self.race_state.nonces_submitted.contains(&tracked_nonces) => {
+				log::warn!(
+					target: "bridge",
+					"[lane {}] Transaction with proof of nonces {:?} has been lost by {}. Going to resubmit",
+					self.params.lane_name,
+					tracked_nonces,
+					P::target_name(),
+				);
+
+				tracing::debug!(
+					parent: &self.nonces_spans.span(&tracked_nonces),
+					"Transaction has been lost. Going to resubmit",
+				);
+				true
+			}
+			TrackedTransactionStatus::Lost => {
+				log::debug!(
+					target: "bridge",
+					"[lane {}] Transaction with proof of nonces {:?} has been lost by {}, but they are already delivered",
+					self.params.lane_name,
+					tracked_nonces,
+					P::target_name(),
+				);
+				false
+			}
+		}
+	}
+
+	/// Process delivery cost, estimated by the target client.
+	fn on_delivery_cost_estimated(&mut self, cost: Result<TC::Cost, TimeoutError<TC::Error>>) -> Result<(), RaceError> {
+		let (at_block, nonces_range, proof_parameters) = self
+			.proofs
+			.cost_estimation
+			.take()
+			.expect("cost_estimation is set when estimation is started; qed");
+		let is_cost_estimated = cost.is_ok();
+
+		let cost = self.target.process_result(
+			cost,
+			|| {
+				format!(
+					"Error estimating cost of delivering nonces {:?} to {}",
+					nonces_range,
+					P::target_name(),
+				)
+			},
+			&self.params,
+			&self.timer,
+			&mut self.reconnect_error,
+		)?;
+		if let Some(cost) = cost {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] {} has estimated cost of delivering nonces {:?}: {:?}",
+				self.params.lane_name,
+				P::target_name(),
+				nonces_range,
+				cost,
+			);
+			if let Some(target_state) = self.race_state.target_state.as_ref() {
+				self.proofs.cost_cache = Some((target_state.best_self.clone(), nonces_range.clone(), cost.clone()));
+			}
+			self.proofs.nonces_to_prove = Some((at_block, nonces_range, proof_parameters, Some(cost)));
+		}
+
+		// nonces are selected again when the target client is back online
+		if !is_cost_estimated {
+			self.source.is_online = true;
+			self.is_selection_required = true;
+		}
+
+		Ok(())
+	}
+
+	/// Process runtime versions of both race nodes. Clients are notified about runtime upgrades
+	/// and the proof that has been generated by the old runtime is generated again, because it
+	/// may be rejected by the new runtime.
+	fn on_runtime_versions(
+		&mut self,
+		race_source: &SC,
+		race_target: &TC,
+		(source_version, target_version): (Option<u32>, Option<u32>),
+	) {
+		self.target.is_online = true;
+
+		let (known_source_version, known_target_version) = self.runtime_versions;
+		if is_runtime_upgraded(known_source_version, source_version) {
+			log::info!(
+				target: "bridge",
+				"[lane {}] {} runtime has been upgraded: spec version {:?} -> {:?}",
+				self.params.lane_name,
+				P::source_name(),
+				known_source_version,
+				source_version,
+			);
+			race_source.on_runtime_upgrade();
+		}
+		if is_runtime_upgraded(known_target_version, target_version) {
+			log::info!(
+				target: "bridge",
+				"[lane {}] {} runtime has been upgraded: spec version {:?} -> {:?}",
+				self.params.lane_name,
+				P::target_name(),
+				known_target_version,
+				target_version,
+			);
+			race_target.on_runtime_upgrade();
+		}
+		self.runtime_versions = (
+			source_version.or(known_source_version),
+			target_version.or(known_target_version),
+		);
+
+		let (proof_source_version, proof_target_version) = self.submission.proof_runtime_versions;
+		let is_proof_outdated = is_runtime_upgraded(proof_source_version, source_version)
+			|| is_runtime_upgraded(proof_target_version, target_version);
+		if let (true, Some((_, nonces_range, _))) = (is_proof_outdated, self.race_state.nonces_to_submit.as_ref()) {
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Runtime has been upgraded since proof of nonces {:?} has been generated. \
+				Regenerating proof",
+				self.params.lane_name,
+				nonces_range,
+			);
+			tracing::debug!(
+				parent: &self.nonces_spans.span(nonces_range),
+				"Runtime has been upgraded. Going to regenerate proof",
+			);
+			self.race_state.nonces_to_submit = None;
+			self.is_selection_required = true;
+		}
+
+		// queued proofs may have been generated before the latest proof, so they're regenerated
+		// after any runtime upgrade
+		let is_any_runtime_upgraded = is_runtime_upgraded(known_source_version, source_version)
+			|| is_runtime_upgraded(known_target_version, target_version);
+		if (is_proof_outdated || is_any_runtime_upgraded) && !self.race_state.proofs_to_submit.is_empty() {
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Runtime has been upgraded since {} queued proofs have been generated. \
+				Regenerating proofs",
+				self.params.lane_name,
+				self.race_state.proofs_to_submit.len(),
+			);
+			self.race_state.proofs_to_submit.clear();
+			self.race_state.nonces_to_submit = None;
+			self.is_selection_required = true;
+		}
+	}
+
+	/// Process relayer balance, read at the target node.
+	fn on_relayer_balance(&mut self, balance: Result<u128, String>) {
+		let balance = match balance {
+			Ok(balance) => balance,
+			Err(error) => {
+				let error = format!("Failed to read relayer balance at {}: {}", P::target_name(), error);
+				if self.relayer_balance.is_unknown {
+					let retry_delay = self
+						.relayer_balance
+						.retry_backoff
+						.next_backoff()
+						.unwrap_or(CONNECTION_ERROR_DELAY);
+					log::warn!(
+						target: "bridge",
+						"[lane {}] {}. Retrying in {}",
+						self.params.lane_name,
+						error,
+						retry_delay.as_secs_f64(),
+					);
+					self.relayer_balance.retry = self.timer.sleep(retry_delay).fuse();
+				} else {
+					// the last known balance is used until the balance is read at next block
+					log::warn!(target: "bridge", "[lane {}] {}", self.params.lane_name, error);
+				}
+				self.target.last_error = Some(error);
+				return;
+			}
+		};
+
+		self.relayer_balance.retry_backoff.reset();
+		if self.relayer_balance.is_unknown {
+			self.relayer_balance.is_unknown = false;
+			self.source.is_online = true;
+			self.is_selection_required = true;
+		}
+
+		let min_balance = self
+			.params
+			.balance_guard
+			.as_ref()
+			.map(|guard| guard.min_balance)
+			.unwrap_or(0);
+		let is_low = balance < min_balance;
+		if is_low && !self.relayer_balance.is_low {
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Relayer balance at {} is {}, which is less than {}. Pausing {} -> {} race",
+				self.params.lane_name,
+				P::target_name(),
+				balance,
+				min_balance,
+				P::source_name(),
+				P::target_name(),
+			);
+		} else if !is_low && self.relayer_balance.is_low {
+			log::info!(
+				target: "bridge",
+				"[lane {}] Relayer balance at {} has recovered to {}. Resuming {} -> {} race",
+				self.params.lane_name,
+				P::target_name(),
+				balance,
+				P::source_name(),
+				P::target_name(),
+			);
+			self.source.is_online = true;
+			self.is_selection_required = true;
+		}
+		self.relayer_balance.is_low = is_low;
+
+		if let Some(metrics) = self.metrics.as_ref() {
+			metrics.update_relayer_balance_low::<P>(&self.params.lane_name, is_low);
+		}
+	}
+
+	/// Read nonces from both clients again, even if their states are not updated.
+	fn on_refresh_tick(&mut self) {
+		self.source.nonces_required = self.race_state.source_state.is_some();
+		self.target.nonces_required = self.race_state.target_state.is_some();
+	}
+
+	/// Read target nonces again when the competition delay has elapsed, to check if selected
+	/// nonces have been delivered by other relayers.
+	fn on_competition_delay_elapsed(&mut self) {
+		if let Some(competing_nonces) = self.proofs.competing_nonces.as_mut() {
+			competing_nonces.stage = CompetingNoncesStage::RefreshRequired;
+			self.target.nonces_required = true;
+		}
+	}
+
+	/// The client is ready to retry failed request.
+	fn on_retry(&mut self, client: FailedClient) {
+		match client {
+			FailedClient::Source => self.source.is_online = true,
+			FailedClient::Target => self.target.is_online = true,
+			FailedClient::Both => {
+				self.source.is_online = true;
+				self.target.is_online = true;
+			}
+		}
+		self.summary.retries += 1;
+	}
+
+	/// Apply control command.
+	fn on_command(&mut self, command: Option<RaceCommand>) {
+		let command = match command {
+			Some(command) => command,
+			None => return,
+		};
+
+		log::info!(
+			target: "bridge",
+			"[lane {}] {} -> {} race has received command: {:?}",
+			self.params.lane_name,
+			P::source_name(),
+			P::target_name(),
+			command,
+		);
+
+		match command {
+			RaceCommand::Pause => self.is_paused = true,
+			RaceCommand::Resume => {
+				// paused time is never counted by stall and resubmit timeouts
+				let now = self.timer.now();
+				self.is_paused = false;
+				self.stall.reset(now);
+				self.submission.restart_resubmit_timeout(now);
+			}
+			RaceCommand::SetMaxNoncesPerProof(_)
+			| RaceCommand::SetMaxWeightPerProof(_)
+			| RaceCommand::SetLaneParameters(_) => (),
+		}
+		self.strategy.apply_command(command);
+		self.is_selection_required = true;
+	}
+
+	/// Remember that the race needs to exit.
+	fn on_exit_signal(&mut self) {
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Exit signal received by {} -> {} race",
+			self.params.lane_name,
+			P::source_name(),
+			P::target_name(),
+		);
+
+		self.exit_requested = true;
+	}
+
+	/// Apply conditions that may suspend delivery: pause command, external delivery condition
+	/// and the relayer balance guard.
+	fn apply_delivery_conditions(&mut self) {
+		let now = self.timer.now();
+
+		// nothing is expected to happen while the race is paused
+		if self.is_paused {
+			self.stall.reset(now);
+			self.submission.restart_resubmit_timeout(now);
+		}
+
+		if let (Some(balance_guard), Some(target_state)) = (
+			self.params.balance_guard.as_ref(),
+			self.race_state.target_state.as_ref(),
+		) {
+			self.relayer_balance.read_at(balance_guard, &target_state.best_self);
+		}
+
+		// new nonces are not delivered while external condition doesn't allow it
+		let can_deliver_now = self
+			.params
+			.delivery_condition
+			.as_ref()
+			.map(|delivery_condition| delivery_condition.can_deliver_now())
+			.unwrap_or(true);
+		if can_deliver_now == self.is_delivery_deferred {
+			log::info!(
+				target: "bridge",
+				"[lane {}] {} -> {} race delivery is {}",
+				self.params.lane_name,
+				P::source_name(),
+				P::target_name(),
+				if can_deliver_now {
+					"resumed"
+				} else {
+					"deferred by external condition"
+				},
+			);
+			self.is_delivery_deferred = !can_deliver_now;
+		}
+		if self.is_delivery_deferred || self.relayer_balance.is_low {
+			self.stall.reset(now);
+		}
+	}
+
+	/// If submitted transaction has been lost or it is not confirmed for too long, we select
+	/// nonces again and resubmit them. If original transaction is confirmed later, the
+	/// `target_nonces_updated` would drop resubmitted nonces.
+	///
+	/// Returns true if nonces are going to be resubmitted. Then trackers of submitted
+	/// transactions and the proof generation need to be dropped.
+	fn prepare_resubmission(&mut self, is_submitted_tx_lost: bool) -> bool {
+		let is_submission_outdated = self.submission.is_outdated(
+			!self.race_state.nonces_submitted.is_empty(),
+			self.params.resubmit_timeout,
+			self.timer.now(),
+		);
+		if is_submission_outdated {
+			for nonces_range in &self.race_state.nonces_submitted {
+				tracing::debug!(
+					parent: &self.nonces_spans.span(nonces_range),
+					"Proof is not confirmed for too long. Going to resubmit",
+				);
+			}
+			log::warn!(
+				target: "bridge",
+				"[lane {}] Nonces {:?} are not confirmed by {} for {:?}. Going to resubmit",
+				self.params.lane_name,
+				self.race_state.nonces_submitted,
+				P::target_name(),
+				self.params.resubmit_timeout,
+			);
+		}
+		if !is_submitted_tx_lost && !is_submission_outdated {
+			return false;
+		}
+
+		// the proof of next nonces (if it has been generated or is being generated) is useless
+		// until resubmitted nonces are delivered. Transactions with proofs of next submitted nonces
+		// would be rejected by the target node, so all of them are resubmitted
+		self.race_state.nonces_submitted.clear();
+		self.race_state.proofs_to_submit.clear();
+		self.race_state.nonces_to_submit = None;
+		self.is_selection_required = true;
+		true
+	}
+
+	/// Report race progress: using logs, metrics, state notifier and the required source header
+	/// callback.
+	fn report_progress(&mut self) {
+		let source_header_lag = self.update_source_header_lag();
+
+		let is_progress_printed = self.progress.print_progress::<P, _>(
+			&self.params.lane_name,
+			&self.strategy,
+			source_header_lag,
+			self.source.last_success,
+			self.target.last_success,
+			self.timer.now(),
+		);
+		if let (true, Some(metrics)) = (is_progress_printed, self.metrics.as_ref()) {
+			metrics.update_strategy_metrics::<P>(&self.params.lane_name, &self.strategy.metrics());
+		}
+
+		if let Some(ref state_notifier) = self.params.state_notifier {
+			let state_snapshot = RaceStateSnapshot::new(
+				&self.race_state,
+				&self.strategy,
+				self.proofs.discarded_proofs,
+				self.is_source_header_lagging,
+			);
+			if self.prev_state_snapshot.as_ref() != Some(&state_snapshot) {
+				let _ = state_notifier.unbounded_send(state_snapshot.clone());
+				self.prev_state_snapshot = Some(state_snapshot);
+			}
+		}
+
+		if let Some(metrics) = self.metrics.as_ref() {
+			let best_nonces = (self.strategy.best_at_source(), self.strategy.best_at_target());
+			if self.prev_best_nonces != Some(best_nonces) {
+				metrics.update_best_nonces::<P>(&self.params.lane_name, best_nonces.0, best_nonces.1);
+				self.prev_best_nonces = Some(best_nonces);
+			}
+		}
+
+		self.report_required_source_header();
+	}
+
+	/// Returns number of blocks the best source header, known to the target node, lags behind the
+	/// best source header. If there are nonces to deliver, but the target node doesn't know recent
+	/// source headers, the race can't make progress until some headers relay catches up.
+	fn update_source_header_lag(&mut self) -> Option<u64> {
+		let source_header_lag = match (
+			self.race_state.source_state.as_ref(),
+			self.race_state.target_state.as_ref(),
+		) {
+			(Some(source_state), Some(target_state)) => Some(
+				source_state
+					.best_self
+					.number()
+					.saturating_sub(target_state.best_peer.number()),
+			),
+			_ => None,
+		};
+		if let Some(max_source_header_lag) = self.params.max_source_header_lag {
+			let is_lagging = !self.strategy.is_empty()
+				&& source_header_lag
+					.map(|source_header_lag| source_header_lag > max_source_header_lag)
+					.unwrap_or(false);
+			if is_lagging && !self.is_source_header_lagging {
+				log::warn!(
+					target: "bridge",
+					"[lane {}] Best {} header known to {} lags {:?} blocks behind. Nonces are waiting for delivery",
+					self.params.lane_name,
+					P::source_name(),
+					P::target_name(),
+					source_header_lag,
+				);
+			} else if !is_lagging && self.is_source_header_lagging {
+				log::info!(
+					target: "bridge",
+					"[lane {}] Best {} header known to {} lags {:?} blocks behind. Lag has recovered",
+					self.params.lane_name,
+					P::source_name(),
+					P::target_name(),
+					source_header_lag,
+				);
+			}
+			self.is_source_header_lagging = is_lagging;
+		}
+
+		source_header_lag
+	}
+
+	/// Pass source header, required by the target node, to the callback, if it has changed.
+	fn report_required_source_header(&mut self) {
+		let target_state = match self.race_state.target_state.as_ref() {
+			Some(target_state) => target_state,
+			None => return,
+		};
+
+		let required_source_header = match self.target_required_source_header {
+			Some((ref current_best, Some(ref required_source_header))) if *current_best == target_state.best_peer => {
+				Some(required_source_header.clone())
+			}
+			_ => self.strategy.required_source_header_at_target(&target_state.best_peer),
+		};
+		if required_source_header != self.prev_required_source_header {
+			if let Some(ref required_source_header) = required_source_header {
+				log::debug!(
+					target: "bridge",
+					"[lane {}] {} needs to know {} header {:?} to continue",
+					self.params.lane_name,
+					P::target_name(),
+					P::source_name(),
+					required_source_header,
+				);
+				(self.on_required_source_header)(required_source_header.clone());
+			}
+			self.prev_required_source_header = required_source_header;
+		}
+	}
+
+	/// Returns true if the race has delivered the final nonce. All submitted nonces are confirmed
+	/// once the target node has received the final nonce, so the race only waits for in-flight
+	/// submission.
+	fn is_race_completed(&self, is_submission_completed: bool) -> bool {
+		let is_final_nonce_delivered = self
+			.params
+			.final_nonce
+			.map(|final_nonce| self.strategy.best_at_target() >= final_nonce)
+			.unwrap_or(false);
+		if !is_final_nonce_delivered || !is_submission_completed {
+			return false;
+		}
+
+		log::info!(
+			target: "bridge",
+			"[lane {}] Final nonce {:?} has been delivered to {}. Exiting {} -> {} race",
+			self.params.lane_name,
+			self.params.final_nonce,
+			P::target_name(),
+			P::source_name(),
+			P::target_name(),
+		);
+		true
+	}
+
+	/// Returns error if any client has stalled. All timeouts are measured by the race timer, so
+	/// the race may run with virtual time.
+	fn check_stall(&mut self) -> Result<(), RaceError> {
+		let now = self.timer.now();
+		self.stall
+			.check(&self.params, &mut self.source, &mut self.target, now)?;
+
+		// nothing is expected to happen if there's nothing to deliver
+		if self.race_state.nonces_to_submit.is_none()
+			&& self.race_state.proofs_to_submit.is_empty()
+			&& self.race_state.nonces_submitted.is_empty()
+			&& self.strategy.is_empty()
+		{
+			self.stall.reset(now);
+		}
+
+		Ok(())
+	}
+
+	/// If the target node already knows much newer source header, we may be able to deliver more
+	/// nonces in the single transaction. We never drop proof that is being submitted, and we never
+	/// select nonces again after reselection, so we can't end up in a livelock.
+	fn reselect_outdated_nonces(&mut self, is_submission_completed: bool) {
+		let is_nonces_to_submit_outdated = self.params.reselect_on_newer_target_state
+			&& !self.submission.is_reselected
+			&& is_submission_completed
+			&& match (
+				self.race_state.nonces_to_submit.as_ref(),
+				self.race_state.target_state.as_ref(),
+			) {
+				(Some((at_block, _, _)), Some(target_state)) => {
+					target_state.best_peer.number() > at_block.number().saturating_add(self.params.reselect_threshold)
+				}
+				_ => false,
+			};
+		if is_nonces_to_submit_outdated {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] {} knows newer header than the header of the pending proof of nonces {:?}. Going to select nonces again",
+				self.params.lane_name,
+				P::target_name(),
+				self.race_state.nonces_to_submit.as_ref().map(|(_, nonces_range, _)| nonces_range),
+			);
+
+			self.race_state.nonces_to_submit = None;
+			self.submission.is_reselected = true;
+			self.is_selection_required = true;
+		}
+	}
+
+	/// Nonces that have been waiting for the competition delay are only proved if they have not
+	/// been delivered by other relayers in the meantime.
+	fn prove_refreshed_competing_nonces(&mut self) {
+		let is_competing_nonces_refreshed = self
+			.proofs
+			.competing_nonces
+			.as_ref()
+			.map(|competing_nonces| competing_nonces.stage == CompetingNoncesStage::Refreshed)
+			.unwrap_or(false);
+		if !is_competing_nonces_refreshed {
+			return;
+		}
+
+		let CompetingNonces {
+			at_block,
+			nonces_range,
+			proof_parameters,
+			..
+		} = self.proofs.competing_nonces.take().expect("checked above; qed");
+		let is_delivered = self.strategy.best_at_target() >= *nonces_range.start();
+		if is_delivered {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Nonces {:?} have been delivered to {} by other relayer",
+				self.params.lane_name,
+				nonces_range,
+				P::target_name(),
+			);
+		}
+
+		if is_delivered || self.is_paused || self.is_delivery_deferred || self.relayer_balance.is_delivery_blocked() {
+			self.source.is_online = true;
+			self.is_selection_required = true;
+		} else {
+			self.proofs.nonces_to_prove = Some((at_block, nonces_range, proof_parameters, None));
+		}
+	}
+
+	/// Select nonces to deliver, if the source client is ready. Selected nonces may need to wait
+	/// for the competition delay before they're proved.
+	///
+	/// If nothing is selected, returns arguments of the source nonces request, if nonces need to
+	/// be read from the source client.
+	#[allow(clippy::type_complexity)]
+	fn next_source_request(&mut self) -> Result<Option<(P::SourceHeaderId, Option<MessageNonce>)>, RaceError> {
+		if !self.source.is_online {
+			return Ok(None);
+		}
+		self.source.is_online = false;
+
+		if let Some((at_block, nonces_range, proof_parameters)) = self.select_nonces()? {
+			self.observer.on_nonces_selected(&nonces_range);
+			tracing::debug!(
+				parent: &self.nonces_spans.span(&nonces_range),
+				at_block = ?at_block,
+				"Nonces have been selected"
+			);
+			match self.params.competition_delay {
+				Some(max_competition_delay) => {
+					let delay = (self.params.random_delay)(max_competition_delay);
+					log::debug!(
+						target: "bridge",
+						"[lane {}] Waiting {:?} before asking {} to prove nonces in range {:?} at block {:?}",
+						self.params.lane_name,
+						delay,
+						P::source_name(),
+						nonces_range,
+						at_block,
+					);
+					self.proofs.competing_nonces = Some(CompetingNonces {
+						stage: CompetingNoncesStage::Delayed,
+						at_block,
+						nonces_range,
+						proof_parameters,
+					});
+					self.proofs.competition_delay = self.timer.sleep(delay).fuse();
+				}
+				None => {
+					self.proofs.nonces_to_prove = Some((at_block, nonces_range, proof_parameters, None));
+				}
+			}
+
+			return Ok(None);
+		}
+
+		let can_read_source_nonces = self.source.nonces_required
+			&& !is_source_queue_full(&self.params, &self.strategy)
+			&& (!self.params.nonces_at_target_known_header || self.race_state.target_state.is_some());
+		if !can_read_source_nonces {
+			self.source.is_online = true;
+			return Ok(None);
+		}
+
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Asking {} about message nonces",
+			self.params.lane_name,
+			P::source_name(),
+		);
+		let source_state = self
+			.race_state
+			.source_state
+			.as_ref()
+			.expect("source_nonces_required is only true when source_state is Some; qed");
+		let best_source_header = match self.params.source_nonces_at {
+			BlockRef::Best => source_state.best_self.clone(),
+			BlockRef::Finalized => source_state.best_finalized_self.clone(),
+		};
+		let at_block = match self.race_state.target_state.as_ref() {
+			Some(target_state)
+				if self.params.nonces_at_target_known_header
+					&& target_state.best_peer.number() < best_source_header.number() =>
+			{
+				target_state.best_peer.clone()
+			}
+			_ => best_source_header,
+		};
+		// nonces that are delivered to the target node are never read from the source node
+		let prev_latest_nonce = self
+			.strategy
+			.best_at_source_opt()
+			.or_else(|| self.strategy.best_at_target_opt());
+		Ok(Some((at_block, prev_latest_nonce)))
+	}
+
+	/// Select nonces to deliver, if selection is allowed and required.
+	#[allow(clippy::type_complexity)]
+	fn select_nonces(
+		&mut self,
+	) -> Result<Option<(P::SourceHeaderId, RangeInclusive<MessageNonce>, SC::ProofParameters)>, RaceError> {
+		// after source reorganization, target node may still see the orphaned header with the same
+		// number. The proof generated at this header would be rejected, so we wait until the target
+		// node sees the new header
+		let (is_target_at_new_header, is_target_at_orphaned_header) =
+			match (self.source_reorged_to.as_ref(), self.race_state.target_state.as_ref()) {
+				(Some(reorged_to), Some(target_state)) => (
+					!reorged_to.is_orphaned_by(&target_state.best_peer),
+					target_state.best_peer.is_orphaned_by(reorged_to)
+						&& reorged_to.is_orphaned_by(&target_state.best_peer),
+				),
+				_ => (false, false),
+			};
+		if is_target_at_new_header {
+			self.source_reorged_to = None;
+		}
+		if self.is_paused
+			|| self.is_delivery_deferred
+			|| self.relayer_balance.is_delivery_blocked()
+			|| is_target_at_orphaned_header
+			|| self.is_target_nonces_outdated
+			|| !self.is_selection_required
+		{
+			return Ok(None);
+		}
+
+		self.is_selection_required = false;
+		let nonces_to_deliver = select_nonces_to_deliver(&self.race_state, &mut self.strategy)?;
+		// nothing is selected, because queued nonces may be unproved at the target node. Then the
+		// target client is asked which source header it needs
+		if nonces_to_deliver.is_none() {
+			if let Some(target_state) = self.race_state.target_state.as_ref() {
+				let current_best = &target_state.best_peer;
+				if self.required_source_header_asked_at.as_ref() != Some(current_best)
+					&& self.strategy.required_source_header_at_target(current_best).is_some()
+				{
+					self.required_source_header_request = Some(current_best.clone());
+				}
+			}
+		}
+		Ok(nonces_to_deliver)
+	}
+
+	/// Returns arguments of the proof generation request, if there are nonces to prove.
+	///
+	/// If strategy cares about the delivery transaction cost, the target client is asked to
+	/// estimate it before the proof is generated. The estimation is reused while the target best
+	/// header is the same. The estimation request is issued along with other target requests.
+	#[allow(clippy::type_complexity)]
+	fn next_proof_to_generate(
+		&mut self,
+	) -> Option<(P::SourceHeaderId, RangeInclusive<MessageNonce>, SC::ProofParameters)> {
+		let (at_block, nonces_range, proof_parameters, estimated_cost) = self.proofs.nonces_to_prove.take()?;
+		let target_best_header = self
+			.race_state
+			.target_state
+			.as_ref()
+			.map(|target_state| &target_state.best_self);
+		let delivery_cost = match self.strategy.delivery_cost_required(&nonces_range) {
+			Some((total_dispatch_weight, total_size)) => {
+				match estimated_cost.or_else(|| self.proofs.cached_cost(target_best_header, &nonces_range)) {
+					Some(cost) => Some(Ok(cost)),
+					None => Some(Err((total_dispatch_weight, total_size))),
+				}
+			}
+			None => None,
+		};
+
+		let can_prove = match delivery_cost {
+			Some(Err((total_dispatch_weight, total_size))) => {
+				self.proofs.cost_estimation_request = Some((nonces_range.clone(), total_dispatch_weight, total_size));
+				self.proofs.cost_estimation = Some((at_block, nonces_range, proof_parameters));
+				return None;
+			}
+			Some(Ok(cost)) => self.strategy.delivery_cost_estimated(&nonces_range, cost),
+			None => true,
+		};
+		if !can_prove {
+			self.source.is_online = true;
+			return None;
+		}
+
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Asking {} to prove nonces in range {:?} at block {:?}",
+			self.params.lane_name,
+			P::source_name(),
+			nonces_range,
+			at_block,
+		);
+		let deadline = self
+			.params
+			.proof_generation_timeout
+			.map(|proof_generation_timeout| self.timer.sleep(proof_generation_timeout));
+		self.proofs.start(
+			self.nonces_spans.span(&nonces_range),
+			nonces_range.clone(),
+			self.timer.now(),
+			deadline,
+		);
+		Some((at_block, nonces_range, proof_parameters))
+	}
+
+	/// Returns next request to the target client, if the client is ready.
+	///
+	/// We never issue concurrent requests to the target client, so the client is online only when
+	/// there are no requests in flight. Proof is verified and submitted first and nonces are only
+	/// read when there's nothing to submit.
+	///
+	/// Proof of next nonces may be generated and submitted while previous nonces are not yet
+	/// confirmed by the target node. But we never submit them out of order. We also never restart
+	/// the submission that is already in progress.
+	fn next_target_request(
+		&mut self,
+		is_submission_completed: bool,
+	) -> Option<TargetRequest<P::SourceHeaderId, P::TargetHeaderId, P::Proof>> {
+		if !self.target.is_online {
+			return None;
+		}
+		self.target.is_online = false;
+
+		let can_submit_proof = self.can_submit_proof(is_submission_completed);
+		let is_proof_verified = self.is_proof_verified();
+		let is_proof_dry_run = self.is_proof_dry_run();
+
+		if self.is_runtime_versions_read_required {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Asking {} and {} about runtime versions",
+				self.params.lane_name,
+				P::source_name(),
+				P::target_name(),
+			);
+			self.is_runtime_versions_read_required = false;
+			return Some(TargetRequest::RuntimeVersions);
+		}
+
+		if let Some((at_block, nonces_range, proof)) = self.race_state.nonces_to_submit.as_ref() {
+			if !is_proof_verified {
+				log::debug!(
+					target: "bridge",
+					"[lane {}] Going to verify proof of messages in range {:?} at {} node",
+					self.params.lane_name,
+					nonces_range,
+					P::target_name(),
+				);
+				return Some(TargetRequest::VerifyProof(
+					at_block.clone(),
+					nonces_range.clone(),
+					proof.clone(),
+				));
+			}
+
+			if !is_proof_dry_run {
+				log::debug!(
+					target: "bridge",
+					"[lane {}] Going to dry run proof of messages in range {:?} at {} node",
+					self.params.lane_name,
+					nonces_range,
+					P::target_name(),
+				);
+				return Some(TargetRequest::DryRunProof(
+					at_block.clone(),
+					nonces_range.clone(),
+					proof.clone(),
+				));
+			}
+		}
+
+		if can_submit_proof
+			&& (self.race_state.nonces_to_submit.is_some() || !self.race_state.proofs_to_submit.is_empty())
+		{
+			return Some(self.start_submission());
+		}
+
+		if let Some((nonces_range, total_dispatch_weight, total_size)) = self.proofs.cost_estimation_request.take() {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Asking {} to estimate cost of delivering nonces {:?}",
+				self.params.lane_name,
+				P::target_name(),
+				nonces_range,
+			);
+			return Some(TargetRequest::EstimateDeliveryCost(
+				nonces_range,
+				total_dispatch_weight,
+				total_size,
+			));
+		}
+
+		if self.target.nonces_required {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Asking {} about message nonces",
+				self.params.lane_name,
+				P::target_name(),
+			);
+			let target_state = self
+				.race_state
+				.target_state
+				.as_ref()
+				.expect("target_nonces_required is only true when target_state is Some; qed");
+			if let Some(competing_nonces) = self.proofs.competing_nonces.as_mut() {
+				if competing_nonces.stage == CompetingNoncesStage::RefreshRequired {
+					competing_nonces.stage = CompetingNoncesStage::RefreshRequested;
+				}
+			}
+			return Some(TargetRequest::Nonces(
+				target_state.best_self.clone(),
+				target_state.best_finalized_self.clone(),
+			));
+		}
+
+		if let Some(current_best) = self.required_source_header_request.take() {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Asking {} about required {} header",
+				self.params.lane_name,
+				P::target_name(),
+				P::source_name(),
+			);
+			self.required_source_header_asked_at = Some(current_best.clone());
+			return Some(TargetRequest::RequiredSourceHeader(current_best));
+		}
+
+		self.target.is_online = true;
+		None
+	}
+
+	/// Returns true if new transaction may be submitted to the target node right now.
+	fn can_submit_proof(&self, is_submission_completed: bool) -> bool {
+		!self.is_paused && self.race_state.nonces_submitted.len() < self.params.max_in_flight && is_submission_completed
+	}
+
+	/// Returns true if the proof that is waiting for submission doesn't need to be verified.
+	fn is_proof_verified(&self) -> bool {
+		!self.params.verify_proofs
+			|| self
+				.race_state
+				.nonces_to_submit
+				.as_ref()
+				.map(|(at_block, nonces_range, _)| self.submission.is_verified(at_block, nonces_range))
+				.unwrap_or(false)
+	}
+
+	/// Returns true if the proof that is waiting for submission doesn't need to be dry run.
+	///
+	/// The target node only knows about delivered nonces, so the dry run is impossible when there
+	/// are submitted or queued proofs of previous nonces.
+	fn is_proof_dry_run(&self) -> bool {
+		!self.params.dry_run_before_submit
+			|| !self.race_state.nonces_submitted.is_empty()
+			|| !self.race_state.proofs_to_submit.is_empty()
+			|| self
+				.race_state
+				.nonces_to_submit
+				.as_ref()
+				.map(|(at_block, nonces_range, _)| self.submission.is_dry_run_passed(at_block, nonces_range))
+				.unwrap_or(false)
+	}
+
+	/// Update maximal number of proofs that are submitted in a single transaction.
+	fn on_batch_submission_support(&mut self, is_batch_submission_supported: bool) {
+		self.submission.max_proofs_per_batch = if is_batch_submission_supported {
+			self.params.max_proofs_per_batch.max(1)
+		} else {
+			1
+		};
+	}
+
+	/// Move the ready proof to the batch queue if it can't be submitted right now.
+	///
+	/// Nonces that follow queued proofs are selected and proved while the target client is busy. Once
+	/// the client is ready, all queued proofs are submitted in a single transaction.
+	fn queue_ready_proof(&mut self, is_submission_completed: bool) {
+		let is_queue_full = self.race_state.proofs_to_submit.len() + 1 >= self.submission.max_proofs_per_batch;
+		if is_queue_full
+			|| self.race_state.nonces_to_submit.is_none()
+			|| !self.is_proof_verified()
+			|| !self.is_proof_dry_run()
+			|| self.can_submit_proof(is_submission_completed)
+		{
+			return;
+		}
+
+		if let Some((at_block, nonces_range, proof)) = self.race_state.nonces_to_submit.take() {
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Proof of messages in range {:?} is queued for batch submission to {}",
+				self.params.lane_name,
+				nonces_range,
+				P::target_name(),
+			);
+			tracing::debug!(parent: &self.nonces_spans.span(&nonces_range), "Proof has been queued");
+			self.race_state
+				.proofs_to_submit
+				.push_back((at_block, nonces_range, proof));
+			self.is_selection_required = true;
+		}
+	}
+
+	/// Start submission of all ready proofs.
+	///
+	/// Queued proofs and the proof that is waiting for submission are submitted in a single batch.
+	fn start_submission(&mut self) -> TargetRequest<P::SourceHeaderId, P::TargetHeaderId, P::Proof> {
+		if self.race_state.proofs_to_submit.is_empty() {
+			let (at_block, nonces_range, proof) = self
+				.race_state
+				.nonces_to_submit
+				.clone()
+				.expect("start_submission is only called when there are proofs to submit; qed");
+			let (attempt, submit_options) =
+				self.submission
+					.start(&self.params.submit_policy, &nonces_range, self.timer.now());
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Going to submit proof of messages in range {:?} to {} node (attempt {}, {:?})",
+				self.params.lane_name,
+				nonces_range,
+				P::target_name(),
+				attempt,
+				submit_options,
+			);
+			return TargetRequest::SubmitProof(at_block, nonces_range, proof, submit_options);
+		}
+
+		let batch = self
+			.race_state
+			.proofs_to_submit
+			.iter()
+			.cloned()
+			.chain(self.race_state.nonces_to_submit.clone())
+			.collect::<Vec<_>>();
+		let nonces_range = *batch[0].1.start()..=*batch[batch.len() - 1].1.end();
+		let (attempt, submit_options) =
+			self.submission
+				.start(&self.params.submit_policy, &nonces_range, self.timer.now());
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Going to submit {} proofs of messages in range {:?} to {} node (attempt {}, {:?})",
+			self.params.lane_name,
+			batch.len(),
+			nonces_range,
+			P::target_name(),
+			attempt,
+			submit_options,
+		);
+		TargetRequest::SubmitProofs(batch, submit_options)
+	}
+
+	/// All in-flight requests have been dropped when the client has been reconnected, so they're
+	/// started again. Strategy and race state are preserved, so the race continues from where it
+	/// has stopped.
+	fn on_reconnected(&mut self) {
+		if let Some(proof_span) = self.proofs.span.take() {
+			tracing::debug!(parent: &proof_span, "Proof generation has been cancelled by reconnect");
+		}
+		self.proofs.nonces = None;
+		self.proofs.cost_estimation = None;
+		self.proofs.cost_estimation_request = None;
+		self.source.is_online = true;
+		self.target.is_online = true;
+		self.is_runtime_versions_read_required = true;
+		self.required_source_header_request = None;
+		self.required_source_header_asked_at = None;
+		self.source.nonces_required = self.race_state.source_state.is_some();
+		self.target.nonces_required = self.race_state.target_state.is_some();
+		self.stall.reset(self.timer.now());
+		self.is_selection_required = true;
+	}
+}
+
+impl<SourceHeaderId, TargetHeaderId, Proof> Default for RaceState<SourceHeaderId, TargetHeaderId, Proof> {
+	fn default() -> Self {
+		RaceState {
+			source_state: None,
+			target_state: None,
+			nonces_to_submit: None,
+			proofs_to_submit: VecDeque::new(),
+			nonces_submitted: VecDeque::new(),
+		}
+	}
+}
+
+impl<SourceHeaderId, TargetHeaderId, Proof> RaceState<SourceHeaderId, TargetHeaderId, Proof> {
+	/// Returns the best nonce that has been submitted or queued for submission. Next nonces
+	/// need to be selected after this nonce.
+	pub fn best_pending_nonce(&self) -> Option<MessageNonce> {
+		self.proofs_to_submit
+			.back()
+			.map(|(_, nonces_range, _)| *nonces_range.end())
+			.or_else(|| self.nonces_submitted.back().map(|nonces_range| *nonces_range.end()))
+	}
+}
+
+/// Tracing spans of nonces ranges that are being delivered.
+///
+/// Every selected range gets its own span, which is entered while the proof of this range is
+/// generated, verified and submitted. The span is closed when the range is delivered. Spans are
+/// keyed by the range end, because it isn't changed when the proof is trimmed.
+struct NoncesSpans {
+	/// Race name, attached to every span.
+	race: String,
+	/// Spans of ranges that are not yet delivered, keyed by the range end.
+	spans: BTreeMap<MessageNonce, tracing::Span>,
+}
+
+impl NoncesSpans {
+	/// Create new spans collection.
+	fn new<P: MessageRace>() -> Self {
+		NoncesSpans {
+			race: format!("{} -> {}", P::source_name(), P::target_name()),
+			spans: BTreeMap::new(),
+		}
+	}
+
+	/// Returns span of given nonces range, creating it if it doesn't exist yet.
+	fn span(&mut self, nonces_range: &RangeInclusive<MessageNonce>) -> tracing::Span {
+		let race = &self.race;
+		self.spans
+			.entry(*nonces_range.end())
+			.or_insert_with(|| tracing::info_span!("nonces", race = %race, range = ?nonces_range))
+			.clone()
+	}
+
+	/// Closes spans of all ranges that have been delivered to the target node.
+	fn close_delivered(&mut self, best_at_target: MessageNonce) {
+		let undelivered_spans = self.spans.split_off(&best_at_target.saturating_add(1));
+		for (_, span) in std::mem::replace(&mut self.spans, undelivered_spans) {
+			tracing::debug!(parent: &span, "Nonces have been delivered");
+		}
+	}
+}
+
+/// Nonces that are going to be proved: header to generate proof at, nonces range, proof parameters
+/// and estimated delivery cost (if it is already known).
+type NoncesToProve<SourceHeaderId, ProofParameters, Cost> = (
+	SourceHeaderId,
+	RangeInclusive<MessageNonce>,
+	ProofParameters,
+	Option<Cost>,
+);
+
+/// Nonces that have been selected for delivery, but are waiting for the competition delay.
+struct CompetingNonces<SourceHeaderId, ProofParameters> {
+	/// Current stage.
+	stage: CompetingNoncesStage,
+	/// Block at which nonces have been selected.
+	at_block: SourceHeaderId,
+	/// Selected nonces.
+	nonces_range: RangeInclusive<MessageNonce>,
+	/// Parameters required to generate proof.
+	proof_parameters: ProofParameters,
+}
+
+/// Stage of nonces that have been selected for delivery, but are waiting for the competition
+/// delay.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompetingNoncesStage {
+	/// Competition delay has not yet elapsed.
+	Delayed,
+	/// Competition delay has elapsed and target nonces need to be read again.
+	RefreshRequired,
+	/// Target nonces are being read.
+	RefreshRequested,
+	/// Target nonces have been read after the competition delay has elapsed.
+	Refreshed,
+}
+
+/// Returns true if too many nonces are queued at the source side of the race.
+fn is_source_queue_full<SourceHeaderId, TargetHeaderId, Proof>(
+	params: &RaceParams,
+	strategy: &impl RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+) -> bool {
+	if strategy.is_source_queue_full() {
+		return true;
+	}
+
+	match params.max_queued_nonces {
+		Some(max_queued_nonces) => {
+			strategy.best_at_source().saturating_sub(strategy.best_at_target()) > max_queued_nonces
+		}
+		None => false,
+	}
+}
+
+/// Process result of the race client future.
+///
+/// Remembers formatted client error (if any) in the `last_error` and converts connection and
+/// fatal errors into the race error. Errors that are reported by `MaybeFatalError::is_fatal` are
+/// fatal, regardless of their classification. The `last_error` is cleared and the `last_success` is
+/// set to `now` when the client future succeeds.
+///
+/// Other errors are counted in the `consecutive_failures`, which is reset when the client
+/// future succeeds. Once it reaches `max_consecutive_failures`, the error is converted into
+/// the race error too.
+#[allow(clippy::too_many_arguments)]
+fn process_race_future_result<TResult, TError, TGoOfflineFuture>(
+	result: Result<TResult, TError>,
+	retry_backoff: &mut ExponentialBackoff,
+	on_success: impl FnOnce(TResult),
+	go_offline_future: &mut Pin<&mut futures::future::Fuse<TGoOfflineFuture>>,
+	go_offline: impl FnOnce(Duration) -> TGoOfflineFuture,
+	error_pattern: impl FnOnce() -> String,
+	failed_client: FailedClient,
+	last_error: &mut Option<String>,
+	last_success: &mut Instant,
+	now: Instant,
+	consecutive_failures: &mut u32,
+	max_consecutive_failures: Option<u32>,
+) -> Result<bool, RaceError>
+where
+	TError: Debug + RelayError + MaybeFatalError,
+	TGoOfflineFuture: FutureExt,
+{
+	let error_pattern = match result {
+		Ok(_) => {
+			*last_error = None;
+			*last_success = now;
+			*consecutive_failures = 0;
+			String::new()
+		}
+		Err(ref error) => {
+			let error_pattern = error_pattern();
+			*last_error = Some(format!("{}: {:?}", error_pattern, error));
+			let classification = if error.is_fatal() {
+				ErrorClassification::Fatal
+			} else {
+				error.classification()
+			};
+			if classification == ErrorClassification::Fatal {
+				return Err(RaceError {
+					failed_client,
+					reason: RaceErrorReason::Fatal(last_error.clone().unwrap_or_default()),
+					summary: RaceSummary::default(),
+				});
+			}
+			if classification != ErrorClassification::Reconnect {
+				*consecutive_failures += 1;
+				if max_consecutive_failures
+					.map(|max_consecutive_failures| *consecutive_failures >= max_consecutive_failures)
+					.unwrap_or(false)
+				{
+					return Err(RaceError {
+						failed_client,
+						reason: RaceErrorReason::ConsecutiveFailures {
+							failures: *consecutive_failures,
+							last_error: last_error.clone().unwrap_or_default(),
+						},
+						summary: RaceSummary::default(),
+					});
+				}
+			}
+			error_pattern
+		}
+	};
+
+	process_future_result(
+		result,
+		retry_backoff,
+		on_success,
+		go_offline_future,
+		go_offline,
+		move || error_pattern,
+	)
+	.fail_if_connection_error(failed_client)
+	.map_err(|failed_client| RaceError {
+		failed_client,
+		reason: RaceErrorReason::Connection(last_error.clone().unwrap_or_default()),
+		summary: RaceSummary::default(),
+	})
+}
+
+/// Remember connection error of the race client in the `reconnect_error`, if the client may be
+/// reconnected. Then the client is considered offline until it is reconnected. Otherwise, the
+/// error is returned.
+fn postpone_connection_error(
+	error: RaceError,
+	params: &RaceParams,
+	reconnect_error: &mut Option<RaceError>,
+) -> Result<bool, RaceError> {
+	match error.reason {
+		RaceErrorReason::Connection(_) if params.max_reconnect_attempts != 0 => {
+			*reconnect_error = Some(error);
+			Ok(false)
+		}
+		_ => Err(error),
+	}
+}
+
+/// Read spec versions of the race source and target nodes runtimes. If the client has failed to
+/// read the version, it is treated as unknown.
+async fn read_runtime_versions_of<P, SC, TC>(
+	race_source: &SC,
+	race_target: &TC,
+	lane_name: &str,
+) -> (Option<u32>, Option<u32>)
+where
+	P: MessageRace,
+	SC: SourceClient<P>,
+	TC: TargetClient<P>,
+{
+	let source_version = race_source.runtime_version().await.unwrap_or_else(|error| {
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Failed to read {} runtime version: {:?}",
+			lane_name,
+			P::source_name(),
+			error,
+		);
+		None
+	});
+	let target_version = race_target.runtime_version().await.unwrap_or_else(|error| {
+		log::debug!(
+			target: "bridge",
+			"[lane {}] Failed to read {} runtime version: {:?}",
+			lane_name,
+			P::target_name(),
+			error,
+		);
+		None
+	});
+	(source_version, target_version)
+}
+
+/// Returns true if the `actual` runtime version differs from the `known` version. Unknown versions
+/// are never treated as upgraded.
+fn is_runtime_upgraded(known: Option<u32>, actual: Option<u32>) -> bool {
+	matches!((known, actual), (Some(known), Some(actual)) if known != actual)
+}
+
+/// Report failed nonces range to the strategy. Returns error if the strategy decides that the
+/// lane is blocked.
+fn report_range_failure<SourceHeaderId, TargetHeaderId, Proof>(
+	strategy: &mut impl RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+	nonces_range: RangeInclusive<MessageNonce>,
+	kind: RangeFailureKind,
+	failed_client: FailedClient,
+) -> Result<(), RaceError> {
+	strategy.range_failed(nonces_range, kind).map_err(|reason| RaceError {
+		failed_client,
+		reason,
+		summary: RaceSummary::default(),
+	})
+}
+
+/// Interval between two race progress messages.
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(10);
+/// Number of last progress intervals that are used to compute the delivery rate.
+const PROGRESS_RATE_WINDOW: usize = 5;
+
+/// Race progress tracker.
+///
+/// Periodically prints race progress, along with the delivery rate (computed over the last
+/// `PROGRESS_RATE_WINDOW` progress intervals) and the estimated time to deliver all nonces
+/// that are currently known to the source node.
+struct ProgressTracker {
+	/// Time when the last progress message has been printed.
+	prev_time: Instant,
+	/// Best nonces at target, sampled at every progress message.
+	samples: VecDeque<(Instant, MessageNonce)>,
+}
+
+impl ProgressTracker {
+	/// Create new progress tracker, started at given time.
+	fn new(now: Instant) -> Self {
+		ProgressTracker {
+			prev_time: now,
+			samples: VecDeque::with_capacity(PROGRESS_RATE_WINDOW + 1),
+		}
+	}
+
+	/// Remember best nonce at target at given time.
+	fn add_sample(&mut self, time: Instant, best_at_target: MessageNonce) {
+		if self.samples.len() > PROGRESS_RATE_WINDOW {
+			self.samples.pop_front();
+		}
+		self.samples.push_back((time, best_at_target));
+	}
+
+	/// Return delivery rate (in nonces per minute), if it is known.
+	fn delivery_rate(&self) -> Option<f64> {
+		let (first_time, first_nonce) = *self.samples.front()?;
+		let (last_time, last_nonce) = *self.samples.back()?;
+		let elapsed = last_time.saturating_duration_since(first_time).as_secs_f64();
+		if elapsed == 0.0 {
+			return None;
+		}
+
+		Some(last_nonce.saturating_sub(first_nonce) as f64 * 60.0 / elapsed)
+	}
+
+	/// Return estimated time to deliver given number of nonces, if it is known.
+	fn eta(&self, backlog: MessageNonce) -> Option<Duration> {
+		if backlog == 0 {
+			return Some(Duration::from_secs(0));
+		}
+
+		match self.delivery_rate() {
+			Some(rate) if rate > 0.0 => Some(Duration::from_secs_f64(backlog as f64 * 60.0 / rate)),
+			_ => None,
+		}
+	}
+
+	/// Print race progress, if `PROGRESS_INTERVAL` has passed since the last message. The `now_time`
+	/// is the current time of the race timer. Returns true if the message has been printed.
+	fn print_progress<P, S>(
+		&mut self,
+		lane_name: &str,
+		strategy: &S,
+		source_header_lag: Option<u64>,
+		source_last_success: Instant,
+		target_last_success: Instant,
+		now_time: Instant,
+	) -> bool
+	where
+		P: MessageRace,
+		S: RaceStrategy<P::SourceHeaderId, P::TargetHeaderId, P::Proof>,
+	{
+		let need_update = now_time.saturating_duration_since(self.prev_time) > PROGRESS_INTERVAL;
+		if !need_update {
+			return false;
+		}
+
+		self.prev_time = now_time;
+
+		// until both nodes have reported their nonces, we can't tell how many nonces are synced
+		let best_nonces = match (strategy.best_at_source_opt(), strategy.best_at_target_opt()) {
+			(Some(best_at_source), Some(best_at_target)) => {
+				self.add_sample(now_time, best_at_target);
+				Some((best_at_source, best_at_target))
+			}
+			_ => None,
+		};
+
+		let synced = best_nonces
+			.map(|(best_at_source, best_at_target)| format!("{} of {}", best_at_target, best_at_source))
+			.unwrap_or_else(|| "unknown".into());
+		let delivery_rate = self
+			.delivery_rate()
+			.map(|rate| format!("{:.2} nonces/min", rate))
+			.unwrap_or_else(|| "unknown".into());
+		let eta = best_nonces
+			.and_then(|(best_at_source, best_at_target)| self.eta(best_at_source.saturating_sub(best_at_target)))
+			.map(|eta| format!("{}s", eta.as_secs()))
+			.unwrap_or_else(|| "unknown".into());
+		let source_header_lag = source_header_lag
+			.map(|source_header_lag| format!("{} blocks", source_header_lag))
+			.unwrap_or_else(|| "unknown".into());
+		let queue_summary = strategy.queue_summary().unwrap_or_else(|| "unknown".into());
+		log::info!(
+			target: "bridge",
+			"[lane {}] {}: synced {} nonces in {} -> {} race. Delivery rate: {}, ETA: {}. \
+			Source header lag: {}. Queue: {}. Last successful responses: {}s ago from {}, {}s ago from {}",
+			lane_name,
+			P::race_kind(),
+			synced,
+			P::source_name(),
+			P::target_name(),
+			delivery_rate,
+			eta,
+			source_header_lag,
+			queue_summary,
+			now_time.saturating_duration_since(source_last_success).as_secs(),
+			P::source_name(),
+			now_time.saturating_duration_since(target_last_success).as_secs(),
+			P::target_name(),
+		);
+		true
+	}
+}
+
+/// Select nonces to deliver.
+///
+/// Strategy panic (e.g. broken invariant of the strategy) is converted into the race error, so
+/// it doesn't bring down other races and lanes that are running in the same process.
+#[allow(clippy::type_complexity)]
+fn select_nonces_to_deliver<SourceHeaderId, TargetHeaderId, Proof, Strategy>(
+	race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+	strategy: &mut Strategy,
+) -> Result<Option<(SourceHeaderId, RangeInclusive<MessageNonce>, Strategy::ProofParameters)>, RaceError>
+where
+	SourceHeaderId: Clone,
+	Strategy: RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+{
+	std::panic::catch_unwind(AssertUnwindSafe(|| {
+		race_state.target_state.as_ref().and_then(|target_state| {
+			strategy
+				.select_nonces_to_deliver(&race_state)
+				.map(|(nonces_range, proof_parameters, at_block)| {
+					(
+						at_block.unwrap_or_else(|| target_state.best_peer.clone()),
+						nonces_range,
+						proof_parameters,
+					)
+				})
+		})
+	}))
+	.map_err(|panic| {
+		let error = panic
+			.downcast_ref::<&str>()
+			.map(|error| error.to_string())
+			.or_else(|| panic.downcast_ref::<String>().cloned())
+			.unwrap_or_else(|| "<unknown>".into());
+		RaceError {
+			failed_client: FailedClient::Both,
+			reason: RaceErrorReason::StrategyPanic(error),
+			summary: RaceSummary::default(),
+		}
+	})
+}
+
+/// Generate proof of given nonces.
+///
+/// If the proof size limit is set, the range is shrunk until the estimated size of its proof fits
+/// the limit. The size is estimated at most `max_estimations` times. Parameters of the proof of
+/// the shrunk range are computed by the `prefix_proof_parameters`.
+async fn generate_proof_within_size_limit<P: MessageRace, SC: SourceClient<P>>(
+	race_source: &SC,
+	lane_name: &str,
+	proof_size_limit: Option<ProofSizeLimit>,
+	at_block: P::SourceHeaderId,
+	mut nonces_range: RangeInclusive<MessageNonce>,
+	mut proof_parameters: SC::ProofParameters,
+	prefix_proof_parameters: PrefixProofParameters<SC::ProofParameters>,
+) -> Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>, P::Proof), SC::Error> {
+	if let Some(proof_size_limit) = proof_size_limit {
+		for _ in 0..proof_size_limit.max_estimations {
+			let estimated_proof_size = race_source
+				.estimate_proof_size(at_block.clone(), nonces_range.clone(), proof_parameters.clone())
+				.await?;
+			let nonces_count = nonces_range.end() - nonces_range.start() + 1;
+			if estimated_proof_size <= proof_size_limit.max_proof_size || nonces_count == 1 {
+				break;
+			}
+
+			// assume that the proof size is proportional to the number of nonces
+			let fitting_nonces_count = (nonces_count as u128 * proof_size_limit.max_proof_size as u128
+				/ estimated_proof_size as u128) as MessageNonce;
+			let shrunk_nonces_range =
+				*nonces_range.start()..=nonces_range.start() + fitting_nonces_count.clamp(1, nonces_count - 1) - 1;
+			log::debug!(
+				target: "bridge",
+				"[lane {}] Estimated size of {} proof of nonces {:?} is {}, which exceeds the limit {}. Shrinking range to {:?}",
+				lane_name,
+				P::source_name(),
+				nonces_range,
+				estimated_proof_size,
+				proof_size_limit.max_proof_size,
+				shrunk_nonces_range,
+			);
+			proof_parameters = prefix_proof_parameters(&shrunk_nonces_range);
+			nonces_range = shrunk_nonces_range;
+		}
+	}
+
+	race_source
+		.generate_proof(at_block, nonces_range, proof_parameters)
+		.await
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::message_race_strategy::{BasicStrategy, QueueOverflowPolicy, RaceCheckpoint};
+	use crate::testing::{
+		header_id, TestError, TestSourceHeaderHash, TestSourceHeaderId, TestSourceHeaderNumber, TestTargetHeaderHash,
+		TestTargetHeaderId, TestTargetHeaderNumber, TestTransactionTracker,
+	};
+	use futures::channel::oneshot;
+	use parking_lot::Mutex;
+	use relay_utils::HeaderId;
+
+	pub type TestProof = RangeInclusive<MessageNonce>;
+
+	pub struct TestRace;
+
+	impl MessageRace for TestRace {
+		type SourceHeaderId = TestSourceHeaderId;
+		type TargetHeaderId = TestTargetHeaderId;
+
+		type MessageNonce = MessageNonce;
+		type Proof = TestProof;
+
+		fn source_name() -> String {
+			"TestSource".into()
+		}
+
+		fn target_name() -> String {
+			"TestTarget".into()
+		}
+
+		fn race_kind() -> String {
+			"Test".into()
+		}
+
+		fn proof_size(proof: &TestProof) -> usize {
+			(proof.end() + 1 - proof.start()) as usize
+		}
+	}
+
+	#[derive(Default)]
+	struct TestRaceData {
+		/// Latest nonce generated at the source node.
+		source_latest_nonce: MessageNonce,
+		/// Latest nonce received by the target node.
+		target_latest_nonce: MessageNonce,
+		/// Latest nonce received by the target node at its best finalized header. If `None`, it
+		/// is the same as the `target_latest_nonce`.
+		target_finalized_latest_nonce: Option<MessageNonce>,
+		/// Headers at which target nonces have been requested, as `(at_best, at_finalized)`.
+		target_nonces_requests: Vec<(TestTargetHeaderId, TestTargetHeaderId)>,
+		/// Options of all started proof submissions.
+		submit_options: Vec<SubmitOptions>,
+		/// Blocks at which source nonces have been requested.
+		source_nonces_requests: Vec<TestSourceHeaderId>,
+		/// Previous latest nonces, passed to source nonces requests.
+		source_nonces_prev_latest: Vec<Option<MessageNonce>>,
+		/// All generated proofs, along with blocks they have been generated at.
+		generated_proofs: Vec<(TestSourceHeaderId, TestProof)>,
+		/// All submitted proofs.
+		submitted_proofs: Vec<TestProof>,
+		/// If set, next `submit_proof` call will notify the first channel when it is started and
+		/// then will wait until the second channel is resolved.
+		submit_gate: Option<(oneshot::Sender<()>, oneshot::Receiver<()>)>,
+		/// If true, `submit_proof` calls never complete.
+		is_submit_stalled: bool,
+		/// Number of started `submit_proof` calls.
+		started_submissions: usize,
+		/// If true, source `nonces` calls fail.
+		is_source_nonces_fails: bool,
+		/// If true, `generate_proof` calls fail.
+		is_generate_proof_fails: bool,
+		/// Number of next source `reconnect` calls that fail. Once the source client is
+		/// reconnected, its `nonces` and `generate_proof` calls no longer fail.
+		source_reconnect_failures: usize,
+		/// If true, the source client doesn't support reconnection.
+		is_source_reconnect_unsupported: bool,
+		/// Number of source `reconnect` calls.
+		source_reconnect_attempts: usize,
+		/// Number of target `reconnect` calls.
+		target_reconnect_attempts: usize,
+		/// Spec version of the source node runtime.
+		source_runtime_version: Option<u32>,
+		/// Spec version of the target node runtime.
+		target_runtime_version: Option<u32>,
+		/// Number of source `on_runtime_upgrade` calls.
+		source_runtime_upgrades: usize,
+		/// Number of target `on_runtime_upgrade` calls.
+		target_runtime_upgrades: usize,
+		/// If true, source `nonces` calls never complete.
+		is_source_nonces_stalled: bool,
+		/// If true, submitted proofs are not included into target chain (i.e. `target_latest_nonce`
+		/// is not updated) until the test does that explicitly.
+		is_target_slow: bool,
+		/// Number of next submitted transactions that are lost.
+		lost_transactions: usize,
+		/// Number of next target `nonces` calls that fail.
+		target_nonces_failures: usize,
+		/// Number of next target `nonces` calls that fail because the target node is busy.
+		busy_target_nonces_failures: usize,
+		/// Number of next submitted proofs that are rejected by the target node.
+		rejected_proofs: usize,
+		/// Number of next proofs that fail verification at the target node.
+		invalid_proofs: usize,
+		/// If true, `submit_proof` calls fail with fatal error.
+		is_submit_fatal: bool,
+		/// Number of next `submit_proof` calls that fail with temporary error.
+		submit_failures: usize,
+		/// Addresses of proofs passed to all `submit_proof` calls.
+		submitted_proofs_addresses: Vec<usize>,
+		/// Every target client request takes this time to complete.
+		target_request_delay: Duration,
+		/// Number of target client requests that are currently in flight.
+		target_requests_in_flight: usize,
+		/// Maximal number of target client requests that have been in flight at the same time.
+		max_target_requests_in_flight: usize,
+		/// All verified proofs, along with blocks they have been generated at.
+		verified_proofs: Vec<(TestSourceHeaderId, TestProof)>,
+		/// If set, next `generate_proof` call will notify the first channel when it is started and
+		/// then will wait until the second channel is resolved.
+		generate_proof_gate: Option<(oneshot::Sender<()>, oneshot::Receiver<()>)>,
+		/// If true, the source client is able to trim generated proofs.
+		is_proof_trimmable: bool,
+		/// If true, `generate_proof` calls never complete.
+		is_generate_proof_stalled: bool,
+		/// Number of `generate_proof` calls that have been cancelled before completion.
+		cancelled_proof_generations: usize,
+		/// Number of `select_nonces_to_deliver` calls of the `CountingStrategy`.
+		nonces_selections: usize,
+		/// Source headers that the race has required to be known to the target node.
+		required_source_headers: Vec<TestSourceHeaderId>,
+		/// Source header that the target client reports as required.
+		target_required_source_header: Option<TestSourceHeaderId>,
+		/// Best source headers passed to all `required_source_header_at_target` calls.
+		target_required_source_header_requests: Vec<TestSourceHeaderId>,
+		/// If true, the `CountingStrategy` uses selector that breaks strategy invariants.
+		is_selector_broken: bool,
+		/// Scripted results of next `estimate_proof_size` calls. If empty, the estimated size is
+		/// the actual proof size.
+		estimated_proof_sizes: VecDeque<u32>,
+		/// Ranges passed to all `estimate_proof_size` calls.
+		proof_size_estimations: Vec<RangeInclusive<MessageNonce>>,
+		/// If set, the `CountingStrategy` requires delivery cost estimation and only approves
+		/// delivery if the cost is not greater than this value.
+		max_delivery_cost: Option<u64>,
+		/// Scripted results of next `estimate_delivery_cost` calls. If empty, the cost is zero.
+		delivery_costs: VecDeque<u64>,
+		/// Ranges passed to all `estimate_delivery_cost` calls.
+		delivery_cost_estimations: Vec<RangeInclusive<MessageNonce>>,
+		/// Number of next `estimate_delivery_cost` calls that fail.
+		delivery_cost_failures: usize,
+		/// Scripted outcomes of next `dry_run_proof` calls. If empty, the submission would succeed.
+		/// If the outcome is `AlreadyDelivered`, proved nonces are delivered by other relayer.
+		dry_run_outcomes: VecDeque<DryRunOutcome>,
+		/// Proofs passed to all `dry_run_proof` calls.
+		dry_run_proofs: Vec<TestProof>,
+		/// If true, the target client is able to submit several proofs in a single transaction.
+		is_batch_submission_supported: bool,
+		/// Nonces ranges of proofs passed to all `submit_proofs` calls.
+		submitted_batches: Vec<Vec<RangeInclusive<MessageNonce>>>,
+	}
+
+	/// Target client error. It is never a connection error.
+	#[derive(Debug)]
+	pub(super) enum TestTargetError {
+		/// Target node is busy, so request may be retried immediately.
+		Busy,
+		/// Request has failed, but may succeed if retried.
+		Temporary,
+		/// Submitted proof has been rejected.
+		ProofRejected,
+		/// Proof has failed verification.
+		InvalidProof,
+		/// Request has failed and would never succeed.
+		Fatal,
+	}
+
+	impl RelayError for TestTargetError {
+		fn classification(&self) -> ErrorClassification {
+			match *self {
+				TestTargetError::Busy => ErrorClassification::RetryImmediately,
+				TestTargetError::Fatal => ErrorClassification::Fatal,
+				TestTargetError::Temporary | TestTargetError::ProofRejected | TestTargetError::InvalidProof => {
+					ErrorClassification::RetryWithBackoff
+				}
+			}
+		}
+	}
+
+	impl MaybeFatalError for TestTargetError {}
+
+	impl MaybeRejectedTransactionError for TestTargetError {
+		fn is_rejected_transaction(&self) -> bool {
+			matches!(*self, TestTargetError::ProofRejected)
+		}
+	}
+
+	struct TestRaceSource {
+		data: Arc<Mutex<TestRaceData>>,
+	}
+
+	#[async_trait]
+	impl SourceClient<TestRace> for TestRaceSource {
+		type Error = TestError;
+		type NoncesRange = RangeInclusive<MessageNonce>;
+		type ProofParameters = ();
+
+		async fn reconnect(&mut self) -> Result<(), ReconnectError<TestError>> {
+			let mut data = self.data.lock();
+			data.source_reconnect_attempts += 1;
+			if data.is_source_reconnect_unsupported {
+				return Err(ReconnectError::Unsupported);
+			}
+			if data.source_reconnect_failures != 0 {
+				data.source_reconnect_failures -= 1;
+				return Err(ReconnectError::Failed(TestError));
+			}
+
+			data.is_source_nonces_fails = false;
+			data.is_generate_proof_fails = false;
+			Ok(())
+		}
+
+		async fn runtime_version(&self) -> Result<Option<u32>, TestError> {
+			Ok(self.data.lock().source_runtime_version)
+		}
+
+		fn on_runtime_upgrade(&self) {
+			self.data.lock().source_runtime_upgrades += 1;
+		}
+
+		async fn nonces(
+			&self,
+			at_block: TestSourceHeaderId,
+			prev_latest_nonce: Option<MessageNonce>,
+		) -> Result<(TestSourceHeaderId, SourceClientNonces<Self::NoncesRange>), TestError> {
+			let is_source_nonces_stalled = {
+				let mut data = self.data.lock();
+				data.source_nonces_requests.push(at_block);
+				data.source_nonces_prev_latest.push(prev_latest_nonce);
+				if data.is_source_nonces_fails {
+					return Err(TestError);
+				}
+				data.is_source_nonces_stalled
+			};
+			if is_source_nonces_stalled {
+				futures::future::pending::<()>().await;
+			}
+
+			let data = self.data.lock();
+			Ok((
+				at_block,
+				SourceClientNonces {
+					new_nonces: prev_latest_nonce.unwrap_or(0) + 1..=data.source_latest_nonce,
+					confirmed_nonce: None,
+					unrewarded_relayer_entries: None,
+				},
+			))
+		}
+
+		async fn estimate_proof_size(
+			&self,
+			_at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			_proof_parameters: (),
+		) -> Result<u32, TestError> {
+			let mut data = self.data.lock();
+			data.proof_size_estimations.push(nonces.clone());
+			Ok(data
+				.estimated_proof_sizes
+				.pop_front()
+				.unwrap_or_else(|| TestRace::proof_size(&nonces) as u32))
+		}
+
+		async fn generate_proof(
+			&self,
+			at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			_proof_parameters: (),
+		) -> Result<(TestSourceHeaderId, RangeInclusive<MessageNonce>, TestProof), TestError> {
+			let mut generate_proof_guard = GenerateProofGuard {
+				data: self.data.clone(),
+				is_completed: false,
+			};
+			let (generate_proof_gate, is_generate_proof_stalled) = {
+				let mut data = self.data.lock();
+				if data.is_generate_proof_fails {
+					generate_proof_guard.is_completed = true;
+					return Err(TestError);
+				}
+				data.generated_proofs.push((at_block, nonces.clone()));
+				(data.generate_proof_gate.take(), data.is_generate_proof_stalled)
+			};
+			if is_generate_proof_stalled {
+				futures::future::pending::<()>().await;
+			}
+			if let Some((started_sender, release_receiver)) = generate_proof_gate {
+				let _ = started_sender.send(());
+				let _ = release_receiver.await;
+			}
+
+			generate_proof_guard.is_completed = true;
+			Ok((at_block, nonces.clone(), nonces))
+		}
+
+		fn trim_proof(&self, _proof: &TestProof, nonces: RangeInclusive<MessageNonce>) -> Option<TestProof> {
+			if self.data.lock().is_proof_trimmable {
+				Some(nonces)
+			} else {
+				None
+			}
+		}
+	}
+
+	struct TestRaceTarget {
+		data: Arc<Mutex<TestRaceData>>,
+	}
+
+	#[async_trait]
+	impl TargetClient<TestRace> for TestRaceTarget {
+		type Error = TestTargetError;
+		type TransactionTracker = TestTransactionTracker;
+		type Cost = u64;
+		type TargetNoncesData = ();
+
+		async fn reconnect(&mut self) -> Result<(), ReconnectError<TestTargetError>> {
+			self.data.lock().target_reconnect_attempts += 1;
+			Ok(())
+		}
+
+		async fn runtime_version(&self) -> Result<Option<u32>, TestTargetError> {
+			Ok(self.data.lock().target_runtime_version)
+		}
+
+		fn on_runtime_upgrade(&self) {
+			self.data.lock().target_runtime_upgrades += 1;
+		}
+
+		async fn nonces(
+			&self,
+			at_best: TestTargetHeaderId,
+			at_finalized: TestTargetHeaderId,
+		) -> Result<(TestTargetHeaderId, TargetNonces<()>), TestTargetError> {
+			let _request_guard = TargetRequestGuard::start(&self.data).await;
+			let mut data = self.data.lock();
+			if data.target_nonces_failures != 0 {
+				data.target_nonces_failures -= 1;
+				return Err(TestTargetError::Temporary);
+			}
+			if data.busy_target_nonces_failures != 0 {
+				data.busy_target_nonces_failures -= 1;
+				return Err(TestTargetError::Busy);
+			}
+			data.target_nonces_requests.push((at_best, at_finalized));
+			Ok((
+				at_best,
+				TargetNonces {
+					at_best: TargetClientNonces {
+						latest_nonce: data.target_latest_nonce,
+						nonces_data: (),
+					},
+					at_finalized: TargetClientNonces {
+						latest_nonce: data.target_finalized_latest_nonce.unwrap_or(data.target_latest_nonce),
+						nonces_data: (),
+					},
+				},
+			))
+		}
+
+		async fn verify_proof(
+			&self,
+			generated_at_block: TestSourceHeaderId,
+			_nonces: RangeInclusive<MessageNonce>,
+			proof: &TestProof,
+		) -> Result<(), TestTargetError> {
+			let _request_guard = TargetRequestGuard::start(&self.data).await;
+			let mut data = self.data.lock();
+			data.verified_proofs.push((generated_at_block, proof.clone()));
+			if data.invalid_proofs != 0 {
+				data.invalid_proofs -= 1;
+				return Err(TestTargetError::InvalidProof);
+			}
+			Ok(())
+		}
+
+		async fn dry_run_proof(
+			&self,
+			_generated_at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			proof: &TestProof,
+		) -> Result<DryRunOutcome, TestTargetError> {
+			let _request_guard = TargetRequestGuard::start(&self.data).await;
+			let mut data = self.data.lock();
+			data.dry_run_proofs.push(proof.clone());
+			let outcome = data.dry_run_outcomes.pop_front().unwrap_or(DryRunOutcome::Success);
+			if outcome == DryRunOutcome::AlreadyDelivered {
+				data.target_latest_nonce = std::cmp::max(data.target_latest_nonce, *nonces.end());
+			}
+			Ok(outcome)
+		}
+
+		async fn required_source_header_at_target(
+			&self,
+			current_best: TestSourceHeaderId,
+		) -> Result<Option<TestSourceHeaderId>, TestTargetError> {
+			let mut data = self.data.lock();
+			data.target_required_source_header_requests.push(current_best);
+			Ok(data.target_required_source_header)
+		}
+
+		async fn submit_proof(
+			&self,
+			_generated_at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			proof: Arc<TestProof>,
+			options: SubmitOptions,
+		) -> Result<(RangeInclusive<MessageNonce>, TestTransactionTracker), TestTargetError> {
+			let _request_guard = TargetRequestGuard::start(&self.data).await;
+			let (submit_gate, is_submit_stalled) = {
+				let mut data = self.data.lock();
+				data.started_submissions += 1;
+				data.submit_options.push(options);
+				(data.submit_gate.take(), data.is_submit_stalled)
+			};
+			if is_submit_stalled {
+				futures::future::pending::<()>().await;
+			}
+			if let Some((started_sender, release_receiver)) = submit_gate {
+				let _ = started_sender.send(());
+				let _ = release_receiver.await;
+			}
+
+			let mut data = self.data.lock();
+			data.submitted_proofs_addresses.push(Arc::as_ptr(&proof) as usize);
+			if data.is_submit_fatal {
+				return Err(TestTargetError::Fatal);
+			}
+			if data.submit_failures != 0 {
+				data.submit_failures -= 1;
+				return Err(TestTargetError::Temporary);
+			}
+			if data.rejected_proofs != 0 {
+				data.rejected_proofs -= 1;
+				return Err(TestTargetError::ProofRejected);
+			}
+			data.submitted_proofs.push((*proof).clone());
+			if data.lost_transactions != 0 {
+				data.lost_transactions -= 1;
+				return Ok((nonces, TestTransactionTracker(TrackedTransactionStatus::Lost)));
+			}
+			if !data.is_target_slow {
+				data.target_latest_nonce = *proof.end();
+			}
+			Ok((nonces, TestTransactionTracker(TrackedTransactionStatus::Finalized)))
+		}
+
+		fn supports_batch_submission(&self) -> bool {
+			self.data.lock().is_batch_submission_supported
+		}
+
+		async fn submit_proofs(
+			&self,
+			batch: Vec<(TestSourceHeaderId, RangeInclusive<MessageNonce>, Arc<TestProof>)>,
+			options: SubmitOptions,
+		) -> Result<(RangeInclusive<MessageNonce>, TestTransactionTracker), TestTargetError> {
+			self.data
+				.lock()
+				.submitted_batches
+				.push(batch.iter().map(|(_, nonces, _)| nonces.clone()).collect());
+
+			let nonces_begin = *batch[0].1.start();
+			let mut tx_tracker = None;
+			for (generated_at_block, nonces, proof) in batch {
+				tx_tracker = Some(self.submit_proof(generated_at_block, nonces, proof, options).await?);
+			}
+			let (nonces, tx_tracker) = tx_tracker.expect("batch is never empty; qed");
+			Ok((nonces_begin..=*nonces.end(), tx_tracker))
+		}
+
+		async fn estimate_delivery_cost(
+			&self,
+			nonces: RangeInclusive<MessageNonce>,
+			_total_dispatch_weight: Weight,
+			_total_size: u32,
+		) -> Result<u64, TestTargetError> {
+			let _request_guard = TargetRequestGuard::start(&self.data).await;
+			let mut data = self.data.lock();
+			data.delivery_cost_estimations.push(nonces);
+			if data.delivery_cost_failures != 0 {
+				data.delivery_cost_failures -= 1;
+				return Err(TestTargetError::Temporary);
+			}
+			Ok(data.delivery_costs.pop_front().unwrap_or(0))
+		}
+	}
+
+	/// Guard of the target client request. Tracks number of concurrent requests.
+	struct TargetRequestGuard {
+		data: Arc<Mutex<TestRaceData>>,
+	}
+
+	impl TargetRequestGuard {
+		/// Start the request and wait for `target_request_delay`.
+		async fn start(data: &Arc<Mutex<TestRaceData>>) -> Self {
+			let target_request_delay = {
+				let mut data = data.lock();
+				data.target_requests_in_flight += 1;
+				data.max_target_requests_in_flight =
+					std::cmp::max(data.max_target_requests_in_flight, data.target_requests_in_flight);
+				data.target_request_delay
+			};
+			let guard = TargetRequestGuard { data: data.clone() };
+			if target_request_delay != Duration::from_secs(0) {
+				async_std::task::sleep(target_request_delay).await;
+			}
+			guard
+		}
+	}
+
+	impl Drop for TargetRequestGuard {
+		fn drop(&mut self) {
+			self.data.lock().target_requests_in_flight -= 1;
+		}
+	}
+
+	/// Guard of the source client `generate_proof` call. Tracks cancelled calls.
+	struct GenerateProofGuard {
+		data: Arc<Mutex<TestRaceData>>,
+		is_completed: bool,
+	}
+
+	impl Drop for GenerateProofGuard {
+		fn drop(&mut self) {
+			if !self.is_completed {
+				self.data.lock().cancelled_proof_generations += 1;
+			}
+		}
+	}
+
+	type TestStrategy = BasicStrategy<
+		TestSourceHeaderNumber,
+		TestSourceHeaderHash,
+		TestTargetHeaderNumber,
+		TestTargetHeaderHash,
+		RangeInclusive<MessageNonce>,
+		TestProof,
+		u64,
+	>;
+
+	/// Strategy that counts `select_nonces_to_deliver` calls. If `is_selector_broken` is set in
+	/// the race data, nonces are selected using selector that breaks strategy invariants.
+	struct CountingStrategy {
+		inner: TestStrategy,
+		data: Arc<Mutex<TestRaceData>>,
+	}
+
+	impl RaceStrategy<TestSourceHeaderId, TestTargetHeaderId, TestProof> for CountingStrategy {
+		type SourceNoncesRange = RangeInclusive<MessageNonce>;
+		type ProofParameters = ();
+		type Checkpoint = <TestStrategy as RaceStrategy<TestSourceHeaderId, TestTargetHeaderId, TestProof>>::Checkpoint;
+		type Cost = u64;
+		type TargetNoncesData = ();
+
+		fn is_empty(&self) -> bool {
+			self.inner.is_empty()
+		}
+
+		fn best_at_source(&self) -> MessageNonce {
+			self.inner.best_at_source()
+		}
+
+		fn best_at_target(&self) -> MessageNonce {
+			self.inner.best_at_target()
+		}
+
+		fn requires_target_finality(&self) -> bool {
+			self.inner.requires_target_finality()
+		}
+
+		fn source_nonces_updated(
+			&mut self,
+			at_block: TestSourceHeaderId,
+			nonces: SourceClientNonces<Self::SourceNoncesRange>,
+		) {
+			self.inner.source_nonces_updated(at_block, nonces)
+		}
+
+		fn source_reorged(&mut self, new_best: &TestSourceHeaderId) {
+			self.inner.source_reorged(new_best)
+		}
+
+		fn target_nonces_updated(
+			&mut self,
+			nonces: TargetClientNonces<()>,
+			race_state: &mut RaceState<TestSourceHeaderId, TestTargetHeaderId, TestProof>,
+		) {
+			self.inner.target_nonces_updated(nonces, race_state)
+		}
+
+		fn select_nonces_to_deliver(
+			&mut self,
+			race_state: &RaceState<TestSourceHeaderId, TestTargetHeaderId, TestProof>,
+		) -> Option<(RangeInclusive<MessageNonce>, (), Option<TestSourceHeaderId>)> {
+			let is_selector_broken = {
+				let mut data = self.data.lock();
+				data.nonces_selections += 1;
+				data.is_selector_broken
+			};
+			if is_selector_broken {
+				return self
+					.inner
+					.select_nonces_to_deliver_with_selector(race_state, |_| Some(0..=0))
+					.unwrap_or_else(|error| panic!("{}", error))
+					.map(|range| (range, (), None));
+			}
+			self.inner.select_nonces_to_deliver(race_state)
+		}
+
+		fn delivery_cost_required(&self, nonces: &RangeInclusive<MessageNonce>) -> Option<(Weight, u32)> {
+			self.data
+				.lock()
+				.max_delivery_cost
+				.map(|_| (nonces.end() - nonces.start() + 1, 0))
+		}
+
+		fn delivery_cost_estimated(&mut self, _nonces: &RangeInclusive<MessageNonce>, cost: u64) -> bool {
+			self.data
+				.lock()
+				.max_delivery_cost
+				.map(|max_delivery_cost| cost <= max_delivery_cost)
+				.unwrap_or(true)
+		}
+
+		fn required_source_header_at_target(&self, current_best: &TestSourceHeaderId) -> Option<TestSourceHeaderId> {
+			self.inner.required_source_header_at_target(current_best)
+		}
+
+		fn checkpoint(&self) -> Self::Checkpoint {
+			self.inner.checkpoint()
+		}
+	}
+
+	/// Race event, recorded by the test observer.
+	#[derive(Debug, PartialEq)]
+	enum TestRaceEvent {
+		NoncesSelected(TestProof),
+		ProofGenerated(TestSourceHeaderId, TestProof),
+		ProofSubmitted(TestProof),
+		RangeConfirmed(TestProof),
+	}
+
+	/// Race observer that records all race events.
+	#[derive(Default)]
+	struct TestRaceObserver {
+		events: Arc<Mutex<Vec<TestRaceEvent>>>,
+	}
+
+	impl RaceObserver<TestRace> for TestRaceObserver {
+		fn on_nonces_selected(&mut self, nonces: &RangeInclusive<MessageNonce>) {
+			self.events.lock().push(TestRaceEvent::NoncesSelected(nonces.clone()));
+		}
+
+		fn on_proof_generated(&mut self, at_block: &TestSourceHeaderId, nonces: &RangeInclusive<MessageNonce>) {
+			self.events
+				.lock()
+				.push(TestRaceEvent::ProofGenerated(*at_block, nonces.clone()));
+		}
+
+		fn on_proof_submitted(&mut self, nonces: &RangeInclusive<MessageNonce>) {
+			self.events.lock().push(TestRaceEvent::ProofSubmitted(nonces.clone()));
+		}
+
+		fn on_range_confirmed(&mut self, nonces: &RangeInclusive<MessageNonce>) {
+			self.events.lock().push(TestRaceEvent::RangeConfirmed(nonces.clone()));
+		}
+	}
+
+	/// Returns state stream that yields given states and then never ends.
+	fn state_stream<S>(states: Vec<S>) -> impl FusedStream<Item = S> {
+		futures::stream::iter(states).chain(futures::stream::pending()).fuse()
+	}
+
+	/// Returns channel of client states. The stream never terminates, even if the sender is
+	/// dropped, so tests may drop it before the race exits.
+	fn state_channel<S>() -> (UnboundedSender<S>, impl FusedStream<Item = S>) {
+		let (sender, receiver) = futures::channel::mpsc::unbounded();
+		(sender, receiver.chain(futures::stream::pending()).fuse())
+	}
+
+	fn source_state(best_self: u64, best_peer: u64) -> SourceClientState<TestRace> {
+		ClientState {
+			best_self: header_id(best_self),
+			best_finalized_self: header_id(best_self),
+			best_peer: header_id(best_peer),
+		}
+	}
+
+	fn target_state(best_self: u64, best_peer: u64) -> TargetClientState<TestRace> {
+		ClientState {
+			best_self: header_id(best_self),
+			best_finalized_self: header_id(best_self),
+			best_peer: header_id(best_peer),
+		}
+	}
+
+	/// Waits until given condition is true for the race data.
+	async fn wait_until(data: &Arc<Mutex<TestRaceData>>, condition: impl Fn(&TestRaceData) -> bool) {
+		for _ in 0..100 {
+			if condition(&data.lock()) {
+				return;
+			}
+			async_std::task::sleep(Duration::from_millis(10)).await;
+		}
+		panic!("Race has not reached expected state in time");
+	}
+
+	pub(super) fn race_params(stall_timeout: Duration) -> RaceParams {
+		RaceParams {
+			source_stall_timeout: stall_timeout,
+			target_stall_timeout: stall_timeout,
+			resubmit_timeout: Duration::from_secs(60),
+			source_retry_backoff: Default::default(),
+			target_retry_backoff: Default::default(),
+			dry_run: false,
+			final_nonce: None,
+			refresh_interval: None,
+			state_notifier: None,
+			verify_proofs: false,
+			dry_run_before_submit: false,
+			reselect_on_newer_target_state: false,
+			reselect_threshold: 0,
+			lane_name: "test".into(),
+			max_in_flight: 1,
+			max_proofs_per_batch: 1,
+			max_queued_nonces: None,
+			competition_delay: None,
+			random_delay: |max_delay| max_delay,
+			proof_generation_timeout: None,
+			proof_size_limit: None,
+			rpc_timeout: Duration::from_secs(60),
+			nonces_at_target_known_header: false,
+			source_nonces_at: BlockRef::Best,
+			source_confirmation_depth: 0,
+			proof_at_queued_header: false,
+			require_target_finality: false,
+			max_source_header_lag: None,
+			max_consecutive_failures: None,
+			max_reconnect_attempts: 0,
+			delivery_condition: None,
+			balance_guard: None,
+			submit_policy: SubmitPolicy::default(),
+		}
+	}
+
+	fn run_test_race(
+		data: Arc<Mutex<TestRaceData>>,
+		source_states: impl FusedStream<Item = SourceClientState<TestRace>>,
+		target_states: impl FusedStream<Item = TargetClientState<TestRace>>,
+		params: RaceParams,
+		exit_signal: impl Future<Output = ()>,
+	) -> impl Future<Output = Result<(), RaceError>> {
+		run_test_race_with_control(
+			data,
+			source_states,
+			target_states,
+			params,
+			futures::stream::pending(),
+			exit_signal,
+		)
+	}
+
+	fn run_test_race_with_control(
+		data: Arc<Mutex<TestRaceData>>,
+		source_states: impl FusedStream<Item = SourceClientState<TestRace>>,
+		target_states: impl FusedStream<Item = TargetClientState<TestRace>>,
+		params: RaceParams,
+		control: impl FusedStream<Item = RaceCommand>,
+		exit_signal: impl Future<Output = ()>,
+	) -> impl Future<Output = Result<(), RaceError>> {
+		run_test_race_with_summary(data, source_states, target_states, params, control, exit_signal)
+			.map(|result| result.map(drop))
+	}
+
+	fn run_test_race_with_summary(
+		data: Arc<Mutex<TestRaceData>>,
+		source_states: impl FusedStream<Item = SourceClientState<TestRace>>,
+		target_states: impl FusedStream<Item = TargetClientState<TestRace>>,
+		params: RaceParams,
+		control: impl FusedStream<Item = RaceCommand>,
+		exit_signal: impl Future<Output = ()>,
+	) -> impl Future<Output = Result<RaceSummary, RaceError>> {
+		let final_nonce = params.final_nonce;
+		let source_confirmation_depth = params.source_confirmation_depth;
+		let proof_at_queued_header = params.proof_at_queued_header;
+		let require_target_finality = params.require_target_finality;
+		run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			params,
+			BasicStrategy::new()
+				.with_final_nonce(final_nonce)
+				.with_source_confirmation_depth(source_confirmation_depth)
+				.with_proof_at_queued_header(proof_at_queued_header)
+				.with_target_finality_required(require_target_finality),
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control,
+				on_checkpoint: |_| (),
+				on_required_source_header: move |header| data.lock().required_source_headers.push(header),
+				observer: (),
+				metrics: None,
+				exit_signal,
+			},
+		)
+	}
+
+	/// Returns source states stream where every block generates new message.
+	fn source_states_with_new_message_at_every_block(
+		data: Arc<Mutex<TestRaceData>>,
+		block_time: Duration,
+	) -> impl FusedStream<Item = SourceClientState<TestRace>> {
+		futures::stream::unfold(1, move |block| {
+			let data = data.clone();
+			async move {
+				async_std::task::sleep(block_time).await;
+				data.lock().source_latest_nonce = block;
+				Some((source_state(block, 0), block + 1))
+			}
+		})
+		.fuse()
+	}
+
+	fn race_params_with_fast_retries(stall_timeout: Duration) -> RaceParams {
+		let fast_retry_backoff = RetryBackoffParams {
+			initial: Duration::from_millis(10),
+			max: Duration::from_millis(10),
+			multiplier: 1.0,
+			..Default::default()
+		};
+		RaceParams {
+			source_retry_backoff: fast_retry_backoff.clone(),
+			target_retry_backoff: fast_retry_backoff,
+			..race_params(stall_timeout)
+		}
+	}
+
+	#[test]
+	fn race_loop_finishes_pending_submission_before_exit() {
+		let (submit_started_sender, submit_started_receiver) = oneshot::channel();
+		let (submit_release_sender, submit_release_receiver) = oneshot::channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			submit_gate: Some((submit_started_sender, submit_release_receiver)),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = async move {
+			// ask race to exit while the proof is being submitted
+			submit_started_receiver.await.unwrap();
+			exit_sender.send(()).unwrap();
+
+			// give the race some time to (incorrectly) drop submission, and then complete it
+			async_std::task::sleep(Duration::from_millis(50)).await;
+			submit_release_sender.send(()).unwrap();
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=10]);
+	}
+
+	fn run_dry_run_test(dry_run_outcomes: Vec<DryRunOutcome>) -> Arc<Mutex<TestRaceData>> {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			dry_run_outcomes: dry_run_outcomes.into(),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				dry_run_before_submit: true,
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		assert_eq!(data.lock().target_latest_nonce, 10);
+		data
+	}
+
+	#[test]
+	fn race_loop_submits_proof_if_dry_run_succeeds() {
+		let data = run_dry_run_test(vec![]);
+		let data = data.lock();
+		assert_eq!(data.dry_run_proofs, vec![1..=10]);
+		assert_eq!(data.submitted_proofs, vec![1..=10]);
+	}
+
+	#[test]
+	fn race_loop_skips_submission_if_dry_run_reports_delivered_nonces() {
+		let data = run_dry_run_test(vec![DryRunOutcome::AlreadyDelivered]);
+		let data = data.lock();
+		assert_eq!(data.dry_run_proofs, vec![1..=10]);
+		assert_eq!(data.generated_proofs.len(), 1);
+		assert_eq!(data.started_submissions, 0);
+	}
+
+	#[test]
+	fn race_loop_regenerates_proof_if_dry_run_fails() {
+		let data = run_dry_run_test(vec![DryRunOutcome::Failure]);
+		let data = data.lock();
+		assert_eq!(data.dry_run_proofs, vec![1..=10, 1..=10]);
+		assert_eq!(data.generated_proofs.len(), 2);
+		assert_eq!(data.submitted_proofs, vec![1..=10]);
+	}
+
+	fn run_runtime_upgrade_test(is_runtime_upgraded: bool) -> Arc<Mutex<TestRaceData>> {
+		let (submit_started_sender, submit_started_receiver) = oneshot::channel();
+		let (submit_release_sender, submit_release_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			source_runtime_version: Some(1),
+			target_runtime_version: Some(1),
+			submit_gate: Some((submit_started_sender, submit_release_receiver)),
+			submit_failures: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// runtime is upgraded after the proof is generated, but before it is submitted
+				submit_started_receiver.await.unwrap();
+				if is_runtime_upgraded {
+					data.lock().target_runtime_version = Some(2);
+				}
+				submit_release_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().target_latest_nonce, 10);
+		assert_eq!(data.lock().started_submissions, 2);
+		data
+	}
+
+	#[test]
+	fn race_loop_regenerates_proof_if_runtime_is_upgraded() {
+		let data = run_runtime_upgrade_test(true);
+		let data = data.lock();
+		assert_eq!(data.generated_proofs.len(), 2);
+		assert_eq!(data.source_runtime_upgrades, 0);
+		assert_eq!(data.target_runtime_upgrades, 1);
+	}
+
+	#[test]
+	fn race_loop_resubmits_proof_if_runtime_is_not_upgraded() {
+		let data = run_runtime_upgrade_test(false);
+		let data = data.lock();
+		assert_eq!(data.generated_proofs.len(), 1);
+		assert_eq!(data.source_runtime_upgrades, 0);
+		assert_eq!(data.target_runtime_upgrades, 0);
+	}
+
+	#[test]
+	fn race_loop_does_not_restart_pending_submission() {
+		let (submit_started_sender, submit_started_receiver) = oneshot::channel();
+		let (submit_release_sender, submit_release_receiver) = oneshot::channel();
+		let (source_states_sender, source_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			submit_gate: Some((submit_started_sender, submit_release_receiver)),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			state_stream(vec![target_state(0, 10)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(10, 0)).unwrap();
+				submit_started_receiver.await.unwrap();
+
+				// wake up the race while the proof is being submitted
+				source_states_sender.unbounded_send(source_state(11, 0)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().started_submissions, 1);
+
+				submit_release_sender.send(()).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().started_submissions, 1);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=10]);
+	}
+
+	#[test]
+	fn race_loop_only_reads_nonces_at_latest_of_queued_states() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0), source_state(2, 0), source_state(3, 0)]),
+			state_stream(vec![target_state(0, 3)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| !data.source_nonces_requests.is_empty()).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().source_nonces_requests, vec![header_id(3)]);
+	}
+
+	#[test]
+	fn race_loop_reads_nonces_at_source_header_known_to_target() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				nonces_at_target_known_header: true,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nonces are not read until we know which source header is known to the target
+				source_states_sender.unbounded_send(source_state(5, 0)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert!(data.lock().source_nonces_requests.is_empty());
+
+				// nonces are read at the best source header known to the target
+				target_states_sender.unbounded_send(target_state(0, 3)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.len() == 1).await;
+
+				// and read again when target sees the new source header
+				target_states_sender.unbounded_send(target_state(1, 4)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.len() == 2).await;
+
+				// but never at the header that is newer than the best source header
+				target_states_sender.unbounded_send(target_state(2, 7)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.len() == 3).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().source_nonces_requests,
+			vec![header_id(3), header_id(4), header_id(5)],
+		);
+	}
+
+	fn run_source_nonces_at_test(source_nonces_at: BlockRef) -> Vec<TestSourceHeaderId> {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				source_nonces_at,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender
+					.unbounded_send(ClientState {
+						best_self: header_id(5),
+						best_finalized_self: header_id(3),
+						best_peer: header_id(0),
+					})
+					.unwrap();
+				target_states_sender.unbounded_send(target_state(0, 0)).unwrap();
+				wait_until(&data, |data| !data.source_nonces_requests.is_empty()).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		let source_nonces_requests = data.lock().source_nonces_requests.clone();
+		source_nonces_requests
+	}
+
+	#[test]
+	fn race_loop_reads_source_nonces_at_best_header() {
+		assert_eq!(run_source_nonces_at_test(BlockRef::Best), vec![header_id(5)]);
+	}
+
+	#[test]
+	fn race_loop_reads_source_nonces_at_finalized_header() {
+		assert_eq!(run_source_nonces_at_test(BlockRef::Finalized), vec![header_id(3)]);
+	}
+
+	#[test]
+	fn race_loop_reports_source_header_required_at_target() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nonces are queued at header#5, which is unknown to the target node
+				source_states_sender.unbounded_send(source_state(5, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+				wait_until(&data, |data| data.required_source_headers.len() == 1).await;
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// once header#5 is known to the target node, nonces are delivered
+				target_states_sender.unbounded_send(target_state(1, 5)).unwrap();
+				wait_until(&data, |data| data.target_latest_nonce == 5).await;
+
+				// new nonces are queued at header#6, which is unknown to the target node
+				data.lock().source_latest_nonce = 7;
+				source_states_sender.unbounded_send(source_state(6, 0)).unwrap();
+				wait_until(&data, |data| data.required_source_headers.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().required_source_headers, vec![header_id(5), header_id(6)]);
+	}
+
+	/// Runs race where nonces are queued at source header#5, but the target node only knows
+	/// source header#1. Returns race data once the target client has been asked which source
+	/// header it needs.
+	fn run_target_required_source_header_test(
+		target_required_source_header: Option<TestSourceHeaderId>,
+	) -> Arc<Mutex<TestRaceData>> {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			target_required_source_header,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(5, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| !data.target_required_source_header_requests.is_empty()).await;
+				// let the race process the answer
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().target_required_source_header_requests, vec![header_id(1)]);
+		assert!(data.lock().generated_proofs.is_empty());
+		data
+	}
+
+	#[test]
+	fn race_loop_relies_on_strategy_if_target_client_is_up_to_date() {
+		let data = run_target_required_source_header_test(None);
+		assert_eq!(data.lock().required_source_headers, vec![header_id(5)]);
+	}
+
+	#[test]
+	fn race_loop_reports_source_header_required_by_target_client() {
+		// e.g. header#3 is the mandatory header that the target node is missing
+		let data = run_target_required_source_header_test(Some(header_id(3)));
+		assert_eq!(data.lock().required_source_headers.last(), Some(&header_id(3)));
+	}
+
+	/// Runs race where the target node has received nonces `1..=5` at its best header#10, but only
+	/// nonces `1..=3` at its best finalized header#8.
+	fn run_target_finality_test(require_target_finality: bool) -> Arc<Mutex<TestRaceData>> {
+		let (source_states_sender, source_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			target_latest_nonce: 5,
+			target_finalized_latest_nonce: Some(3),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			state_stream(vec![ClientState {
+				best_self: header_id(10),
+				best_finalized_self: header_id(8),
+				best_peer: header_id(1),
+			}]),
+			RaceParams {
+				require_target_finality,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// source nonces are only read after target nonces, so that nothing is selected
+				// before the target nonces are known
+				wait_until(&data, |data| !data.target_nonces_requests.is_empty()).await;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				if require_target_finality {
+					wait_until(&data, |data| !data.generated_proofs.is_empty()).await;
+				} else {
+					wait_until(&data, |data| !data.source_nonces_requests.is_empty()).await;
+					async_std::task::sleep(Duration::from_millis(50)).await;
+				}
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		data
+	}
+
+	#[test]
+	fn race_loop_reads_target_nonces_at_best_and_finalized_headers() {
+		let data = run_target_finality_test(false);
+		assert_eq!(data.lock().target_nonces_requests[0], (header_id(10), header_id(8)));
+	}
+
+	#[test]
+	fn race_loop_trusts_nonces_at_best_target_header_by_default() {
+		let data = run_target_finality_test(false);
+		assert!(data.lock().generated_proofs.is_empty());
+	}
+
+	#[test]
+	fn race_loop_trusts_nonces_at_finalized_target_header_if_finality_is_required() {
+		let data = run_target_finality_test(true);
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 4..=5)]);
+	}
+
+	#[test]
+	fn race_loop_reads_nonces_periodically_if_states_are_not_updated() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(10)),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| data.source_nonces_requests.len() == 1).await;
+
+				// new message is only noticed after refresh
+				data.lock().source_latest_nonce = 1;
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert!(data
+			.lock()
+			.source_nonces_requests
+			.iter()
+			.all(|at_block| *at_block == header_id(1)));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=1]);
+	}
+
+	#[test]
+	fn race_loop_does_not_stall_while_delivering_backlog() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+		const BLOCK_TIME: Duration = Duration::from_millis(20);
+
+		// every source block generates new message and target always lags three source blocks
+		// behind => the strategy queue is never empty and we always have something to deliver
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+		let source_states = {
+			let data = data.clone();
+			futures::stream::unfold(1, move |block| {
+				let data = data.clone();
+				async move {
+					async_std::task::sleep(BLOCK_TIME).await;
+					data.lock().source_latest_nonce = block;
+					Some((source_state(block, 0), block + 1))
+				}
+			})
+		};
+		let target_states = futures::stream::unfold(1, |block| async move {
+			async_std::task::sleep(BLOCK_TIME).await;
+			Some((target_state(block, block.saturating_sub(3)), block + 1))
+		});
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states.fuse(),
+			target_states.fuse(),
+			race_params(STALL_TIMEOUT),
+			async_std::task::sleep(STALL_TIMEOUT * 5),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		assert!(data.lock().submitted_proofs.len() > 1);
+	}
+
+	#[test]
+	fn race_loop_reports_client_connection_error() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_source_nonces_fails: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data,
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			race_params(Duration::from_secs(60)),
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection("Error retrieving nonces from TestSource: TestError".into()),
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_reconnects_client_after_connection_error() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			is_generate_proof_fails: true,
+			source_reconnect_failures: 2,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				max_reconnect_attempts: 3,
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		let data = data.lock();
+		assert_eq!(data.source_reconnect_attempts, 3);
+		assert_eq!(data.target_reconnect_attempts, 0);
+		assert_eq!(data.target_latest_nonce, 10);
+		// the strategy still knows nonces that have been read before the client is reconnected (nonces
+		// may be delivered before they're read again)
+		assert_eq!(data.source_nonces_prev_latest[0], None);
+		assert!(data.source_nonces_prev_latest[1..]
+			.iter()
+			.all(|nonce| *nonce == Some(10)));
+	}
+
+	#[test]
+	fn race_loop_reports_connection_error_if_client_is_not_reconnected() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_source_nonces_fails: true,
+			source_reconnect_failures: 5,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			RaceParams {
+				max_reconnect_attempts: 3,
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection("Error retrieving nonces from TestSource: TestError".into()),
+			)),
+		);
+		assert_eq!(data.lock().source_reconnect_attempts, 3);
+	}
+
+	#[test]
+	fn race_loop_does_not_retry_reconnect_if_client_does_not_support_it() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_source_nonces_fails: true,
+			is_source_reconnect_unsupported: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			RaceParams {
+				max_reconnect_attempts: 3,
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection("Error retrieving nonces from TestSource: TestError".into()),
+			)),
+		);
+		assert_eq!(data.lock().source_reconnect_attempts, 1);
+	}
+
+	#[test]
+	fn both_failed_clients_are_reconnected() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_reconnect_failures: 1,
+			..Default::default()
+		}));
+
+		let is_reconnected = async_std::task::block_on(reconnect_failed_client::<TestRace, _, _>(
+			&mut TestRaceSource { data: data.clone() },
+			&mut TestRaceTarget { data: data.clone() },
+			&RaceParams {
+				max_reconnect_attempts: 3,
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			&AsyncStdTimer,
+			futures::future::pending().fuse(),
+			RaceError {
+				failed_client: FailedClient::Both,
+				reason: RaceErrorReason::Connection("TestError".into()),
+				summary: RaceSummary::default(),
+			},
+		));
+
+		assert_eq!(is_reconnected, Ok(true));
+		let data = data.lock();
+		assert_eq!(data.source_reconnect_attempts, 2);
+		assert_eq!(data.target_reconnect_attempts, 1);
+	}
+
+	#[test]
+	fn race_loop_reports_connection_error_if_source_nonces_request_times_out() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_source_nonces_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data,
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			RaceParams {
+				rpc_timeout: Duration::from_millis(100),
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection(
+					"Error retrieving nonces from TestSource: Request has timed out after 100ms".into()
+				),
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_reports_connection_error_if_target_nonces_request_times_out() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			target_request_delay: Duration::from_secs(60 * 60),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data,
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			RaceParams {
+				rpc_timeout: Duration::from_millis(100),
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Target,
+				RaceErrorReason::Connection(
+					"Error retrieving nonces from TestTarget: Request has timed out after 100ms".into()
+				),
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_fails_when_source_state_stream_terminates() {
+		let race_loop = run_test_race(
+			Arc::new(Mutex::new(TestRaceData::default())),
+			futures::stream::iter(vec![source_state(1, 0)]).fuse(),
+			state_stream(vec![target_state(0, 1)]),
+			race_params(Duration::from_secs(60)),
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection("TestSource state stream has terminated".into()),
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_fails_when_target_state_stream_terminates() {
+		let race_loop = run_test_race(
+			Arc::new(Mutex::new(TestRaceData::default())),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(vec![target_state(0, 1)]).fuse(),
+			race_params(Duration::from_secs(60)),
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Target,
+				RaceErrorReason::Connection("TestTarget state stream has terminated".into()),
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_fails_immediately_after_fatal_error() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_submit_fatal: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(1, 1)]),
+			race_params_with_fast_retries(Duration::from_secs(60)),
+			futures::future::pending(),
+		);
+
+		let result = async_std::task::block_on(race_loop);
+		assert!(matches!(
+			result,
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Fatal(_),
+				..
+			})
+		));
+		assert_eq!(data.lock().started_submissions, 1);
+	}
+
+	#[test]
+	fn race_loop_blames_target_client_if_only_target_is_stalled() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+		const BLOCK_TIME: Duration = Duration::from_millis(20);
+
+		// every source block generates new message, but the target never completes proof submission
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_submit_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states_with_new_message_at_every_block(data.clone(), BLOCK_TIME),
+			state_stream(vec![target_state(0, 1)]),
+			race_params(STALL_TIMEOUT),
+			futures::future::pending(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall { last_error: None, .. },
+				..
+			})
+		));
+		assert!(data.lock().submitted_proofs.is_empty());
+	}
+
+	#[test]
+	fn race_loop_blames_source_client_if_only_source_is_stalled() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+		// the source never generates proof, while the target keeps answering nonces requests
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_generate_proof_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(STALL_TIMEOUT / 5),
+				..race_params(STALL_TIMEOUT)
+			},
+			futures::future::pending(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Source,
+				reason: RaceErrorReason::Stall { last_error: None, .. },
+				..
+			})
+		));
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn race_loop_blames_both_clients_if_both_are_stalled() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+		// the source never generates proof and the target never answers nonces requests
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_generate_proof_stalled: true,
+			target_request_delay: Duration::from_secs(60 * 60),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data,
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(STALL_TIMEOUT / 5),
+				..race_params(STALL_TIMEOUT)
+			},
+			futures::future::pending(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Both,
+				reason: RaceErrorReason::Stall { last_error: None, .. },
+				..
+			})
+		));
+	}
+
+	/// Timer that sleeps in real time, but its clock is advanced by a minute on every call.
+	#[derive(Clone)]
+	struct FastClockTimer {
+		started_at: Instant,
+		calls: Arc<std::sync::atomic::AtomicU32>,
+	}
+
+	impl FastClockTimer {
+		fn new() -> Self {
+			FastClockTimer {
+				started_at: Instant::now(),
+				calls: Default::default(),
+			}
+		}
+	}
+
+	impl Timer for FastClockTimer {
+		fn now(&self) -> Instant {
+			let calls = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+			self.started_at + Duration::from_secs(60) * calls
+		}
+
+		fn sleep(&self, delay: Duration) -> futures::future::BoxFuture<'static, ()> {
+			AsyncStdTimer.sleep(delay)
+		}
+	}
+
+	#[test]
+	fn race_loop_measures_stall_timeout_using_race_timer() {
+		// the source never generates proof. The stall timeout is never reached by the system clock,
+		// so the race only fails if the timeout is measured by the race timer
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_generate_proof_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_with_timer(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data },
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(10)),
+				..race_params(Duration::from_secs(60 * 60))
+			},
+			BasicStrategy::new(),
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: state_stream(vec![target_state(0, 1)]),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: futures::future::pending(),
+			},
+			FastClockTimer::new(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), race_loop)),
+			Ok(Err(RaceError {
+				reason: RaceErrorReason::Stall { .. },
+				..
+			}))
+		));
+	}
+
+	#[test]
+	fn race_loop_reports_time_since_last_successful_responses_on_stall() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+		const BLOCK_TIME: Duration = Duration::from_millis(20);
+
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_submit_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states_with_new_message_at_every_block(data, BLOCK_TIME),
+			state_stream(vec![target_state(0, 1)]),
+			race_params(STALL_TIMEOUT),
+			futures::future::pending(),
+		);
+
+		match async_std::task::block_on(race_loop) {
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall {
+					since_source_success,
+					since_target_success,
+					..
+				},
+				..
+			}) => {
+				// source keeps answering, while target has been silent since the submission
+				assert!(since_source_success < STALL_TIMEOUT);
+				assert!(since_target_success >= STALL_TIMEOUT);
+			}
+			result => panic!("unexpected race result: {:?}", result),
+		}
+	}
+
+	#[test]
+	fn race_loop_retries_busy_client_immediately() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			busy_target_nonces_failures: 3,
+			..Default::default()
+		}));
+
+		// if busy client is retried after backoff, the race would not finish in time
+		let slow_retry_backoff = RetryBackoffParams {
+			initial: Duration::from_secs(60),
+			max: Duration::from_secs(60),
+			..Default::default()
+		};
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				target_retry_backoff: slow_retry_backoff,
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(async_std::future::timeout(Duration::from_secs(10), race_loop)),
+			Ok(Ok(())),
+		);
+		let data = data.lock();
+		assert_eq!(data.busy_target_nonces_failures, 0);
+		assert_eq!(data.target_latest_nonce, 10);
+	}
+
+	#[test]
+	fn race_loop_fails_after_too_many_consecutive_failures() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			target_nonces_failures: 3,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(10)),
+				max_consecutive_failures: Some(3),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(
+			async_std::task::block_on(race_loop).map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Target,
+				RaceErrorReason::ConsecutiveFailures {
+					failures: 3,
+					last_error: "Error retrieving nonces from TestTarget: Temporary".into(),
+				},
+			)),
+		);
+	}
+
+	#[test]
+	fn race_loop_resets_consecutive_failures_after_successful_request() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			target_nonces_failures: 2,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(10)),
+				max_consecutive_failures: Some(3),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// two requests fail, then some request succeeds and two more requests fail
+				wait_until(&data, |data| data.target_nonces_failures == 0).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				data.lock().target_nonces_failures = 2;
+				wait_until(&data, |data| data.target_nonces_failures == 0).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+	}
+
+	#[test]
+	fn race_loop_attaches_last_client_error_to_stall_error() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+		const BLOCK_TIME: Duration = Duration::from_millis(20);
+
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_submit_stalled: true,
+			target_nonces_failures: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states_with_new_message_at_every_block(data, BLOCK_TIME),
+			state_stream(vec![target_state(0, 1)]),
+			race_params_with_fast_retries(STALL_TIMEOUT),
+			futures::future::pending(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall { last_error: Some(ref last_error), .. },
+				..
+			}) if last_error == "Error retrieving nonces from TestTarget: Temporary"
+		));
+	}
+
+	#[test]
+	fn race_loop_forgets_client_error_after_successful_request() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+		const BLOCK_TIME: Duration = Duration::from_millis(20);
+
+		let (target_states_sender, target_states) = state_channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_submit_stalled: true,
+			target_nonces_failures: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states_with_new_message_at_every_block(data.clone(), BLOCK_TIME),
+			target_states,
+			race_params_with_fast_retries(STALL_TIMEOUT),
+			futures::future::pending(),
+		);
+		let race_driver = async move {
+			// first request fails and the next one succeeds
+			target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+			wait_until(&data, |data| data.target_nonces_failures == 0).await;
+			target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+			target_states_sender
+		};
+
+		let (result, _target_states_sender) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(matches!(
+			result,
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall { last_error: None, .. },
+				..
+			})
+		));
+	}
+
+	#[test]
+	fn race_loop_generates_next_proof_while_previous_is_not_confirmed() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs == vec![1..=5]).await;
+
+				// new messages are generated at the source, but the target hasn't yet received
+				// previous messages => proof is generated, but not submitted
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 2).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+
+				// previous messages are received by the target => next proof is submitted
+				data.lock().target_latest_nonce = 5;
+				target_states_sender.unbounded_send(target_state(3, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=10]);
+	}
+
+	fn run_batch_submission_test(is_batch_submission_supported: bool) -> Arc<Mutex<TestRaceData>> {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			is_batch_submission_supported,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				max_proofs_per_batch: 2,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs == vec![1..=5]).await;
+
+				// the target hasn't yet received previous messages, so next proof can't be submitted
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 2).await;
+				data.lock().source_latest_nonce = 15;
+				source_states_sender.unbounded_send(source_state(3, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(3, 3)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+
+				// previous messages are received by the target => pending proofs are submitted
+				data.lock().target_latest_nonce = 5;
+				data.lock().is_target_slow = false;
+				target_states_sender.unbounded_send(target_state(4, 3)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() >= 2).await;
+				target_states_sender.unbounded_send(target_state(5, 3)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 3).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		data
+	}
+
+	#[test]
+	fn race_loop_submits_queued_proofs_in_single_batch() {
+		let data = run_batch_submission_test(true);
+		let data = data.lock();
+		assert_eq!(
+			data.generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10), (header_id(3), 11..=15)],
+		);
+		assert_eq!(data.submitted_batches, vec![vec![6..=10, 11..=15]]);
+		assert_eq!(data.submitted_proofs, vec![1..=5, 6..=10, 11..=15]);
+	}
+
+	#[test]
+	fn race_loop_never_queues_proofs_if_batch_submission_is_unsupported() {
+		let data = run_batch_submission_test(false);
+		let data = data.lock();
+		assert_eq!(
+			data.generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10), (header_id(3), 11..=15)],
+		);
+		assert!(data.submitted_batches.is_empty());
+		assert_eq!(data.submitted_proofs, vec![1..=5, 6..=10, 11..=15]);
+	}
+
+	#[test]
+	fn race_loop_submits_proofs_while_previous_are_not_confirmed() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				max_in_flight: 2,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				for block in 1..=3 {
+					data.lock().source_latest_nonce = block * 5;
+					source_states_sender.unbounded_send(source_state(block, 0)).unwrap();
+					target_states_sender.unbounded_send(target_state(block, block)).unwrap();
+					wait_until(&data, |data| data.generated_proofs.len() == block as usize).await;
+				}
+
+				// the in-flight window is full => third proof isn't submitted
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=10]);
+
+				// first proof is confirmed => third proof is submitted
+				data.lock().target_latest_nonce = 5;
+				target_states_sender.unbounded_send(target_state(4, 3)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 3).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=10, 11..=15]);
+	}
+
+	#[test]
+	fn race_loop_pauses_source_nonces_queries_while_queue_is_full() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				max_queued_nonces: Some(5),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// 10 nonces are queued at block#1 => queue is full
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+
+				// source nonces are not read at block#2
+				data.lock().source_latest_nonce = 20;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert!(!data.lock().source_nonces_requests.contains(&header_id(2)));
+
+				// target has received all queued nonces => source nonces are read at block#2
+				data.lock().target_latest_nonce = 10;
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(2))).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+	}
+
+	#[test]
+	fn race_loop_passes_unknown_prev_latest_nonce_to_source_client() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			BasicStrategy::new(),
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: exit_receiver.map(|_| ()),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nothing is known about nonces yet
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+
+				// source has reported its nonces
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(2))).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		assert_eq!(data.lock().source_nonces_prev_latest[..2], [None, Some(10)]);
+	}
+
+	#[test]
+	fn race_loop_passes_known_target_nonce_to_source_client() {
+		let (source_states_sender, source_states) = state_channel();
+		let (_target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			..Default::default()
+		}));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			BasicStrategy::new_with_target_nonce(5),
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: exit_receiver.map(|_| ()),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		assert_eq!(data.lock().source_nonces_prev_latest[0], Some(5));
+	}
+
+	#[test]
+	fn race_loop_pauses_source_nonces_queries_while_strategy_queue_is_full() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			BasicStrategy::new().with_max_queue_len(1, QueueOverflowPolicy::RefuseNewNonces),
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: exit_receiver.map(|_| ()),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// single range is queued at block#1 => queue is full
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+
+				// source nonces are not read at block#2
+				data.lock().source_latest_nonce = 20;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert!(!data.lock().source_nonces_requests.contains(&header_id(2)));
+
+				// target has received all queued nonces => source nonces are read at block#2
+				data.lock().target_latest_nonce = 10;
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(2))).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+	}
+
+	#[test]
+	fn race_loop_limits_selected_nonces_after_command() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (control_sender, control) = futures::channel::mpsc::unbounded();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race_with_control(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			control,
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// all nonces are selected before the command
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+
+				// at most 3 nonces are selected after the command
+				control_sender
+					.unbounded_send(RaceCommand::SetMaxNoncesPerProof(3))
+					.unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				data.lock().source_latest_nonce = 15;
+				source_states_sender.unbounded_send(source_state(2, 1)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() >= 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs[..2],
+			[(header_id(1), 1..=5), (header_id(2), 6..=8)],
+		);
+	}
+
+	#[test]
+	fn race_loop_does_not_select_nonces_while_paused() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (control_sender, control) = futures::channel::mpsc::unbounded();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race_with_control(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			control,
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nonces are read, but not selected while the race is paused
+				control_sender.unbounded_send(RaceCommand::Pause).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// nonces are selected once the race is resumed
+				control_sender.unbounded_send(RaceCommand::Resume).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn race_loop_defers_delivery_while_delivery_condition_is_not_met() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+		let can_deliver = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(50)),
+				delivery_condition: Some(can_deliver.clone()),
+				..race_params(Duration::from_millis(300))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nonces are read, but not selected while delivery is deferred. The race doesn't
+				// stall, even though deferral lasts longer than the stall timeout
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+				async_std::task::sleep(Duration::from_millis(600)).await;
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// nonces are delivered once the condition allows it
+				can_deliver.store(true, std::sync::atomic::Ordering::SeqCst);
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+
+				// and are deferred again when it doesn't
+				can_deliver.store(false, std::sync::atomic::Ordering::SeqCst);
+				async_std::task::sleep(Duration::from_millis(100)).await;
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(2))).await;
+				async_std::task::sleep(Duration::from_millis(200)).await;
+				assert_eq!(data.lock().generated_proofs.len(), 1);
+
+				can_deliver.store(true, std::sync::atomic::Ordering::SeqCst);
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10)]
+		);
+	}
+
+	#[test]
+	fn race_loop_pauses_delivery_while_relayer_balance_is_low() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		// balance dips below the threshold at first two target blocks and then recovers
+		let balance_requests = Arc::new(Mutex::new(Vec::new()));
+		let relayer_balance = {
+			let balance_requests = balance_requests.clone();
+			move || -> RelayerBalanceFuture {
+				let mut balance_requests = balance_requests.lock();
+				let balance = [50, 50, 150].get(balance_requests.len()).copied().unwrap_or(150);
+				balance_requests.push(balance);
+				Box::pin(futures::future::ready(Ok(balance)))
+			}
+		};
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				balance_guard: Some(BalanceGuardParams {
+					relayer_balance: Arc::new(relayer_balance),
+					min_balance: 100,
+				}),
+				..race_params(Duration::from_millis(300))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			let balance_requests = balance_requests.clone();
+			async move {
+				// nonces are read, but not selected while balance is low. The race doesn't stall,
+				// even though balance stays low longer than the stall timeout
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+				async_std::task::sleep(Duration::from_millis(200)).await;
+
+				// balance is read at most once per target block
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				async_std::task::sleep(Duration::from_millis(200)).await;
+				assert_eq!(balance_requests.lock().len(), 1);
+
+				target_states_sender.unbounded_send(target_state(2, 1)).unwrap();
+				async_std::task::sleep(Duration::from_millis(200)).await;
+				assert_eq!(balance_requests.lock().len(), 2);
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// nonces are delivered once balance recovers
+				target_states_sender.unbounded_send(target_state(3, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(*balance_requests.lock(), vec![50, 50, 150]);
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn race_loop_selects_nothing_until_relayer_balance_is_read() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let (balance_sender, balance_receiver) = oneshot::channel::<u128>();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			..Default::default()
+		}));
+
+		let balance_receiver = Arc::new(Mutex::new(Some(balance_receiver)));
+		let relayer_balance = move || -> RelayerBalanceFuture {
+			let balance_receiver = balance_receiver.lock().take();
+			Box::pin(async move {
+				match balance_receiver {
+					Some(balance_receiver) => balance_receiver.await.map_err(|e| e.to_string()),
+					None => Ok(150),
+				}
+			})
+		};
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(1, 1)]),
+			RaceParams {
+				balance_guard: Some(BalanceGuardParams {
+					relayer_balance: Arc::new(relayer_balance),
+					min_balance: 100,
+				}),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// both clients have reported their nonces, but balance is not yet read
+				wait_until(&data, |data| {
+					!data.source_nonces_requests.is_empty() && !data.target_nonces_requests.is_empty()
+				})
+				.await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// nonces are delivered once balance is known to be high enough
+				balance_sender.send(150).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn race_loop_retries_relayer_balance_read_if_it_has_failed() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			..Default::default()
+		}));
+
+		let balance_reads = Arc::new(std::sync::atomic::AtomicU32::new(0));
+		let relayer_balance = {
+			let balance_reads = balance_reads.clone();
+			move || -> RelayerBalanceFuture {
+				let read_index = balance_reads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+				Box::pin(async move {
+					match read_index {
+						0 => Err("Failed to read balance".into()),
+						_ => Ok(150),
+					}
+				})
+			}
+		};
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(1, 1)]),
+			RaceParams {
+				balance_guard: Some(BalanceGuardParams {
+					relayer_balance: Arc::new(relayer_balance),
+					min_balance: 100,
+				}),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// the target block is not changed, but balance is read again after failure
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(balance_reads.load(std::sync::atomic::Ordering::SeqCst), 2);
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[cfg(feature = "tokio-watch")]
+	#[test]
+	fn watch_receiver_works_as_delivery_condition() {
+		let (sender, receiver) = tokio::sync::watch::channel(false);
+		assert!(!receiver.can_deliver_now());
+
+		sender.broadcast(true).unwrap();
+		assert!(receiver.can_deliver_now());
+	}
+
+	#[test]
+	fn race_loop_waits_for_competition_delay_before_generating_proof() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				competition_delay: Some(Duration::from_millis(400)),
+				random_delay: |max_delay| max_delay / 2,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+
+				// nonces are selected, but the proof is only generated after the delay
+				async_std::task::sleep(Duration::from_millis(100)).await;
+				assert!(data.lock().generated_proofs.is_empty());
+
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	/// Returns ranges that have been estimated before the first proof has been generated and the
+	/// range of the first proof.
+	fn run_proof_size_limit_test(
+		max_estimations: u32,
+		estimated_proof_sizes: Vec<u32>,
+	) -> (Vec<RangeInclusive<MessageNonce>>, RangeInclusive<MessageNonce>) {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			estimated_proof_sizes: estimated_proof_sizes.into(),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				proof_size_limit: Some(ProofSizeLimit {
+					max_proof_size: 8,
+					max_estimations,
+				}),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| !data.submitted_proofs.is_empty()).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		let data = data.lock();
+		let proved_nonces = data.generated_proofs[0].1.clone();
+		let estimated_nonces = data
+			.proof_size_estimations
+			.iter()
+			.take_while(|nonces| nonces.start() == proved_nonces.start())
+			.cloned()
+			.collect();
+		(estimated_nonces, proved_nonces)
+	}
+
+	#[test]
+	fn race_loop_generates_proof_without_estimations_if_proof_size_is_not_limited() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert!(data.lock().proof_size_estimations.is_empty());
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=10)]);
+	}
+
+	#[test]
+	fn race_loop_generates_proof_of_selected_range_if_it_fits_proof_size_limit() {
+		assert_eq!(run_proof_size_limit_test(3, vec![8]), (vec![1..=10], 1..=10));
+	}
+
+	#[test]
+	fn race_loop_shrinks_range_until_estimated_proof_size_fits_the_limit() {
+		// 1..=10 => 20 bytes, so 4 nonces may fit. 1..=4 => 12 bytes, so 2 nonces may fit.
+		// 1..=2 => 2 bytes (actual proof size)
+		assert_eq!(
+			run_proof_size_limit_test(3, vec![20, 12]),
+			(vec![1..=10, 1..=4, 1..=2], 1..=2)
+		);
+	}
+
+	#[test]
+	fn race_loop_caps_number_of_proof_size_estimations() {
+		// 1..=10 => 20 bytes, so 4 nonces may fit. 1..=4 => 12 bytes, so 2 nonces may fit. But the
+		// range is not estimated again
+		assert_eq!(run_proof_size_limit_test(2, vec![20, 12]), (vec![1..=10, 1..=4], 1..=2));
+	}
+
+	#[test]
+	fn race_loop_generates_proof_of_single_nonce_even_if_it_exceeds_proof_size_limit() {
+		assert_eq!(
+			run_proof_size_limit_test(5, vec![100, 100]),
+			(vec![1..=10, 1..=1], 1..=1)
+		);
+	}
+
+	#[test]
+	fn race_loop_does_not_generate_proof_if_nonces_are_delivered_by_competitor() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				competition_delay: Some(Duration::from_millis(200)),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(1))).await;
+
+				// other relayer delivers nonces while we're waiting
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				data.lock().target_latest_nonce = 5;
+
+				async_std::task::sleep(Duration::from_millis(400)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert!(data.lock().generated_proofs.is_empty());
+		assert!(data.lock().submitted_proofs.is_empty());
+	}
+
+	#[test]
+	fn race_loop_generates_proof_again_if_proof_generation_times_out() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_generate_proof_stalled: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				proof_generation_timeout: Some(Duration::from_millis(100)),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// the first proof generation never completes and target moves on meanwhile
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 1).await;
+				data.lock().is_generate_proof_stalled = false;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 2)).unwrap();
+
+				// proof is generated again at the newer header after the timeout
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 1..=5)],
+		);
+		assert_eq!(data.lock().cancelled_proof_generations, 1);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+	}
+
+	/// Runs race where other relayer delivers nonces up to `competitor_nonce` while the proof of
+	/// nonces `1..=5` is generated.
+	fn run_race_with_competing_relayer(
+		competitor_nonce: MessageNonce,
+		is_proof_trimmable: bool,
+	) -> (Arc<Mutex<TestRaceData>>, Vec<RaceStateSnapshot>) {
+		let (generate_started_sender, generate_started_receiver) = oneshot::channel();
+		let (generate_release_sender, generate_release_receiver) = oneshot::channel();
+		let (state_notifier, state_snapshots) = futures::channel::mpsc::unbounded();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			generate_proof_gate: Some((generate_started_sender, generate_release_receiver)),
+			is_proof_trimmable,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(Duration::from_millis(20)),
+				state_notifier: Some(state_notifier),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				generate_started_receiver.await.unwrap();
+				data.lock().target_latest_nonce = competitor_nonce;
+				// let the race see new target nonce before the proof is generated
+				async_std::task::sleep(Duration::from_millis(100)).await;
+				// proof generation is cancelled if all nonces have been delivered
+				let _ = generate_release_sender.send(());
+				async_std::task::sleep(Duration::from_millis(200)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		let state_snapshots = async_std::task::block_on(state_snapshots.collect::<Vec<_>>());
+		(data, state_snapshots)
+	}
+
+	#[test]
+	fn race_loop_cancels_proof_generation_if_nonces_are_delivered_by_competitor() {
+		let (data, state_snapshots) = run_race_with_competing_relayer(5, true);
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+		assert_eq!(data.lock().cancelled_proof_generations, 1);
+		assert!(data.lock().submitted_proofs.is_empty());
+		assert_eq!(
+			state_snapshots.last().map(|snapshot| snapshot.discarded_proofs),
+			Some(0)
+		);
+	}
+
+	#[test]
+	fn race_loop_trims_proof_if_nonces_are_partially_delivered_by_competitor() {
+		let (data, state_snapshots) = run_race_with_competing_relayer(3, true);
+		assert_eq!(data.lock().generated_proofs, vec![(header_id(1), 1..=5)]);
+		assert_eq!(data.lock().submitted_proofs, vec![4..=5]);
+		assert_eq!(
+			state_snapshots.last().map(|snapshot| snapshot.discarded_proofs),
+			Some(0)
+		);
+	}
+
+	#[test]
+	fn race_loop_reselects_nonces_if_untrimmable_proof_is_partially_delivered_by_competitor() {
+		let (data, state_snapshots) = run_race_with_competing_relayer(3, false);
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(1), 4..=5)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![4..=5]);
+		assert_eq!(
+			state_snapshots.last().map(|snapshot| snapshot.discarded_proofs),
+			Some(1)
+		);
+	}
+
+	#[test]
+	fn race_loop_does_not_submit_proofs_while_paused() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (control_sender, control) = futures::channel::mpsc::unbounded();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race_with_control(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				refresh_interval: Some(STALL_TIMEOUT / 4),
+				..race_params(STALL_TIMEOUT)
+			},
+			control,
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// proof of 1..=5 is submitted and proof of 6..=10 is waiting for submission
+				for block in 1..=2 {
+					data.lock().source_latest_nonce = block * 5;
+					source_states_sender.unbounded_send(source_state(block, 0)).unwrap();
+					target_states_sender.unbounded_send(target_state(block, block)).unwrap();
+					wait_until(&data, |data| data.generated_proofs.len() == block as usize).await;
+				}
+
+				control_sender.unbounded_send(RaceCommand::Pause).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+
+				// submitted nonces are confirmed and new nonces are read, but nothing is proved
+				// or submitted for longer than the stall timeout, while the race is paused
+				{
+					let mut data = data.lock();
+					data.target_latest_nonce = 5;
+					data.source_latest_nonce = 15;
+				}
+				source_states_sender.unbounded_send(source_state(3, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(3, 3)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(3))).await;
+				async_std::task::sleep(STALL_TIMEOUT * 2).await;
+				assert_eq!(data.lock().generated_proofs.len(), 2);
+				assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+
+				// delivery continues from the current state once the race is resumed
+				control_sender.unbounded_send(RaceCommand::Resume).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=10]);
+	}
+
+	#[test]
+	fn race_loop_selects_nonces_again_if_target_knows_newer_source_header() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				reselect_on_newer_target_state: true,
+				reselect_threshold: 2,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// proof of 1..=5 is submitted and proof of 6..=10 is waiting for submission
+				for block in 1..=2 {
+					data.lock().source_latest_nonce = block * 5;
+					source_states_sender.unbounded_send(source_state(block, 0)).unwrap();
+					target_states_sender.unbounded_send(target_state(block, block)).unwrap();
+					wait_until(&data, |data| data.generated_proofs.len() == block as usize).await;
+				}
+
+				// target knows about source header#5 => 6..=20 are selected instead of 6..=10
+				data.lock().source_latest_nonce = 20;
+				source_states_sender.unbounded_send(source_state(5, 0)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.contains(&header_id(5))).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				target_states_sender.unbounded_send(target_state(3, 5)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 3).await;
+
+				// nonces are never selected again for the same pending proof
+				target_states_sender.unbounded_send(target_state(4, 10)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().generated_proofs.len(), 3);
+
+				data.lock().target_latest_nonce = 5;
+				target_states_sender.unbounded_send(target_state(5, 10)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10), (header_id(5), 6..=20)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=20]);
+	}
+
+	#[test]
+	fn race_loop_never_issues_concurrent_target_requests() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			target_request_delay: Duration::from_millis(20),
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states_with_new_message_at_every_block(data.clone(), Duration::from_millis(5)),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(5)).await;
+					target_state(block, block)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				verify_proofs: true,
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		assert_eq!(data.lock().target_latest_nonce, 10);
+		assert_eq!(data.lock().max_target_requests_in_flight, 1);
+	}
+
+	#[test]
+	fn race_loop_resubmits_proof_if_transaction_is_lost() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			lost_transactions: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(10), 1..=10), (header_id(10), 1..=10)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=10, 1..=10]);
+	}
+
+	#[test]
+	fn race_loop_submits_proofs_without_tip_by_default() {
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(10, 0)]),
+			state_stream(vec![target_state(0, 10)]),
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().submit_options, vec![SubmitOptions::default()]);
+	}
+
+	#[test]
+	fn race_loop_escalates_tip_when_proof_is_resubmitted() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			lost_transactions: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				submit_policy: SubmitPolicy {
+					tip: Arc::new(|attempt| 100 * attempt as u128),
+					mortality: Some(64),
+				},
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// first transaction is lost => the same nonces are resubmitted with higher tip
+				source_states_sender.unbounded_send(source_state(10, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 10)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+
+				// new nonces are submitted with the initial tip
+				data.lock().source_latest_nonce = 15;
+				source_states_sender.unbounded_send(source_state(11, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 11)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 3).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=10, 1..=10, 11..=15]);
+		assert_eq!(
+			data.lock()
+				.submit_options
+				.iter()
+				.map(|options| (options.tip, options.mortality))
+				.collect::<Vec<_>>(),
+			vec![(100, Some(64)), (200, Some(64)), (100, Some(64))],
+		);
+	}
+
+	#[test]
+	fn race_loop_resubmits_proof_if_it_is_not_confirmed_for_too_long() {
+		const RESUBMIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				resubmit_timeout: RESUBMIT_TIMEOUT,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+
+				// nonces are not confirmed in time => proof is regenerated and resubmitted
+				async_std::task::sleep(RESUBMIT_TIMEOUT * 2).await;
+				target_states_sender.unbounded_send(target_state(2, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+
+				// original transaction is confirmed after resubmission => resubmitted nonces are
+				// not delivered again
+				{
+					let mut data = data.lock();
+					data.target_latest_nonce = 5;
+					data.source_latest_nonce = 10;
+				}
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(3, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 3).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(1), 1..=5), (header_id(2), 6..=10)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 1..=5, 6..=10]);
+	}
+
+	#[test]
+	fn race_loop_blames_target_client_if_resubmitted_proofs_are_never_included() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(200);
+		const RESUBMIT_TIMEOUT: Duration = Duration::from_millis(30);
+		const BLOCK_TIME: Duration = Duration::from_millis(10);
+
+		// target accepts all transactions, but never includes them into blocks
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+		let target_states = futures::stream::unfold(1, |block| async move {
+			async_std::task::sleep(BLOCK_TIME).await;
+			Some((target_state(block, 1), block + 1))
+		});
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			target_states.fuse(),
+			RaceParams {
+				resubmit_timeout: RESUBMIT_TIMEOUT,
+				..race_params(STALL_TIMEOUT)
+			},
+			futures::future::pending(),
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall { last_error: None, .. },
+				..
+			})
+		));
+		assert!(data.lock().submitted_proofs.len() > 1);
+	}
+
+	#[test]
+	fn race_loop_does_not_resubmit_proof_if_it_is_confirmed_in_time() {
+		const RESUBMIT_TIMEOUT: Duration = Duration::from_millis(100);
+
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				resubmit_timeout: RESUBMIT_TIMEOUT,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+
+				// nonces are confirmed before resubmit timeout
+				data.lock().target_latest_nonce = 5;
+				target_states_sender.unbounded_send(target_state(2, 1)).unwrap();
+
+				// => nothing is resubmitted after the timeout
+				async_std::task::sleep(RESUBMIT_TIMEOUT * 2).await;
+				target_states_sender.unbounded_send(target_state(3, 1)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+	}
+
+	#[test]
+	fn race_loop_exits_once_final_nonce_is_delivered() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(7),
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=7]);
+		assert_eq!(data.lock().target_latest_nonce, 7);
+	}
+
+	#[test]
+	fn race_loop_updates_metrics() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			..Default::default()
+		}));
+		let metrics = MessageRaceMetrics::new("test_race");
+		let registry = relay_utils::metrics::Registry::new();
+		relay_utils::metrics::Metrics::register(&metrics, &registry).unwrap();
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			RaceParams {
+				final_nonce: Some(7),
+				..race_params(Duration::from_secs(60))
+			},
+			BasicStrategy::new().with_final_nonce(Some(7)),
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: futures::stream::iter(1..)
+					.then(|block| async move {
+						async_std::task::sleep(Duration::from_millis(10)).await;
+						target_state(block, 1)
+					})
+					.fuse(),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: Some(metrics),
+				exit_signal: futures::future::pending(),
+			},
+		);
+		assert_eq!(async_std::task::block_on(race_loop).map(drop), Ok(()));
+
+		let gauge_value = |name: &str| {
+			registry
+				.gather()
+				.into_iter()
+				.find(|family| family.get_name() == name)
+				.map(|family| {
+					let metric = &family.get_metric()[0];
+					assert_eq!(
+						metric
+							.get_label()
+							.iter()
+							.map(|label| (label.get_name(), label.get_value()))
+							.collect::<Vec<_>>(),
+						vec![("lane", "test"), ("race", "Test")],
+					);
+					metric.get_gauge().get_value() as u64
+				})
+		};
+		assert_eq!(gauge_value("test_race_best_nonce_at_source"), Some(10));
+		assert_eq!(gauge_value("test_race_best_nonce_at_target"), Some(7));
+		assert_eq!(gauge_value("test_race_backlog"), Some(3));
+	}
+
+	#[test]
+	fn race_loop_updates_proof_metrics() {
+		let (source_states_sender, source_states) = state_channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			submit_failures: 1,
+			..Default::default()
+		}));
+		let metrics = MessageRaceMetrics::new("test_race");
+		let registry = relay_utils::metrics::Registry::new();
+		relay_utils::metrics::Metrics::register(&metrics, &registry).unwrap();
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params_with_fast_retries(Duration::from_secs(60)),
+			BasicStrategy::new(),
+			RaceContext {
+				source_updated: source_states,
+				target_updated: futures::stream::iter(1..)
+					.then(|block| async move {
+						async_std::task::sleep(Duration::from_millis(10)).await;
+						target_state(block, block)
+					})
+					.fuse(),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: Some(metrics),
+				exit_signal: futures::future::pending(),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// first proof is submitted after single failed submission
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				wait_until(&data, |data| data.target_latest_nonce == 5).await;
+
+				// second proof generation fails with connection error
+				{
+					let mut data = data.lock();
+					data.source_latest_nonce = 10;
+					data.is_generate_proof_fails = true;
+				}
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				source_states_sender
+			}
+		};
+
+		let (result, _source_states_sender) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(
+			result.map_err(|error| (error.failed_client, error.reason)),
+			Err((
+				FailedClient::Source,
+				RaceErrorReason::Connection("Error generating proof at TestSource: TestError".into())
+			)),
+		);
+
+		let metric_families = registry.gather();
+		let metric_values = |name: &str, is_histogram: bool| {
+			metric_families
+				.iter()
+				.find(|family| family.get_name() == name)
+				.map(|family| {
+					family
+						.get_metric()
+						.iter()
+						.map(|metric| {
+							let labels = metric
+								.get_label()
+								.iter()
+								.filter(|label| label.get_name() == "kind" || label.get_name() == "class")
+								.map(|label| label.get_value().to_string())
+								.collect::<Vec<_>>();
+							let value = if is_histogram {
+								metric.get_histogram().get_sample_count()
+							} else {
+								metric.get_counter().get_value() as u64
+							};
+							(labels, value)
+						})
+						.collect::<Vec<_>>()
+				})
+				.unwrap_or_default()
+		};
+		assert_eq!(
+			metric_values("test_race_proofs_generated_total", false),
+			vec![(vec![], 1)]
+		);
+		assert_eq!(
+			metric_values("test_race_proofs_submitted_total", false),
+			vec![(vec![], 1)]
+		);
+		assert_eq!(
+			metric_values("test_race_proof_failures_total", false),
+			vec![
+				(vec!["connection".into(), "generation".into()], 1),
+				(vec!["logical".into(), "submission".into()], 1),
+			],
+		);
+		assert_eq!(
+			metric_values("test_race_proof_generation_duration_seconds", true),
+			vec![(vec![], 2)]
+		);
+		assert_eq!(
+			metric_values("test_race_proof_submission_duration_seconds", true),
+			vec![(vec![], 2)]
+		);
+	}
+
+	#[test]
+	fn race_loop_sends_state_snapshot_when_state_changes() {
+		let (state_notifier, state_snapshots) = futures::channel::mpsc::unbounded();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data,
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(10),
+				state_notifier: Some(state_notifier),
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		let state_snapshots = async_std::task::block_on(state_snapshots.collect::<Vec<_>>());
+		assert!(state_snapshots.windows(2).all(|pair| pair[0] != pair[1]));
+		assert!(state_snapshots.contains(&RaceStateSnapshot {
+			best_at_source: 10,
+			best_at_target: 0,
+			nonces_to_submit: None,
+			nonces_submitted: vec![1..=10],
+			discarded_proofs: 0,
+			is_source_header_lagging: false,
+		}));
+		assert_eq!(
+			state_snapshots.last(),
+			Some(&RaceStateSnapshot {
+				best_at_source: 10,
+				best_at_target: 10,
+				nonces_to_submit: None,
+				nonces_submitted: vec![],
+				discarded_proofs: 0,
+				is_source_header_lagging: false,
+			}),
+		);
+	}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use crate::message_race_strategy::BasicStrategy;
-	use relay_utils::HeaderId;
+	#[test]
+	fn race_loop_reports_source_header_lag_once_until_it_recovers() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (state_notifier, state_snapshots) = futures::channel::mpsc::unbounded();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				state_notifier: Some(state_notifier),
+				max_source_header_lag: Some(2),
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// lag is large, but there are no nonces to deliver
+				source_states_sender.unbounded_send(source_state(5, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.len() == 1).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+
+				// nonces are waiting for delivery and lag is still large
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(6, 0)).unwrap();
+				wait_until(&data, |data| data.source_nonces_requests.len() == 2).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+
+				// lag is exactly at the limit
+				target_states_sender.unbounded_send(target_state(1, 4)).unwrap();
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		let state_snapshots = async_std::task::block_on(state_snapshots.collect::<Vec<_>>());
+		let lagging_states = state_snapshots
+			.iter()
+			.map(|snapshot| snapshot.is_source_header_lagging)
+			.fold(Vec::new(), |mut states, is_lagging| {
+				if states.last() != Some(&is_lagging) {
+					states.push(is_lagging);
+				}
+				states
+			});
+		assert_eq!(lagging_states, vec![false, true, false]);
+	}
+
+	#[test]
+	fn race_loop_only_selects_nonces_when_something_has_changed() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData::default()));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			CountingStrategy {
+				inner: BasicStrategy::new(),
+				data: data.clone(),
+			},
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: exit_receiver.map(|_| ()),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// nonces are selected at most once per target state, source nonces and target nonces
+				// update
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+				wait_until(&data, |data| data.nonces_selections != 0).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				let nonces_selections = data.lock().nonces_selections;
+				assert!(nonces_selections <= 3);
+
+				// same states are waking up the race, but nonces are not selected again
+				for _ in 0..10 {
+					source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+					target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+					async_std::task::sleep(Duration::from_millis(5)).await;
+				}
+				assert_eq!(data.lock().nonces_selections, nonces_selections);
+
+				// new nonces are selected
+				data.lock().source_latest_nonce = 5;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+	}
+
+	fn run_delivery_cost_test(
+		data: Arc<Mutex<TestRaceData>>,
+		source_states: impl FusedStream<Item = SourceClientState<TestRace>>,
+		target_states: impl FusedStream<Item = TargetClientState<TestRace>>,
+		exit_signal: impl Future<Output = ()>,
+	) -> impl Future<Output = Result<(), RaceError>> {
+		run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			CountingStrategy {
+				inner: BasicStrategy::new(),
+				data,
+			},
+			RaceContext {
+				source_updated: source_states,
+				target_updated: target_states,
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal,
+			},
+		)
+		.map(|result| result.map(drop))
+	}
+
+	#[test]
+	fn race_loop_estimates_delivery_cost_before_generating_proof() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			max_delivery_cost: Some(10),
+			delivery_costs: vec![10].into(),
+			..Default::default()
+		}));
+
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let race_loop = run_delivery_cost_test(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		let data = data.lock();
+		assert_eq!(data.delivery_cost_estimations, vec![1..=5]);
+		assert_eq!(data.generated_proofs, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn race_loop_does_not_generate_proof_if_delivery_cost_is_declined() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			max_delivery_cost: Some(10),
+			delivery_costs: vec![11].into(),
+			..Default::default()
+		}));
+
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let race_loop = run_delivery_cost_test(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| !data.delivery_cost_estimations.is_empty()).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		let data = data.lock();
+		assert_eq!(data.delivery_cost_estimations, vec![1..=5]);
+		assert!(data.generated_proofs.is_empty());
+		assert!(data.submitted_proofs.is_empty());
+	}
+
+	#[test]
+	fn race_loop_reuses_delivery_cost_estimation_until_target_header_changes() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			max_delivery_cost: Some(10),
+			delivery_costs: vec![11, 10].into(),
+			..Default::default()
+		}));
+
+		let race_loop = run_delivery_cost_test(data.clone(), source_states, target_states, exit_receiver.map(|_| ()));
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				// delivery is declined at target block#0
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(0, 1)).unwrap();
+				wait_until(&data, |data| !data.delivery_cost_estimations.is_empty()).await;
+
+				// nonces are selected again at the same target block, but the cost is not
+				// estimated again
+				let nonces_selections = data.lock().nonces_selections;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				wait_until(&data, |data| data.nonces_selections > nonces_selections).await;
+				async_std::task::sleep(Duration::from_millis(50)).await;
+				assert_eq!(data.lock().delivery_cost_estimations.len(), 1);
+				assert!(data.lock().generated_proofs.is_empty());
+
+				// cost is estimated again at the new target block and delivery is approved
+				target_states_sender.unbounded_send(target_state(1, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		let data = data.lock();
+		assert_eq!(data.delivery_cost_estimations, vec![1..=5, 1..=5]);
+		assert_eq!(data.submitted_proofs, vec![1..=5]);
+	}
+
+	#[test]
+	fn race_loop_retries_failed_delivery_cost_estimation_after_backoff() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			max_delivery_cost: Some(10),
+			delivery_cost_failures: 1,
+			target_request_delay: Duration::from_millis(20),
+			..Default::default()
+		}));
+
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params_with_fast_retries(Duration::from_secs(60)),
+			CountingStrategy {
+				inner: BasicStrategy::new(),
+				data: data.clone(),
+			},
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: state_stream(vec![target_state(0, 1)]),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: exit_receiver.map(|_| ()),
+			},
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert!(result.is_ok());
+		let data = data.lock();
+		assert_eq!(data.delivery_cost_estimations, vec![1..=5, 1..=5]);
+		assert_eq!(data.generated_proofs, vec![(header_id(1), 1..=5)]);
+		// estimation is never issued while other target requests are in flight
+		assert_eq!(data.max_target_requests_in_flight, 1);
+	}
+
+	#[test]
+	fn race_loop_fails_if_strategy_panics() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_selector_broken: true,
+			..Default::default()
+		}));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			race_params(Duration::from_secs(60)),
+			CountingStrategy {
+				inner: BasicStrategy::new(),
+				data,
+			},
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: state_stream(vec![target_state(0, 1)]),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: futures::future::pending(),
+			},
+		);
+
+		assert!(matches!(
+			async_std::task::block_on(race_loop),
+			Err(RaceError {
+				failed_client: FailedClient::Both,
+				reason: RaceErrorReason::StrategyPanic(ref error),
+				..
+			}) if error.starts_with("Incorrect implementation of internal `selector` function")
+		));
+	}
+
+	#[test]
+	fn race_loop_returns_summary_on_exit() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			target_nonces_failures: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race_with_summary(
+			data,
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				final_nonce: Some(5),
+				refresh_interval: Some(Duration::from_millis(10)),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::stream::pending(),
+			futures::future::pending(),
+		);
+
+		let summary = async_std::task::block_on(race_loop).unwrap();
+		assert_eq!(
+			summary,
+			RaceSummary {
+				delivered_nonces: 5,
+				generated_proofs: 1,
+				submitted_proofs: 1,
+				retries: 1,
+				runtime: summary.runtime,
+			},
+		);
+		assert_ne!(summary.runtime, Duration::from_secs(0));
+	}
+
+	#[test]
+	fn race_loop_attaches_summary_to_error() {
+		const STALL_TIMEOUT: Duration = Duration::from_millis(100);
+
+		// the proof is submitted, but never included into the target chain
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race_with_summary(
+			data,
+			state_stream(vec![source_state(1, 0)]),
+			state_stream(vec![target_state(0, 1)]),
+			RaceParams {
+				refresh_interval: Some(STALL_TIMEOUT / 5),
+				..race_params(STALL_TIMEOUT)
+			},
+			futures::stream::pending(),
+			futures::future::pending(),
+		);
+
+		let error = async_std::task::block_on(race_loop).unwrap_err();
+		assert_eq!(error.failed_client, FailedClient::Target);
+		assert_eq!(
+			error.summary,
+			RaceSummary {
+				delivered_nonces: 0,
+				generated_proofs: 1,
+				submitted_proofs: 1,
+				retries: 0,
+				runtime: error.summary.runtime,
+			},
+		);
+		assert!(error.summary.runtime >= STALL_TIMEOUT);
+	}
+
+	#[test]
+	fn race_loop_notifies_observer_about_race_events() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			..Default::default()
+		}));
+		let observer = TestRaceObserver::default();
+		let events = observer.events.clone();
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data },
+			RaceParams {
+				final_nonce: Some(5),
+				..race_params(Duration::from_secs(60))
+			},
+			BasicStrategy::new().with_final_nonce(Some(5)),
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: futures::stream::iter(1..)
+					.then(|block| async move {
+						async_std::task::sleep(Duration::from_millis(10)).await;
+						target_state(block, 1)
+					})
+					.fuse(),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer,
+				metrics: None,
+				exit_signal: futures::future::pending(),
+			},
+		);
+
+		assert!(async_std::task::block_on(race_loop).is_ok());
+		assert_eq!(
+			*events.lock(),
+			vec![
+				TestRaceEvent::NoncesSelected(1..=5),
+				TestRaceEvent::ProofGenerated(header_id(1), 1..=5),
+				TestRaceEvent::ProofSubmitted(1..=5),
+				TestRaceEvent::RangeConfirmed(1..=5),
+			],
+		);
+	}
+
+	#[test]
+	fn race_loop_continues_from_checkpoint_and_produces_new_checkpoints() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			target_latest_nonce: 3,
+			..Default::default()
+		}));
+		let checkpoints = Arc::new(Mutex::new(Vec::new()));
+
+		let race_loop = run(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			RaceParams {
+				final_nonce: Some(5),
+				..race_params(Duration::from_secs(60))
+			},
+			BasicStrategy::from_checkpoint(RaceCheckpoint {
+				target_nonce: 3,
+				source_queue: vec![(header_id(1), 4..=5)],
+			})
+			.with_final_nonce(Some(5)),
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: futures::stream::iter(1..)
+					.then(|block| async move {
+						async_std::task::sleep(Duration::from_millis(10)).await;
+						target_state(block, 1)
+					})
+					.fuse(),
+				control: futures::stream::pending(),
+				on_checkpoint: {
+					let checkpoints = checkpoints.clone();
+					move |checkpoint| checkpoints.lock().push(checkpoint)
+				},
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: futures::future::pending(),
+			},
+		);
+
+		assert!(async_std::task::block_on(race_loop).is_ok());
+		assert_eq!(data.lock().submitted_proofs, vec![4..=5]);
+		assert_eq!(
+			*checkpoints.lock(),
+			vec![RaceCheckpoint {
+				target_nonce: 5,
+				source_queue: vec![],
+			}],
+		);
+	}
+
+	#[test]
+	fn race_loop_never_submits_proofs_in_dry_run_mode() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				dry_run: true,
+				..race_params(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 1).await;
+
+				// next nonces are selected, even though target has never received previous nonces
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10)],
+		);
+		assert_eq!(data.lock().started_submissions, 0);
+	}
+
+	#[test]
+	fn race_loop_regenerates_proof_if_it_is_rejected() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			rejected_proofs: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params_with_fast_retries(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.rejected_proofs == 0).await;
+
+				// the rejected proof is regenerated at the best source header known to the target
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().started_submissions, 2);
+		assert_eq!(data.lock().generated_proofs.len(), 2);
+		assert_eq!(data.lock().generated_proofs[0], (header_id(1), 1..=5));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+	}
+
+	#[test]
+	fn race_loop_does_not_copy_proof_when_retrying_submission() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			submit_failures: 2,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(5),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		let data = data.lock();
+		assert_eq!(data.generated_proofs.len(), 1);
+		assert_eq!(data.submitted_proofs, vec![1..=5]);
+		assert_eq!(data.submitted_proofs_addresses.len(), 3);
+		assert!(data
+			.submitted_proofs_addresses
+			.iter()
+			.all(|address| *address == data.submitted_proofs_addresses[0]));
+	}
+
+	#[test]
+	fn race_loop_regenerates_proof_if_it_fails_verification() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			invalid_proofs: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			RaceParams {
+				verify_proofs: true,
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.invalid_proofs == 0).await;
+
+				// the invalid proof is regenerated at the newer block
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(data.lock().started_submissions, 1);
+		assert_eq!(data.lock().generated_proofs.len(), 2);
+		assert_eq!(data.lock().generated_proofs[0], (header_id(1), 1..=5));
+		let data = data.lock();
+		assert_eq!(data.verified_proofs, data.generated_proofs);
+		assert_eq!(data.submitted_proofs, vec![1..=5]);
+	}
+
+	#[test]
+	fn race_loop_does_not_verify_proofs_by_default() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			invalid_proofs: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			state_stream(vec![source_state(1, 0)]),
+			futures::stream::iter(1..)
+				.then(|block| async move {
+					async_std::task::sleep(Duration::from_millis(10)).await;
+					target_state(block, 1)
+				})
+				.fuse(),
+			RaceParams {
+				final_nonce: Some(5),
+				..race_params(Duration::from_secs(60))
+			},
+			futures::future::pending(),
+		);
+
+		assert_eq!(async_std::task::block_on(race_loop), Ok(()));
+		assert!(data.lock().verified_proofs.is_empty());
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5]);
+	}
+
+	#[test]
+	fn race_loop_regenerates_proof_if_source_is_reorged() {
+		let (source_states_sender, source_states) = state_channel();
+		let (target_states_sender, target_states) = state_channel();
+		let (exit_sender, exit_receiver) = oneshot::channel();
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 5,
+			is_target_slow: true,
+			..Default::default()
+		}));
+
+		let race_loop = run_test_race(
+			data.clone(),
+			source_states,
+			target_states,
+			race_params(Duration::from_secs(60)),
+			exit_receiver.map(|_| ()),
+		);
+		let race_driver = {
+			let data = data.clone();
+			async move {
+				source_states_sender.unbounded_send(source_state(1, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(1, 1)).unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 1).await;
+
+				// proof of next nonces is generated at header#2, but it isn't submitted yet
+				data.lock().source_latest_nonce = 10;
+				source_states_sender.unbounded_send(source_state(2, 0)).unwrap();
+				target_states_sender.unbounded_send(target_state(2, 2)).unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 2).await;
+
+				// header#2 is replaced with header#2' => proof is generated again
+				source_states_sender
+					.unbounded_send(ClientState {
+						best_self: HeaderId(2, 22),
+						best_finalized_self: HeaderId(2, 22),
+						best_peer: header_id(0),
+					})
+					.unwrap();
+				target_states_sender
+					.unbounded_send(ClientState {
+						best_self: header_id(3),
+						best_finalized_self: header_id(3),
+						best_peer: HeaderId(2, 22),
+					})
+					.unwrap();
+				wait_until(&data, |data| data.generated_proofs.len() == 3).await;
+
+				// regenerated proof is submitted once previous nonces are delivered
+				data.lock().target_latest_nonce = 5;
+				target_states_sender
+					.unbounded_send(ClientState {
+						best_self: header_id(4),
+						best_finalized_self: header_id(4),
+						best_peer: HeaderId(2, 22),
+					})
+					.unwrap();
+				wait_until(&data, |data| data.submitted_proofs.len() == 2).await;
+				exit_sender.send(()).unwrap();
+			}
+		};
+
+		let (result, _) = async_std::task::block_on(futures::future::join(race_loop, race_driver));
+		assert_eq!(result, Ok(()));
+		assert_eq!(
+			data.lock().generated_proofs,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10), (HeaderId(2, 22), 6..=10)],
+		);
+		assert_eq!(data.lock().submitted_proofs, vec![1..=5, 6..=10]);
+	}
 
 	#[test]
 	fn proof_is_generated_at_best_block_known_to_target_node() {
@@ -470,29 +8530,33 @@ mod tests {
 		let mut race_state = RaceState::<_, _, ()> {
 			source_state: Some(ClientState {
 				best_self: HeaderId(BEST_AT_SOURCE, BEST_AT_SOURCE),
+				best_finalized_self: HeaderId(BEST_AT_SOURCE, BEST_AT_SOURCE),
 				best_peer: HeaderId(0, 0),
 			}),
 			target_state: Some(ClientState {
 				best_self: HeaderId(0, 0),
+				best_finalized_self: HeaderId(0, 0),
 				best_peer: HeaderId(BEST_AT_TARGET, BEST_AT_TARGET),
 			}),
 			nonces_to_submit: None,
-			nonces_submitted: None,
+			proofs_to_submit: VecDeque::new(),
+			nonces_submitted: VecDeque::new(),
 		};
 
 		// we have some nonces to deliver and they're generated at GENERATED_AT < BEST_AT_SOURCE
-		let mut strategy = BasicStrategy::new();
+		let mut strategy = BasicStrategy::<_, _, _, _, _, _, ()>::new();
 		strategy.source_nonces_updated(
 			HeaderId(GENERATED_AT, GENERATED_AT),
 			SourceClientNonces {
 				new_nonces: 0..=10,
 				confirmed_nonce: None,
+				unrewarded_relayer_entries: None,
 			},
 		);
 		strategy.target_nonces_updated(
 			TargetClientNonces {
 				latest_nonce: 5u64,
-				confirmed_nonce: None,
+				nonces_data: (),
 			},
 			&mut race_state,
 		);
@@ -500,7 +8564,164 @@ mod tests {
 		// the proof will be generated on source, but using BEST_AT_TARGET block
 		assert_eq!(
 			select_nonces_to_deliver(&race_state, &mut strategy),
-			Some((HeaderId(BEST_AT_TARGET, BEST_AT_TARGET), 6..=10, (),))
+			Ok(Some((HeaderId(BEST_AT_TARGET, BEST_AT_TARGET), 6..=10, (),)))
+		);
+	}
+
+	#[test]
+	fn proof_is_generated_at_header_where_nonces_have_been_queued() {
+		const GENERATED_AT: u64 = 6;
+		const BEST_AT_SOURCE: u64 = 10;
+		const BEST_AT_TARGET: u64 = 8;
+
+		// target node only knows about source' BEST_AT_TARGET block
+		// source node has BEST_AT_SOURCE > BEST_AT_TARGET block
+		let mut race_state = RaceState::<_, _, ()> {
+			source_state: Some(ClientState {
+				best_self: HeaderId(BEST_AT_SOURCE, BEST_AT_SOURCE),
+				best_finalized_self: HeaderId(BEST_AT_SOURCE, BEST_AT_SOURCE),
+				best_peer: HeaderId(0, 0),
+			}),
+			target_state: Some(ClientState {
+				best_self: HeaderId(0, 0),
+				best_finalized_self: HeaderId(0, 0),
+				best_peer: HeaderId(BEST_AT_TARGET, BEST_AT_TARGET),
+			}),
+			nonces_to_submit: None,
+			proofs_to_submit: VecDeque::new(),
+			nonces_submitted: VecDeque::new(),
+		};
+
+		// we have some nonces to deliver and they're generated at GENERATED_AT < BEST_AT_TARGET
+		let mut strategy = BasicStrategy::<_, _, _, _, _, _, ()>::new().with_proof_at_queued_header(true);
+		strategy.source_nonces_updated(
+			HeaderId(GENERATED_AT, GENERATED_AT),
+			SourceClientNonces {
+				new_nonces: 0..=10,
+				confirmed_nonce: None,
+				unrewarded_relayer_entries: None,
+			},
+		);
+		strategy.target_nonces_updated(
+			TargetClientNonces {
+				latest_nonce: 5u64,
+				nonces_data: (),
+			},
+			&mut race_state,
+		);
+
+		// the proof will be generated on source, using GENERATED_AT block
+		assert_eq!(
+			select_nonces_to_deliver(&race_state, &mut strategy),
+			Ok(Some((HeaderId(GENERATED_AT, GENERATED_AT), 6..=10, (),)))
+		);
+	}
+
+	#[test]
+	fn nonces_spans_are_kept_until_nonces_are_delivered() {
+		let mut spans = NoncesSpans::new::<TestRace>();
+		spans.span(&(1..=5));
+		spans.span(&(6..=10));
+		// trimmed range shares the span with the original range
+		spans.span(&(3..=5));
+		assert_eq!(spans.spans.keys().cloned().collect::<Vec<_>>(), vec![5, 10]);
+
+		spans.close_delivered(7);
+		assert_eq!(spans.spans.keys().cloned().collect::<Vec<_>>(), vec![10]);
+
+		spans.close_delivered(10);
+		assert!(spans.spans.is_empty());
+	}
+
+	#[test]
+	fn progress_tracker_computes_delivery_rate() {
+		let start = Instant::now();
+		let mut tracker = ProgressTracker::new(start);
+		assert_eq!(tracker.delivery_rate(), None);
+
+		tracker.add_sample(start, 10);
+		assert_eq!(tracker.delivery_rate(), None);
+
+		tracker.add_sample(start + Duration::from_secs(30), 20);
+		tracker.add_sample(start + Duration::from_secs(60), 40);
+		assert_eq!(tracker.delivery_rate(), Some(30.0));
+	}
+
+	#[test]
+	fn progress_tracker_computes_delivery_rate_over_sliding_window() {
+		let start = Instant::now();
+		let mut tracker = ProgressTracker::new(start);
+
+		// nothing has been delivered during first interval
+		tracker.add_sample(start, 0);
+		for i in 1..=PROGRESS_RATE_WINDOW as u64 + 1 {
+			tracker.add_sample(start + Duration::from_secs(60 * i), 10 * i);
+		}
+
+		// the first interval has left the window
+		assert_eq!(tracker.samples.len(), PROGRESS_RATE_WINDOW + 1);
+		assert_eq!(tracker.delivery_rate(), Some(10.0));
+	}
+
+	#[test]
+	fn progress_tracker_computes_eta() {
+		let start = Instant::now();
+		let mut tracker = ProgressTracker::new(start);
+		assert_eq!(tracker.eta(0), Some(Duration::from_secs(0)));
+		assert_eq!(tracker.eta(10), None);
+
+		// nothing is delivered => ETA is unknown
+		tracker.add_sample(start, 10);
+		tracker.add_sample(start + Duration::from_secs(60), 10);
+		assert_eq!(tracker.eta(10), None);
+
+		// 10 nonces per minute
+		tracker.add_sample(start + Duration::from_secs(120), 30);
+		assert_eq!(tracker.eta(30), Some(Duration::from_secs(180)));
+	}
+
+	#[cfg(feature = "tokio-timer")]
+	#[test]
+	fn race_loop_works_with_tokio_timer() {
+		let data = Arc::new(Mutex::new(TestRaceData {
+			source_latest_nonce: 10,
+			target_nonces_failures: 1,
+			..Default::default()
+		}));
+
+		let race_loop = run_with_timer(
+			TestRaceSource { data: data.clone() },
+			TestRaceTarget { data: data.clone() },
+			RaceParams {
+				final_nonce: Some(7),
+				refresh_interval: Some(Duration::from_millis(10)),
+				..race_params_with_fast_retries(Duration::from_secs(60))
+			},
+			BasicStrategy::new().with_final_nonce(Some(7)),
+			RaceContext {
+				source_updated: state_stream(vec![source_state(1, 0)]),
+				target_updated: futures::stream::iter(1..)
+					.then(|block| async move {
+						tokio::time::delay_for(Duration::from_millis(10)).await;
+						target_state(block, 1)
+					})
+					.fuse(),
+				control: futures::stream::pending(),
+				on_checkpoint: |_| (),
+				on_required_source_header: |_| (),
+				observer: (),
+				metrics: None,
+				exit_signal: futures::future::pending(),
+			},
+			relay_utils::TokioTimer,
 		);
+
+		let mut runtime = tokio::runtime::Builder::new()
+			.basic_scheduler()
+			.enable_time()
+			.build()
+			.unwrap();
+		assert_eq!(runtime.block_on(race_loop).map(drop), Ok(()));
+		assert_eq!(data.lock().submitted_proofs, vec![1..=7]);
 	}
 }
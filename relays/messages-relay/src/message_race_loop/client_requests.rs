@@ -0,0 +1,223 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Requests to the race clients and handling of their failures.
+
+use super::{postpone_connection_error, process_race_future_result, RaceError, RaceParams};
+
+use backoff::{backoff::Backoff, ExponentialBackoff};
+use futures::future::{BoxFuture, FutureExt};
+use relay_utils::{FailedClient, MaybeFatalError, RelayError, RetryBackoffParams, Timer, CONNECTION_ERROR_DELAY};
+use std::{fmt::Debug, pin::Pin, time::Instant};
+
+/// Requests to one of the race clients.
+///
+/// We never issue concurrent requests of the same kind to the client. The client is online when
+/// it is ready to serve new requests. After the request failure, the client is offline until the
+/// retry delay elapses.
+pub(super) struct ClientRequests {
+	/// Client that is blamed for failures of its requests.
+	client: FailedClient,
+	/// True if the client is ready to serve new requests.
+	pub(super) is_online: bool,
+	/// True if nonces need to be read from the client.
+	pub(super) nonces_required: bool,
+	/// Backoff of retries after request failures.
+	retry_backoff: ExponentialBackoff,
+	/// Formatted error of the last failed request, if the client has not recovered yet.
+	pub(super) last_error: Option<String>,
+	/// Time of the last successful response of the client.
+	pub(super) last_success: Instant,
+	/// Number of non-connection errors in a row.
+	consecutive_failures: u32,
+	/// Resolves when the client is ready to retry failed request.
+	pub(super) go_offline_future: futures::future::Fuse<BoxFuture<'static, ()>>,
+}
+
+impl ClientRequests {
+	/// Create requests state of the client that is ready to serve requests.
+	pub(super) fn new(client: FailedClient, retry_backoff: &RetryBackoffParams, now: Instant) -> Self {
+		ClientRequests {
+			client,
+			is_online: true,
+			nonces_required: false,
+			retry_backoff: retry_backoff.backoff(),
+			last_error: None,
+			last_success: now,
+			consecutive_failures: 0,
+			go_offline_future: futures::future::Fuse::terminated(),
+		}
+	}
+
+	/// Process result of the client request and return the response, if the request has
+	/// succeeded.
+	///
+	/// Errors are handled by the `process_race_future_result`. Connection errors are postponed
+	/// (see `postpone_connection_error`), so the client stays offline until it is reconnected.
+	pub(super) fn process_result<TResult, TError>(
+		&mut self,
+		result: Result<TResult, TError>,
+		error_pattern: impl FnOnce() -> String,
+		params: &RaceParams,
+		timer: &impl Timer,
+		reconnect_error: &mut Option<RaceError>,
+	) -> Result<Option<TResult>, RaceError>
+	where
+		TError: Debug + RelayError + MaybeFatalError,
+	{
+		let mut response = None;
+		self.is_online = process_race_future_result(
+			result,
+			&mut self.retry_backoff,
+			|result| response = Some(result),
+			&mut Pin::new(&mut self.go_offline_future),
+			|delay| timer.sleep(delay),
+			error_pattern,
+			self.client,
+			&mut self.last_error,
+			&mut self.last_success,
+			timer.now(),
+			&mut self.consecutive_failures,
+			params.max_consecutive_failures,
+		)
+		.or_else(|error| postpone_connection_error(error, params, reconnect_error))?;
+		Ok(response)
+	}
+
+	/// Remember error that hasn't been reported by the client itself (e.g. if the request has
+	/// timed out). The request is retried after backoff.
+	pub(super) fn retry_after_error(&mut self, error: String, lane_name: &str, timer: &impl Timer) {
+		let retry_delay = self.retry_backoff.next_backoff().unwrap_or(CONNECTION_ERROR_DELAY);
+		log::error!(
+			target: "bridge",
+			"[lane {}] {}. Retrying in {}",
+			lane_name,
+			error,
+			retry_delay.as_secs_f64(),
+		);
+
+		self.last_error = Some(error);
+		self.go_offline_future = timer.sleep(retry_delay).fuse();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::message_race_loop::tests::{race_params, TestTargetError};
+	use crate::testing::TestError;
+	use futures::future::FusedFuture;
+	use relay_utils::AsyncStdTimer;
+	use std::time::Duration;
+
+	fn client_requests() -> ClientRequests {
+		ClientRequests::new(FailedClient::Target, &Default::default(), Instant::now())
+	}
+
+	#[test]
+	fn response_is_returned_if_request_has_succeeded() {
+		let mut requests = client_requests();
+		requests.last_error = Some("error".into());
+		requests.consecutive_failures = 1;
+
+		let mut reconnect_error = None;
+		let result = requests.process_result::<_, TestTargetError>(
+			Ok(42),
+			|| "request".into(),
+			&race_params(Duration::from_secs(60)),
+			&AsyncStdTimer,
+			&mut reconnect_error,
+		);
+
+		assert!(matches!(result, Ok(Some(42))));
+		assert!(requests.is_online);
+		assert_eq!(requests.last_error, None);
+		assert_eq!(requests.consecutive_failures, 0);
+		assert!(reconnect_error.is_none());
+	}
+
+	#[test]
+	fn client_goes_offline_if_request_has_failed() {
+		let mut requests = client_requests();
+
+		let mut reconnect_error = None;
+		let result = requests.process_result::<u32, _>(
+			Err(TestTargetError::Temporary),
+			|| "request".into(),
+			&race_params(Duration::from_secs(60)),
+			&AsyncStdTimer,
+			&mut reconnect_error,
+		);
+
+		assert!(matches!(result, Ok(None)));
+		assert!(!requests.is_online);
+		assert_eq!(requests.last_error, Some("request: Temporary".into()));
+		assert_eq!(requests.consecutive_failures, 1);
+		assert!(!requests.go_offline_future.is_terminated());
+		assert!(reconnect_error.is_none());
+	}
+
+	#[test]
+	fn connection_error_is_postponed_if_client_may_be_reconnected() {
+		let mut requests = client_requests();
+
+		let mut reconnect_error = None;
+		let result = requests.process_result::<u32, _>(
+			Err(TestError),
+			|| "request".into(),
+			&RaceParams {
+				max_reconnect_attempts: 1,
+				..race_params(Duration::from_secs(60))
+			},
+			&AsyncStdTimer,
+			&mut reconnect_error,
+		);
+
+		assert!(matches!(result, Ok(None)));
+		assert!(!requests.is_online);
+		assert_eq!(
+			reconnect_error.map(|error| error.failed_client),
+			Some(FailedClient::Target)
+		);
+	}
+
+	#[test]
+	fn connection_error_is_returned_if_client_may_not_be_reconnected() {
+		let mut requests = client_requests();
+
+		let mut reconnect_error = None;
+		let result = requests.process_result::<u32, _>(
+			Err(TestError),
+			|| "request".into(),
+			&race_params(Duration::from_secs(60)),
+			&AsyncStdTimer,
+			&mut reconnect_error,
+		);
+
+		assert_eq!(
+			result.map_err(|error| error.failed_client).err(),
+			Some(FailedClient::Target)
+		);
+		assert!(reconnect_error.is_none());
+	}
+
+	#[test]
+	fn request_is_retried_after_unreported_error() {
+		let mut requests = client_requests();
+
+		requests.retry_after_error("request has timed out".into(), "test", &AsyncStdTimer);
+
+		assert_eq!(requests.last_error, Some("request has timed out".into()));
+		assert!(!requests.go_offline_future.is_terminated());
+	}
+}
@@ -0,0 +1,176 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! State of the proof generation of selected nonces.
+
+use super::{CompetingNonces, NoncesToProve};
+
+use bp_message_lane::{MessageNonce, Weight};
+use futures::future::{BoxFuture, FutureExt};
+use std::{ops::RangeInclusive, time::Instant};
+
+/// Preparation and generation of the proof of selected nonces.
+///
+/// Selected nonces may wait for the competition delay and for the delivery cost estimation before
+/// their proof is generated. If the proof size limit is set, the range is shrunk before the proof
+/// is generated (see `generate_proof_within_size_limit`).
+pub(super) struct ProofGeneration<SourceHeaderId, TargetHeaderId, ProofParameters, Cost> {
+	/// Nonces that have been selected for delivery, but are waiting for the competition delay.
+	pub(super) competing_nonces: Option<CompetingNonces<SourceHeaderId, ProofParameters>>,
+	/// Resolves when the competition delay of `competing_nonces` has elapsed.
+	pub(super) competition_delay: futures::future::Fuse<BoxFuture<'static, ()>>,
+	/// Nonces that are going to be proved, once the strategy approves their delivery cost.
+	pub(super) nonces_to_prove: Option<NoncesToProve<SourceHeaderId, ProofParameters, Cost>>,
+	/// Nonces whose delivery cost is being estimated by the target client.
+	pub(super) cost_estimation: Option<(SourceHeaderId, RangeInclusive<MessageNonce>, ProofParameters)>,
+	/// Estimation request that is waiting until the target client is ready to serve it.
+	pub(super) cost_estimation_request: Option<(RangeInclusive<MessageNonce>, Weight, u32)>,
+	/// The last estimated delivery cost. It is reused while the best target header is the same.
+	pub(super) cost_cache: Option<(TargetHeaderId, RangeInclusive<MessageNonce>, Cost)>,
+	/// Nonces whose proof is being generated.
+	pub(super) nonces: Option<RangeInclusive<MessageNonce>>,
+	/// Span of nonces whose proof is being generated.
+	pub(super) span: Option<tracing::Span>,
+	/// Time when the proof generation has started.
+	pub(super) started_at: Instant,
+	/// Resolves when the proof generation times out.
+	pub(super) deadline: futures::future::Fuse<BoxFuture<'static, ()>>,
+	/// Number of generated proofs that have been discarded, because their nonces have been
+	/// delivered by other relayers.
+	pub(super) discarded_proofs: u64,
+}
+
+impl<SourceHeaderId, TargetHeaderId, ProofParameters, Cost>
+	ProofGeneration<SourceHeaderId, TargetHeaderId, ProofParameters, Cost>
+{
+	/// Create state of proof generation, where nothing is being proved.
+	pub(super) fn new(now: Instant) -> Self {
+		ProofGeneration {
+			competing_nonces: None,
+			competition_delay: futures::future::Fuse::terminated(),
+			nonces_to_prove: None,
+			cost_estimation: None,
+			cost_estimation_request: None,
+			cost_cache: None,
+			nonces: None,
+			span: None,
+			started_at: now,
+			deadline: futures::future::Fuse::terminated(),
+			discarded_proofs: 0,
+		}
+	}
+
+	/// Returns true if selected nonces are waiting for the competition delay or for the delivery
+	/// cost estimation.
+	pub(super) fn is_preparing(&self) -> bool {
+		self.cost_estimation.is_some() || self.competing_nonces.is_some()
+	}
+
+	/// Forget about nonces that are waiting for the competition delay or for the delivery cost
+	/// estimation.
+	pub(super) fn cancel_preparation(&mut self) {
+		self.cost_estimation = None;
+		self.cost_estimation_request = None;
+		self.competing_nonces = None;
+	}
+
+	/// Remember that the proof generation of given nonces has been started.
+	pub(super) fn start(
+		&mut self,
+		nonces_span: tracing::Span,
+		nonces_range: RangeInclusive<MessageNonce>,
+		now: Instant,
+		deadline: Option<BoxFuture<'static, ()>>,
+	) {
+		self.span = Some(nonces_span);
+		self.nonces = Some(nonces_range);
+		self.started_at = now;
+		if let Some(deadline) = deadline {
+			self.deadline = deadline.fuse();
+		}
+	}
+
+	/// Returns cached delivery cost of given nonces, if it has been estimated at the current best
+	/// target header.
+	pub(super) fn cached_cost(
+		&self,
+		target_best_header: Option<&TargetHeaderId>,
+		nonces_range: &RangeInclusive<MessageNonce>,
+	) -> Option<Cost>
+	where
+		TargetHeaderId: PartialEq,
+		Cost: Clone,
+	{
+		match self.cost_cache {
+			Some((ref cached_at, ref cached_nonces_range, ref cached_cost))
+				if Some(cached_at) == target_best_header && cached_nonces_range == nonces_range =>
+			{
+				Some(cached_cost.clone())
+			}
+			_ => None,
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use futures::future::FusedFuture;
+
+	type TestProofGeneration = ProofGeneration<u64, u64, (), u128>;
+
+	#[test]
+	fn preparation_is_cancelled() {
+		let mut proofs = TestProofGeneration::new(Instant::now());
+		assert!(!proofs.is_preparing());
+
+		proofs.cost_estimation = Some((1, 1..=10, ()));
+		proofs.cost_estimation_request = Some((1..=10, 100, 1024));
+		assert!(proofs.is_preparing());
+
+		proofs.cancel_preparation();
+		assert!(!proofs.is_preparing());
+		assert_eq!(proofs.cost_estimation_request, None);
+	}
+
+	#[test]
+	fn proof_generation_is_started() {
+		let started_at = Instant::now();
+		let mut proofs = TestProofGeneration::new(started_at);
+
+		proofs.start(tracing::Span::none(), 1..=10, started_at, None);
+		assert_eq!(proofs.nonces, Some(1..=10));
+		assert!(proofs.deadline.is_terminated());
+
+		proofs.start(
+			tracing::Span::none(),
+			11..=20,
+			started_at,
+			Some(futures::future::pending().boxed()),
+		);
+		assert_eq!(proofs.nonces, Some(11..=20));
+		assert!(!proofs.deadline.is_terminated());
+	}
+
+	#[test]
+	fn cached_cost_is_only_used_at_the_same_target_header() {
+		let mut proofs = TestProofGeneration::new(Instant::now());
+		assert_eq!(proofs.cached_cost(Some(&10), &(1..=10)), None);
+
+		proofs.cost_cache = Some((10, 1..=10, 1_000));
+		assert_eq!(proofs.cached_cost(Some(&10), &(1..=10)), Some(1_000));
+		assert_eq!(proofs.cached_cost(Some(&11), &(1..=10)), None);
+		assert_eq!(proofs.cached_cost(Some(&10), &(1..=11)), None);
+		assert_eq!(proofs.cached_cost(None, &(1..=10)), None);
+	}
+}
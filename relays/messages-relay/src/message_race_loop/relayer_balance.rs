@@ -0,0 +1,128 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Relayer balance at the race target node, watched by the balance guard.
+
+use super::{BalanceGuardParams, RaceParams, RelayerBalanceFuture};
+
+use backoff::ExponentialBackoff;
+use futures::future::{BoxFuture, FusedFuture, FutureExt};
+
+/// Relayer balance at the target node, that is watched by the balance guard.
+pub(super) struct RelayerBalance<TargetHeaderId> {
+	/// Resolves into the relayer balance.
+	pub(super) read: futures::future::Fuse<RelayerBalanceFuture>,
+	/// If the first balance read has failed, it is retried after backoff, because nothing is
+	/// selected until the balance is known. This future resolves when the read may be retried.
+	pub(super) retry: futures::future::Fuse<BoxFuture<'static, ()>>,
+	/// Backoff of retries of the first balance read.
+	pub(super) retry_backoff: ExponentialBackoff,
+	/// Target header where the balance has been read for the last time.
+	pub(super) checked_at: Option<TargetHeaderId>,
+	/// True if the balance is low.
+	pub(super) is_low: bool,
+	/// True if the balance has never been read yet. Nothing is selected until the balance is
+	/// read for the first time.
+	pub(super) is_unknown: bool,
+}
+
+impl<TargetHeaderId: Clone + PartialEq> RelayerBalance<TargetHeaderId> {
+	/// Create relayer balance that is unknown if the balance guard is enabled.
+	pub(super) fn new(params: &RaceParams) -> Self {
+		RelayerBalance {
+			read: futures::future::Fuse::terminated(),
+			retry: futures::future::Fuse::terminated(),
+			retry_backoff: params.target_retry_backoff.backoff(),
+			checked_at: None,
+			is_low: false,
+			is_unknown: params.balance_guard.is_some(),
+		}
+	}
+
+	/// Start reading the balance, unless it has already been read at the best target header.
+	/// So the balance is read at most once per target block.
+	pub(super) fn read_at(&mut self, balance_guard: &BalanceGuardParams, best_target_header: &TargetHeaderId) {
+		if self.read.is_terminated() && self.checked_at.as_ref() != Some(best_target_header) {
+			self.checked_at = Some(best_target_header.clone());
+			self.read = (balance_guard.relayer_balance)().fuse();
+		}
+	}
+
+	/// Returns true if new nonces can't be selected because of the relayer balance.
+	pub(super) fn is_delivery_blocked(&self) -> bool {
+		self.is_low || self.is_unknown
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::message_race_loop::tests::race_params;
+	use std::{
+		sync::{
+			atomic::{AtomicUsize, Ordering},
+			Arc,
+		},
+		time::Duration,
+	};
+
+	fn balance_guard(reads: Arc<AtomicUsize>) -> BalanceGuardParams {
+		BalanceGuardParams {
+			relayer_balance: Arc::new(move || {
+				reads.fetch_add(1, Ordering::SeqCst);
+				futures::future::ready(Ok(1_000)).boxed()
+			}),
+			min_balance: 100,
+		}
+	}
+
+	#[test]
+	fn delivery_is_blocked_until_balance_is_known() {
+		let mut params = race_params(Duration::from_secs(60));
+		assert!(!RelayerBalance::<u64>::new(&params).is_delivery_blocked());
+
+		params.balance_guard = Some(balance_guard(Default::default()));
+		let mut balance = RelayerBalance::<u64>::new(&params);
+		assert!(balance.is_delivery_blocked());
+
+		balance.is_unknown = false;
+		assert!(!balance.is_delivery_blocked());
+
+		balance.is_low = true;
+		assert!(balance.is_delivery_blocked());
+	}
+
+	#[test]
+	fn balance_is_read_at_most_once_per_target_header() {
+		let reads = Arc::new(AtomicUsize::new(0));
+		let guard = balance_guard(reads.clone());
+		let mut params = race_params(Duration::from_secs(60));
+		params.balance_guard = Some(guard.clone());
+		let mut balance = RelayerBalance::<u64>::new(&params);
+
+		balance.read_at(&guard, &1);
+		assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+		// the read is still in progress
+		balance.read_at(&guard, &2);
+		assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+		assert_eq!(async_std::task::block_on(&mut balance.read), Ok(1_000));
+		balance.read_at(&guard, &1);
+		assert_eq!(reads.load(Ordering::SeqCst), 1);
+
+		balance.read_at(&guard, &2);
+		assert_eq!(reads.load(Ordering::SeqCst), 2);
+		assert_eq!(balance.checked_at, Some(2));
+	}
+}
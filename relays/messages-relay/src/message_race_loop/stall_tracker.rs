@@ -0,0 +1,192 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Detection of race clients that have stalled.
+
+use super::{client_requests::ClientRequests, RaceError, RaceErrorReason, RaceParams, RaceSummary};
+
+use relay_utils::FailedClient;
+use std::time::Instant;
+
+/// Tracker of the race progress, which detects stalled clients.
+pub(super) struct StallTracker {
+	/// Time when the source client has made progress for the last time.
+	source_countdown: Instant,
+	/// Time when the target client has made progress for the last time.
+	target_countdown: Instant,
+}
+
+impl StallTracker {
+	/// Create tracker of the race that has just started.
+	pub(super) fn new(now: Instant) -> Self {
+		StallTracker {
+			source_countdown: now,
+			target_countdown: now,
+		}
+	}
+
+	/// Remember that the source client has made progress.
+	pub(super) fn on_source_progress(&mut self, now: Instant) {
+		self.source_countdown = now;
+	}
+
+	/// Remember that the target client has made progress.
+	pub(super) fn on_target_progress(&mut self, now: Instant) {
+		self.target_countdown = now;
+	}
+
+	/// Restart stall timeouts of both clients. It is used when no progress is expected (e.g.
+	/// while the race is paused).
+	pub(super) fn reset(&mut self, now: Instant) {
+		self.source_countdown = now;
+		self.target_countdown = now;
+	}
+
+	/// Returns error if any client has not made any progress for too long.
+	///
+	/// When one client is stalled, the other one usually can't make any progress either. So if
+	/// both have stalled, we only blame the client that has stopped answering our requests.
+	pub(super) fn check(
+		&self,
+		params: &RaceParams,
+		source: &mut ClientRequests,
+		target: &mut ClientRequests,
+		now: Instant,
+	) -> Result<(), RaceError> {
+		let is_source_stalled = now.saturating_duration_since(self.source_countdown) > params.source_stall_timeout;
+		let is_target_stalled = now.saturating_duration_since(self.target_countdown) > params.target_stall_timeout;
+		let since_source_success = now.saturating_duration_since(source.last_success);
+		let since_target_success = now.saturating_duration_since(target.last_success);
+		let is_source_responsive = since_source_success <= params.source_stall_timeout;
+		let is_target_responsive = since_target_success <= params.target_stall_timeout;
+		let stalled_client = match (is_source_stalled, is_target_stalled) {
+			(true, true) if is_source_responsive && !is_target_responsive => {
+				Some((FailedClient::Target, target.last_error.take()))
+			}
+			(true, true) if !is_source_responsive && is_target_responsive => {
+				Some((FailedClient::Source, source.last_error.take()))
+			}
+			(true, true) => Some((
+				FailedClient::Both,
+				match (source.last_error.take(), target.last_error.take()) {
+					(Some(source_error), Some(target_error)) => Some(format!("{}; {}", source_error, target_error)),
+					(source_error, target_error) => source_error.or(target_error),
+				},
+			)),
+			(true, false) => Some((FailedClient::Source, source.last_error.take())),
+			(false, true) => Some((FailedClient::Target, target.last_error.take())),
+			(false, false) => None,
+		};
+
+		match stalled_client {
+			Some((failed_client, last_error)) => Err(RaceError {
+				failed_client,
+				reason: RaceErrorReason::Stall {
+					last_error,
+					since_source_success,
+					since_target_success,
+				},
+				summary: RaceSummary::default(),
+			}),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::message_race_loop::tests::race_params;
+	use std::time::Duration;
+
+	const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+	fn check_at(
+		tracker: &StallTracker,
+		source: &mut ClientRequests,
+		target: &mut ClientRequests,
+		now: Instant,
+	) -> Option<FailedClient> {
+		tracker
+			.check(&race_params(STALL_TIMEOUT), source, target, now)
+			.err()
+			.map(|error| error.failed_client)
+	}
+
+	#[test]
+	fn stalled_client_is_blamed() {
+		let started_at = Instant::now();
+		let later = started_at + STALL_TIMEOUT * 2;
+		let mut tracker = StallTracker::new(started_at);
+		let mut source = ClientRequests::new(FailedClient::Source, &Default::default(), started_at);
+		let mut target = ClientRequests::new(FailedClient::Target, &Default::default(), started_at);
+
+		assert_eq!(check_at(&tracker, &mut source, &mut target, started_at), None);
+
+		tracker.on_target_progress(later);
+		assert_eq!(
+			check_at(&tracker, &mut source, &mut target, later),
+			Some(FailedClient::Source)
+		);
+
+		tracker.on_source_progress(later);
+		assert_eq!(check_at(&tracker, &mut source, &mut target, later), None);
+
+		tracker.on_source_progress(started_at);
+		tracker.on_target_progress(started_at);
+		tracker.reset(later);
+		assert_eq!(check_at(&tracker, &mut source, &mut target, later), None);
+	}
+
+	#[test]
+	fn unresponsive_client_is_blamed_if_both_clients_have_stalled() {
+		let started_at = Instant::now();
+		let later = started_at + STALL_TIMEOUT * 2;
+		let tracker = StallTracker::new(started_at);
+		let mut source = ClientRequests::new(FailedClient::Source, &Default::default(), later);
+		let mut target = ClientRequests::new(FailedClient::Target, &Default::default(), started_at);
+		target.last_error = Some("target error".into());
+
+		let error = tracker.check(&race_params(STALL_TIMEOUT), &mut source, &mut target, later);
+		assert!(matches!(
+			error,
+			Err(RaceError {
+				failed_client: FailedClient::Target,
+				reason: RaceErrorReason::Stall { last_error: Some(ref last_error), .. },
+				..
+			}) if last_error == "target error"
+		));
+		assert_eq!(target.last_error, None);
+	}
+
+	#[test]
+	fn both_clients_are_blamed_if_both_are_unresponsive() {
+		let started_at = Instant::now();
+		let later = started_at + STALL_TIMEOUT * 2;
+		let tracker = StallTracker::new(started_at);
+		let mut source = ClientRequests::new(FailedClient::Source, &Default::default(), started_at);
+		let mut target = ClientRequests::new(FailedClient::Target, &Default::default(), started_at);
+		source.last_error = Some("source error".into());
+		target.last_error = Some("target error".into());
+
+		let error = tracker.check(&race_params(STALL_TIMEOUT), &mut source, &mut target, later);
+		assert!(matches!(
+			error,
+			Err(RaceError {
+				failed_client: FailedClient::Both,
+				reason: RaceErrorReason::Stall { last_error: Some(ref last_error), .. },
+				..
+			}) if last_error == "source error; target error"
+		));
+	}
+}
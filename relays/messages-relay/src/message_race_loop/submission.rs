@@ -0,0 +1,230 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! State of submission of generated proofs to the target node.
+
+use super::{SubmitOptions, SubmitPolicy};
+
+use bp_message_lane::MessageNonce;
+use std::{
+	collections::BTreeMap,
+	ops::RangeInclusive,
+	time::{Duration, Instant},
+};
+
+/// Submission of generated proofs to the target node.
+pub(super) struct Submission<SourceHeaderId> {
+	/// Number of submissions of nonces, keyed by the first submitted nonce. Resubmitted nonces
+	/// always start from the same nonce, because they're selected again after the best nonce at
+	/// the target node.
+	attempts: BTreeMap<MessageNonce, u32>,
+	/// The best nonce that has ever been submitted.
+	best_submitted_nonce: Option<MessageNonce>,
+	/// Time when the oldest unconfirmed submission has been made. Submitted nonces are
+	/// resubmitted if they're not confirmed during `RaceParams::resubmit_timeout`.
+	oldest_submitted_at: Instant,
+	/// Time when the last submission has started.
+	pub(super) started_at: Instant,
+	/// The last proof that has been verified by the target client.
+	pub(super) verified_proof: Option<(SourceHeaderId, RangeInclusive<MessageNonce>)>,
+	/// The last proof whose submission has been successfully dry run by the target client.
+	pub(super) dry_run_passed_proof: Option<(SourceHeaderId, RangeInclusive<MessageNonce>)>,
+	/// True if nonces of the proof that is waiting for submission have already been selected
+	/// again, because the target node has seen newer source header.
+	pub(super) is_reselected: bool,
+	/// Runtime versions that have been known when the proof that is waiting for submission has
+	/// been generated.
+	pub(super) proof_runtime_versions: (Option<u32>, Option<u32>),
+	/// Nonces of the submission that is in progress.
+	pub(super) nonces: Option<RangeInclusive<MessageNonce>>,
+	/// Maximal number of proofs in a single submission. It is 1 if the target client doesn't
+	/// support batch submission.
+	pub(super) max_proofs_per_batch: usize,
+}
+
+impl<SourceHeaderId: PartialEq> Submission<SourceHeaderId> {
+	/// Create state of submission, where nothing is submitted yet.
+	pub(super) fn new(now: Instant) -> Self {
+		Submission {
+			attempts: BTreeMap::new(),
+			best_submitted_nonce: None,
+			oldest_submitted_at: now,
+			started_at: now,
+			verified_proof: None,
+			dry_run_passed_proof: None,
+			is_reselected: false,
+			proof_runtime_versions: (None, None),
+			nonces: None,
+			max_proofs_per_batch: 1,
+		}
+	}
+
+	/// Returns options of the next submission of given nonces.
+	pub(super) fn start(
+		&mut self,
+		policy: &SubmitPolicy,
+		nonces_range: &RangeInclusive<MessageNonce>,
+		now: Instant,
+	) -> (u32, SubmitOptions) {
+		let attempt = self.attempts.entry(*nonces_range.start()).or_insert(0);
+		*attempt += 1;
+		self.started_at = now;
+		self.nonces = Some(nonces_range.clone());
+		(*attempt, policy.submit_options(*attempt))
+	}
+
+	/// Remember that the proof of given nonces has been submitted. Returns true if new nonces have
+	/// been submitted.
+	///
+	/// Resubmission of the same nonces isn't a progress - otherwise we'd never report the target
+	/// client that is accepting, but never including our transactions.
+	pub(super) fn on_submitted(
+		&mut self,
+		nonces_range: &RangeInclusive<MessageNonce>,
+		is_oldest_unconfirmed: bool,
+		now: Instant,
+	) -> bool {
+		self.is_reselected = false;
+		// the resubmit timeout is started by the oldest unconfirmed submission
+		if is_oldest_unconfirmed {
+			self.oldest_submitted_at = now;
+		}
+
+		let is_new_nonces_submitted = self
+			.best_submitted_nonce
+			.map(|best_submitted_nonce| *nonces_range.end() > best_submitted_nonce)
+			.unwrap_or(true);
+		if is_new_nonces_submitted {
+			self.best_submitted_nonce = Some(*nonces_range.end());
+		}
+		is_new_nonces_submitted
+	}
+
+	/// Forget submission attempts of delivered nonces.
+	pub(super) fn on_delivered(&mut self, best_at_target: MessageNonce) {
+		self.attempts = self.attempts.split_off(&best_at_target.saturating_add(1));
+	}
+
+	/// Restart the resubmit timeout.
+	pub(super) fn restart_resubmit_timeout(&mut self, now: Instant) {
+		self.oldest_submitted_at = now;
+	}
+
+	/// Returns true if the oldest unconfirmed submission is not confirmed for too long.
+	pub(super) fn is_outdated(&self, has_unconfirmed: bool, resubmit_timeout: Duration, now: Instant) -> bool {
+		has_unconfirmed && now.saturating_duration_since(self.oldest_submitted_at) > resubmit_timeout
+	}
+
+	/// Returns true if the proof of given nonces has been verified by the target client.
+	pub(super) fn is_verified(&self, at_block: &SourceHeaderId, nonces_range: &RangeInclusive<MessageNonce>) -> bool {
+		matches!(
+			self.verified_proof,
+			Some((ref verified_at, ref verified_nonces)) if verified_at == at_block && verified_nonces == nonces_range
+		)
+	}
+
+	/// Returns true if submission of the proof of given nonces has been successfully dry run by
+	/// the target client.
+	pub(super) fn is_dry_run_passed(
+		&self,
+		at_block: &SourceHeaderId,
+		nonces_range: &RangeInclusive<MessageNonce>,
+	) -> bool {
+		matches!(
+			self.dry_run_passed_proof,
+			Some((ref dry_run_at, ref dry_run_nonces)) if dry_run_at == at_block && dry_run_nonces == nonces_range
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use std::sync::Arc;
+
+	#[test]
+	fn submission_attempts_are_counted_until_nonces_are_delivered() {
+		let now = Instant::now();
+		let policy = SubmitPolicy {
+			tip: Arc::new(|attempt| attempt as u128 * 100),
+			mortality: Some(16),
+		};
+		let mut submission = Submission::<u64>::new(now);
+
+		assert_eq!(
+			submission.start(&policy, &(1..=5), now),
+			(
+				1,
+				SubmitOptions {
+					tip: 100,
+					mortality: Some(16)
+				}
+			)
+		);
+		assert_eq!(submission.nonces, Some(1..=5));
+		assert_eq!(submission.start(&policy, &(1..=5), now).0, 2);
+		assert_eq!(submission.start(&policy, &(1..=7), now).0, 3);
+		assert_eq!(submission.start(&policy, &(6..=10), now).0, 1);
+
+		submission.on_delivered(5);
+		assert_eq!(submission.start(&policy, &(1..=5), now).0, 1);
+		assert_eq!(submission.start(&policy, &(6..=10), now).0, 2);
+	}
+
+	#[test]
+	fn only_submission_of_new_nonces_is_progress() {
+		let now = Instant::now();
+		let mut submission = Submission::<u64>::new(now);
+
+		assert!(submission.on_submitted(&(1..=5), true, now));
+		assert!(!submission.on_submitted(&(1..=5), true, now));
+		assert!(!submission.on_submitted(&(3..=4), false, now));
+		assert!(submission.on_submitted(&(1..=7), true, now));
+		assert_eq!(submission.best_submitted_nonce, Some(7));
+	}
+
+	#[test]
+	fn oldest_unconfirmed_submission_becomes_outdated() {
+		let resubmit_timeout = Duration::from_secs(60);
+		let submitted_at = Instant::now();
+		let later = submitted_at + resubmit_timeout * 2;
+		let mut submission = Submission::<u64>::new(submitted_at);
+
+		submission.on_submitted(&(1..=5), true, submitted_at);
+		assert!(!submission.is_outdated(true, resubmit_timeout, submitted_at));
+		assert!(!submission.is_outdated(false, resubmit_timeout, later));
+		assert!(submission.is_outdated(true, resubmit_timeout, later));
+
+		submission.on_submitted(&(6..=10), false, later);
+		assert!(submission.is_outdated(true, resubmit_timeout, later));
+
+		submission.restart_resubmit_timeout(later);
+		assert!(!submission.is_outdated(true, resubmit_timeout, later));
+	}
+
+	#[test]
+	fn verified_and_dry_run_proofs_are_matched_by_header_and_nonces() {
+		let mut submission = Submission::<u64>::new(Instant::now());
+		assert!(!submission.is_verified(&1, &(1..=5)));
+		assert!(!submission.is_dry_run_passed(&1, &(1..=5)));
+
+		submission.verified_proof = Some((1, 1..=5));
+		submission.dry_run_passed_proof = Some((2, 1..=5));
+		assert!(submission.is_verified(&1, &(1..=5)));
+		assert!(!submission.is_verified(&2, &(1..=5)));
+		assert!(!submission.is_verified(&1, &(1..=4)));
+		assert!(submission.is_dry_run_passed(&2, &(1..=5)));
+		assert!(!submission.is_dry_run_passed(&1, &(1..=5)));
+		assert!(!submission.is_dry_run_passed(&2, &(1..=6)));
+	}
+}
@@ -19,16 +19,27 @@ use crate::message_lane_loop::{
 	TargetClientState,
 };
 use crate::message_race_loop::{
-	MessageRace, NoncesRange, SourceClient, SourceClientNonces, TargetClient, TargetClientNonces,
+	MessageRace, NoncesRange, RaceContext, RaceError, RaceParams, RaceSummary, ReconnectError, SourceClient,
+	SourceClientNonces, TargetClient, TargetClientNonces, TargetNonces,
 };
-use crate::message_race_strategy::BasicStrategy;
+use crate::message_race_loop::{
+	RaceCommand, RaceErrorReason, RaceState, RaceStrategy, RangeFailureKind, StrategyMetricsSnapshot, SubmitOptions,
+};
+use crate::message_race_strategy::{BasicStrategy, Clock, RaceCheckpoint, SystemClock};
 use crate::metrics::MessageLaneLoopMetrics;
 
 use async_trait::async_trait;
-use bp_message_lane::MessageNonce;
+use bp_message_lane::{MessageNonce, Weight};
 use futures::stream::FusedStream;
-use relay_utils::FailedClient;
-use std::{marker::PhantomData, ops::RangeInclusive, time::Duration};
+use num_traits::Zero;
+use std::{
+	collections::VecDeque,
+	future::Future,
+	marker::PhantomData,
+	ops::RangeInclusive,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 /// Message receiving confirmations delivery strategy.
 type ReceivingConfirmationsBasicStrategy<P> = BasicStrategy<
@@ -40,30 +51,293 @@ type ReceivingConfirmationsBasicStrategy<P> = BasicStrategy<
 	<P as MessageLane>::MessagesReceivingProof,
 >;
 
+/// Lazy receiving confirmations delivery parameters.
+#[derive(Debug, Clone, Copy)]
+pub struct LazyConfirmationsParams {
+	/// Confirmations are delivered once more than this number of messages is received by the
+	/// target node, but not yet confirmed at the source node.
+	pub max_unconfirmed_messages: MessageNonce,
+	/// Confirmations are delivered once there are at least this number of unrewarded relayer
+	/// entries at the target node. It should be lower than the limit of the target node, so that
+	/// confirmations are delivered before the message delivery race is blocked by the limit.
+	pub max_unrewarded_relayer_entries: MessageNonce,
+	/// Confirmations are delivered once the oldest unconfirmed message has been waiting for
+	/// this long.
+	pub max_confirmation_delay: Duration,
+}
+
+/// Receiving confirmations delivery strategy that only delivers confirmations when it is
+/// worthwhile.
+///
+/// Every confirmation costs a source chain transaction, so confirmations are withheld until
+/// there are too many unconfirmed messages, until the target node is close to its unrewarded
+/// relayer entries limit (the message delivery race is blocked by this limit), or until the
+/// oldest unconfirmed message has been waiting for too long.
+pub struct LazyConfirmationsStrategy<P: MessageLane, C = SystemClock> {
+	/// Wrapped strategy.
+	strategy: ReceivingConfirmationsBasicStrategy<P>,
+	/// Lazy delivery parameters. If `None`, selection of the wrapped strategy is never withheld.
+	params: Option<LazyConfirmationsParams>,
+	/// Latest known number of unrewarded relayer entries at the target node.
+	unrewarded_relayer_entries: Option<MessageNonce>,
+	/// Ends of unconfirmed nonces ranges along with moments when they have been seen for the
+	/// first time. Ranges are ordered by nonces.
+	unconfirmed_since: VecDeque<(MessageNonce, Instant)>,
+	/// Clock.
+	clock: C,
+}
+
+impl<P: MessageLane> LazyConfirmationsStrategy<P> {
+	/// Create new lazy confirmations strategy.
+	pub fn new(strategy: ReceivingConfirmationsBasicStrategy<P>, params: Option<LazyConfirmationsParams>) -> Self {
+		Self::with_clock(strategy, params, SystemClock)
+	}
+}
+
+impl<P: MessageLane, C: Clock> LazyConfirmationsStrategy<P, C> {
+	/// Create new lazy confirmations strategy that is using given clock.
+	pub fn with_clock(
+		strategy: ReceivingConfirmationsBasicStrategy<P>,
+		params: Option<LazyConfirmationsParams>,
+		clock: C,
+	) -> Self {
+		LazyConfirmationsStrategy {
+			strategy,
+			params,
+			unrewarded_relayer_entries: None,
+			unconfirmed_since: VecDeque::new(),
+			clock,
+		}
+	}
+
+	/// Update unconfirmed nonces after nonces at any side of the race have been updated.
+	fn update_unconfirmed_nonces(&mut self) {
+		let best_at_source = self.strategy.best_at_source();
+		let best_at_target = self.strategy.best_at_target();
+
+		// forget about confirmed nonces
+		while let Some((end, _)) = self.unconfirmed_since.front() {
+			if *end > best_at_target {
+				break;
+			}
+			self.unconfirmed_since.pop_front();
+		}
+		// forget about nonces that are not known to the source anymore (e.g. after reorg)
+		while let Some((end, _)) = self.unconfirmed_since.back() {
+			if *end <= best_at_source {
+				break;
+			}
+			self.unconfirmed_since.pop_back();
+		}
+		// remember when new nonces have been seen
+		let best_unconfirmed_nonce = self
+			.unconfirmed_since
+			.back()
+			.map(|(end, _)| *end)
+			.unwrap_or(best_at_target);
+		if best_at_source > best_unconfirmed_nonce {
+			self.unconfirmed_since.push_back((best_at_source, self.clock.now()));
+		}
+	}
+}
+
+impl<P: MessageLane, C: Clock> RaceStrategy<TargetHeaderIdOf<P>, SourceHeaderIdOf<P>, P::MessagesReceivingProof>
+	for LazyConfirmationsStrategy<P, C>
+{
+	type SourceNoncesRange = RangeInclusive<MessageNonce>;
+	type ProofParameters = ();
+	type Checkpoint = RaceCheckpoint<TargetHeaderIdOf<P>, RangeInclusive<MessageNonce>>;
+	type Cost = P::SourceChainBalance;
+	type TargetNoncesData = ();
+
+	fn is_empty(&self) -> bool {
+		self.strategy.is_empty()
+	}
+
+	fn is_source_queue_full(&self) -> bool {
+		self.strategy.is_source_queue_full()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		self.strategy.queue_summary()
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		self.strategy.metrics()
+	}
+
+	fn best_at_source(&self) -> MessageNonce {
+		self.strategy.best_at_source()
+	}
+
+	fn best_at_target(&self) -> MessageNonce {
+		self.strategy.best_at_target()
+	}
+
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_source_opt()
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_target_opt()
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.strategy.requires_target_finality()
+	}
+
+	fn source_nonces_updated(
+		&mut self,
+		at_block: TargetHeaderIdOf<P>,
+		nonces: SourceClientNonces<Self::SourceNoncesRange>,
+	) {
+		if nonces.unrewarded_relayer_entries.is_some() {
+			self.unrewarded_relayer_entries = nonces.unrewarded_relayer_entries;
+		}
+		self.strategy.source_nonces_updated(at_block, nonces);
+		self.update_unconfirmed_nonces();
+	}
+
+	fn source_reorged(&mut self, new_best: &TargetHeaderIdOf<P>) {
+		self.strategy.source_reorged(new_best);
+		self.update_unconfirmed_nonces();
+	}
+
+	fn target_nonces_updated(
+		&mut self,
+		nonces: TargetClientNonces<()>,
+		race_state: &mut RaceState<TargetHeaderIdOf<P>, SourceHeaderIdOf<P>, P::MessagesReceivingProof>,
+	) {
+		self.strategy.target_nonces_updated(nonces, race_state);
+		self.update_unconfirmed_nonces();
+	}
+
+	fn select_nonces_to_deliver(
+		&mut self,
+		race_state: &RaceState<TargetHeaderIdOf<P>, SourceHeaderIdOf<P>, P::MessagesReceivingProof>,
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<TargetHeaderIdOf<P>>,
+	)> {
+		let (nonces, proof_parameters, proof_header) = self.strategy.select_nonces_to_deliver(race_state)?;
+		let params = match self.params {
+			Some(params) => params,
+			None => return Some((nonces, proof_parameters, proof_header)),
+		};
+
+		let unconfirmed_messages = self
+			.strategy
+			.best_at_source()
+			.saturating_sub(self.strategy.best_at_target());
+		if unconfirmed_messages > params.max_unconfirmed_messages {
+			return Some((nonces, proof_parameters, proof_header));
+		}
+
+		let unrewarded_relayer_entries = self.unrewarded_relayer_entries.unwrap_or(0);
+		if unrewarded_relayer_entries >= params.max_unrewarded_relayer_entries {
+			return Some((nonces, proof_parameters, proof_header));
+		}
+
+		let now = self.clock.now();
+		let waited = self
+			.unconfirmed_since
+			.front()
+			.map(|(_, unconfirmed_since)| now.saturating_duration_since(*unconfirmed_since))
+			.unwrap_or_default();
+		if waited >= params.max_confirmation_delay {
+			return Some((nonces, proof_parameters, proof_header));
+		}
+
+		log::debug!(
+			target: "bridge",
+			"Withholding delivery of confirmations {:?}: {} messages and {} unrewarded relayer entries are \
+			waiting for confirmation for {:?}",
+			nonces,
+			unconfirmed_messages,
+			unrewarded_relayer_entries,
+			waited,
+		);
+
+		None
+	}
+
+	fn required_source_header_at_target(&self, current_best: &TargetHeaderIdOf<P>) -> Option<TargetHeaderIdOf<P>> {
+		self.strategy.required_source_header_at_target(current_best)
+	}
+
+	fn apply_command(&mut self, command: RaceCommand) {
+		self.strategy.apply_command(command)
+	}
+
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		self.strategy.range_failed(nonces, kind)
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		self.strategy.checkpoint()
+	}
+}
+
 /// Run receiving confirmations race.
+#[allow(clippy::too_many_arguments)]
 pub async fn run<P: MessageLane>(
 	source_client: impl MessageLaneSourceClient<P>,
 	source_state_updates: impl FusedStream<Item = SourceClientState<P>>,
 	target_client: impl MessageLaneTargetClient<P>,
 	target_state_updates: impl FusedStream<Item = TargetClientState<P>>,
-	stall_timeout: Duration,
+	race_params: RaceParams,
+	lazy_confirmations: Option<LazyConfirmationsParams>,
 	metrics_msg: Option<MessageLaneLoopMetrics>,
-) -> Result<(), FailedClient> {
+	checkpoint: Option<RaceCheckpoint<TargetHeaderIdOf<P>, RangeInclusive<MessageNonce>>>,
+	on_checkpoint: impl FnMut(RaceCheckpoint<TargetHeaderIdOf<P>, RangeInclusive<MessageNonce>>),
+	on_required_source_header: impl FnMut(TargetHeaderIdOf<P>),
+	exit_signal: impl Future<Output = ()>,
+) -> Result<RaceSummary, RaceError> {
+	let final_nonce = race_params.final_nonce;
+	let source_confirmation_depth = race_params.source_confirmation_depth;
+	let proof_at_queued_header = race_params.proof_at_queued_header;
+	let require_target_finality = race_params.require_target_finality;
+	let race_metrics = metrics_msg
+		.as_ref()
+		.map(|metrics_msg| metrics_msg.receiving_race().clone());
 	crate::message_race_loop::run(
 		ReceivingConfirmationsRaceSource {
 			client: target_client,
 			metrics_msg: metrics_msg.clone(),
+			read_unrewarded_relayer_entries: lazy_confirmations.is_some(),
 			_phantom: Default::default(),
 		},
-		target_state_updates,
 		ReceivingConfirmationsRaceTarget {
 			client: source_client,
 			metrics_msg,
 			_phantom: Default::default(),
 		},
-		source_state_updates,
-		stall_timeout,
-		ReceivingConfirmationsBasicStrategy::<P>::new(),
+		race_params,
+		LazyConfirmationsStrategy::<P>::new(
+			checkpoint
+				.map(ReceivingConfirmationsBasicStrategy::<P>::from_checkpoint)
+				.unwrap_or_default()
+				.with_final_nonce(final_nonce)
+				.with_source_confirmation_depth(source_confirmation_depth)
+				.with_proof_at_queued_header(proof_at_queued_header)
+				.with_target_finality_required(require_target_finality),
+			lazy_confirmations,
+		),
+		RaceContext {
+			source_updated: target_state_updates,
+			target_updated: source_state_updates,
+			control: futures::stream::pending(),
+			on_checkpoint,
+			on_required_source_header,
+			observer: (),
+			metrics: race_metrics,
+			exit_signal,
+		},
 	)
 	.await
 }
@@ -85,12 +359,22 @@ impl<P: MessageLane> MessageRace for ReceivingConfirmationsRace<P> {
 	fn target_name() -> String {
 		format!("{}::ReceivingConfirmationsDelivery", P::TARGET_NAME)
 	}
+
+	fn race_kind() -> String {
+		"Receiving confirmations delivery".into()
+	}
+
+	fn proof_size(proof: &P::MessagesReceivingProof) -> usize {
+		P::messages_receiving_proof_size(proof)
+	}
 }
 
 /// Message receiving confirmations race source, which is a target of the lane.
 struct ReceivingConfirmationsRaceSource<P: MessageLane, C> {
 	client: C,
 	metrics_msg: Option<MessageLaneLoopMetrics>,
+	/// If true, number of unrewarded relayer entries is read along with nonces.
+	read_unrewarded_relayer_entries: bool,
 	_phantom: PhantomData<P>,
 }
 
@@ -104,20 +388,40 @@ where
 	type NoncesRange = RangeInclusive<MessageNonce>;
 	type ProofParameters = ();
 
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		self.client = self.client.clone().reconnect().await.map_err(ReconnectError::Failed)?;
+		Ok(())
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.client.runtime_version().await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
 	async fn nonces(
 		&self,
 		at_block: TargetHeaderIdOf<P>,
-		prev_latest_nonce: MessageNonce,
+		prev_latest_nonce: Option<MessageNonce>,
 	) -> Result<(TargetHeaderIdOf<P>, SourceClientNonces<Self::NoncesRange>), Self::Error> {
 		let (at_block, latest_received_nonce) = self.client.latest_received_nonce(at_block).await?;
 		if let Some(metrics_msg) = self.metrics_msg.as_ref() {
-			metrics_msg.update_target_latest_received_nonce::<P>(latest_received_nonce);
+			metrics_msg.update_target_latest_received_nonce(latest_received_nonce);
 		}
+		let unrewarded_relayer_entries = if self.read_unrewarded_relayer_entries {
+			let (_, unrewarded_relayers) = self.client.unrewarded_relayers_state(at_block.clone()).await?;
+			Some(unrewarded_relayers.unrewarded_relayer_entries)
+		} else {
+			None
+		};
 		Ok((
 			at_block,
 			SourceClientNonces {
-				new_nonces: prev_latest_nonce + 1..=latest_received_nonce,
+				new_nonces: prev_latest_nonce.unwrap_or(0) + 1..=latest_received_nonce,
 				confirmed_nonce: None,
+				unrewarded_relayer_entries,
 			},
 		))
 	}
@@ -141,6 +445,19 @@ where
 			.await
 			.map(|(at_block, proof)| (at_block, nonces, proof))
 	}
+
+	async fn estimate_proof_size(
+		&self,
+		at_block: TargetHeaderIdOf<P>,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof_parameters: Self::ProofParameters,
+	) -> Result<u32, Self::Error> {
+		// receiving proof doesn't depend on nonces
+		self.client
+			.prove_messages_receiving(at_block)
+			.await
+			.map(|(_, proof)| P::messages_receiving_proof_size(&proof) as u32)
+	}
 }
 
 /// Message receiving confirmations race target, which is a source of the lane.
@@ -157,20 +474,49 @@ where
 	C: MessageLaneSourceClient<P>,
 {
 	type Error = C::Error;
+	type TransactionTracker = C::TransactionTracker;
+	type Cost = P::SourceChainBalance;
+	type TargetNoncesData = ();
+
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		self.client = self.client.clone().reconnect().await.map_err(ReconnectError::Failed)?;
+		Ok(())
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.client.runtime_version().await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
 
 	async fn nonces(
 		&self,
-		at_block: SourceHeaderIdOf<P>,
-	) -> Result<(SourceHeaderIdOf<P>, TargetClientNonces), Self::Error> {
-		let (at_block, latest_confirmed_nonce) = self.client.latest_confirmed_received_nonce(at_block).await?;
+		at_best: SourceHeaderIdOf<P>,
+		at_finalized: SourceHeaderIdOf<P>,
+	) -> Result<(SourceHeaderIdOf<P>, TargetNonces<()>), Self::Error> {
+		let is_best_finalized = at_best == at_finalized;
+		let (at_best, latest_confirmed_nonce) = self.client.latest_confirmed_received_nonce(at_best).await?;
+		let finalized_confirmed_nonce = if is_best_finalized {
+			latest_confirmed_nonce
+		} else {
+			self.client.latest_confirmed_received_nonce(at_finalized).await?.1
+		};
 		if let Some(metrics_msg) = self.metrics_msg.as_ref() {
-			metrics_msg.update_source_latest_confirmed_nonce::<P>(latest_confirmed_nonce);
+			metrics_msg.update_source_latest_confirmed_nonce(latest_confirmed_nonce);
 		}
 		Ok((
-			at_block,
-			TargetClientNonces {
-				latest_nonce: latest_confirmed_nonce,
-				confirmed_nonce: None,
+			at_best,
+			TargetNonces {
+				at_best: TargetClientNonces {
+					latest_nonce: latest_confirmed_nonce,
+					nonces_data: (),
+				},
+				at_finalized: TargetClientNonces {
+					latest_nonce: finalized_confirmed_nonce,
+					nonces_data: (),
+				},
 			},
 		))
 	}
@@ -179,12 +525,26 @@ where
 		&self,
 		generated_at_block: TargetHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
-		proof: P::MessagesReceivingProof,
-	) -> Result<RangeInclusive<MessageNonce>, Self::Error> {
-		self.client
-			.submit_messages_receiving_proof(generated_at_block, proof)
+		proof: Arc<P::MessagesReceivingProof>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		// lane client needs owned proof, so it is copied here unless the race has already dropped it
+		let proof = Arc::try_unwrap(proof).unwrap_or_else(|proof| (*proof).clone());
+		let tx_tracker = self
+			.client
+			.submit_messages_receiving_proof(generated_at_block, proof, options)
 			.await?;
-		Ok(nonces)
+		Ok((nonces, tx_tracker))
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		_nonces: RangeInclusive<MessageNonce>,
+		_total_dispatch_weight: Weight,
+		_total_size: u32,
+	) -> Result<P::SourceChainBalance, Self::Error> {
+		// receiving confirmations strategy never asks for the delivery cost
+		Ok(Zero::zero())
 	}
 }
 
@@ -206,11 +566,128 @@ impl NoncesRange for RangeInclusive<MessageNonce> {
 			Some(std::cmp::max(self.begin(), next_nonce)..=end)
 		}
 	}
+
+	fn not_greater_than(self, nonce: MessageNonce) -> Option<Self> {
+		let begin = *self.start();
+		if begin > nonce {
+			None
+		} else {
+			Some(begin..=std::cmp::min(*self.end(), nonce))
+		}
+	}
+
+	fn merge_with_next(&mut self, next: Self) {
+		*self = *self.start()..=*next.end();
+	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use crate::message_lane_loop::ClientState;
+	use crate::message_race_strategy::tests::{SelectNonces, TestClock};
+	use crate::testing::{
+		header_id, TestMessageLane, TestMessagesReceivingProof, TestSourceHeaderId, TestTargetHeaderId,
+	};
+
+	type TestRaceState = RaceState<TestTargetHeaderId, TestSourceHeaderId, TestMessagesReceivingProof>;
+	type TestStrategy = LazyConfirmationsStrategy<TestMessageLane, TestClock>;
+
+	fn source_nonces(
+		new_nonces: RangeInclusive<MessageNonce>,
+		unrewarded_relayer_entries: MessageNonce,
+	) -> SourceClientNonces<RangeInclusive<MessageNonce>> {
+		SourceClientNonces {
+			new_nonces,
+			confirmed_nonce: None,
+			unrewarded_relayer_entries: Some(unrewarded_relayer_entries),
+		}
+	}
+
+	fn target_nonces(latest_nonce: MessageNonce) -> TargetClientNonces<()> {
+		TargetClientNonces {
+			latest_nonce,
+			nonces_data: (),
+		}
+	}
+
+	fn prepare_strategy(params: Option<LazyConfirmationsParams>) -> (TestClock, TestRaceState, TestStrategy) {
+		let clock = TestClock(Arc::new(parking_lot::Mutex::new(Instant::now())));
+		let race_state = RaceState {
+			source_state: Some(ClientState {
+				best_self: header_id(1),
+				best_finalized_self: header_id(1),
+				best_peer: header_id(1),
+			}),
+			target_state: Some(ClientState {
+				best_self: header_id(1),
+				best_finalized_self: header_id(1),
+				best_peer: header_id(1),
+			}),
+			nonces_to_submit: None,
+			proofs_to_submit: Default::default(),
+			nonces_submitted: Default::default(),
+		};
+		let mut strategy = LazyConfirmationsStrategy::with_clock(BasicStrategy::new(), params, clock.clone());
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=3, 1));
+		(clock, race_state, strategy)
+	}
+
+	fn lazy_confirmations_params() -> Option<LazyConfirmationsParams> {
+		Some(LazyConfirmationsParams {
+			max_unconfirmed_messages: 5,
+			max_unrewarded_relayer_entries: 3,
+			max_confirmation_delay: Duration::from_secs(60),
+		})
+	}
+
+	#[test]
+	fn lazy_confirmations_strategy_without_params_never_withholds_selection() {
+		let (_, race_state, mut strategy) = prepare_strategy(None);
+		assert_eq!(strategy.select_nonces(&race_state), Some((1..=3, ())));
+	}
+
+	#[test]
+	fn lazy_confirmations_strategy_withholds_confirmations() {
+		let (clock, race_state, mut strategy) = prepare_strategy(lazy_confirmations_params());
+		clock.advance(Duration::from_secs(59));
+		assert_eq!(strategy.select_nonces(&race_state), None);
+	}
+
+	#[test]
+	fn lazy_confirmations_strategy_delivers_when_too_many_messages_are_unconfirmed() {
+		let (_, race_state, mut strategy) = prepare_strategy(lazy_confirmations_params());
+		strategy.source_nonces_updated(header_id(1), source_nonces(4..=5, 1));
+		assert_eq!(strategy.select_nonces(&race_state), None);
+
+		strategy.source_nonces_updated(header_id(1), source_nonces(6..=6, 1));
+		assert_eq!(strategy.select_nonces(&race_state), Some((1..=6, ())));
+	}
+
+	#[test]
+	fn lazy_confirmations_strategy_delivers_when_unrewarded_relayer_entries_limit_is_approaching() {
+		let (_, race_state, mut strategy) = prepare_strategy(lazy_confirmations_params());
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=3, 2));
+		assert_eq!(strategy.select_nonces(&race_state), None);
+
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=3, 3));
+		assert_eq!(strategy.select_nonces(&race_state), Some((1..=3, ())));
+	}
+
+	#[test]
+	fn lazy_confirmations_strategy_delivers_when_oldest_message_has_been_waiting_for_too_long() {
+		let (clock, mut race_state, mut strategy) = prepare_strategy(lazy_confirmations_params());
+		clock.advance(Duration::from_secs(30));
+		strategy.source_nonces_updated(header_id(1), source_nonces(4..=4, 1));
+		clock.advance(Duration::from_secs(30));
+		assert_eq!(strategy.select_nonces(&race_state), Some((1..=4, ())));
+
+		// once nonces 1..=3 are confirmed, the oldest unconfirmed nonce has been waiting for 30s
+		strategy.target_nonces_updated(target_nonces(3), &mut race_state);
+		assert_eq!(strategy.select_nonces(&race_state), None);
+		clock.advance(Duration::from_secs(30));
+		assert_eq!(strategy.select_nonces(&race_state), Some((4..=4, ())));
+	}
 
 	#[test]
 	fn range_inclusive_works_as_nonces_range() {
@@ -223,6 +700,15 @@ mod tests {
 		assert_eq!(range.clone().greater_than(20), Some(21..=30));
 		assert_eq!(range.clone().greater_than(25), Some(26..=30));
 		assert_eq!(range.clone().greater_than(29), Some(30..=30));
-		assert_eq!(range.greater_than(30), None);
+		assert_eq!(range.clone().greater_than(30), None);
+		assert_eq!(range.clone().not_greater_than(19), None);
+		assert_eq!(range.clone().not_greater_than(20), Some(20..=20));
+		assert_eq!(range.clone().not_greater_than(25), Some(20..=25));
+		assert_eq!(range.clone().not_greater_than(30), Some(20..=30));
+		assert_eq!(range.clone().not_greater_than(40), Some(20..=30));
+
+		let mut merged_range = range;
+		merged_range.merge_with_next(31..=40);
+		assert_eq!(merged_range, 20..=40);
 	}
 }
@@ -16,14 +16,213 @@
 //! 1) there are more nonces on the source side than on the target side;
 //! 2) new nonces may be proved to target node (i.e. they have appeared at the
 //!    block, which is known to the target node).
+//!
+//! Batching strategy wraps other strategy and withholds its selection until there are
+//! enough deliverable nonces, or until the oldest deliverable nonce has waited for too long.
+//!
+//! Rate-limited strategy wraps other strategy and truncates its selection, so that no more
+//! than given number of nonces is selected per minute.
+//!
+//! Composite strategy selects nonces using basic strategy and then restricts selected range with
+//! chain of selection constraints.
 
-use crate::message_race_loop::{NoncesRange, RaceState, RaceStrategy, SourceClientNonces, TargetClientNonces};
+use crate::message_race_loop::{
+	NoncesRange, PrefixProofParameters, RaceCommand, RaceErrorReason, RaceState, RaceStrategy, RangeFailureKind,
+	ReorgAwareHeaderId, SourceClientNonces, StrategyMetricsSnapshot, TargetClientNonces,
+};
 
-use bp_message_lane::MessageNonce;
+use bp_message_lane::{MessageNonce, Weight};
 use relay_utils::HeaderId;
-use std::{collections::VecDeque, marker::PhantomData, ops::RangeInclusive};
+use std::{
+	collections::VecDeque,
+	marker::PhantomData,
+	ops::RangeInclusive,
+	time::{Duration, Instant},
+};
+
+/// Checkpoint of the basic strategy state. It may be used to restore the strategy state after
+/// restart, so that nonces that are already known to the strategy are not read again.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct RaceCheckpoint<SourceHeaderId, SourceNoncesRange> {
+	/// Best nonce known to target node.
+	pub target_nonce: MessageNonce,
+	/// All queued nonces, along with source headers where they have been seen.
+	pub source_queue: Vec<(SourceHeaderId, SourceNoncesRange)>,
+}
+
+/// Version of the basic strategy snapshot format. It must be bumped whenever the format is
+/// changed, so that snapshots of older versions are rejected.
+pub const STRATEGY_SNAPSHOT_VERSION: u32 = 1;
+
+/// Snapshot of the basic strategy state. Unlike the `RaceCheckpoint`, it holds the whole state
+/// of the strategy, including confirmed and coalesced nonces. Strategy parameters (limits,
+/// selector, etc.) are not included.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "checkpoint", derive(serde::Serialize, serde::Deserialize))]
+pub struct StrategySnapshot<SourceHeaderId, SourceNoncesRange> {
+	/// Version of the snapshot format.
+	pub version: u32,
+	/// Best nonce known to target node.
+	pub target_nonce: MessageNonce,
+	/// Latest confirmed nonce, reported by the source node.
+	pub confirmed_nonce: Option<MessageNonce>,
+	/// All queued nonces, along with source headers where they have been seen.
+	pub source_queue: Vec<(SourceHeaderId, SourceNoncesRange)>,
+	/// Coalesced nonces, along with the latest source header where they have been seen.
+	pub coalesced_nonces: Option<(SourceHeaderId, RangeInclusive<MessageNonce>)>,
+}
+
+/// Error returned when the basic strategy can't be restored from the snapshot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+	/// Snapshot has unsupported version of the format.
+	UnsupportedVersion(u32),
+	/// Snapshot is malformed. Formatted description is attached.
+	Malformed(String),
+}
+
+impl std::fmt::Display for SnapshotError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match *self {
+			SnapshotError::UnsupportedVersion(version) => write!(
+				f,
+				"Unsupported strategy snapshot version {}. Expected version {}",
+				version, STRATEGY_SNAPSHOT_VERSION,
+			),
+			SnapshotError::Malformed(ref error) => write!(f, "Malformed strategy snapshot: {}", error),
+		}
+	}
+}
+
+/// What basic strategy does with new source nonces when its queue is full.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueueOverflowPolicy {
+	/// New nonces are not queued and the strategy reports that its queue is full, so the race
+	/// stops reading source nonces until queued nonces are delivered. Refused nonces are read
+	/// again later.
+	RefuseNewNonces,
+	/// New nonces are coalesced into single summary range, which only holds nonces (without any
+	/// associated data). Nonces of the summary range are never selected. Once all queued nonces
+	/// are delivered, the summary range is dropped, so its nonces are read from the source again.
+	CoalesceNewNonces,
+}
+
+/// What basic strategy does with nonces range that has failed to be delivered too many times in
+/// a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeFailureAction {
+	/// Withhold the range (and all following nonces) for `initial_delay`. Every next time the
+	/// range reaches the failure threshold, the delay is doubled, but it never exceeds `max_delay`.
+	Park {
+		/// Delay after the range has reached the failure threshold for the first time.
+		initial_delay: Duration,
+		/// Maximal delay.
+		max_delay: Duration,
+	},
+	/// Fail the race with `RaceErrorReason::LaneBlocked` error.
+	Fail,
+}
+
+/// Consecutive failures of the range that starts at given nonce.
+#[derive(Debug)]
+struct RangeFailures {
+	/// First nonce of the failed range.
+	nonce: MessageNonce,
+	/// Number of consecutive failures since the range has been parked last time.
+	failures: u32,
+	/// Delay of the last parking, if the range has been parked.
+	park_delay: Option<Duration>,
+	/// Time until the range is withheld.
+	parked_until: Option<Instant>,
+}
+
+/// Error returned when nonces selector returns range that isn't a suffix of the passed range.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SelectorError {
+	/// Selector has returned empty range.
+	EmptyRange {
+		/// Range that has been passed to the selector.
+		passed_range: RangeInclusive<MessageNonce>,
+		/// Range that has been returned by the selector.
+		returned_range: RangeInclusive<MessageNonce>,
+	},
+	/// Selector has returned range that begins before the passed range.
+	BeginsBeforePassedRange {
+		/// Range that has been passed to the selector.
+		passed_range: RangeInclusive<MessageNonce>,
+		/// Range that has been returned by the selector.
+		returned_range: RangeInclusive<MessageNonce>,
+	},
+	/// Selector has returned range that ends at other nonce than the passed range.
+	EndMismatch {
+		/// Range that has been passed to the selector.
+		passed_range: RangeInclusive<MessageNonce>,
+		/// Range that has been returned by the selector.
+		returned_range: RangeInclusive<MessageNonce>,
+	},
+}
+
+impl std::fmt::Display for SelectorError {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		let (violation, passed_range, returned_range) = match self {
+			SelectorError::EmptyRange {
+				passed_range,
+				returned_range,
+			} => ("is empty", passed_range, returned_range),
+			SelectorError::BeginsBeforePassedRange {
+				passed_range,
+				returned_range,
+			} => ("begins before original range", passed_range, returned_range),
+			SelectorError::EndMismatch {
+				passed_range,
+				returned_range,
+			} => ("doesn't end with original range", passed_range, returned_range),
+		};
+		write!(
+			f,
+			"Incorrect implementation of internal `selector` function. Returned range {:?} {}. Original range: {:?}",
+			returned_range, violation, passed_range,
+		)
+	}
+}
+
+/// Nonces selector, stored by the basic strategy.
+struct StoredSelector<SourceNoncesRange>(Box<dyn FnMut(SourceNoncesRange) -> Option<SourceNoncesRange> + Send>);
+
+impl<SourceNoncesRange> std::fmt::Debug for StoredSelector<SourceNoncesRange> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str("StoredSelector")
+	}
+}
+
+/// Builder of parameters of the proof of selected nonces.
+type ProofParametersBuilder<ProofParameters> =
+	dyn Fn(&RangeInclusive<MessageNonce>, Option<MessageNonce>) -> ProofParameters + Send;
+
+/// Proof parameters builder, stored by the basic strategy.
+struct StoredProofParametersBuilder<ProofParameters>(Box<ProofParametersBuilder<ProofParameters>>);
+
+impl<ProofParameters> std::fmt::Debug for StoredProofParametersBuilder<ProofParameters> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str("StoredProofParametersBuilder")
+	}
+}
+
+/// Clock, stored by the basic strategy.
+struct StoredClock(Box<dyn Clock + Send>);
+
+impl std::fmt::Debug for StoredClock {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.write_str("StoredClock")
+	}
+}
 
 /// Nonces delivery strategy.
+///
+/// The strategy never asks for the delivery cost, so the `Cost` is only used to match the cost
+/// type of the race target client. Parameters of the proof of selected nonces are built by the
+/// proof parameters builder (see `with_proof_parameters_builder`).
 #[derive(Debug)]
 pub struct BasicStrategy<
 	SourceHeaderNumber,
@@ -32,20 +231,124 @@ pub struct BasicStrategy<
 	TargetHeaderHash,
 	SourceNoncesRange,
 	Proof,
+	Cost = (),
+	ProofParameters = (),
 > {
 	/// All queued nonces.
 	source_queue: VecDeque<(HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange)>,
 	/// Best nonce known to target node.
 	target_nonce: MessageNonce,
+	/// If true, the source node has reported its nonces (or they have been restored from the
+	/// checkpoint).
+	is_source_nonce_known: bool,
+	/// If true, the target node has reported its nonces (or they have been restored from the
+	/// checkpoint).
+	is_target_nonce_known: bool,
+	/// Latest confirmed nonce, reported by the source node (if the race is tracking it).
+	confirmed_nonce: Option<MessageNonce>,
+	/// If true, the lower nonce, reported by the target node, rolls back the `target_nonce`.
+	is_target_nonce_rollback_allowed: bool,
+	/// If true, the `target_nonce` has been restored from the checkpoint and has not yet been
+	/// updated by the target node.
+	is_target_nonce_restored: bool,
+	/// If set, nonces after this nonce are never queued or selected.
+	final_nonce: Option<MessageNonce>,
+	/// Best nonce that has been reported by the source node, but has been discarded because it
+	/// is after the `final_nonce`.
+	best_discarded_nonce: Option<MessageNonce>,
+	/// If set, at most this number of nonces is selected for the single proof.
+	max_nonces_per_proof: Option<MessageNonce>,
+	/// If set, contiguous ranges are merged into single queue entry while it has at most this
+	/// number of nonces.
+	max_merged_range_len: Option<MessageNonce>,
+	/// If set, at most this number of entries is kept in the `source_queue`.
+	max_queue_len: Option<(usize, QueueOverflowPolicy)>,
+	/// Summary range of nonces that have been coalesced because the queue has been full, along
+	/// with the latest source header where they have been seen.
+	coalesced_nonces: Option<(
+		HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+		RangeInclusive<MessageNonce>,
+	)>,
+	/// Number of the latest source headers (known to the target node) that are treated as
+	/// unstable. Nonces that have been queued at these headers are never selected.
+	source_confirmation_depth: u32,
+	/// If true, proofs are generated at the newest source header where selected nonces have
+	/// been queued.
+	proof_at_queued_header: bool,
+	/// If true, only nonces at the best finalized target header are trusted.
+	require_target_finality: bool,
+	/// Selector that is used by the `select_nonces_to_deliver`. If it is not set, the whole
+	/// queued ranges are selected.
+	selector: Option<StoredSelector<SourceNoncesRange>>,
+	/// Builder of parameters of the proof of selected nonces. If it is not set, default
+	/// parameters are used.
+	proof_parameters_builder: Option<StoredProofParametersBuilder<ProofParameters>>,
+	/// If set, the range that has failed to be delivered this number of times in a row is
+	/// handled according to the action.
+	failure_threshold: Option<(u32, RangeFailureAction)>,
+	/// Consecutive failures of the range that has failed last.
+	range_failures: Option<RangeFailures>,
+	/// Clock that is used to withhold parked ranges.
+	clock: StoredClock,
+	/// Number of source nonces updates that have been refused because of the gap.
+	source_nonce_gaps: u64,
 	/// Unused generic types dump.
-	_phantom: PhantomData<(TargetHeaderNumber, TargetHeaderHash, Proof)>,
+	_phantom: PhantomData<(TargetHeaderNumber, TargetHeaderHash, Proof, Cost)>,
+}
+
+impl<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	> Default
+	for BasicStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	>
+where
+	SourceHeaderHash: Clone,
+	SourceHeaderNumber: Clone + Ord + From<u32> + std::ops::Add<Output = SourceHeaderNumber>,
+	SourceNoncesRange: NoncesRange,
+{
+	fn default() -> Self {
+		Self::new()
+	}
 }
 
-impl<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof>
-	BasicStrategy<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof>
+impl<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	>
+	BasicStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	>
 where
 	SourceHeaderHash: Clone,
-	SourceHeaderNumber: Clone + Ord,
+	SourceHeaderNumber: Clone + Ord + From<u32> + std::ops::Add<Output = SourceHeaderNumber>,
 	SourceNoncesRange: NoncesRange,
 {
 	/// Create new delivery strategy.
@@ -53,10 +356,375 @@ where
 		BasicStrategy {
 			source_queue: VecDeque::new(),
 			target_nonce: Default::default(),
+			is_source_nonce_known: false,
+			is_target_nonce_known: false,
+			confirmed_nonce: None,
+			is_target_nonce_rollback_allowed: false,
+			is_target_nonce_restored: false,
+			final_nonce: None,
+			best_discarded_nonce: None,
+			max_nonces_per_proof: None,
+			max_merged_range_len: None,
+			max_queue_len: None,
+			coalesced_nonces: None,
+			source_confirmation_depth: 0,
+			proof_at_queued_header: false,
+			require_target_finality: false,
+			selector: None,
+			proof_parameters_builder: None,
+			failure_threshold: None,
+			range_failures: None,
+			clock: StoredClock(Box::new(SystemClock)),
+			source_nonce_gaps: 0,
 			_phantom: Default::default(),
 		}
 	}
 
+	/// Create new delivery strategy, assuming that nonces up to `target_nonce` are already
+	/// delivered to the target node. So nonces are read from the source node, starting from
+	/// the next nonce.
+	///
+	/// Like the checkpoint nonce, the `target_nonce` is never trusted more than the target node:
+	/// if the first nonce reported by the target node is lower, the strategy state is reset to
+	/// the reported nonce.
+	pub fn new_with_target_nonce(target_nonce: MessageNonce) -> Self {
+		let mut strategy = Self::new();
+		strategy.target_nonce = target_nonce;
+		strategy.is_target_nonce_known = true;
+		strategy.is_target_nonce_restored = true;
+		strategy
+	}
+
+	/// Create new delivery strategy that is using given `selector` to select nonces in the
+	/// `select_nonces_to_deliver`.
+	///
+	/// See `select_nonces_to_deliver_with_selector` for the selector contract. If the selector
+	/// breaks the contract, the error is logged and nothing is selected.
+	pub fn with_selector(
+		selector: impl FnMut(SourceNoncesRange) -> Option<SourceNoncesRange> + Send + 'static,
+	) -> Self {
+		let mut strategy = Self::new();
+		strategy.selector = Some(StoredSelector(Box::new(selector)));
+		strategy
+	}
+
+	/// Create new delivery strategy, restoring its state from the checkpoint.
+	///
+	/// The checkpoint is never trusted more than the target node: if the first nonce reported by
+	/// the target node is lower than the checkpoint nonce, the strategy state is reset to the
+	/// reported nonce. Malformed checkpoint is ignored.
+	pub fn from_checkpoint(
+		checkpoint: RaceCheckpoint<HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange>,
+	) -> Self {
+		let mut strategy = Self::new();
+		if let Err(error) = validate_source_queue(checkpoint.target_nonce, &checkpoint.source_queue) {
+			log::warn!(target: "bridge", "Ignoring malformed checkpoint: {}", error);
+			return strategy;
+		}
+
+		strategy.source_queue = checkpoint.source_queue.into();
+		strategy.target_nonce = checkpoint.target_nonce;
+		strategy.is_source_nonce_known = true;
+		strategy.is_target_nonce_known = true;
+		strategy.is_target_nonce_restored = true;
+		strategy
+	}
+
+	/// Return snapshot of the strategy state.
+	pub fn snapshot(&self) -> StrategySnapshot<HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange> {
+		StrategySnapshot {
+			version: STRATEGY_SNAPSHOT_VERSION,
+			target_nonce: self.target_nonce,
+			confirmed_nonce: self.confirmed_nonce,
+			source_queue: self.source_queue.iter().cloned().collect(),
+			coalesced_nonces: self.coalesced_nonces.clone(),
+		}
+	}
+
+	/// Restore the strategy state from the snapshot. Strategy parameters are not changed.
+	///
+	/// Like the checkpoint, the snapshot is never trusted more than the target node (see
+	/// `from_checkpoint`). If the snapshot has unsupported version or is malformed, error is
+	/// returned and the strategy state is left untouched.
+	pub fn restore(
+		&mut self,
+		snapshot: StrategySnapshot<HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange>,
+	) -> Result<(), SnapshotError> {
+		if snapshot.version != STRATEGY_SNAPSHOT_VERSION {
+			return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+		}
+		validate_source_queue(snapshot.target_nonce, &snapshot.source_queue).map_err(SnapshotError::Malformed)?;
+		if let Some((_, ref coalesced_nonces)) = snapshot.coalesced_nonces {
+			let prev_queued_end = snapshot
+				.source_queue
+				.last()
+				.map(|(_, queued_range)| queued_range.end())
+				.unwrap_or(snapshot.target_nonce);
+			if coalesced_nonces.is_empty() || *coalesced_nonces.start() <= prev_queued_end {
+				return Err(SnapshotError::Malformed(format!(
+					"coalesced nonces {:?} are not following nonce {}",
+					coalesced_nonces, prev_queued_end,
+				)));
+			}
+		}
+
+		self.source_queue = snapshot.source_queue.into();
+		self.target_nonce = snapshot.target_nonce;
+		self.confirmed_nonce = snapshot.confirmed_nonce;
+		self.coalesced_nonces = snapshot.coalesced_nonces;
+		self.range_failures = None;
+		self.is_source_nonce_known = true;
+		self.is_target_nonce_known = true;
+		self.is_target_nonce_restored = true;
+		Ok(())
+	}
+
+	/// Set builder of parameters of the proof of selected nonces. The builder is called with
+	/// selected nonces and the latest confirmed nonce, reported by the source node.
+	pub fn with_proof_parameters_builder(
+		mut self,
+		builder: impl Fn(&RangeInclusive<MessageNonce>, Option<MessageNonce>) -> ProofParameters + Send + 'static,
+	) -> Self {
+		self.proof_parameters_builder = Some(StoredProofParametersBuilder(Box::new(builder)));
+		self
+	}
+
+	/// Never queue or select nonces after `final_nonce` (if it is set).
+	///
+	/// This is used to drain the lane: all nonces up to `final_nonce` are delivered, but new
+	/// nonces, generated at the source after that, are discarded as soon as they're reported.
+	pub fn with_final_nonce(mut self, final_nonce: Option<MessageNonce>) -> Self {
+		self.final_nonce = final_nonce;
+		self
+	}
+
+	/// Never select more than `max_batch` nonces (if it is set) for the single proof. The limit
+	/// may be changed later with the `RaceCommand::SetMaxNoncesPerProof` command.
+	///
+	/// Huge queued range (e.g. all messages, emitted at the single source block) is delivered in
+	/// chunks of at most `max_batch` nonces. Undelivered nonces stay queued at the same header, so
+	/// next chunk is selected once the previous one is submitted or delivered.
+	pub fn with_max_batch(mut self, max_batch: Option<MessageNonce>) -> Self {
+		self.max_nonces_per_proof = max_batch;
+		self
+	}
+
+	/// Only select nonces that have been queued at source headers which are at least
+	/// `source_confirmation_depth` blocks behind the best source header known to the target node.
+	///
+	/// The target node may import source header that is later reorged out. Proofs that are
+	/// generated at such headers are useless, so the last `source_confirmation_depth` headers are
+	/// treated as unstable.
+	pub fn with_source_confirmation_depth(mut self, source_confirmation_depth: u32) -> Self {
+		self.source_confirmation_depth = source_confirmation_depth;
+		self
+	}
+
+	/// Ask to generate proofs of selected nonces at the newest source header where they have
+	/// been queued, instead of the best source header known to the target node.
+	///
+	/// Proofs at older headers are usually smaller. They are also not affected by reorgs of the
+	/// recent source headers.
+	pub fn with_proof_at_queued_header(mut self, proof_at_queued_header: bool) -> Self {
+		self.proof_at_queued_header = proof_at_queued_header;
+		self
+	}
+
+	/// Only trust nonces at the best finalized target header.
+	///
+	/// Nonces at the best target header may be reverted by the target chain reorg. If that
+	/// happens, the relayer would wait for confirmations that never come.
+	pub fn with_target_finality_required(mut self, require_target_finality: bool) -> Self {
+		self.require_target_finality = require_target_finality;
+		self
+	}
+
+	/// Returns source header where proof of selected `nonces` should be generated, or `None` if
+	/// the proof should be generated at the best source header known to the target node.
+	pub fn proof_header(
+		&self,
+		nonces: &RangeInclusive<MessageNonce>,
+	) -> Option<HeaderId<SourceHeaderHash, SourceHeaderNumber>> {
+		if !self.proof_at_queued_header {
+			return None;
+		}
+
+		// ranges are queued in order, so the last range that includes selected nonces has been
+		// queued at the newest header
+		self.source_queue
+			.iter()
+			.take_while(|(_, queued_range)| queued_range.begin() <= *nonces.end())
+			.filter(|(_, queued_range)| queued_range.end() >= *nonces.start())
+			.last()
+			.map(|(queued_at, _)| queued_at.clone())
+	}
+
+	/// Merge newly queued range into the last queue entry if ranges are contiguous and the merged
+	/// entry would have at most `max_merged_len` nonces.
+	///
+	/// The merged entry is queued at the newer source header. It means that nonces of the merged
+	/// entry are only selected when the newer header is known to the target node and that proofs
+	/// are generated at the newer header.
+	pub fn with_range_merging(mut self, max_merged_len: MessageNonce) -> Self {
+		self.max_merged_range_len = Some(max_merged_len);
+		self
+	}
+
+	/// Keep at most `max_queue_len` entries in the source queue. When the queue is full, new
+	/// nonces are handled according to the `overflow_policy`.
+	pub fn with_max_queue_len(mut self, max_queue_len: usize, overflow_policy: QueueOverflowPolicy) -> Self {
+		self.max_queue_len = Some((max_queue_len, overflow_policy));
+		self
+	}
+
+	/// Handle the range that has failed to be delivered `failure_threshold` times in a row
+	/// according to the `action`. Failures are counted per first nonce of the range, so the
+	/// counter is reset when the other range fails or when the range is delivered.
+	///
+	/// Nonces are delivered in order, so the parked range is blocking all following nonces. The
+	/// race should be configured to refresh nonces periodically (see `RaceParams::refresh_interval`),
+	/// so that selection is retried once the parking delay is over.
+	pub fn with_failure_threshold(mut self, failure_threshold: u32, action: RangeFailureAction) -> Self {
+		self.failure_threshold = Some((failure_threshold, action));
+		self
+	}
+
+	/// Return first nonce of the range that is currently parked, if there's any.
+	pub fn parked_nonce(&self) -> Option<MessageNonce> {
+		let now = self.clock.0.now();
+		self.range_failures
+			.as_ref()
+			.filter(|range_failures| {
+				range_failures
+					.parked_until
+					.map(|parked_until| parked_until > now)
+					.unwrap_or(false)
+			})
+			.map(|range_failures| range_failures.nonce)
+	}
+
+	/// Return latest confirmed nonce, reported by the source node.
+	pub fn confirmed_nonce(&self) -> Option<MessageNonce> {
+		self.confirmed_nonce
+	}
+
+	/// Return number of entries in the source queue.
+	pub fn source_queue_len(&self) -> usize {
+		self.source_queue.len()
+	}
+
+	/// Return queued ranges, along with source headers where they have been seen.
+	pub fn queued_ranges(
+		&self,
+	) -> impl Iterator<Item = (&HeaderId<SourceHeaderHash, SourceHeaderNumber>, &SourceNoncesRange)> {
+		self.source_queue
+			.iter()
+			.map(|(queued_at, queued_range)| (queued_at, queued_range))
+	}
+
+	/// Return total number of nonces in queued ranges. Coalesced nonces are not included.
+	pub fn total_queued_nonces(&self) -> MessageNonce {
+		self.source_queue
+			.iter()
+			.map(|(_, queued_range)| {
+				queued_range
+					.end()
+					.saturating_sub(queued_range.begin())
+					.saturating_add(1)
+			})
+			.fold(0, MessageNonce::saturating_add)
+	}
+
+	/// Return human-readable summary of the source queue.
+	pub fn summary(&self) -> String
+	where
+		SourceHeaderNumber: std::fmt::Debug,
+	{
+		let mut summary = match self.source_queue.front() {
+			Some((oldest_queued_at, _)) => format!(
+				"{} nonces in {} ranges, oldest queued at source header {:?}",
+				self.total_queued_nonces(),
+				self.source_queue.len(),
+				oldest_queued_at.0,
+			),
+			None => "empty".into(),
+		};
+		if let Some((_, ref coalesced_nonces)) = self.coalesced_nonces {
+			summary += &format!(", coalesced nonces {:?}", coalesced_nonces);
+		}
+		summary
+	}
+
+	/// Allow target nonce to go backwards.
+	///
+	/// Nonces are read at the best finalized header of the target node. So normally the latest
+	/// nonce at the target node never decreases and lower nonces are ignored. But if the target
+	/// chain is reverted (e.g. the bridged chain has been reorganized after emergency finality
+	/// reset), messages that have already been delivered may be missing at the target node. In
+	/// this mode, the lower nonce rolls back the `target_nonce` and missing messages are
+	/// delivered again.
+	pub fn with_target_nonce_rollback(mut self, is_target_nonce_rollback_allowed: bool) -> Self {
+		self.is_target_nonce_rollback_allowed = is_target_nonce_rollback_allowed;
+		self
+	}
+
+	/// Remove queued nonces that are not greater than the given `nonce`.
+	fn remove_queued_nonces(&mut self, nonce: MessageNonce) {
+		// queued ranges are ordered by nonces, so all fully removed entries are at the front
+		let removed_entries = self
+			.source_queue
+			.iter()
+			.take_while(|(_, range)| range.end() <= nonce)
+			.count();
+		self.source_queue.drain(..removed_entries);
+
+		// at most one entry needs to be trimmed
+		if let Some(true) = self.source_queue.front().map(|(_, range)| range.begin() <= nonce) {
+			if let Some((at_block, range)) = self.source_queue.pop_front() {
+				let subrange = range
+					.greater_than(nonce)
+					.expect("range that ends after the nonce has nonces greater than the nonce; qed");
+				self.source_queue.push_front((at_block, subrange));
+			}
+		}
+
+		// delivered range can't fail anymore
+		if let Some(true) = self
+			.range_failures
+			.as_ref()
+			.map(|range_failures| range_failures.nonce <= nonce)
+		{
+			self.range_failures = None;
+		}
+
+		// once all queued nonces are delivered, the summary range is dropped and its nonces are
+		// read from the source again
+		if self.source_queue.is_empty() && self.coalesced_nonces.take().is_some() {
+			log::debug!(
+				target: "bridge",
+				"All queued nonces are delivered. Coalesced nonces are going to be read again",
+			);
+		}
+	}
+
+	/// Return nonces that would be selected by the `select_nonces_to_deliver` right now, without
+	/// any side effects. It may be used for monitoring purposes.
+	///
+	/// The stored selector (see `with_selector`) may have side effects, so it is not called here
+	/// and whole queued ranges are selected instead.
+	pub fn peek_nonces_to_deliver(
+		&self,
+		race_state: &RaceState<
+			HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
+			Proof,
+		>,
+	) -> Option<RangeInclusive<MessageNonce>> {
+		self.select_nonces_to_deliver_with_selector(race_state, |_| None)
+			.ok()
+			.flatten()
+	}
+
 	/// Should return `Some(nonces)` if we need to deliver proof of `nonces` (and associated
 	/// data) from source to target node.
 	///
@@ -64,63 +732,114 @@ where
 	/// range needs to be delivered. If there are some nonces in the range that can't be delivered
 	/// right now, it should return `Some` with 'undeliverable' nonces. Please keep in mind that
 	/// this should be the sub-range that the passed range ends with, because nonces are always
-	/// delivered in-order. Otherwise the function will panic.
+	/// delivered in-order. Otherwise the function returns error and the queue is left untouched.
+	///
+	/// Selected nonces are not removed from the queue - they're only removed when the target node
+	/// confirms that they have been delivered. So if submitted transaction is lost, the same nonces
+	/// will be selected again.
 	pub fn select_nonces_to_deliver_with_selector(
-		&mut self,
+		&self,
 		race_state: &RaceState<
 			HeaderId<SourceHeaderHash, SourceHeaderNumber>,
 			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
 			Proof,
 		>,
 		mut selector: impl FnMut(SourceNoncesRange) -> Option<SourceNoncesRange>,
-	) -> Option<RangeInclusive<MessageNonce>> {
+	) -> Result<Option<RangeInclusive<MessageNonce>>, SelectorError> {
 		// if we have already selected nonces that we want to submit, do nothing
 		if race_state.nonces_to_submit.is_some() {
-			return None;
-		}
-
-		// if we already submitted some nonces, do nothing
-		if race_state.nonces_submitted.is_some() {
-			return None;
+			return Ok(None);
 		}
 
-		// 1) we want to deliver all nonces, starting from `target_nonce + 1`
+		// 1) we want to deliver all nonces, starting from `target_nonce + 1`. If we have already
+		// submitted some nonces, then we're starting after them - the new proof will be submitted
+		// once submitted nonces are confirmed by the target node. Nonces that are confirmed at the
+		// source node are never delivered
 		// 2) we can't deliver new nonce until header, that has emitted this nonce, is finalized
-		// by target client
+		// by target client and is at least `source_confirmation_depth` blocks old
 		// 3) selector is used for more complicated logic
-		let best_header_at_target = &race_state.target_state.as_ref()?.best_peer;
+		let nonces_begin = race_state
+			.best_pending_nonce()
+			.map(|best_pending_nonce| std::cmp::max(best_pending_nonce, self.target_nonce))
+			.unwrap_or(self.target_nonce)
+			.max(self.confirmed_nonce.unwrap_or(0))
+			+ 1;
+		let best_header_at_target = match race_state.target_state.as_ref() {
+			Some(target_state) => &target_state.best_peer,
+			None => return Ok(None),
+		};
+		let source_confirmation_depth = SourceHeaderNumber::from(self.source_confirmation_depth);
+		let max_nonces_end = self
+			.max_nonces_per_proof
+			.map(|max_nonces_per_proof| (nonces_begin - 1).saturating_add(max_nonces_per_proof));
+		// nonces of the parked range (and following nonces) are withheld
+		let parked_nonces_end = self.parked_nonce().map(|parked_nonce| parked_nonce.saturating_sub(1));
+		let nonces_end_limit = self
+			.final_nonce
+			.into_iter()
+			.chain(max_nonces_end)
+			.chain(parked_nonces_end)
+			.min();
 		let mut nonces_end = None;
 
-		while let Some((queued_at, queued_range)) = self.source_queue.pop_front() {
-			// select (sub) range to deliver
+		for (queued_at, queued_range) in &self.source_queue {
+			// skip nonces that are already submitted
+			if queued_range.end() < nonces_begin {
+				continue;
+			}
+			let queued_range = match queued_range.clone().greater_than(nonces_begin - 1) {
+				Some(queued_range) => queued_range,
+				None => continue,
+			};
+			// skip nonces that are after the final nonce or that don't fit into the single proof
+			let queued_range = match nonces_end_limit {
+				Some(nonces_end_limit) => match queued_range.not_greater_than(nonces_end_limit) {
+					Some(queued_range) => queued_range,
+					None => break,
+				},
+				None => queued_range,
+			};
+
+			// if header that has queued the range is not yet finalized at bridged chain,
+			// we can't prove anything
+			if queued_at.0 > best_header_at_target.0 {
+				break;
+			}
+			// if header that has queued the range is not yet confirmed by enough descendants,
+			// it may still be reorged out
+			if queued_at.0.clone() + source_confirmation_depth.clone() > best_header_at_target.0 {
+				break;
+			}
+
+			// selector returns `Some(range)` if this `range` can't be delivered right now
 			let queued_range_begin = queued_range.begin();
 			let queued_range_end = queued_range.end();
-			let range_to_requeue = if queued_at.0 > best_header_at_target.0 {
-				// if header that has queued the range is not yet finalized at bridged chain,
-				// we can't prove anything
-				Some(queued_range)
-			} else {
-				// selector returns `Some(range)` if this `range` needs to be requeued
-				selector(queued_range)
-			};
+			match selector(queued_range) {
+				Some(range_to_postpone) => {
+					let passed_range = queued_range_begin..=queued_range_end;
+					let returned_range = range_to_postpone.begin()..=range_to_postpone.end();
+					if returned_range.is_empty() {
+						return Err(SelectorError::EmptyRange {
+							passed_range,
+							returned_range,
+						});
+					}
+					if *returned_range.start() < queued_range_begin {
+						return Err(SelectorError::BeginsBeforePassedRange {
+							passed_range,
+							returned_range,
+						});
+					}
+					if *returned_range.end() != queued_range_end {
+						return Err(SelectorError::EndMismatch {
+							passed_range,
+							returned_range,
+						});
+					}
 
-			// requeue (sub) range and update range to deliver
-			match range_to_requeue {
-				Some(range_to_requeue) => {
-					assert!(
-						range_to_requeue.begin() <= range_to_requeue.end()
-							&& range_to_requeue.begin() >= queued_range_begin
-							&& range_to_requeue.end() == queued_range_end,
-						"Incorrect implementation of internal `selector` function. Expected original\
-						range {:?} to end with returned range {:?}",
-						queued_range_begin..=queued_range_end,
-						range_to_requeue,
-					);
-
-					if range_to_requeue.begin() != queued_range_begin {
-						nonces_end = Some(range_to_requeue.begin() - 1);
+					if range_to_postpone.begin() != queued_range_begin {
+						nonces_end = Some(range_to_postpone.begin() - 1);
 					}
-					self.source_queue.push_front((queued_at, range_to_requeue));
 					break;
 				}
 				None => {
@@ -129,57 +848,232 @@ where
 			}
 		}
 
-		nonces_end.map(|nonces_end| RangeInclusive::new(self.target_nonce + 1, nonces_end))
+		Ok(nonces_end.map(|nonces_end| RangeInclusive::new(nonces_begin, nonces_end)))
 	}
 }
 
-impl<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof>
-	RaceStrategy<HeaderId<SourceHeaderHash, SourceHeaderNumber>, HeaderId<TargetHeaderHash, TargetHeaderNumber>, Proof>
-	for BasicStrategy<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof>
+impl<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	> RaceStrategy<HeaderId<SourceHeaderHash, SourceHeaderNumber>, HeaderId<TargetHeaderHash, TargetHeaderNumber>, Proof>
+	for BasicStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+		ProofParameters,
+	>
 where
-	SourceHeaderHash: Clone,
-	SourceHeaderNumber: Clone + Ord,
+	SourceHeaderHash: Clone + PartialEq,
+	SourceHeaderNumber:
+		Clone + Ord + From<u32> + Into<u64> + std::ops::Add<Output = SourceHeaderNumber> + std::fmt::Debug,
 	SourceNoncesRange: NoncesRange,
+	ProofParameters: Default,
 {
 	type SourceNoncesRange = SourceNoncesRange;
-	type ProofParameters = ();
+	type ProofParameters = ProofParameters;
+	type Checkpoint = RaceCheckpoint<HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange>;
+	type Cost = Cost;
+	type TargetNoncesData = ();
 
 	fn is_empty(&self) -> bool {
-		self.source_queue.is_empty()
+		self.source_queue.is_empty() && self.coalesced_nonces.is_none()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		Some(self.summary())
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		StrategyMetricsSnapshot {
+			queued_ranges: Some(self.source_queue.len() as u64),
+			queued_nonces: Some(self.total_queued_nonces()),
+			oldest_queued_at: self
+				.source_queue
+				.front()
+				.map(|(oldest_queued_at, _)| oldest_queued_at.0.clone().into()),
+			source_nonce_gaps: Some(self.source_nonce_gaps),
+		}
+	}
+
+	fn is_source_queue_full(&self) -> bool {
+		match self.max_queue_len {
+			Some((max_queue_len, QueueOverflowPolicy::RefuseNewNonces)) => self.source_queue.len() >= max_queue_len,
+			_ => false,
+		}
 	}
 
 	fn best_at_source(&self) -> MessageNonce {
-		std::cmp::max(
-			self.source_queue
-				.back()
-				.map(|(_, range)| range.end())
-				.unwrap_or(self.target_nonce),
-			self.target_nonce,
-		)
+		let best_queued_nonce = self
+			.coalesced_nonces
+			.as_ref()
+			.map(|(_, coalesced_nonces)| *coalesced_nonces.end())
+			.or_else(|| self.source_queue.back().map(|(_, range)| range.end()))
+			.unwrap_or(self.target_nonce)
+			.max(self.best_discarded_nonce.unwrap_or(0));
+		std::cmp::max(best_queued_nonce, self.target_nonce).max(self.confirmed_nonce.unwrap_or(0))
 	}
 
 	fn best_at_target(&self) -> MessageNonce {
 		self.target_nonce
 	}
 
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		if self.is_source_nonce_known {
+			Some(self.best_at_source())
+		} else {
+			None
+		}
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		if self.is_target_nonce_known {
+			Some(self.target_nonce)
+		} else {
+			None
+		}
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.require_target_finality
+	}
+
 	fn source_nonces_updated(
 		&mut self,
 		at_block: HeaderId<SourceHeaderHash, SourceHeaderNumber>,
 		nonces: SourceClientNonces<SourceNoncesRange>,
 	) {
+		let is_best_nonce_known = self.is_source_nonce_known || self.is_target_nonce_known;
+		self.is_source_nonce_known = true;
+
+		// confirmed nonces can never need delivery
+		if let Some(confirmed_nonce) = nonces.confirmed_nonce {
+			self.confirmed_nonce = Some(confirmed_nonce);
+			self.remove_queued_nonces(confirmed_nonce);
+		}
+
 		let prev_best_at_source = self.best_at_source();
-		self.source_queue.extend(
-			nonces
-				.new_nonces
-				.greater_than(prev_best_at_source)
-				.into_iter()
-				.map(move |range| (at_block.clone(), range)),
-		)
+		let new_nonces = match nonces.new_nonces.greater_than(prev_best_at_source) {
+			Some(new_nonces) => new_nonces,
+			None => return,
+		};
+
+		// the target node would reject delivery of nonces that don't follow the best nonce. So if
+		// the source client has skipped some nonces (e.g. because of the bug or pruned state), we
+		// refuse the whole update. The nonces are requested again at the next source header
+		let expected_begin = prev_best_at_source.saturating_add(1);
+		if is_best_nonce_known && new_nonces.begin() > expected_begin {
+			log::error!(
+				target: "bridge",
+				"Lane blocked: missing nonces {:?}. Source client has returned nonces {:?} after the best nonce {}",
+				expected_begin..=new_nonces.begin() - 1,
+				new_nonces.begin()..=new_nonces.end(),
+				prev_best_at_source,
+			);
+			self.source_nonce_gaps += 1;
+			return;
+		}
+
+		// nonces after the final nonce are never delivered, so there's no point in queueing them
+		let new_nonces = match self.final_nonce {
+			Some(final_nonce) if new_nonces.end() > final_nonce => {
+				log::trace!(
+					target: "bridge",
+					"Discarding source nonces {:?} after the final nonce {}",
+					std::cmp::max(new_nonces.begin(), final_nonce + 1)..=new_nonces.end(),
+					final_nonce,
+				);
+				self.best_discarded_nonce = Some(new_nonces.end());
+				match new_nonces.not_greater_than(final_nonce) {
+					Some(new_nonces) => new_nonces,
+					None => return,
+				}
+			}
+			_ => new_nonces,
+		};
+
+		// nonces are never queued after the summary range
+		if let Some((coalesced_at, coalesced_nonces)) = self.coalesced_nonces.as_mut() {
+			*coalesced_nonces = *coalesced_nonces.start()..=new_nonces.end();
+			*coalesced_at = at_block;
+			return;
+		}
+
+		if let (Some(max_merged_range_len), Some((queued_at, queued_range))) =
+			(self.max_merged_range_len, self.source_queue.back_mut())
+		{
+			let is_contiguous = queued_range.end().checked_add(1) == Some(new_nonces.begin());
+			let merged_range_len = new_nonces.end().saturating_sub(queued_range.begin()).saturating_add(1);
+			if is_contiguous && merged_range_len <= max_merged_range_len {
+				queued_range.merge_with_next(new_nonces);
+				*queued_at = at_block;
+				return;
+			}
+		}
+
+		if let Some((max_queue_len, overflow_policy)) = self.max_queue_len {
+			if self.source_queue.len() >= max_queue_len {
+				match overflow_policy {
+					QueueOverflowPolicy::RefuseNewNonces => {
+						log::debug!(
+							target: "bridge",
+							"Source queue is full ({} entries). Refusing nonces {:?}",
+							self.source_queue.len(),
+							new_nonces.begin()..=new_nonces.end(),
+						);
+					}
+					QueueOverflowPolicy::CoalesceNewNonces => {
+						log::debug!(
+							target: "bridge",
+							"Source queue is full ({} entries). Coalescing nonces {:?}",
+							self.source_queue.len(),
+							new_nonces.begin()..=new_nonces.end(),
+						);
+						self.coalesced_nonces = Some((at_block, new_nonces.begin()..=new_nonces.end()));
+					}
+				}
+				return;
+			}
+		}
+
+		self.source_queue.push_back((at_block, new_nonces));
+	}
+
+	fn source_reorged(&mut self, new_best: &HeaderId<SourceHeaderHash, SourceHeaderNumber>) {
+		// nonces are queued in order, so once we've found orphaned entry, all following entries
+		// are also orphaned. They'll be queued again when nonces are read at the new best header
+		let first_orphaned_entry = self
+			.source_queue
+			.iter()
+			.position(|(queued_at, _)| queued_at.is_orphaned_by(new_best));
+		if let Some(first_orphaned_entry) = first_orphaned_entry {
+			self.source_queue.truncate(first_orphaned_entry);
+		}
+		let is_coalesced_nonces_orphaned = self
+			.coalesced_nonces
+			.as_ref()
+			.map(|(coalesced_at, _)| coalesced_at.is_orphaned_by(new_best))
+			.unwrap_or(false);
+		if is_coalesced_nonces_orphaned {
+			self.coalesced_nonces = None;
+		}
+		// we don't know headers where discarded nonces have been seen, so they're forgotten. They
+		// will be read (and discarded) again at the new best header
+		self.best_discarded_nonce = None;
 	}
 
 	fn target_nonces_updated(
 		&mut self,
-		nonces: TargetClientNonces,
+		nonces: TargetClientNonces<()>,
 		race_state: &mut RaceState<
 			HeaderId<SourceHeaderHash, SourceHeaderNumber>,
 			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
@@ -187,21 +1081,44 @@ where
 		>,
 	) {
 		let nonce = nonces.latest_nonce;
+		let is_target_nonce_restored = std::mem::replace(&mut self.is_target_nonce_restored, false);
+		self.is_target_nonce_known = true;
 
 		if nonce < self.target_nonce {
+			if is_target_nonce_restored {
+				log::warn!(
+					target: "bridge",
+					"Target nonce {} is lower than the restored nonce {}. Ignoring the restored state",
+					nonce,
+					self.target_nonce,
+				);
+			} else if self.is_target_nonce_rollback_allowed {
+				log::warn!(
+					target: "bridge",
+					"Target nonce has been rolled back from {} to {}. Going to redeliver messages",
+					self.target_nonce,
+					nonce,
+				);
+			} else {
+				return;
+			}
+
+			// we don't know headers where rolled back nonces have been generated, so we are
+			// forgetting all queued nonces. They all will be read again at the best source header,
+			// because `best_at_source` is now the rolled back nonce. Selected and submitted nonces
+			// are not following the rolled back nonce, so they can't be delivered
+			self.source_queue.clear();
+			self.coalesced_nonces = None;
+			self.best_discarded_nonce = None;
+			self.range_failures = None;
+			race_state.nonces_to_submit = None;
+			race_state.proofs_to_submit.clear();
+			race_state.nonces_submitted.clear();
+			self.target_nonce = nonce;
 			return;
 		}
 
-		while let Some(true) = self.source_queue.front().map(|(_, range)| range.begin() <= nonce) {
-			let maybe_subrange = self
-				.source_queue
-				.pop_front()
-				.and_then(|(at_block, range)| range.greater_than(nonce).map(|subrange| (at_block, subrange)));
-			if let Some((at_block, subrange)) = maybe_subrange {
-				self.source_queue.push_front((at_block, subrange));
-				break;
-			}
-		}
+		self.remove_queued_nonces(nonce);
 
 		let need_to_select_new_nonces = race_state
 			.nonces_to_submit
@@ -212,13 +1129,15 @@ where
 			race_state.nonces_to_submit = None;
 		}
 
-		let need_new_nonces_to_submit = race_state
-			.nonces_submitted
-			.as_ref()
-			.map(|nonces| *nonces.end() <= nonce)
-			.unwrap_or(false);
-		if need_new_nonces_to_submit {
-			race_state.nonces_submitted = None;
+		while let Some(true) = race_state
+			.proofs_to_submit
+			.front()
+			.map(|(_, nonces, _)| *nonces.end() <= nonce)
+		{
+			race_state.proofs_to_submit.pop_front();
+		}
+		while let Some(true) = race_state.nonces_submitted.front().map(|nonces| *nonces.end() <= nonce) {
+			race_state.nonces_submitted.pop_front();
 		}
 
 		self.target_nonce = nonce;
@@ -231,167 +1150,2227 @@ where
 			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
 			Proof,
 		>,
-	) -> Option<(RangeInclusive<MessageNonce>, Self::ProofParameters)> {
-		self.select_nonces_to_deliver_with_selector(race_state, |_| None)
-			.map(|range| (range, ()))
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<HeaderId<SourceHeaderHash, SourceHeaderNumber>>,
+	)> {
+		let mut stored_selector = self.selector.take();
+		let selected_nonces = match stored_selector {
+			Some(StoredSelector(ref mut selector)) => self.select_nonces_to_deliver_with_selector(race_state, selector),
+			None => self.select_nonces_to_deliver_with_selector(race_state, |_| None),
+		};
+		self.selector = stored_selector;
+
+		match selected_nonces {
+			Ok(selected_nonces) => selected_nonces,
+			Err(error) => {
+				log::error!(target: "bridge", "{}. Selecting nothing", error);
+				None
+			}
+		}
+		.map(|range| {
+			let proof_parameters = match self.proof_parameters_builder {
+				Some(StoredProofParametersBuilder(ref builder)) => builder(&range, self.confirmed_nonce),
+				None => Default::default(),
+			};
+			let proof_header = self.proof_header(&range);
+			(range, proof_parameters, proof_header)
+		})
 	}
-}
 
-#[cfg(test)]
-mod tests {
-	use super::*;
-	use crate::message_lane::MessageLane;
-	use crate::message_lane_loop::{
-		tests::{header_id, TestMessageLane, TestMessagesProof},
-		ClientState,
-	};
+	fn required_source_header_at_target(
+		&self,
+		current_best: &HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+	) -> Option<HeaderId<SourceHeaderHash, SourceHeaderNumber>> {
+		// delivered nonces may still be in the queue until the target node confirms them. Queued
+		// entries are ordered by headers, so the first unprovable entry blocks all next entries
+		self.source_queue
+			.iter()
+			.map(|(queued_at, _)| queued_at)
+			.find(|queued_at| queued_at.0 > current_best.0)
+			.cloned()
+	}
 
-	type SourceNoncesRange = RangeInclusive<MessageNonce>;
+	fn apply_command(&mut self, command: RaceCommand) {
+		if let RaceCommand::SetMaxNoncesPerProof(max_nonces_per_proof) = command {
+			self.max_nonces_per_proof = Some(max_nonces_per_proof as MessageNonce);
+		}
+	}
 
-	type BasicStrategy<P> = super::BasicStrategy<
-		<P as MessageLane>::SourceHeaderNumber,
-		<P as MessageLane>::SourceHeaderHash,
-		<P as MessageLane>::TargetHeaderNumber,
-		<P as MessageLane>::TargetHeaderHash,
-		SourceNoncesRange,
-		<P as MessageLane>::MessagesProof,
-	>;
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		let (failure_threshold, action) = match self.failure_threshold {
+			Some(failure_threshold) => failure_threshold,
+			None => return Ok(()),
+		};
 
-	fn source_nonces(new_nonces: SourceNoncesRange) -> SourceClientNonces<SourceNoncesRange> {
-		SourceClientNonces {
-			new_nonces,
-			confirmed_nonce: None,
+		// delivered nonces can't block the lane
+		let nonce = *nonces.start();
+		if nonce <= self.target_nonce {
+			return Ok(());
+		}
+
+		let is_same_range = self
+			.range_failures
+			.as_ref()
+			.map(|range_failures| range_failures.nonce == nonce)
+			.unwrap_or(false);
+		if !is_same_range {
+			self.range_failures = Some(RangeFailures {
+				nonce,
+				failures: 0,
+				park_delay: None,
+				parked_until: None,
+			});
+		}
+		let range_failures = self.range_failures.as_mut().expect("range failures are set above; qed");
+		range_failures.failures += 1;
+		if range_failures.failures < failure_threshold {
+			log::debug!(
+				target: "bridge",
+				"Delivery of nonces {:?} has failed ({:?}) {} times in a row",
+				nonces,
+				kind,
+				range_failures.failures,
+			);
+			return Ok(());
+		}
+
+		match action {
+			RangeFailureAction::Park {
+				initial_delay,
+				max_delay,
+			} => {
+				let park_delay = range_failures
+					.park_delay
+					.map(|park_delay| std::cmp::min(park_delay.saturating_mul(2), max_delay))
+					.unwrap_or(initial_delay);
+				log::warn!(
+					target: "bridge",
+					"Delivery of nonces {:?} has failed ({:?}) {} times in a row. Parking the range for {}s",
+					nonces,
+					kind,
+					range_failures.failures,
+					park_delay.as_secs_f64(),
+				);
+				range_failures.failures = 0;
+				range_failures.park_delay = Some(park_delay);
+				range_failures.parked_until = Some(self.clock.0.now() + park_delay);
+				Ok(())
+			}
+			RangeFailureAction::Fail => {
+				log::error!(
+					target: "bridge",
+					"Lane blocked at nonce {}: delivery has failed ({:?}) {} times in a row",
+					nonce,
+					kind,
+					range_failures.failures,
+				);
+				Err(RaceErrorReason::LaneBlocked {
+					nonce,
+					failures: range_failures.failures,
+					last_failure: kind,
+				})
+			}
+		}
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		RaceCheckpoint {
+			target_nonce: self.target_nonce,
+			source_queue: self.source_queue.iter().cloned().collect(),
+		}
+	}
+}
+
+/// Check that queued ranges are never empty, never overlap, are never delivered and are queued
+/// in order.
+fn validate_source_queue<SourceHeaderNumber: Ord, SourceHeaderHash, SourceNoncesRange: NoncesRange>(
+	target_nonce: MessageNonce,
+	source_queue: &[(HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange)],
+) -> Result<(), String> {
+	let mut prev_queued = (None, target_nonce);
+	for (queued_at, queued_range) in source_queue {
+		let (prev_queued_at, prev_queued_end) = prev_queued;
+		let is_valid_entry = queued_range.begin() <= queued_range.end()
+			&& queued_range.begin() > prev_queued_end
+			&& prev_queued_at
+				.map(|prev_queued_at| prev_queued_at <= &queued_at.0)
+				.unwrap_or(true);
+		if !is_valid_entry {
+			return Err(format!(
+				"queued range {:?} is not following nonce {} or queued at older header",
+				queued_range.begin()..=queued_range.end(),
+				prev_queued_end,
+			));
+		}
+
+		prev_queued = (Some(&queued_at.0), queued_range.end());
+	}
+
+	Ok(())
+}
+
+/// Batching strategy parameters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchingParams {
+	/// Nonces are selected immediately if there are at least this number of deliverable nonces.
+	pub min_batch_size: MessageNonce,
+	/// Nonces are selected if the oldest deliverable nonce has been waiting for this long.
+	///
+	/// The wait time is only checked when the race is selecting nonces, so the race should be
+	/// configured to refresh nonces periodically (see `RaceParams::refresh_interval`).
+	pub max_wait: Duration,
+}
+
+/// Strategy that withholds selection of the wrapped strategy until the batch is large enough,
+/// or until the oldest deliverable nonce has waited for too long.
+///
+/// Deliverable nonces are nonces that are selected by the wrapped strategy. So if the wrapped
+/// strategy limits its selection (e.g. with `BasicStrategy::with_max_batch`), the limit should not
+/// be less than `min_batch_size`. Otherwise nonces are only selected after `max_wait`.
+#[derive(Debug)]
+pub struct BatchingStrategy<Strategy> {
+	/// Wrapped strategy.
+	strategy: Strategy,
+	/// Batching parameters. If `None`, selection of the wrapped strategy is never withheld.
+	params: Option<BatchingParams>,
+	/// Ends of deliverable nonces ranges along with moments when they have become deliverable.
+	/// Ranges are ordered by nonces.
+	deliverable_since: VecDeque<(MessageNonce, Instant)>,
+}
+
+impl<Strategy> BatchingStrategy<Strategy> {
+	/// Create new batching strategy.
+	pub fn new(strategy: Strategy, params: Option<BatchingParams>) -> Self {
+		BatchingStrategy {
+			strategy,
+			params,
+			deliverable_since: VecDeque::new(),
+		}
+	}
+
+	/// Update deliverable nonces and return the moment when the oldest deliverable nonce has
+	/// become deliverable.
+	fn update_deliverable_nonces(&mut self, nonces: &RangeInclusive<MessageNonce>, now: Instant) -> Instant {
+		// forget about nonces that are already delivered (or at least selected)
+		while let Some((end, _)) = self.deliverable_since.front() {
+			if *end >= *nonces.start() {
+				break;
+			}
+			self.deliverable_since.pop_front();
+		}
+		// forget about nonces that are not deliverable anymore (e.g. after source reorg)
+		while let Some((end, _)) = self.deliverable_since.back() {
+			if *end <= *nonces.end() {
+				break;
+			}
+			self.deliverable_since.pop_back();
+		}
+		// remember when new nonces have become deliverable
+		let is_new_nonces_deliverable = self
+			.deliverable_since
+			.back()
+			.map(|(end, _)| *end < *nonces.end())
+			.unwrap_or(true);
+		if is_new_nonces_deliverable {
+			self.deliverable_since.push_back((*nonces.end(), now));
+		}
+
+		self.deliverable_since
+			.front()
+			.map(|(_, deliverable_since)| *deliverable_since)
+			.unwrap_or(now)
+	}
+}
+
+impl<SourceHeaderId, TargetHeaderId, Proof, Strategy> RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>
+	for BatchingStrategy<Strategy>
+where
+	Strategy: RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+{
+	type SourceNoncesRange = Strategy::SourceNoncesRange;
+	type ProofParameters = Strategy::ProofParameters;
+	type Checkpoint = Strategy::Checkpoint;
+	type Cost = Strategy::Cost;
+	type TargetNoncesData = Strategy::TargetNoncesData;
+
+	fn is_empty(&self) -> bool {
+		self.strategy.is_empty()
+	}
+
+	fn is_source_queue_full(&self) -> bool {
+		self.strategy.is_source_queue_full()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		self.strategy.queue_summary()
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		self.strategy.metrics()
+	}
+
+	fn best_at_source(&self) -> MessageNonce {
+		self.strategy.best_at_source()
+	}
+
+	fn best_at_target(&self) -> MessageNonce {
+		self.strategy.best_at_target()
+	}
+
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_source_opt()
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_target_opt()
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.strategy.requires_target_finality()
+	}
+
+	fn source_nonces_updated(&mut self, at_block: SourceHeaderId, nonces: SourceClientNonces<Self::SourceNoncesRange>) {
+		self.strategy.source_nonces_updated(at_block, nonces)
+	}
+
+	fn source_reorged(&mut self, new_best: &SourceHeaderId) {
+		self.strategy.source_reorged(new_best)
+	}
+
+	fn target_nonces_updated(
+		&mut self,
+		nonces: TargetClientNonces<Self::TargetNoncesData>,
+		race_state: &mut RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+	) {
+		self.strategy.target_nonces_updated(nonces, race_state)
+	}
+
+	fn select_nonces_to_deliver(
+		&mut self,
+		race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<SourceHeaderId>,
+	)> {
+		let (nonces, proof_parameters, proof_header) = self.strategy.select_nonces_to_deliver(race_state)?;
+		let params = match self.params {
+			Some(params) => params,
+			None => return Some((nonces, proof_parameters, proof_header)),
+		};
+
+		let now = Instant::now();
+		let deliverable_since = self.update_deliverable_nonces(&nonces, now);
+		let batch_size = nonces.end() - nonces.start() + 1;
+		let waited = now.duration_since(deliverable_since);
+		if batch_size < params.min_batch_size && waited < params.max_wait {
+			log::debug!(
+				target: "bridge",
+				"Withholding delivery of nonces {:?}: batch size {} is less than {} and nonces have been waiting for {:?}",
+				nonces,
+				batch_size,
+				params.min_batch_size,
+				waited,
+			);
+			return None;
+		}
+
+		Some((nonces, proof_parameters, proof_header))
+	}
+
+	fn required_source_header_at_target(&self, current_best: &SourceHeaderId) -> Option<SourceHeaderId> {
+		self.strategy.required_source_header_at_target(current_best)
+	}
+
+	fn prefix_proof_parameters(
+		&self,
+		nonces: &RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> PrefixProofParameters<Self::ProofParameters>
+	where
+		Self::ProofParameters: Clone + Send + 'static,
+	{
+		self.strategy.prefix_proof_parameters(nonces, proof_parameters)
+	}
+
+	fn delivery_cost_required(&self, nonces: &RangeInclusive<MessageNonce>) -> Option<(Weight, u32)> {
+		self.strategy.delivery_cost_required(nonces)
+	}
+
+	fn delivery_cost_estimated(&mut self, nonces: &RangeInclusive<MessageNonce>, cost: Self::Cost) -> bool {
+		self.strategy.delivery_cost_estimated(nonces, cost)
+	}
+
+	fn apply_command(&mut self, command: RaceCommand) {
+		self.strategy.apply_command(command)
+	}
+
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		self.strategy.range_failed(nonces, kind)
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		self.strategy.checkpoint()
+	}
+}
+
+/// Source of current time.
+pub trait Clock {
+	/// Returns current time.
+	fn now(&self) -> Instant;
+}
+
+/// Clock that is using system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+}
+
+/// Strategy that limits number of nonces selected by the wrapped strategy per minute.
+///
+/// Limit is implemented as token bucket, which holds at most `max_nonces_per_minute` tokens and
+/// is refilled continuously. Every selected nonce consumes one token. If the wrapped strategy
+/// selects more nonces than there are tokens in the bucket, the selected range is truncated.
+/// Proof parameters of the wrapped strategy are returned as is, so they may describe more nonces
+/// than are actually selected (e.g. cumulative dispatch weight of delivered messages would be
+/// overestimated).
+///
+/// Nothing is selected while the bucket is empty. The race should be configured to refresh nonces
+/// periodically (see `RaceParams::refresh_interval`), so that selection is retried once the bucket
+/// is refilled.
+#[derive(Debug)]
+pub struct RateLimitedStrategy<Strategy, C = SystemClock> {
+	/// Wrapped strategy.
+	strategy: Strategy,
+	/// Clock that is used to refill the bucket.
+	clock: C,
+	/// Maximal number of nonces that may be selected per minute. It is also the bucket capacity.
+	max_nonces_per_minute: MessageNonce,
+	/// Number of nonces that may be selected right now.
+	tokens: MessageNonce,
+	/// Time when the bucket has been refilled last time.
+	refilled_at: Instant,
+}
+
+impl<Strategy> RateLimitedStrategy<Strategy> {
+	/// Create new rate-limited strategy that is using system clock.
+	pub fn new(strategy: Strategy, max_nonces_per_minute: MessageNonce) -> Self {
+		Self::with_clock(strategy, max_nonces_per_minute, SystemClock)
+	}
+}
+
+impl<Strategy, C: Clock> RateLimitedStrategy<Strategy, C> {
+	/// Create new rate-limited strategy that is using given clock.
+	pub fn with_clock(strategy: Strategy, max_nonces_per_minute: MessageNonce, clock: C) -> Self {
+		let refilled_at = clock.now();
+		RateLimitedStrategy {
+			strategy,
+			clock,
+			max_nonces_per_minute,
+			tokens: max_nonces_per_minute,
+			refilled_at,
+		}
+	}
+
+	/// Refill the bucket and return number of available tokens.
+	fn refill(&mut self) -> MessageNonce {
+		const NANOS_PER_MINUTE: u128 = 60 * 1_000_000_000;
+
+		let now = self.clock.now();
+		if self.tokens >= self.max_nonces_per_minute || self.max_nonces_per_minute == 0 {
+			self.refilled_at = now;
+			return self.tokens;
+		}
+
+		let elapsed = now.saturating_duration_since(self.refilled_at).as_nanos();
+		let new_tokens = elapsed.saturating_mul(self.max_nonces_per_minute as u128) / NANOS_PER_MINUTE;
+		let missing_tokens = (self.max_nonces_per_minute - self.tokens) as u128;
+		if new_tokens >= missing_tokens {
+			self.tokens = self.max_nonces_per_minute;
+			self.refilled_at = now;
+		} else if new_tokens != 0 {
+			// only account time that has been spent on refilling whole tokens, so the remainder
+			// is not lost
+			let refill_time = new_tokens * NANOS_PER_MINUTE / self.max_nonces_per_minute as u128;
+			self.tokens += new_tokens as MessageNonce;
+			self.refilled_at += Duration::from_nanos(refill_time as u64);
+		}
+
+		self.tokens
+	}
+}
+
+impl<SourceHeaderId, TargetHeaderId, Proof, Strategy, C> RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>
+	for RateLimitedStrategy<Strategy, C>
+where
+	Strategy: RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>,
+	C: Clock,
+{
+	type SourceNoncesRange = Strategy::SourceNoncesRange;
+	type ProofParameters = Strategy::ProofParameters;
+	type Checkpoint = Strategy::Checkpoint;
+	type Cost = Strategy::Cost;
+	type TargetNoncesData = Strategy::TargetNoncesData;
+
+	fn is_empty(&self) -> bool {
+		self.strategy.is_empty()
+	}
+
+	fn is_source_queue_full(&self) -> bool {
+		self.strategy.is_source_queue_full()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		self.strategy.queue_summary()
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		self.strategy.metrics()
+	}
+
+	fn best_at_source(&self) -> MessageNonce {
+		self.strategy.best_at_source()
+	}
+
+	fn best_at_target(&self) -> MessageNonce {
+		self.strategy.best_at_target()
+	}
+
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_source_opt()
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_target_opt()
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.strategy.requires_target_finality()
+	}
+
+	fn source_nonces_updated(&mut self, at_block: SourceHeaderId, nonces: SourceClientNonces<Self::SourceNoncesRange>) {
+		self.strategy.source_nonces_updated(at_block, nonces)
+	}
+
+	fn source_reorged(&mut self, new_best: &SourceHeaderId) {
+		self.strategy.source_reorged(new_best)
+	}
+
+	fn target_nonces_updated(
+		&mut self,
+		nonces: TargetClientNonces<Self::TargetNoncesData>,
+		race_state: &mut RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+	) {
+		self.strategy.target_nonces_updated(nonces, race_state)
+	}
+
+	fn select_nonces_to_deliver(
+		&mut self,
+		race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<SourceHeaderId>,
+	)> {
+		let tokens = self.refill();
+		if tokens == 0 {
+			log::debug!(
+				target: "bridge",
+				"Not selecting nonces: limit of {} nonces per minute is reached",
+				self.max_nonces_per_minute,
+			);
+			return None;
+		}
+
+		let (nonces, proof_parameters, proof_header) = self.strategy.select_nonces_to_deliver(race_state)?;
+		let nonces_end = std::cmp::min(*nonces.end(), nonces.start().saturating_add(tokens - 1));
+		self.tokens -= nonces_end - nonces.start() + 1;
+		Some((*nonces.start()..=nonces_end, proof_parameters, proof_header))
+	}
+
+	fn required_source_header_at_target(&self, current_best: &SourceHeaderId) -> Option<SourceHeaderId> {
+		self.strategy.required_source_header_at_target(current_best)
+	}
+
+	fn prefix_proof_parameters(
+		&self,
+		nonces: &RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> PrefixProofParameters<Self::ProofParameters>
+	where
+		Self::ProofParameters: Clone + Send + 'static,
+	{
+		self.strategy.prefix_proof_parameters(nonces, proof_parameters)
+	}
+
+	fn delivery_cost_required(&self, nonces: &RangeInclusive<MessageNonce>) -> Option<(Weight, u32)> {
+		self.strategy.delivery_cost_required(nonces)
+	}
+
+	fn delivery_cost_estimated(&mut self, nonces: &RangeInclusive<MessageNonce>, cost: Self::Cost) -> bool {
+		self.strategy.delivery_cost_estimated(nonces, cost)
+	}
+
+	fn apply_command(&mut self, command: RaceCommand) {
+		self.strategy.apply_command(command)
+	}
+
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		self.strategy.range_failed(nonces, kind)
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		self.strategy.checkpoint()
+	}
+}
+
+/// Constraint that is restricting nonces, selected by the `CompositeStrategy`.
+pub trait SelectionConstraint<SourceNoncesRange> {
+	/// Restrict candidate `nonces` range. The `queued` are queued source ranges that are covering the
+	/// whole range, selected by the basic strategy (they may cover more nonces than the `nonces`, if
+	/// the range has been restricted by previous constraints).
+	///
+	/// Must return prefix of the `nonces` range, or `None` if nothing may be selected.
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		queued: &[SourceNoncesRange],
+	) -> Option<RangeInclusive<MessageNonce>>;
+}
+
+impl<SourceNoncesRange, F> SelectionConstraint<SourceNoncesRange> for F
+where
+	F: FnMut(RangeInclusive<MessageNonce>, &[SourceNoncesRange]) -> Option<RangeInclusive<MessageNonce>>,
+{
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		queued: &[SourceNoncesRange],
+	) -> Option<RangeInclusive<MessageNonce>> {
+		self(nonces, queued)
+	}
+}
+
+/// Constraint that limits number of selected nonces.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxNoncesConstraint(pub MessageNonce);
+
+impl<SourceNoncesRange> SelectionConstraint<SourceNoncesRange> for MaxNoncesConstraint {
+	fn restrict(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		_queued: &[SourceNoncesRange],
+	) -> Option<RangeInclusive<MessageNonce>> {
+		if self.0 == 0 {
+			return None;
+		}
+
+		let nonces_end = std::cmp::min(*nonces.end(), nonces.start().saturating_add(self.0 - 1));
+		Some(*nonces.start()..=nonces_end)
+	}
+}
+
+/// Strategy that selects nonces using `BasicStrategy` and then restricts selected range using
+/// chain of selection constraints.
+///
+/// Constraints are evaluated in the order they have been added to the strategy. Every constraint
+/// receives the range, restricted by all previous constraints. If some constraint returns `None`,
+/// next constraints are not evaluated and nothing is selected.
+pub struct CompositeStrategy<
+	SourceHeaderNumber,
+	SourceHeaderHash,
+	TargetHeaderNumber,
+	TargetHeaderHash,
+	SourceNoncesRange,
+	Proof,
+	Cost = (),
+> {
+	/// Basic strategy.
+	strategy: BasicStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+	>,
+	/// Selection constraints.
+	constraints: Vec<Box<dyn SelectionConstraint<SourceNoncesRange> + Send>>,
+}
+
+impl<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof, Cost>
+	CompositeStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+	>
+{
+	/// Create new composite strategy without any constraints.
+	pub fn new(
+		strategy: BasicStrategy<
+			SourceHeaderNumber,
+			SourceHeaderHash,
+			TargetHeaderNumber,
+			TargetHeaderHash,
+			SourceNoncesRange,
+			Proof,
+			Cost,
+		>,
+	) -> Self {
+		CompositeStrategy {
+			strategy,
+			constraints: Vec::new(),
 		}
 	}
 
-	fn target_nonces(latest_nonce: MessageNonce) -> TargetClientNonces {
-		TargetClientNonces {
-			latest_nonce,
-			confirmed_nonce: None,
-		}
+	/// Add constraint to the end of constraints chain.
+	pub fn with_constraint(mut self, constraint: impl SelectionConstraint<SourceNoncesRange> + Send + 'static) -> Self {
+		self.constraints.push(Box::new(constraint));
+		self
+	}
+}
+
+impl<SourceHeaderNumber, SourceHeaderHash, TargetHeaderNumber, TargetHeaderHash, SourceNoncesRange, Proof, Cost>
+	RaceStrategy<HeaderId<SourceHeaderHash, SourceHeaderNumber>, HeaderId<TargetHeaderHash, TargetHeaderNumber>, Proof>
+	for CompositeStrategy<
+		SourceHeaderNumber,
+		SourceHeaderHash,
+		TargetHeaderNumber,
+		TargetHeaderHash,
+		SourceNoncesRange,
+		Proof,
+		Cost,
+	>
+where
+	SourceHeaderHash: Clone + PartialEq,
+	SourceHeaderNumber:
+		Clone + Ord + From<u32> + Into<u64> + std::ops::Add<Output = SourceHeaderNumber> + std::fmt::Debug,
+	SourceNoncesRange: NoncesRange,
+{
+	type SourceNoncesRange = SourceNoncesRange;
+	type ProofParameters = ();
+	type Checkpoint = RaceCheckpoint<HeaderId<SourceHeaderHash, SourceHeaderNumber>, SourceNoncesRange>;
+	type Cost = Cost;
+	type TargetNoncesData = ();
+
+	fn is_empty(&self) -> bool {
+		self.strategy.is_empty()
+	}
+
+	fn is_source_queue_full(&self) -> bool {
+		self.strategy.is_source_queue_full()
+	}
+
+	fn queue_summary(&self) -> Option<String> {
+		self.strategy.queue_summary()
+	}
+
+	fn metrics(&self) -> StrategyMetricsSnapshot {
+		self.strategy.metrics()
+	}
+
+	fn best_at_source(&self) -> MessageNonce {
+		self.strategy.best_at_source()
+	}
+
+	fn best_at_target(&self) -> MessageNonce {
+		self.strategy.best_at_target()
+	}
+
+	fn best_at_source_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_source_opt()
+	}
+
+	fn best_at_target_opt(&self) -> Option<MessageNonce> {
+		self.strategy.best_at_target_opt()
+	}
+
+	fn requires_target_finality(&self) -> bool {
+		self.strategy.requires_target_finality()
+	}
+
+	fn source_nonces_updated(
+		&mut self,
+		at_block: HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+		nonces: SourceClientNonces<Self::SourceNoncesRange>,
+	) {
+		self.strategy.source_nonces_updated(at_block, nonces)
+	}
+
+	fn source_reorged(&mut self, new_best: &HeaderId<SourceHeaderHash, SourceHeaderNumber>) {
+		self.strategy.source_reorged(new_best)
+	}
+
+	fn target_nonces_updated(
+		&mut self,
+		nonces: TargetClientNonces<()>,
+		race_state: &mut RaceState<
+			HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
+			Proof,
+		>,
+	) {
+		self.strategy.target_nonces_updated(nonces, race_state)
+	}
+
+	fn select_nonces_to_deliver(
+		&mut self,
+		race_state: &RaceState<
+			HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+			HeaderId<TargetHeaderHash, TargetHeaderNumber>,
+			Proof,
+		>,
+	) -> Option<(
+		RangeInclusive<MessageNonce>,
+		Self::ProofParameters,
+		Option<HeaderId<SourceHeaderHash, SourceHeaderNumber>>,
+	)> {
+		// selector never postpones any nonces, so it can't break the contract
+		let mut queued = Vec::new();
+		let mut nonces = self
+			.strategy
+			.select_nonces_to_deliver_with_selector(race_state, |queued_range| {
+				queued.push(queued_range);
+				None
+			})
+			.ok()
+			.flatten()?;
+
+		for constraint in &mut self.constraints {
+			let restricted_nonces = constraint.restrict(nonces.clone(), &queued)?;
+			assert!(
+				restricted_nonces.start() == nonces.start()
+					&& restricted_nonces.start() <= restricted_nonces.end()
+					&& restricted_nonces.end() <= nonces.end(),
+				"Incorrect implementation of selection constraint. Expected prefix of {:?}, got {:?}",
+				nonces,
+				restricted_nonces,
+			);
+			nonces = restricted_nonces;
+		}
+
+		let proof_header = self.strategy.proof_header(&nonces);
+		Some((nonces, (), proof_header))
+	}
+
+	fn required_source_header_at_target(
+		&self,
+		current_best: &HeaderId<SourceHeaderHash, SourceHeaderNumber>,
+	) -> Option<HeaderId<SourceHeaderHash, SourceHeaderNumber>> {
+		self.strategy.required_source_header_at_target(current_best)
+	}
+
+	fn apply_command(&mut self, command: RaceCommand) {
+		self.strategy.apply_command(command)
+	}
+
+	fn range_failed(
+		&mut self,
+		nonces: RangeInclusive<MessageNonce>,
+		kind: RangeFailureKind,
+	) -> Result<(), RaceErrorReason> {
+		self.strategy.range_failed(nonces, kind)
+	}
+
+	fn checkpoint(&self) -> Self::Checkpoint {
+		self.strategy.checkpoint()
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::message_lane::MessageLane;
+	use crate::message_lane_loop::ClientState;
+	use crate::testing::{header_id, TestMessageLane, TestMessagesProof, TestSourceHeaderId, TestTargetHeaderId};
+	use std::sync::Arc;
+
+	type SourceNoncesRange = RangeInclusive<MessageNonce>;
+
+	/// Selection of nonces without the proof header, for tests that don't care about it.
+	pub(crate) trait SelectNonces<SourceHeaderId, TargetHeaderId, Proof>:
+		RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>
+	{
+		fn select_nonces(
+			&mut self,
+			race_state: &RaceState<SourceHeaderId, TargetHeaderId, Proof>,
+		) -> Option<(RangeInclusive<MessageNonce>, Self::ProofParameters)> {
+			self.select_nonces_to_deliver(race_state)
+				.map(|(nonces, proof_parameters, _)| (nonces, proof_parameters))
+		}
+	}
+
+	impl<SourceHeaderId, TargetHeaderId, Proof, Strategy> SelectNonces<SourceHeaderId, TargetHeaderId, Proof> for Strategy where
+		Strategy: RaceStrategy<SourceHeaderId, TargetHeaderId, Proof>
+	{
+	}
+
+	type BasicStrategy<P> = super::BasicStrategy<
+		<P as MessageLane>::SourceHeaderNumber,
+		<P as MessageLane>::SourceHeaderHash,
+		<P as MessageLane>::TargetHeaderNumber,
+		<P as MessageLane>::TargetHeaderHash,
+		SourceNoncesRange,
+		<P as MessageLane>::MessagesProof,
+	>;
+
+	type CompositeStrategy<P> = super::CompositeStrategy<
+		<P as MessageLane>::SourceHeaderNumber,
+		<P as MessageLane>::SourceHeaderHash,
+		<P as MessageLane>::TargetHeaderNumber,
+		<P as MessageLane>::TargetHeaderHash,
+		SourceNoncesRange,
+		<P as MessageLane>::MessagesProof,
+	>;
+
+	fn source_nonces(new_nonces: SourceNoncesRange) -> SourceClientNonces<SourceNoncesRange> {
+		SourceClientNonces {
+			new_nonces,
+			confirmed_nonce: None,
+			unrewarded_relayer_entries: None,
+		}
+	}
+
+	fn target_nonces(latest_nonce: MessageNonce) -> TargetClientNonces<()> {
+		TargetClientNonces {
+			latest_nonce,
+			nonces_data: (),
+		}
+	}
+
+	#[test]
+	fn strategy_is_empty_works() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		assert_eq!(strategy.is_empty(), true);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		assert_eq!(strategy.is_empty(), false);
+	}
+
+	#[test]
+	fn best_nonces_are_unknown_until_reported() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		assert_eq!(strategy.best_at_source_opt(), None);
+		assert_eq!(strategy.best_at_target_opt(), None);
+
+		strategy.target_nonces_updated(target_nonces(0), &mut Default::default());
+		assert_eq!(strategy.best_at_source_opt(), None);
+		assert_eq!(strategy.best_at_target_opt(), Some(0));
+
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		assert_eq!(strategy.best_at_source_opt(), Some(5));
+		assert_eq!(strategy.best_at_target_opt(), Some(0));
+	}
+
+	#[test]
+	fn best_nonces_are_known_after_restoring_from_checkpoint() {
+		let strategy = BasicStrategy::<TestMessageLane>::from_checkpoint(RaceCheckpoint {
+			target_nonce: 5,
+			source_queue: vec![(header_id(1), 6..=10)],
+		});
+		assert_eq!(strategy.best_at_source_opt(), Some(10));
+		assert_eq!(strategy.best_at_target_opt(), Some(5));
+	}
+
+	#[test]
+	fn contiguous_source_nonces_are_queued() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10)]
+		);
+		assert_eq!(strategy.metrics().source_nonce_gaps, Some(0));
+	}
+
+	#[test]
+	fn source_nonces_with_gap_are_refused() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+
+		// nonces 6..=7 are missing, so the update is refused
+		strategy.source_nonces_updated(header_id(2), source_nonces(8..=10));
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 1..=5)]);
+		assert_eq!(strategy.best_at_source(), 5);
+		assert_eq!(strategy.metrics().source_nonce_gaps, Some(1));
+
+		// and the following update that starts right after the best nonce is queued
+		strategy.source_nonces_updated(header_id(3), source_nonces(6..=10));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (header_id(3), 6..=10)]
+		);
+
+		// nonces after the best nonce, reported by the target node, are not gapped
+		strategy.target_nonces_updated(target_nonces(20), &mut state);
+		strategy.source_nonces_updated(header_id(4), source_nonces(21..=25));
+		assert_eq!(strategy.source_queue, vec![(header_id(4), 21..=25)]);
+		assert_eq!(strategy.metrics().source_nonce_gaps, Some(1));
+	}
+
+	#[test]
+	fn first_source_nonces_are_never_gapped() {
+		// neither source, nor target nonces are known yet, so any range is accepted
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(10..=15));
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 10..=15)]);
+		assert_eq!(strategy.metrics().source_nonce_gaps, Some(0));
+	}
+
+	#[test]
+	fn strategy_metrics_reflect_queue_mutations() {
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(2, QueueOverflowPolicy::CoalesceNewNonces);
+		assert_eq!(
+			strategy.metrics(),
+			StrategyMetricsSnapshot {
+				queued_ranges: Some(0),
+				queued_nonces: Some(0),
+				oldest_queued_at: None,
+				source_nonce_gaps: Some(0),
+			},
+		);
+
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		// coalesced nonces are not queued
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
+		assert_eq!(
+			strategy.metrics(),
+			StrategyMetricsSnapshot {
+				queued_ranges: Some(2),
+				queued_nonces: Some(10),
+				oldest_queued_at: Some(1),
+				source_nonce_gaps: Some(0),
+			},
+		);
+
+		strategy.target_nonces_updated(target_nonces(7), &mut Default::default());
+		assert_eq!(
+			strategy.metrics(),
+			StrategyMetricsSnapshot {
+				queued_ranges: Some(1),
+				queued_nonces: Some(3),
+				oldest_queued_at: Some(2),
+				source_nonce_gaps: Some(0),
+			},
+		);
+
+		strategy.source_reorged(&HeaderId(2, 42));
+		assert_eq!(
+			strategy.metrics(),
+			StrategyMetricsSnapshot {
+				queued_ranges: Some(0),
+				queued_nonces: Some(0),
+				oldest_queued_at: None,
+				source_nonce_gaps: Some(0),
+			},
+		);
+	}
+
+	#[test]
+	fn strategy_is_started_with_known_target_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new_with_target_nonce(10);
+		assert_eq!(strategy.best_at_source_opt(), None);
+		assert_eq!(strategy.best_at_target_opt(), Some(10));
+
+		// nonces that are delivered are never queued
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=20));
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 11..=20)]);
+
+		// target node knows newer nonce
+		strategy.target_nonces_updated(target_nonces(15), &mut Default::default());
+		assert_eq!(strategy.best_at_target(), 15);
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 16..=20)]);
+	}
+
+	#[test]
+	fn known_target_nonce_is_dropped_if_target_node_reports_older_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new_with_target_nonce(10);
+		strategy.source_nonces_updated(header_id(1), source_nonces(11..=20));
+
+		strategy.target_nonces_updated(target_nonces(5), &mut Default::default());
+		assert_eq!(strategy.best_at_target(), 5);
+		assert!(strategy.source_queue.is_empty());
+		assert_eq!(strategy.best_at_source(), 5);
+	}
+
+	#[test]
+	fn best_at_source_is_never_lower_than_target_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		assert_eq!(strategy.best_at_source(), 0);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		assert_eq!(strategy.best_at_source(), 5);
+		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
+		assert_eq!(strategy.source_queue, vec![]);
+		assert_eq!(strategy.best_at_source(), 10);
+	}
+
+	#[test]
+	fn source_nonce_is_never_lower_than_known_target_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		assert_eq!(strategy.source_queue, vec![]);
+	}
+
+	#[test]
+	fn source_nonce_is_never_lower_than_latest_known_source_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(1..=3));
+		strategy.source_nonces_updated(header_id(2), source_nonces(1..=5));
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 1..=5)]);
+	}
+
+	#[test]
+	fn target_nonce_is_never_lower_than_latest_known_target_nonce() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
+		strategy.target_nonces_updated(target_nonces(5), &mut Default::default());
+		assert_eq!(strategy.target_nonce, 10);
+	}
+
+	#[test]
+	fn target_nonce_is_rolled_back_if_allowed() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_target_nonce_rollback(true);
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=15));
+		strategy.target_nonces_updated(target_nonces(10), &mut state);
+		state.nonces_submitted.push_back(11..=15);
+
+		// target has lost nonces 6..=10 and has never seen nonces 11..=15 => they're selected again
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		assert_eq!(strategy.target_nonce, 5);
+		assert_eq!(strategy.source_queue, vec![]);
+		assert_eq!(strategy.best_at_source(), 5);
+		assert!(state.nonces_submitted.is_empty());
+
+		strategy.source_nonces_updated(header_id(3), source_nonces(6..=15));
+		assert_eq!(strategy.select_nonces(&state), Some((6..=15, ())));
+	}
+
+	#[test]
+	fn updated_target_nonce_removes_queued_entries() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
+		strategy.source_nonces_updated(header_id(4), source_nonces(16..=20));
+		strategy.target_nonces_updated(target_nonces(15), &mut Default::default());
+		assert_eq!(strategy.source_queue, vec![(header_id(4), 16..=20)]);
+		strategy.target_nonces_updated(target_nonces(17), &mut Default::default());
+		assert_eq!(strategy.source_queue, vec![(header_id(4), 18..=20)]);
+	}
+
+	#[test]
+	fn nonces_seen_at_orphaned_headers_are_dropped_on_source_reorg() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
+
+		// header#3 has been replaced with another header => nonces 11..=15 must be read again
+		strategy.source_reorged(&HeaderId(3, 33));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=10)]
+		);
+		assert_eq!(strategy.best_at_source(), 10);
+
+		// best header is now header#1 => nonces 6..=10 must be read again
+		strategy.source_reorged(&header_id(1));
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 1..=5)]);
+		assert_eq!(strategy.best_at_source(), 5);
+	}
+
+	fn confirmed_source_nonces(
+		new_nonces: SourceNoncesRange,
+		confirmed_nonce: MessageNonce,
+	) -> SourceClientNonces<SourceNoncesRange> {
+		SourceClientNonces {
+			new_nonces,
+			confirmed_nonce: Some(confirmed_nonce),
+			unrewarded_relayer_entries: None,
+		}
+	}
+
+	fn strategy_with_queued_ranges() -> BasicStrategy<TestMessageLane> {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(6..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=15));
+		strategy
+	}
+
+	#[test]
+	fn confirmed_nonce_before_queued_ranges_is_ignored() {
+		let mut strategy = strategy_with_queued_ranges();
+		strategy.source_nonces_updated(header_id(3), confirmed_source_nonces(6..=15, 5));
+		assert_eq!(strategy.confirmed_nonce(), Some(5));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 6..=10), (header_id(2), 11..=15)]
+		);
+	}
+
+	#[test]
+	fn confirmed_nonce_between_queued_ranges_trims_queue() {
+		let mut strategy = strategy_with_queued_ranges();
+		strategy.source_nonces_updated(header_id(3), confirmed_source_nonces(6..=15, 10));
+		assert_eq!(strategy.confirmed_nonce(), Some(10));
+		assert_eq!(strategy.source_queue, vec![(header_id(2), 11..=15)]);
+
+		strategy.source_nonces_updated(header_id(3), confirmed_source_nonces(6..=15, 12));
+		assert_eq!(strategy.source_queue, vec![(header_id(2), 13..=15)]);
+	}
+
+	#[test]
+	fn confirmed_nonce_after_queued_ranges_empties_queue() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = strategy_with_queued_ranges();
+		strategy.source_nonces_updated(header_id(3), confirmed_source_nonces(6..=20, 17));
+		assert_eq!(strategy.confirmed_nonce(), Some(17));
+		assert_eq!(strategy.source_queue, vec![(header_id(3), 18..=20)]);
+		assert_eq!(strategy.best_at_source(), 20);
+
+		// confirmed nonces are never selected, even if the target node hasn't reported them yet
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((18..=20, ())));
+	}
+
+	fn strategy_with_failure_threshold(action: RangeFailureAction) -> (TestClock, BasicStrategy<TestMessageLane>) {
+		let clock = TestClock(Arc::new(parking_lot::Mutex::new(Instant::now())));
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_failure_threshold(3, action);
+		strategy.clock = StoredClock(Box::new(clock.clone()));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=20));
+		(clock, strategy)
+	}
+
+	fn race_state_with_target_state() -> RaceState<TestSourceHeaderId, TestTargetHeaderId, TestMessagesProof> {
+		RaceState {
+			target_state: Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(2),
+			}),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn range_failures_are_ignored_by_default() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		for _ in 0..10 {
+			assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+		}
+		assert_eq!(
+			strategy.select_nonces(&race_state_with_target_state()),
+			Some((1..=10, ()))
+		);
+	}
+
+	#[test]
+	fn lane_is_blocked_when_range_fails_threshold_times() {
+		let (_, mut strategy) = strategy_with_failure_threshold(RangeFailureAction::Fail);
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofSubmission), Ok(()));
+		assert_eq!(
+			strategy.range_failed(1..=10, RangeFailureKind::ProofSubmission),
+			Err(RaceErrorReason::LaneBlocked {
+				nonce: 1,
+				failures: 3,
+				last_failure: RangeFailureKind::ProofSubmission,
+			}),
+		);
+	}
+
+	#[test]
+	fn range_failures_counter_is_reset_by_other_range_failure_and_by_delivery() {
+		let (_, mut strategy) = strategy_with_failure_threshold(RangeFailureAction::Fail);
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+
+		// failure of the other range resets the counter
+		assert_eq!(
+			strategy.range_failed(11..=20, RangeFailureKind::ProofGeneration),
+			Ok(())
+		);
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+
+		// delivery of the range resets the counter
+		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
+		assert!(strategy.range_failures.is_none());
+		assert_eq!(
+			strategy.range_failed(11..=20, RangeFailureKind::ProofGeneration),
+			Ok(())
+		);
+		assert_eq!(
+			strategy.range_failed(11..=20, RangeFailureKind::ProofGeneration),
+			Ok(())
+		);
+
+		// failures of delivered nonces are ignored
+		assert_eq!(strategy.range_failed(1..=10, RangeFailureKind::ProofGeneration), Ok(()));
+		assert_eq!(
+			strategy.range_failed(11..=20, RangeFailureKind::ProofGeneration),
+			Err(RaceErrorReason::LaneBlocked {
+				nonce: 11,
+				failures: 3,
+				last_failure: RangeFailureKind::ProofGeneration,
+			}),
+		);
+	}
+
+	#[test]
+	fn parked_range_is_withheld_with_exponential_delay() {
+		let (clock, mut strategy) = strategy_with_failure_threshold(RangeFailureAction::Park {
+			initial_delay: Duration::from_secs(10),
+			max_delay: Duration::from_secs(30),
+		});
+		let state = race_state_with_target_state();
+
+		// nonces before the parked range are still selected
+		for _ in 0..3 {
+			assert_eq!(
+				strategy.range_failed(11..=20, RangeFailureKind::ProofGeneration),
+				Ok(())
+			);
+		}
+		assert_eq!(strategy.parked_nonce(), Some(11));
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// the range is selected again once the delay is over
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(strategy.parked_nonce(), None);
+		assert_eq!(strategy.select_nonces(&state), Some((11..=20, ())));
+
+		// the delay is doubled every next time the range reaches the threshold
+		for _ in 0..3 {
+			assert_eq!(
+				strategy.range_failed(11..=20, RangeFailureKind::ProofSubmission),
+				Ok(())
+			);
+		}
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(strategy.select_nonces(&state), None);
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(strategy.select_nonces(&state), Some((11..=20, ())));
+
+		// ... but never exceeds the max delay
+		for _ in 0..3 {
+			assert_eq!(
+				strategy.range_failed(11..=20, RangeFailureKind::ProofSubmission),
+				Ok(())
+			);
+		}
+		clock.advance(Duration::from_secs(20));
+		assert_eq!(strategy.select_nonces(&state), None);
+		clock.advance(Duration::from_secs(10));
+		assert_eq!(strategy.select_nonces(&state), Some((11..=20, ())));
+	}
+
+	#[test]
+	fn contiguous_ranges_are_not_merged_by_default() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		strategy.source_nonces_updated(header_id(2), source_nonces(2..=2));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=1), (header_id(2), 2..=2)]
+		);
+	}
+
+	#[test]
+	fn contiguous_ranges_are_merged_if_range_merging_is_enabled() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_range_merging(5);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=2));
+		strategy.source_nonces_updated(header_id(2), source_nonces(3..=3));
+		assert_eq!(strategy.source_queue, vec![(header_id(2), 1..=3)]);
+
+		// merged range would have 6 nonces => new entry is queued
+		strategy.source_nonces_updated(header_id(3), source_nonces(4..=6));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(2), 1..=3), (header_id(3), 4..=6)]
+		);
+
+		// already queued nonces are ignored
+		strategy.source_nonces_updated(header_id(4), source_nonces(1..=7));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(2), 1..=3), (header_id(4), 4..=7)]
+		);
+
+		// ranges with gap between them are never queued (and so never merged)
+		strategy.source_nonces_updated(header_id(5), source_nonces(10..=10));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(2), 1..=3), (header_id(4), 4..=7)]
+		);
+	}
+
+	#[test]
+	fn merged_range_is_selected_when_newer_header_is_known_to_target() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_range_merging(100);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		// nonces 1..=5 have been queued at header#1, but now they're merged with nonces from
+		// header#2, so nothing is selected until header#2 is known to the target node
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(1),
+		});
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert_eq!(
+			strategy.required_source_header_at_target(&header_id(1)),
+			Some(header_id(2))
+		);
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+	}
+
+	#[test]
+	fn queue_introspection_works() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(2, QueueOverflowPolicy::CoalesceNewNonces);
+		assert_eq!(strategy.queued_ranges().count(), 0);
+		assert_eq!(strategy.total_queued_nonces(), 0);
+		assert_eq!(strategy.summary(), "empty");
+
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=7));
+		assert_eq!(
+			strategy.queued_ranges().collect::<Vec<_>>(),
+			vec![(&header_id(1), &(1..=5)), (&header_id(2), &(6..=7))],
+		);
+		assert_eq!(strategy.total_queued_nonces(), 7);
+		assert_eq!(
+			strategy.summary(),
+			"7 nonces in 2 ranges, oldest queued at source header 1"
+		);
+
+		strategy.source_nonces_updated(header_id(3), source_nonces(8..=10));
+		assert_eq!(strategy.total_queued_nonces(), 7);
+		assert_eq!(
+			strategy.summary(),
+			"7 nonces in 2 ranges, oldest queued at source header 1, coalesced nonces 8..=10",
+		);
+
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+		assert_eq!(
+			strategy.queued_ranges().collect::<Vec<_>>(),
+			vec![(&header_id(1), &(4..=5)), (&header_id(2), &(6..=7))],
+		);
+		assert_eq!(strategy.total_queued_nonces(), 4);
+
+		strategy.target_nonces_updated(target_nonces(6), &mut state);
+		assert_eq!(strategy.total_queued_nonces(), 1);
+		assert_eq!(
+			strategy.summary(),
+			"1 nonces in 1 ranges, oldest queued at source header 2, coalesced nonces 8..=10",
+		);
+		assert_eq!(strategy.queue_summary(), Some(strategy.summary()));
+
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		assert_eq!(strategy.summary(), "empty");
+	}
+
+	#[test]
+	fn new_nonces_are_refused_when_queue_is_full() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(2, QueueOverflowPolicy::RefuseNewNonces);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		strategy.source_nonces_updated(header_id(2), source_nonces(2..=2));
+		assert!(strategy.is_source_queue_full());
+
+		strategy.source_nonces_updated(header_id(3), source_nonces(3..=3));
+		assert_eq!(strategy.source_queue_len(), 2);
+		assert_eq!(strategy.best_at_source(), 2);
+
+		// refused nonces are queued once there's a room in the queue
+		strategy.target_nonces_updated(target_nonces(1), &mut state);
+		assert!(!strategy.is_source_queue_full());
+		strategy.source_nonces_updated(header_id(4), source_nonces(3..=4));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(2), 2..=2), (header_id(4), 3..=4)]
+		);
+	}
+
+	#[test]
+	fn new_nonces_are_coalesced_when_queue_is_full() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(2, QueueOverflowPolicy::CoalesceNewNonces);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		strategy.source_nonces_updated(header_id(2), source_nonces(2..=2));
+		strategy.source_nonces_updated(header_id(3), source_nonces(3..=3));
+		strategy.source_nonces_updated(header_id(4), source_nonces(4..=5));
+		assert!(!strategy.is_source_queue_full());
+		assert_eq!(strategy.source_queue_len(), 2);
+		assert_eq!(strategy.coalesced_nonces, Some((header_id(4), 3..=5)));
+		assert_eq!(strategy.best_at_source(), 5);
+
+		// coalesced nonces are never selected
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(4),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=2, ())));
+
+		// once queued nonces are delivered, coalesced nonces are read again
+		strategy.target_nonces_updated(target_nonces(2), &mut state);
+		assert!(strategy.is_empty());
+		assert_eq!(strategy.coalesced_nonces, None);
+		assert_eq!(strategy.best_at_source(), 2);
+		strategy.source_nonces_updated(header_id(5), source_nonces(3..=5));
+		assert_eq!(strategy.source_queue, vec![(header_id(5), 3..=5)]);
+	}
+
+	#[test]
+	fn coalesced_nonces_are_dropped_on_source_reorg() {
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(1, QueueOverflowPolicy::CoalesceNewNonces);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		assert_eq!(strategy.best_at_source(), 10);
+
+		strategy.source_reorged(&HeaderId(2, 22));
+		assert_eq!(strategy.coalesced_nonces, None);
+		assert_eq!(strategy.best_at_source(), 5);
+	}
+
+	#[test]
+	fn selected_nonces_are_dropped_on_target_nonce_update() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.nonces_to_submit = Some((header_id(1), 5..=10, Arc::new((5..=10, None))));
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		assert!(state.nonces_to_submit.is_some());
+		strategy.target_nonces_updated(target_nonces(10), &mut state);
+		assert!(state.nonces_to_submit.is_none());
+	}
+
+	#[test]
+	fn submitted_nonces_are_dropped_on_target_nonce_update() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.nonces_submitted.push_back(5..=10);
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		assert_eq!(state.nonces_submitted, vec![5..=10]);
+		strategy.target_nonces_updated(target_nonces(10), &mut state);
+		assert!(state.nonces_submitted.is_empty());
+	}
+
+	#[test]
+	fn confirmed_in_flight_nonces_are_dropped_from_the_front_on_target_nonce_update() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.nonces_submitted.extend(vec![1..=5, 6..=10, 11..=15]);
+
+		// first range is partially confirmed => nothing is dropped
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+		assert_eq!(state.nonces_submitted, vec![1..=5, 6..=10, 11..=15]);
+
+		// first range is confirmed and second range is partially confirmed
+		strategy.target_nonces_updated(target_nonces(8), &mut state);
+		assert_eq!(state.nonces_submitted, vec![6..=10, 11..=15]);
+
+		// both remaining ranges are confirmed at once
+		strategy.target_nonces_updated(target_nonces(15), &mut state);
+		assert!(state.nonces_submitted.is_empty());
+	}
+
+	#[test]
+	fn nothing_is_selected_if_something_is_already_selected() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.nonces_to_submit = Some((header_id(1), 1..=10, Arc::new((1..=10, None))));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn nonces_after_submitted_nonces_are_selected() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+
+		state.nonces_submitted.push_back(1..=10);
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=15));
+		assert_eq!(strategy.select_nonces(&state), Some((11..=15, ())));
+	}
+
+	#[test]
+	fn proof_parameters_are_built_by_builder() {
+		// outbound lane state proof is required if the confirmed nonce at the target lags the
+		// confirmed nonce at the source
+		let confirmed_nonce_at_target = 5;
+		let select_nonces = |confirmed_nonce_at_source| {
+			let mut state = RaceState::default();
+			let mut strategy =
+				super::BasicStrategy::<_, _, _, _, SourceNoncesRange, TestMessagesProof, (), bool>::new()
+					.with_proof_parameters_builder(move |_, confirmed_nonce_at_source| {
+						confirmed_nonce_at_source > Some(confirmed_nonce_at_target)
+					});
+			state.target_state = Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(1),
+			});
+			strategy.source_nonces_updated(
+				header_id(1),
+				SourceClientNonces {
+					new_nonces: 6..=10,
+					confirmed_nonce: confirmed_nonce_at_source,
+					unrewarded_relayer_entries: None,
+				},
+			);
+			strategy.target_nonces_updated(target_nonces(5), &mut state);
+			strategy.select_nonces(&state)
+		};
+
+		assert_eq!(select_nonces(None), Some((6..=10, false)));
+		assert_eq!(select_nonces(Some(4)), Some((6..=10, false)));
+		assert_eq!(select_nonces(Some(5)), Some((6..=10, false)));
+		assert_eq!(select_nonces(Some(6)), Some((7..=10, true)));
+	}
+
+	#[test]
+	fn default_proof_parameters_are_used_without_builder() {
+		let mut state = RaceState::default();
+		let mut strategy = super::BasicStrategy::<_, _, _, _, SourceNoncesRange, TestMessagesProof, (), bool>::new();
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(1),
+		});
+		strategy.source_nonces_updated(header_id(1), confirmed_source_nonces(6..=10, 6));
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		assert_eq!(strategy.select_nonces(&state), Some((7..=10, false)));
+	}
+
+	#[test]
+	fn nonces_after_last_in_flight_nonces_are_selected() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=15));
+		strategy.source_nonces_updated(header_id(3), source_nonces(16..=20));
+		state.nonces_submitted.extend(vec![1..=10, 11..=15]);
+		assert_eq!(strategy.select_nonces(&state), Some((16..=20, ())));
+
+		// partial confirmation of the in-flight window doesn't change selection
+		strategy.target_nonces_updated(target_nonces(12), &mut state);
+		assert_eq!(state.nonces_submitted, vec![11..=15]);
+		assert_eq!(strategy.select_nonces(&state), Some((16..=20, ())));
+	}
+
+	#[test]
+	fn nonces_after_queued_proofs_are_selected() {
+		let mut state = RaceState::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=15));
+		strategy.source_nonces_updated(header_id(3), source_nonces(16..=20));
+		state.nonces_submitted.push_back(1..=10);
+		state
+			.proofs_to_submit
+			.push_back((header_id(2), 11..=15, Arc::new((11..=15, None))));
+		assert_eq!(strategy.select_nonces(&state), Some((16..=20, ())));
+
+		// queued proofs of delivered nonces are dropped
+		strategy.target_nonces_updated(target_nonces(12), &mut state);
+		assert_eq!(state.proofs_to_submit.len(), 1);
+		strategy.target_nonces_updated(target_nonces(15), &mut state);
+		assert!(state.proofs_to_submit.is_empty());
+		assert_eq!(strategy.select_nonces(&state), Some((16..=20, ())));
+	}
+
+	#[test]
+	fn proof_header_is_the_newest_header_where_selected_nonces_have_been_queued() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_proof_at_queued_header(true);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(5),
+		});
+
+		assert_eq!(
+			strategy.select_nonces_to_deliver(&state),
+			Some((1..=15, (), Some(header_id(3))))
+		);
+		assert_eq!(strategy.proof_header(&(1..=7)), Some(header_id(2)));
+		assert_eq!(strategy.proof_header(&(1..=5)), Some(header_id(1)));
+		assert_eq!(strategy.proof_header(&(7..=12)), Some(header_id(3)));
+
+		// proof header is not returned unless asked
+		let mut strategy = strategy.with_proof_at_queued_header(false);
+		assert_eq!(strategy.select_nonces_to_deliver(&state), Some((1..=15, (), None)));
+	}
+
+	#[test]
+	fn select_nonces_to_deliver_works() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		strategy.source_nonces_updated(header_id(2), source_nonces(2..=2));
+		strategy.source_nonces_updated(header_id(3), source_nonces(3..=6));
+		strategy.source_nonces_updated(header_id(5), source_nonces(7..=8));
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(4),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=6, ())));
+		strategy.target_nonces_updated(target_nonces(6), &mut state);
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(5),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((7..=8, ())));
+		strategy.target_nonces_updated(target_nonces(8), &mut state);
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn nonces_after_final_nonce_are_never_selected() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_final_nonce(Some(7));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=7, ())));
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn nonces_after_final_nonce_are_never_queued() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_final_nonce(Some(7));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=7)]
+		);
+		assert_eq!(strategy.best_at_source(), 10);
+
+		// nonces that are generated after the final nonce are discarded
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=12));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (header_id(2), 6..=7)]
+		);
+		assert_eq!(strategy.source_nonce_gaps, 0);
+		assert_eq!(strategy.best_at_source(), 12);
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=7, ())));
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		strategy.source_nonces_updated(header_id(4), source_nonces(13..=15));
+		assert!(strategy.source_queue.is_empty());
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn nonces_before_final_nonce_are_queued_again_after_source_reorg() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_final_nonce(Some(7));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		assert_eq!(strategy.best_at_source(), 10);
+
+		strategy.source_reorged(&HeaderId(2, 200));
+		assert_eq!(strategy.best_at_source(), 5);
+		strategy.source_nonces_updated(HeaderId(2, 200), source_nonces(6..=12));
+		assert_eq!(
+			strategy.source_queue,
+			vec![(header_id(1), 1..=5), (HeaderId(2, 200), 6..=7)]
+		);
+		assert_eq!(strategy.best_at_source(), 12);
+	}
+
+	#[test]
+	fn nonces_queued_at_unconfirmed_headers_are_never_selected() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_source_confirmation_depth(2);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		// header#1 is only 1 block old
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// header#1 is exactly 2 blocks old
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=5, ())));
+
+		// header#2 is exactly 2 blocks old
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(4),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+	}
+
+	#[test]
+	fn nonces_queued_at_best_header_at_target_are_selected_by_default() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(1),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=5, ())));
+	}
+
+	#[test]
+	fn selector_never_receives_nonces_queued_at_unconfirmed_headers() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_source_confirmation_depth(1);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		// header#2 is the best header at target => only range, queued at header#1, is passed to the
+		// selector
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		let mut passed_ranges = Vec::new();
+		let selected_nonces = strategy.select_nonces_to_deliver_with_selector(&state, |range| {
+			passed_ranges.push(range);
+			None
+		});
+		assert_eq!(selected_nonces, Ok(Some(1..=5)));
+		assert_eq!(passed_ranges, vec![1..=5]);
+
+		// header#2 is exactly 1 block old
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		let mut passed_ranges = Vec::new();
+		let selected_nonces = strategy.select_nonces_to_deliver_with_selector(&state, |range| {
+			passed_ranges.push(range);
+			None
+		});
+		assert_eq!(selected_nonces, Ok(Some(1..=10)));
+		assert_eq!(passed_ranges, vec![1..=5, 6..=10]);
+	}
+
+	#[test]
+	fn required_source_header_at_target_is_header_of_first_unprovable_queue_entry() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		assert_eq!(strategy.required_source_header_at_target(&header_id(1)), None);
+
+		strategy.source_nonces_updated(header_id(2), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(3), source_nonces(6..=10));
+		assert_eq!(
+			strategy.required_source_header_at_target(&header_id(1)),
+			Some(header_id(2))
+		);
+		assert_eq!(
+			strategy.required_source_header_at_target(&header_id(2)),
+			Some(header_id(3))
+		);
+
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		assert_eq!(
+			strategy.required_source_header_at_target(&header_id(2)),
+			Some(header_id(3))
+		);
+		assert_eq!(strategy.required_source_header_at_target(&header_id(3)), None);
 	}
 
 	#[test]
-	fn strategy_is_empty_works() {
+	fn selected_nonces_are_limited_by_max_nonces_per_proof_command() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
 		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		assert_eq!(strategy.is_empty(), true);
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
-		assert_eq!(strategy.is_empty(), false);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+
+		strategy.apply_command(RaceCommand::SetMaxNoncesPerProof(7));
+		assert_eq!(strategy.select_nonces(&state), Some((1..=7, ())));
+
+		strategy.apply_command(RaceCommand::SetMaxNoncesPerProof(3));
+		state.nonces_submitted.push_back(1..=3);
+		assert_eq!(strategy.select_nonces(&state), Some((4..=6, ())));
+
+		strategy.apply_command(RaceCommand::SetMaxNoncesPerProof(0));
+		assert_eq!(strategy.select_nonces(&state), None);
 	}
 
 	#[test]
-	fn best_at_source_is_never_lower_than_target_nonce() {
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		assert_eq!(strategy.best_at_source(), 0);
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
-		assert_eq!(strategy.best_at_source(), 5);
-		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
-		assert_eq!(strategy.source_queue, vec![]);
-		assert_eq!(strategy.best_at_source(), 10);
+	fn huge_queued_range_is_delivered_in_chunks() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new().with_max_batch(Some(3_000));
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10_000));
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(1),
+		});
+
+		// next chunk is selected while previous chunk is submitted
+		assert_eq!(strategy.select_nonces(&state), Some((1..=3_000, ())));
+		state.nonces_submitted.push_back(1..=3_000);
+		assert_eq!(strategy.select_nonces(&state), Some((3_001..=6_000, ())));
+		state.nonces_submitted.push_back(3_001..=6_000);
+
+		// ... and once previous chunks are delivered
+		strategy.target_nonces_updated(target_nonces(6_000), &mut state);
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 6_001..=10_000)]);
+		assert_eq!(strategy.select_nonces(&state), Some((6_001..=9_000, ())));
+		strategy.target_nonces_updated(target_nonces(9_000), &mut state);
+		assert_eq!(strategy.select_nonces(&state), Some((9_001..=10_000, ())));
+		strategy.target_nonces_updated(target_nonces(10_000), &mut state);
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert!(strategy.is_empty());
 	}
 
 	#[test]
-	fn source_nonce_is_never_lower_than_known_target_nonce() {
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
-		assert_eq!(strategy.source_queue, vec![]);
+	fn selected_nonces_are_limited_by_max_batch() {
+		let select_with_max_batch = |max_batch| {
+			let mut state = RaceState::<_, _, TestMessagesProof>::default();
+			let mut strategy = BasicStrategy::<TestMessageLane>::new().with_max_batch(Some(max_batch));
+			strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+			strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+			state.target_state = Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(2),
+			});
+			strategy.select_nonces(&state)
+		};
+
+		// queued nonces are larger than the cap => the second queued range is split
+		assert_eq!(select_with_max_batch(7), Some((1..=7, ())));
+		// queued nonces are equal to the cap
+		assert_eq!(select_with_max_batch(10), Some((1..=10, ())));
+		// queued nonces are smaller than the cap
+		assert_eq!(select_with_max_batch(20), Some((1..=10, ())));
 	}
 
 	#[test]
-	fn source_nonce_is_never_lower_than_latest_known_source_nonce() {
+	fn strategy_is_restored_from_checkpoint() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
 		let mut strategy = BasicStrategy::<TestMessageLane>::new();
 		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
-		strategy.source_nonces_updated(header_id(2), source_nonces(1..=3));
-		strategy.source_nonces_updated(header_id(2), source_nonces(1..=5));
-		assert_eq!(strategy.source_queue, vec![(header_id(1), 1..=5)]);
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+
+		let checkpoint = strategy.checkpoint();
+		assert_eq!(
+			checkpoint,
+			RaceCheckpoint {
+				target_nonce: 3,
+				source_queue: vec![(header_id(1), 4..=5), (header_id(2), 6..=10)],
+			},
+		);
+
+		let mut strategy = BasicStrategy::<TestMessageLane>::from_checkpoint(checkpoint);
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.best_at_source(), 10);
+		assert_eq!(strategy.best_at_target(), 3);
+		assert_eq!(strategy.select_nonces(&state), Some((4..=10, ())));
 	}
 
 	#[test]
-	fn target_nonce_is_never_lower_than_latest_known_target_nonce() {
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		strategy.target_nonces_updated(target_nonces(10), &mut Default::default());
-		strategy.target_nonces_updated(target_nonces(5), &mut Default::default());
-		assert_eq!(strategy.target_nonce, 10);
+	fn restored_strategy_trusts_target_node_nonce() {
+		let checkpoint = RaceCheckpoint {
+			target_nonce: 5,
+			source_queue: vec![(header_id(1), 6..=10)],
+		};
+
+		// when target node knows newer nonce, delivered nonces are pruned
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::from_checkpoint(checkpoint.clone());
+		strategy.target_nonces_updated(target_nonces(7), &mut state);
+		assert_eq!(strategy.best_at_target(), 7);
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 8..=10)]);
+
+		// when target node knows older nonce, the checkpoint is dropped
+		let mut strategy = BasicStrategy::<TestMessageLane>::from_checkpoint(checkpoint.clone());
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+		assert_eq!(strategy.best_at_target(), 3);
+		assert!(strategy.source_queue.is_empty());
+
+		// older nonce is ignored once the restored nonce is confirmed by the target node
+		let mut strategy = BasicStrategy::<TestMessageLane>::from_checkpoint(checkpoint);
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+		assert_eq!(strategy.best_at_target(), 5);
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 6..=10)]);
 	}
 
 	#[test]
-	fn updated_target_nonce_removes_queued_entries() {
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+	fn malformed_checkpoint_is_ignored() {
+		let restored_target_nonce = |source_queue| {
+			BasicStrategy::<TestMessageLane>::from_checkpoint(RaceCheckpoint {
+				target_nonce: 5,
+				source_queue,
+			})
+			.best_at_target()
+		};
+
+		assert_eq!(restored_target_nonce(vec![(header_id(1), 6..=10)]), 5);
+		// queued range is already delivered
+		assert_eq!(restored_target_nonce(vec![(header_id(1), 3..=10)]), 0);
+		// queued range is empty
+		assert_eq!(
+			restored_target_nonce(vec![(header_id(1), RangeInclusive::new(10, 6))]),
+			0
+		);
+		// queued ranges are overlapping
+		assert_eq!(
+			restored_target_nonce(vec![(header_id(1), 6..=10), (header_id(2), 10..=15)]),
+			0
+		);
+		// queued ranges are seen at headers in wrong order
+		assert_eq!(
+			restored_target_nonce(vec![(header_id(2), 6..=10), (header_id(1), 11..=15)]),
+			0
+		);
+	}
+
+	fn strategy_with_non_trivial_state() -> BasicStrategy<TestMessageLane> {
+		let mut strategy =
+			BasicStrategy::<TestMessageLane>::new().with_max_queue_len(2, QueueOverflowPolicy::CoalesceNewNonces);
 		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
 		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
 		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
-		strategy.source_nonces_updated(header_id(4), source_nonces(16..=20));
-		strategy.target_nonces_updated(target_nonces(15), &mut Default::default());
-		assert_eq!(strategy.source_queue, vec![(header_id(4), 16..=20)]);
-		strategy.target_nonces_updated(target_nonces(17), &mut Default::default());
-		assert_eq!(strategy.source_queue, vec![(header_id(4), 18..=20)]);
+		strategy.source_nonces_updated(
+			header_id(4),
+			SourceClientNonces {
+				new_nonces: 16..=20,
+				confirmed_nonce: Some(2),
+				unrewarded_relayer_entries: None,
+			},
+		);
+		strategy.target_nonces_updated(target_nonces(3), &mut Default::default());
+		strategy
 	}
 
 	#[test]
-	fn selected_nonces_are_dropped_on_target_nonce_update() {
-		let mut state = RaceState::default();
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		state.nonces_to_submit = Some((header_id(1), 5..=10, (5..=10, None)));
-		strategy.target_nonces_updated(target_nonces(7), &mut state);
-		assert!(state.nonces_to_submit.is_some());
-		strategy.target_nonces_updated(target_nonces(10), &mut state);
-		assert!(state.nonces_to_submit.is_none());
-	}
+	fn strategy_is_restored_from_snapshot() {
+		let strategy = strategy_with_non_trivial_state();
+		let snapshot = strategy.snapshot();
+		assert_eq!(
+			snapshot,
+			StrategySnapshot {
+				version: STRATEGY_SNAPSHOT_VERSION,
+				target_nonce: 3,
+				confirmed_nonce: Some(2),
+				source_queue: vec![(header_id(1), 4..=5), (header_id(2), 6..=10)],
+				coalesced_nonces: Some((header_id(4), 11..=20)),
+			},
+		);
 
-	#[test]
-	fn submitted_nonces_are_dropped_on_target_nonce_update() {
-		let mut state = RaceState::default();
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		state.nonces_submitted = Some(5..=10);
-		strategy.target_nonces_updated(target_nonces(7), &mut state);
-		assert!(state.nonces_submitted.is_some());
-		strategy.target_nonces_updated(target_nonces(10), &mut state);
-		assert!(state.nonces_submitted.is_none());
+		let mut restored_strategy = BasicStrategy::<TestMessageLane>::new();
+		assert_eq!(restored_strategy.restore(snapshot.clone()), Ok(()));
+		assert_eq!(restored_strategy.snapshot(), snapshot);
+		assert_eq!(restored_strategy.best_at_source_opt(), Some(20));
+		assert_eq!(restored_strategy.best_at_target_opt(), Some(3));
+		assert_eq!(restored_strategy.summary(), strategy.summary());
+
+		let state = RaceState {
+			target_state: Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(4),
+			}),
+			..Default::default()
+		};
+		assert_eq!(restored_strategy.select_nonces(&state), Some((4..=10, ())));
 	}
 
 	#[test]
-	fn nothing_is_selected_if_something_is_already_selected() {
-		let mut state = RaceState::default();
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		state.nonces_to_submit = Some((header_id(1), 1..=10, (1..=10, None)));
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
-		assert_eq!(strategy.select_nonces_to_deliver(&state), None);
+	fn malformed_snapshot_is_rejected() {
+		let mut strategy = strategy_with_non_trivial_state();
+		let original_snapshot = strategy.snapshot();
+
+		let mut snapshot = original_snapshot.clone();
+		snapshot.version = STRATEGY_SNAPSHOT_VERSION + 1;
+		assert_eq!(
+			strategy.restore(snapshot),
+			Err(SnapshotError::UnsupportedVersion(STRATEGY_SNAPSHOT_VERSION + 1)),
+		);
+
+		// queued range is already delivered
+		let mut snapshot = original_snapshot.clone();
+		snapshot.target_nonce = 5;
+		assert!(matches!(strategy.restore(snapshot), Err(SnapshotError::Malformed(_))));
+
+		// coalesced nonces are overlapping queued nonces
+		let mut snapshot = original_snapshot.clone();
+		snapshot.coalesced_nonces = Some((header_id(4), 10..=20));
+		assert!(matches!(strategy.restore(snapshot), Err(SnapshotError::Malformed(_))));
+
+		// the strategy state is left untouched
+		assert_eq!(strategy.snapshot(), original_snapshot);
 	}
 
+	#[cfg(feature = "checkpoint")]
 	#[test]
-	fn nothing_is_selected_if_something_is_already_submitted() {
-		let mut state = RaceState::default();
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		state.nonces_submitted = Some(1..=10);
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
-		assert_eq!(strategy.select_nonces_to_deliver(&state), None);
+	fn strategy_snapshot_is_serialized() {
+		let strategy = strategy_with_non_trivial_state();
+		let serialized_snapshot = serde_json::to_string(&strategy.snapshot()).unwrap();
+
+		let mut restored_strategy = BasicStrategy::<TestMessageLane>::new();
+		restored_strategy
+			.restore(serde_json::from_str(&serialized_snapshot).unwrap())
+			.unwrap();
+		assert_eq!(restored_strategy.snapshot(), strategy.snapshot());
+
+		// snapshots of other versions are rejected
+		let serialized_snapshot =
+			serialized_snapshot.replace(&format!("\"version\":{}", STRATEGY_SNAPSHOT_VERSION), "\"version\":0");
+		assert_eq!(
+			restored_strategy.restore(serde_json::from_str(&serialized_snapshot).unwrap()),
+			Err(SnapshotError::UnsupportedVersion(0)),
+		);
 	}
 
 	#[test]
-	fn select_nonces_to_deliver_works() {
+	fn peek_agrees_with_select_and_leaves_strategy_untouched() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new()
+			.with_max_batch(Some(7))
+			.with_source_confirmation_depth(1);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=5));
+		strategy.source_nonces_updated(header_id(2), source_nonces(6..=10));
+		strategy.source_nonces_updated(header_id(3), source_nonces(11..=15));
 		let mut state = RaceState::<_, _, TestMessagesProof>::default();
-		let mut strategy = BasicStrategy::<TestMessageLane>::new();
-		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
-		strategy.source_nonces_updated(header_id(2), source_nonces(2..=2));
-		strategy.source_nonces_updated(header_id(3), source_nonces(6..=6));
-		strategy.source_nonces_updated(header_id(5), source_nonces(8..=8));
+		assert_eq!(strategy.peek_nonces_to_deliver(&state), None);
 
-		state.target_state = Some(ClientState {
-			best_self: header_id(0),
-			best_peer: header_id(4),
-		});
-		assert_eq!(strategy.select_nonces_to_deliver(&state), Some((1..=6, ())));
-		strategy.target_nonces_updated(target_nonces(6), &mut state);
-		assert_eq!(strategy.select_nonces_to_deliver(&state), None);
+		for best_peer in 1..=4 {
+			state.target_state = Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(best_peer),
+			});
+			let snapshot = strategy.snapshot();
+			let peeked_nonces = strategy.peek_nonces_to_deliver(&state);
+			assert_eq!(strategy.snapshot(), snapshot);
+			assert_eq!(peeked_nonces, strategy.select_nonces(&state).map(|(nonces, _)| nonces));
+		}
 
-		state.target_state = Some(ClientState {
-			best_self: header_id(0),
-			best_peer: header_id(5),
-		});
-		assert_eq!(strategy.select_nonces_to_deliver(&state), Some((7..=8, ())));
-		strategy.target_nonces_updated(target_nonces(8), &mut state);
-		assert_eq!(strategy.select_nonces_to_deliver(&state), None);
+		// submitted nonces are respected
+		state.nonces_submitted.push_back(1..=7);
+		assert_eq!(strategy.peek_nonces_to_deliver(&state), Some(8..=14));
 	}
 
 	#[test]
@@ -402,45 +3381,373 @@ mod tests {
 
 		state.target_state = Some(ClientState {
 			best_self: header_id(0),
+			best_finalized_self: header_id(0),
 			best_peer: header_id(1),
 		});
 		assert_eq!(
 			strategy.select_nonces_to_deliver_with_selector(&state, |_| Some(50..=100)),
-			Some(1..=49),
+			Ok(Some(1..=49)),
 		);
 	}
 
-	fn run_panic_test_for_incorrect_selector(
+	fn run_test_for_incorrect_selector(
 		invalid_selector: impl Fn(SourceNoncesRange) -> Option<SourceNoncesRange>,
-	) {
+	) -> Result<Option<RangeInclusive<MessageNonce>>, SelectorError> {
 		let mut state = RaceState::<_, _, TestMessagesProof>::default();
 		let mut strategy = BasicStrategy::<TestMessageLane>::new();
 		strategy.source_nonces_updated(header_id(1), source_nonces(1..=100));
 		strategy.target_nonces_updated(target_nonces(50), &mut state);
 		state.target_state = Some(ClientState {
 			best_self: header_id(0),
+			best_finalized_self: header_id(0),
 			best_peer: header_id(1),
 		});
 
-		strategy.select_nonces_to_deliver_with_selector(&state, invalid_selector);
+		let result = strategy.select_nonces_to_deliver_with_selector(&state, invalid_selector);
+
+		// the queue is left untouched
+		assert_eq!(strategy.source_queue, vec![(header_id(1), 51..=100)]);
+		assert_eq!(strategy.select_nonces(&state), Some((51..=100, ())));
+
+		result
 	}
 
 	#[test]
-	#[should_panic]
-	fn select_nonces_to_deliver_panics_if_selector_returns_empty_range() {
-		#[allow(clippy::reversed_empty_ranges)]
-		run_panic_test_for_incorrect_selector(|_| Some(2..=1))
+	fn queue_is_intact_if_selector_fails_in_the_middle_of_the_queue() {
+		let mut state = RaceState::<_, _, TestMessagesProof>::default();
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=20));
+		strategy.source_nonces_updated(header_id(3), source_nonces(21..=30));
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(3),
+		});
+		let original_queue = strategy.source_queue.clone();
+
+		// selector accepts the first range and fails on the second one
+		let mut passed_ranges = Vec::new();
+		let result = strategy.select_nonces_to_deliver_with_selector(&state, |range| {
+			passed_ranges.push(range.clone());
+			if passed_ranges.len() == 2 {
+				Some(range.begin() - 1..=*range.end())
+			} else {
+				None
+			}
+		});
+		assert_eq!(
+			result,
+			Err(SelectorError::BeginsBeforePassedRange {
+				passed_range: 11..=20,
+				returned_range: 10..=20,
+			}),
+		);
+		assert_eq!(passed_ranges, vec![6..=10, 11..=20]);
+		assert_eq!(strategy.source_queue, original_queue);
+		assert_eq!(strategy.select_nonces(&state), Some((6..=30, ())));
 	}
 
 	#[test]
-	#[should_panic]
-	fn select_nonces_to_deliver_panics_if_selector_returns_range_that_starts_before_passed_range() {
-		run_panic_test_for_incorrect_selector(|range| Some(range.begin() - 1..=*range.end()))
+	fn delivered_nonces_are_removed_from_multiple_queue_entries() {
+		let mut strategy = BasicStrategy::<TestMessageLane>::new();
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy.source_nonces_updated(header_id(2), source_nonces(11..=20));
+		strategy.source_nonces_updated(header_id(3), source_nonces(21..=30));
+
+		strategy.target_nonces_updated(target_nonces(20), &mut Default::default());
+		assert_eq!(strategy.source_queue, vec![(header_id(3), 21..=30)]);
+
+		strategy.target_nonces_updated(target_nonces(25), &mut Default::default());
+		assert_eq!(strategy.source_queue, vec![(header_id(3), 26..=30)]);
+	}
+
+	#[test]
+	#[allow(clippy::reversed_empty_ranges)]
+	fn select_nonces_to_deliver_fails_if_selector_returns_empty_range() {
+		assert_eq!(
+			run_test_for_incorrect_selector(|_| Some(2..=1)),
+			Err(SelectorError::EmptyRange {
+				passed_range: 51..=100,
+				returned_range: 2..=1,
+			}),
+		);
+	}
+
+	#[test]
+	fn select_nonces_to_deliver_fails_if_selector_returns_range_that_starts_before_passed_range() {
+		assert_eq!(
+			run_test_for_incorrect_selector(|range| Some(range.begin() - 1..=*range.end())),
+			Err(SelectorError::BeginsBeforePassedRange {
+				passed_range: 51..=100,
+				returned_range: 50..=100,
+			}),
+		);
+	}
+
+	#[test]
+	fn select_nonces_to_deliver_fails_if_selector_returns_range_with_mismatched_end() {
+		assert_eq!(
+			run_test_for_incorrect_selector(|range| Some(range.begin()..=*range.end() + 1)),
+			Err(SelectorError::EndMismatch {
+				passed_range: 51..=100,
+				returned_range: 51..=101,
+			}),
+		);
+	}
+
+	#[test]
+	fn stored_selector_splits_range_across_successive_selections() {
+		let mut state = batching_race_state();
+		let mut is_first_call = true;
+		let mut strategy = BasicStrategy::<TestMessageLane>::with_selector(move |range: SourceNoncesRange| {
+			if is_first_call {
+				is_first_call = false;
+				Some(*range.start() + 5..=*range.end())
+			} else {
+				None
+			}
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+
+		assert_eq!(strategy.select_nonces(&state), Some((1..=5, ())));
+		strategy.target_nonces_updated(target_nonces(5), &mut state);
+		assert_eq!(strategy.select_nonces(&state), Some((6..=10, ())));
+	}
+
+	#[test]
+	fn stored_selector_that_breaks_contract_selects_nothing() {
+		let state = batching_race_state();
+		let mut strategy = BasicStrategy::<TestMessageLane>::with_selector(|range: SourceNoncesRange| {
+			Some(*range.start() - 1..=*range.end())
+		});
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	fn batching_strategy(min_batch_size: MessageNonce) -> BatchingStrategy<BasicStrategy<TestMessageLane>> {
+		let mut strategy = BatchingStrategy::new(
+			BasicStrategy::<TestMessageLane>::new(),
+			Some(BatchingParams {
+				min_batch_size,
+				max_wait: Duration::from_secs(60),
+			}),
+		);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=3));
+		strategy
+	}
+
+	fn batching_race_state() -> RaceState<TestSourceHeaderId, TestTargetHeaderId, TestMessagesProof> {
+		RaceState {
+			target_state: Some(ClientState {
+				best_self: header_id(0),
+				best_finalized_self: header_id(0),
+				best_peer: header_id(1),
+			}),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn batching_strategy_withholds_small_batch() {
+		let state = batching_race_state();
+		let mut strategy = batching_strategy(5);
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert_eq!(strategy.select_nonces(&state), None);
+	}
+
+	#[test]
+	fn batching_strategy_selects_large_batch_immediately() {
+		let state = batching_race_state();
+		let mut strategy = batching_strategy(3);
+		assert_eq!(strategy.select_nonces(&state), Some((1..=3, ())));
+	}
+
+	#[test]
+	fn batching_strategy_selects_small_batch_after_max_wait() {
+		let mut state = batching_race_state();
+		let mut strategy = batching_strategy(5);
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// new deliverable nonces don't restart the wait
+		strategy.source_nonces_updated(header_id(2), source_nonces(4..=4));
+		state.target_state = Some(ClientState {
+			best_self: header_id(0),
+			best_finalized_self: header_id(0),
+			best_peer: header_id(2),
+		});
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert_eq!(strategy.deliverable_since.len(), 2);
+
+		// once the oldest nonce has been waiting for too long, everything is selected
+		strategy.deliverable_since[0].1 = Instant::now() - Duration::from_secs(120);
+		assert_eq!(strategy.select_nonces(&state), Some((1..=4, ())));
+
+		// when the oldest nonces are delivered, the wait time of remaining nonces is used
+		strategy.source_nonces_updated(header_id(2), source_nonces(5..=5));
+		strategy.target_nonces_updated(target_nonces(3), &mut state);
+		assert_eq!(strategy.select_nonces(&state), None);
+		assert_eq!(strategy.deliverable_since.len(), 2);
+	}
+
+	#[test]
+	fn batching_strategy_without_params_never_withholds_selection() {
+		let state = batching_race_state();
+		let mut strategy = BatchingStrategy::new(BasicStrategy::<TestMessageLane>::new(), None);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1));
+		assert_eq!(strategy.select_nonces(&state), Some((1..=1, ())));
+	}
+
+	#[derive(Clone)]
+	pub struct TestClock(pub Arc<parking_lot::Mutex<Instant>>);
+
+	impl TestClock {
+		pub fn advance(&self, duration: Duration) {
+			*self.0.lock() += duration;
+		}
+	}
+
+	impl Clock for TestClock {
+		fn now(&self) -> Instant {
+			*self.0.lock()
+		}
+	}
+
+	fn rate_limited_strategy(
+		max_nonces_per_minute: MessageNonce,
+	) -> (
+		TestClock,
+		RateLimitedStrategy<BasicStrategy<TestMessageLane>, TestClock>,
+	) {
+		let clock = TestClock(Arc::new(parking_lot::Mutex::new(Instant::now())));
+		let mut strategy = RateLimitedStrategy::with_clock(
+			BasicStrategy::<TestMessageLane>::new(),
+			max_nonces_per_minute,
+			clock.clone(),
+		);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=1000));
+		(clock, strategy)
+	}
+
+	fn select_and_deliver(
+		strategy: &mut RateLimitedStrategy<BasicStrategy<TestMessageLane>, TestClock>,
+		state: &mut RaceState<TestSourceHeaderId, TestTargetHeaderId, TestMessagesProof>,
+	) -> MessageNonce {
+		match strategy.select_nonces(state) {
+			Some((nonces, ())) => {
+				strategy.target_nonces_updated(target_nonces(*nonces.end()), state);
+				nonces.end() - nonces.start() + 1
+			}
+			None => 0,
+		}
+	}
+
+	#[test]
+	fn rate_limited_strategy_truncates_selected_nonces() {
+		let mut state = batching_race_state();
+		let (clock, mut strategy) = rate_limited_strategy(10);
+
+		// the bucket is full initially
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+		strategy.target_nonces_updated(target_nonces(10), &mut state);
+		// and is empty now
+		assert_eq!(strategy.select_nonces(&state), None);
+
+		// half of the bucket is refilled in 30 seconds
+		clock.advance(Duration::from_secs(30));
+		assert_eq!(strategy.select_nonces(&state), Some((11..=15, ())));
+	}
+
+	#[test]
+	fn rate_limited_strategy_respects_budget_in_the_long_run() {
+		let mut state = batching_race_state();
+		let (clock, mut strategy) = rate_limited_strategy(10);
+
+		// select and deliver nonces every 7 seconds for 10 minutes
+		let mut selected = select_and_deliver(&mut strategy, &mut state);
+		for step in 1..=600 / 7 {
+			clock.advance(Duration::from_secs(7));
+			selected += select_and_deliver(&mut strategy, &mut state);
+			assert!(selected <= 10 + step * 7 * 10 / 60);
+		}
+		clock.advance(Duration::from_secs(600 % 7));
+		selected += select_and_deliver(&mut strategy, &mut state);
+		assert_eq!(selected, 10 + 100);
+	}
+
+	#[test]
+	fn rate_limited_strategy_only_consumes_selected_nonces() {
+		let mut state = batching_race_state();
+		let (clock, mut strategy) = rate_limited_strategy(10);
+		strategy.target_nonces_updated(target_nonces(997), &mut state);
+
+		assert_eq!(strategy.select_nonces(&state), Some((998..=1000, ())));
+		strategy.target_nonces_updated(target_nonces(1000), &mut state);
+		strategy.source_nonces_updated(header_id(1), source_nonces(1001..=1100));
+		assert_eq!(strategy.select_nonces(&state), Some((1001..=1007, ())));
+
+		// the bucket never holds more than `max_nonces_per_minute` tokens
+		clock.advance(Duration::from_secs(600));
+		strategy.target_nonces_updated(target_nonces(1007), &mut state);
+		assert_eq!(strategy.select_nonces(&state), Some((1008..=1017, ())));
+	}
+
+	fn composite_strategy() -> CompositeStrategy<TestMessageLane> {
+		let mut strategy = super::CompositeStrategy::new(BasicStrategy::<TestMessageLane>::new());
+		strategy.source_nonces_updated(header_id(1), source_nonces(1..=10));
+		strategy
+	}
+
+	#[test]
+	fn composite_strategy_without_constraints_selects_all_nonces() {
+		let state = batching_race_state();
+		let mut strategy = composite_strategy();
+		assert_eq!(strategy.select_nonces(&state), Some((1..=10, ())));
+	}
+
+	#[test]
+	fn composite_strategy_applies_constraints_in_order() {
+		let state = batching_race_state();
+		let seen_by_second_constraint = Arc::new(std::sync::Mutex::new(None));
+		let seen_by_second_constraint_clone = seen_by_second_constraint.clone();
+		let mut strategy = composite_strategy()
+			.with_constraint(MaxNoncesConstraint(5))
+			.with_constraint(move |nonces: RangeInclusive<MessageNonce>, _: &[SourceNoncesRange]| {
+				*seen_by_second_constraint_clone.lock().unwrap() = Some(nonces.clone());
+				Some(*nonces.start()..=*nonces.end() - 1)
+			})
+			.with_constraint(MaxNoncesConstraint(3));
+
+		assert_eq!(strategy.select_nonces(&state), Some((1..=3, ())));
+		assert_eq!(*seen_by_second_constraint.lock().unwrap(), Some(1..=5));
+	}
+
+	#[test]
+	fn composite_strategy_selects_nothing_if_any_constraint_rejects_selection() {
+		let state = batching_race_state();
+		let mut strategy = composite_strategy()
+			.with_constraint(MaxNoncesConstraint(5))
+			.with_constraint(MaxNoncesConstraint(0))
+			.with_constraint(
+				|_: RangeInclusive<MessageNonce>, _: &[SourceNoncesRange]| -> Option<_> {
+					unreachable!("constraints after rejecting constraint are not called")
+				},
+			);
+
+		assert_eq!(strategy.select_nonces(&state), None);
 	}
 
 	#[test]
 	#[should_panic]
-	fn select_nonces_to_deliver_panics_if_selector_returns_range_with_mismatched_end() {
-		run_panic_test_for_incorrect_selector(|range| Some(range.begin()..=*range.end() + 1))
+	fn composite_strategy_panics_if_constraint_returns_non_prefix_range() {
+		let state = batching_race_state();
+		let mut strategy =
+			composite_strategy().with_constraint(|nonces: RangeInclusive<MessageNonce>, _: &[SourceNoncesRange]| {
+				Some(*nonces.start() + 1..=*nonces.end())
+			});
+
+		strategy.select_nonces(&state);
 	}
 }
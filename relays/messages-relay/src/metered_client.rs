@@ -0,0 +1,643 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Bridges Common.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Race clients wrappers that are exposing metrics of all client calls, so that the race loop
+//! itself doesn't need to measure every request.
+
+use crate::message_race_loop::{
+	DryRunOutcome, MessageRace, ReconnectError, SourceClient, SourceClientNonces, SubmitOptions, TargetClient,
+	TargetNonces,
+};
+use crate::metrics::MessageRaceMetrics;
+
+use async_trait::async_trait;
+use bp_message_lane::{MessageNonce, Weight};
+use relay_utils::{MaybeFatalError, RelayError};
+use std::{future::Future, marker::PhantomData, ops::RangeInclusive, sync::Arc, time::Instant};
+
+/// Race source client that records number, duration and errors of all calls to the wrapped
+/// client, labeled by race and method name.
+pub struct MeteredSourceClient<P, C> {
+	client: C,
+	lane: String,
+	metrics: Option<MessageRaceMetrics>,
+	_phantom: PhantomData<P>,
+}
+
+impl<P, C> MeteredSourceClient<P, C> {
+	/// Wrap given client. If metrics are not provided, calls are only delegated to the client.
+	pub fn new(client: C, lane: String, metrics: Option<MessageRaceMetrics>) -> Self {
+		MeteredSourceClient {
+			client,
+			lane,
+			metrics,
+			_phantom: Default::default(),
+		}
+	}
+}
+
+#[async_trait]
+impl<P, C> SourceClient<P> for MeteredSourceClient<P, C>
+where
+	P: MessageRace + Send + Sync,
+	P::SourceHeaderId: Send,
+	P::Proof: Send,
+	C: SourceClient<P> + Send + Sync,
+{
+	type Error = C::Error;
+	type NoncesRange = C::NoncesRange;
+	type ProofParameters = C::ProofParameters;
+
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"source",
+			"reconnect",
+			self.client.reconnect(),
+		)
+		.await
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"source",
+			"runtime_version",
+			self.client.runtime_version(),
+		)
+		.await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
+	async fn nonces(
+		&self,
+		at_block: P::SourceHeaderId,
+		prev_latest_nonce: Option<MessageNonce>,
+	) -> Result<(P::SourceHeaderId, SourceClientNonces<Self::NoncesRange>), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"source",
+			"nonces",
+			self.client.nonces(at_block, prev_latest_nonce),
+		)
+		.await
+	}
+
+	async fn generate_proof(
+		&self,
+		at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> Result<(P::SourceHeaderId, RangeInclusive<MessageNonce>, P::Proof), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"source",
+			"generate_proof",
+			self.client.generate_proof(at_block, nonces, proof_parameters),
+		)
+		.await
+	}
+
+	async fn estimate_proof_size(
+		&self,
+		at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: Self::ProofParameters,
+	) -> Result<u32, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"source",
+			"estimate_proof_size",
+			self.client.estimate_proof_size(at_block, nonces, proof_parameters),
+		)
+		.await
+	}
+
+	fn trim_proof(&self, proof: &P::Proof, nonces: RangeInclusive<MessageNonce>) -> Option<P::Proof> {
+		self.client.trim_proof(proof, nonces)
+	}
+}
+
+/// Race target client that records number, duration and errors of all calls to the wrapped
+/// client, labeled by race and method name.
+pub struct MeteredTargetClient<P, C> {
+	client: C,
+	lane: String,
+	metrics: Option<MessageRaceMetrics>,
+	_phantom: PhantomData<P>,
+}
+
+impl<P, C> MeteredTargetClient<P, C> {
+	/// Wrap given client. If metrics are not provided, calls are only delegated to the client.
+	pub fn new(client: C, lane: String, metrics: Option<MessageRaceMetrics>) -> Self {
+		MeteredTargetClient {
+			client,
+			lane,
+			metrics,
+			_phantom: Default::default(),
+		}
+	}
+}
+
+#[async_trait]
+impl<P, C> TargetClient<P> for MeteredTargetClient<P, C>
+where
+	P: MessageRace + Send + Sync,
+	P::SourceHeaderId: Send + Sync,
+	P::TargetHeaderId: Send,
+	P::Proof: Send + Sync,
+	C: TargetClient<P> + Send + Sync,
+{
+	type Error = C::Error;
+	type TransactionTracker = C::TransactionTracker;
+	type Cost = C::Cost;
+	type TargetNoncesData = C::TargetNoncesData;
+
+	async fn reconnect(&mut self) -> Result<(), ReconnectError<Self::Error>> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"reconnect",
+			self.client.reconnect(),
+		)
+		.await
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"runtime_version",
+			self.client.runtime_version(),
+		)
+		.await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
+	async fn nonces(
+		&self,
+		at_best: P::TargetHeaderId,
+		at_finalized: P::TargetHeaderId,
+	) -> Result<(P::TargetHeaderId, TargetNonces<Self::TargetNoncesData>), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"nonces",
+			self.client.nonces(at_best, at_finalized),
+		)
+		.await
+	}
+
+	async fn verify_proof(
+		&self,
+		generated_at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::Proof,
+	) -> Result<(), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"verify_proof",
+			self.client.verify_proof(generated_at_block, nonces, proof),
+		)
+		.await
+	}
+
+	async fn dry_run_proof(
+		&self,
+		generated_at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::Proof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"dry_run_proof",
+			self.client.dry_run_proof(generated_at_block, nonces, proof),
+		)
+		.await
+	}
+
+	async fn required_source_header_at_target(
+		&self,
+		current_best: P::SourceHeaderId,
+	) -> Result<Option<P::SourceHeaderId>, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"required_source_header_at_target",
+			self.client.required_source_header_at_target(current_best),
+		)
+		.await
+	}
+
+	async fn submit_proof(
+		&self,
+		generated_at_block: P::SourceHeaderId,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: Arc<P::Proof>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"submit_proof",
+			self.client.submit_proof(generated_at_block, nonces, proof, options),
+		)
+		.await
+	}
+
+	fn supports_batch_submission(&self) -> bool {
+		self.client.supports_batch_submission()
+	}
+
+	#[allow(clippy::type_complexity)]
+	async fn submit_proofs(
+		&self,
+		batch: Vec<(P::SourceHeaderId, RangeInclusive<MessageNonce>, Arc<P::Proof>)>,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"submit_proofs",
+			self.client.submit_proofs(batch, options),
+		)
+		.await
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<Self::Cost, Self::Error> {
+		metered_call::<P, _, _>(
+			&self.metrics,
+			&self.lane,
+			"target",
+			"estimate_delivery_cost",
+			self.client
+				.estimate_delivery_cost(nonces, total_dispatch_weight, total_size),
+		)
+		.await
+	}
+}
+
+/// Wait for the client call to complete and update metrics with its result.
+async fn metered_call<P: MessageRace, T, E: RelayError + MaybeFatalError>(
+	metrics: &Option<MessageRaceMetrics>,
+	lane: &str,
+	client: &str,
+	method: &str,
+	call: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+	let started_at = Instant::now();
+	let result = call.await;
+	if let Some(metrics) = metrics.as_ref() {
+		metrics.update_client_call::<P, _, _>(lane, client, method, started_at.elapsed(), &result);
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::message_lane_loop::TrackedTransactionStatus;
+	use crate::message_race_loop::{
+		tests::{TestProof, TestRace},
+		TargetClientNonces,
+	};
+	use crate::testing::{header_id, TestSourceHeaderId, TestTargetHeaderId, TestTransactionTracker};
+	use parking_lot::Mutex;
+	use relay_utils::{
+		metrics::{Metrics, Registry},
+		ErrorClassification, MaybeRejectedTransactionError,
+	};
+	use std::collections::VecDeque;
+
+	#[derive(Debug)]
+	struct TestClientError(ErrorClassification);
+
+	impl RelayError for TestClientError {
+		fn classification(&self) -> ErrorClassification {
+			self.0
+		}
+	}
+
+	impl MaybeFatalError for TestClientError {}
+
+	impl MaybeRejectedTransactionError for TestClientError {
+		fn is_rejected_transaction(&self) -> bool {
+			false
+		}
+	}
+
+	#[derive(Default)]
+	struct TestClient {
+		/// Errors that are returned by next calls. Calls succeed once the queue is empty.
+		errors: Mutex<VecDeque<ErrorClassification>>,
+		/// Number of `on_runtime_upgrade` calls.
+		runtime_upgrades: Mutex<usize>,
+	}
+
+	impl TestClient {
+		fn with_errors(errors: Vec<ErrorClassification>) -> Self {
+			TestClient {
+				errors: Mutex::new(errors.into_iter().collect()),
+				..Default::default()
+			}
+		}
+
+		fn result<T>(&self, value: T) -> Result<T, TestClientError> {
+			match self.errors.lock().pop_front() {
+				Some(classification) => Err(TestClientError(classification)),
+				None => Ok(value),
+			}
+		}
+	}
+
+	#[async_trait]
+	impl SourceClient<TestRace> for TestClient {
+		type Error = TestClientError;
+		type NoncesRange = RangeInclusive<MessageNonce>;
+		type ProofParameters = ();
+
+		async fn reconnect(&mut self) -> Result<(), ReconnectError<TestClientError>> {
+			self.result(()).map_err(ReconnectError::Failed)
+		}
+
+		async fn runtime_version(&self) -> Result<Option<u32>, TestClientError> {
+			self.result(None)
+		}
+
+		fn on_runtime_upgrade(&self) {
+			*self.runtime_upgrades.lock() += 1;
+		}
+
+		async fn nonces(
+			&self,
+			at_block: TestSourceHeaderId,
+			_prev_latest_nonce: Option<MessageNonce>,
+		) -> Result<(TestSourceHeaderId, SourceClientNonces<RangeInclusive<MessageNonce>>), TestClientError> {
+			self.result((
+				at_block,
+				SourceClientNonces {
+					new_nonces: 1..=1,
+					confirmed_nonce: None,
+					unrewarded_relayer_entries: None,
+				},
+			))
+		}
+
+		async fn generate_proof(
+			&self,
+			at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			_proof_parameters: (),
+		) -> Result<(TestSourceHeaderId, RangeInclusive<MessageNonce>, TestProof), TestClientError> {
+			self.result((at_block, nonces.clone(), nonces))
+		}
+
+		fn trim_proof(&self, _proof: &TestProof, nonces: RangeInclusive<MessageNonce>) -> Option<TestProof> {
+			Some(nonces)
+		}
+	}
+
+	#[async_trait]
+	impl TargetClient<TestRace> for TestClient {
+		type Error = TestClientError;
+		type TransactionTracker = TestTransactionTracker;
+		type Cost = u64;
+		type TargetNoncesData = ();
+
+		async fn reconnect(&mut self) -> Result<(), ReconnectError<TestClientError>> {
+			self.result(()).map_err(ReconnectError::Failed)
+		}
+
+		async fn runtime_version(&self) -> Result<Option<u32>, TestClientError> {
+			self.result(None)
+		}
+
+		fn on_runtime_upgrade(&self) {
+			*self.runtime_upgrades.lock() += 1;
+		}
+
+		async fn nonces(
+			&self,
+			at_best: TestTargetHeaderId,
+			_at_finalized: TestTargetHeaderId,
+		) -> Result<(TestTargetHeaderId, TargetNonces<()>), TestClientError> {
+			let nonces = TargetClientNonces {
+				latest_nonce: 0,
+				nonces_data: (),
+			};
+			self.result((
+				at_best,
+				TargetNonces {
+					at_best: nonces.clone(),
+					at_finalized: nonces,
+				},
+			))
+		}
+
+		async fn verify_proof(
+			&self,
+			_generated_at_block: TestSourceHeaderId,
+			_nonces: RangeInclusive<MessageNonce>,
+			_proof: &TestProof,
+		) -> Result<(), TestClientError> {
+			self.result(())
+		}
+
+		async fn dry_run_proof(
+			&self,
+			_generated_at_block: TestSourceHeaderId,
+			_nonces: RangeInclusive<MessageNonce>,
+			_proof: &TestProof,
+		) -> Result<DryRunOutcome, TestClientError> {
+			self.result(DryRunOutcome::Success)
+		}
+
+		async fn required_source_header_at_target(
+			&self,
+			_current_best: TestSourceHeaderId,
+		) -> Result<Option<TestSourceHeaderId>, TestClientError> {
+			self.result(None)
+		}
+
+		async fn submit_proof(
+			&self,
+			_generated_at_block: TestSourceHeaderId,
+			nonces: RangeInclusive<MessageNonce>,
+			_proof: Arc<TestProof>,
+			_options: SubmitOptions,
+		) -> Result<(RangeInclusive<MessageNonce>, TestTransactionTracker), TestClientError> {
+			self.result((nonces, TestTransactionTracker(TrackedTransactionStatus::Finalized)))
+		}
+
+		async fn estimate_delivery_cost(
+			&self,
+			_nonces: RangeInclusive<MessageNonce>,
+			_total_dispatch_weight: Weight,
+			_total_size: u32,
+		) -> Result<u64, TestClientError> {
+			self.result(0)
+		}
+	}
+
+	fn registered_metrics() -> (MessageRaceMetrics, Registry) {
+		let metrics = MessageRaceMetrics::new("test_race");
+		let registry = Registry::new();
+		metrics.register(&registry).unwrap();
+		(metrics, registry)
+	}
+
+	/// Returns value of the counter or number of samples of the histogram with given labels.
+	fn metric_value(registry: &Registry, name: &str, labels: &[(&str, &str)]) -> u64 {
+		registry
+			.gather()
+			.into_iter()
+			.filter(|family| family.get_name() == name)
+			.flat_map(|family| family.get_metric().to_vec())
+			.find(|metric| {
+				labels.iter().all(|(name, value)| {
+					metric
+						.get_label()
+						.iter()
+						.any(|label| label.get_name() == *name && label.get_value() == *value)
+				})
+			})
+			// fields of other metric types are default (zero)
+			.map(|metric| metric.get_counter().get_value() as u64 + metric.get_histogram().get_sample_count())
+			.unwrap_or(0)
+	}
+
+	#[test]
+	fn metered_source_client_records_calls() {
+		let (metrics, registry) = registered_metrics();
+		let client = MeteredSourceClient::<TestRace, _>::new(
+			TestClient::with_errors(vec![ErrorClassification::Reconnect]),
+			"test_lane".into(),
+			Some(metrics),
+		);
+
+		async_std::task::block_on(async {
+			assert!(client.nonces(header_id(1), None).await.is_err());
+			assert!(client.nonces(header_id(1), None).await.is_ok());
+			assert!(client.generate_proof(header_id(1), 1..=1, ()).await.is_ok());
+		});
+
+		let source = |method| {
+			vec![
+				("race", "Test"),
+				("lane", "test_lane"),
+				("client", "source"),
+				("method", method),
+			]
+		};
+		assert_eq!(
+			metric_value(&registry, "test_race_client_calls_total", &source("nonces")),
+			2
+		);
+		assert_eq!(
+			metric_value(&registry, "test_race_client_call_duration_seconds", &source("nonces")),
+			2
+		);
+		assert_eq!(
+			metric_value(&registry, "test_race_client_calls_total", &source("generate_proof")),
+			1
+		);
+		let errors = |method, class| {
+			let mut labels = source(method);
+			labels.push(("class", class));
+			metric_value(&registry, "test_race_client_call_errors_total", &labels)
+		};
+		assert_eq!(errors("nonces", "reconnect"), 1);
+		assert_eq!(errors("generate_proof", "reconnect"), 0);
+	}
+
+	#[test]
+	fn metered_target_client_records_errors_by_class() {
+		let (metrics, registry) = registered_metrics();
+		let client = MeteredTargetClient::<TestRace, _>::new(
+			TestClient::with_errors(vec![
+				ErrorClassification::RetryImmediately,
+				ErrorClassification::RetryWithBackoff,
+				ErrorClassification::Fatal,
+			]),
+			"test_lane".into(),
+			Some(metrics),
+		);
+
+		async_std::task::block_on(async {
+			for _ in 0..4 {
+				let _ = client
+					.submit_proof(header_id(1), 1..=1, Arc::new(1..=1), SubmitOptions::default())
+					.await;
+			}
+		});
+
+		let submit_proof = vec![
+			("race", "Test"),
+			("lane", "test_lane"),
+			("client", "target"),
+			("method", "submit_proof"),
+		];
+		assert_eq!(
+			metric_value(&registry, "test_race_client_calls_total", &submit_proof),
+			4
+		);
+		let errors = |class| {
+			let mut labels = submit_proof.clone();
+			labels.push(("class", class));
+			metric_value(&registry, "test_race_client_call_errors_total", &labels)
+		};
+		assert_eq!(errors("retry_immediately"), 1);
+		assert_eq!(errors("retry_with_backoff"), 1);
+		assert_eq!(errors("reconnect"), 0);
+		assert_eq!(errors("fatal"), 1);
+	}
+
+	#[test]
+	fn metered_client_delegates_calls_that_are_not_metered() {
+		let client = MeteredTargetClient::<TestRace, _>::new(TestClient::default(), "test_lane".into(), None);
+		client.on_runtime_upgrade();
+		assert_eq!(*client.client.runtime_upgrades.lock(), 1);
+
+		let client = MeteredSourceClient::<TestRace, _>::new(TestClient::default(), "test_lane".into(), None);
+		assert_eq!(client.trim_proof(&(1..=5), 1..=3), Some(1..=3));
+	}
+}
@@ -18,9 +18,14 @@
 
 use crate::message_lane::MessageLane;
 use crate::message_lane_loop::{SourceClientState, TargetClientState};
+use crate::message_race_loop::{MessageRace, StrategyMetricsSnapshot};
 
 use bp_message_lane::MessageNonce;
-use relay_utils::metrics::{register, GaugeVec, Metrics, Opts, Registry, U64};
+use relay_utils::{
+	metrics::{register, CounterVec, GaugeVec, HistogramOpts, HistogramVec, Metrics, Opts, Registry, U64},
+	ErrorClassification, MaybeFatalError, RelayError,
+};
+use std::time::Duration;
 
 /// Message lane relay metrics.
 ///
@@ -32,12 +37,86 @@ pub struct MessageLaneLoopMetrics {
 	/// Lane state nonces: "source_latest_generated", "source_latest_confirmed",
 	/// "target_latest_received", "target_latest_confirmed".
 	lane_state_nonces: GaugeVec<U64>,
+	/// Metrics of the messages delivery race.
+	delivery_race: MessageRaceMetrics,
+	/// Metrics of the receiving confirmations delivery race.
+	receiving_race: MessageRaceMetrics,
+}
+
+/// Message race metrics.
+///
+/// Cloning only clones references.
+#[derive(Clone)]
+pub struct MessageRaceMetrics {
+	/// Best nonce at the race source, labeled by race and lane.
+	best_nonce_at_source: GaugeVec<U64>,
+	/// Best nonce at the race target, labeled by race and lane.
+	best_nonce_at_target: GaugeVec<U64>,
+	/// Number of nonces that are known to the race source, but not yet delivered to the race
+	/// target, labeled by race and lane.
+	backlog: GaugeVec<U64>,
+	/// Number of generated proofs, labeled by race and lane.
+	proofs_generated: CounterVec<U64>,
+	/// Number of submitted proofs, labeled by race and lane.
+	proofs_submitted: CounterVec<U64>,
+	/// Number of failed proof generations and submissions, labeled by race, lane, kind
+	/// ("generation" or "submission") and class ("connection" or "logical") of the failure.
+	proof_failures: CounterVec<U64>,
+	/// Duration of proof generations, labeled by race and lane.
+	proof_generation_duration: HistogramVec,
+	/// Duration of proof submissions, labeled by race and lane.
+	proof_submission_duration: HistogramVec,
+	/// Number of nonces ranges, queued by the race strategy, labeled by race and lane.
+	strategy_queued_ranges: GaugeVec<U64>,
+	/// Total number of nonces, queued by the race strategy, labeled by race and lane.
+	strategy_queued_nonces: GaugeVec<U64>,
+	/// Number of the source block where the oldest queued range has been seen (or zero if nothing
+	/// is queued), labeled by race and lane.
+	strategy_oldest_queued_block: GaugeVec<U64>,
+	/// Number of source nonces updates, refused by the race strategy because of the gap, labeled
+	/// by race and lane.
+	strategy_source_nonce_gaps: GaugeVec<U64>,
+	/// 1 if the race is paused because relayer balance at the target node is low, labeled by
+	/// race and lane.
+	relayer_balance_low: GaugeVec<U64>,
+	/// Number of race client calls, labeled by race, lane, client ("source" or "target") and
+	/// method.
+	client_calls: CounterVec<U64>,
+	/// Number of failed race client calls, labeled by race, lane, client, method and class of
+	/// the error ("retry_immediately", "retry_with_backoff", "reconnect" or "fatal").
+	client_call_errors: CounterVec<U64>,
+	/// Duration of race client calls, labeled by race, lane, client and method.
+	client_call_duration: HistogramVec,
 }
 
 impl Metrics for MessageLaneLoopMetrics {
 	fn register(&self, registry: &Registry) -> Result<(), String> {
 		register(self.best_block_numbers.clone(), registry).map_err(|e| e.to_string())?;
 		register(self.lane_state_nonces.clone(), registry).map_err(|e| e.to_string())?;
+		self.delivery_race.register(registry)?;
+		self.receiving_race.register(registry)?;
+		Ok(())
+	}
+}
+
+impl Metrics for MessageRaceMetrics {
+	fn register(&self, registry: &Registry) -> Result<(), String> {
+		register(self.best_nonce_at_source.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.best_nonce_at_target.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.backlog.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.proofs_generated.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.proofs_submitted.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.proof_failures.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.proof_generation_duration.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.proof_submission_duration.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.strategy_queued_ranges.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.strategy_queued_nonces.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.strategy_oldest_queued_block.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.strategy_source_nonce_gaps.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.relayer_balance_low.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.client_calls.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.client_call_errors.clone(), registry).map_err(|e| e.to_string())?;
+		register(self.client_call_duration.clone(), registry).map_err(|e| e.to_string())?;
 		Ok(())
 	}
 }
@@ -52,11 +131,23 @@ impl Default for MessageLaneLoopMetrics {
 			.expect("metric is static and thus valid; qed"),
 			lane_state_nonces: GaugeVec::new(Opts::new("lane_state_nonces", "Nonces of the lane state"), &["type"])
 				.expect("metric is static and thus valid; qed"),
+			delivery_race: MessageRaceMetrics::new("messages_delivery_race"),
+			receiving_race: MessageRaceMetrics::new("receiving_confirmations_race"),
 		}
 	}
 }
 
 impl MessageLaneLoopMetrics {
+	/// Metrics of the messages delivery race.
+	pub fn delivery_race(&self) -> &MessageRaceMetrics {
+		&self.delivery_race
+	}
+
+	/// Metrics of the receiving confirmations delivery race.
+	pub fn receiving_race(&self) -> &MessageRaceMetrics {
+		&self.receiving_race
+	}
+
 	/// Update source client state metrics.
 	pub fn update_source_state<P: MessageLane>(&self, source_client_state: SourceClientState<P>) {
 		self.best_block_numbers
@@ -78,30 +169,258 @@ impl MessageLaneLoopMetrics {
 	}
 
 	/// Update latest generated nonce at source.
-	pub fn update_source_latest_generated_nonce<P: MessageLane>(&self, source_latest_generated_nonce: MessageNonce) {
+	pub fn update_source_latest_generated_nonce(&self, source_latest_generated_nonce: MessageNonce) {
 		self.lane_state_nonces
 			.with_label_values(&["source_latest_generated"])
 			.set(source_latest_generated_nonce);
 	}
 
 	/// Update latest confirmed nonce at source.
-	pub fn update_source_latest_confirmed_nonce<P: MessageLane>(&self, source_latest_confirmed_nonce: MessageNonce) {
+	pub fn update_source_latest_confirmed_nonce(&self, source_latest_confirmed_nonce: MessageNonce) {
 		self.lane_state_nonces
 			.with_label_values(&["source_latest_confirmed"])
 			.set(source_latest_confirmed_nonce);
 	}
 
 	/// Update latest received nonce at target.
-	pub fn update_target_latest_received_nonce<P: MessageLane>(&self, target_latest_generated_nonce: MessageNonce) {
+	pub fn update_target_latest_received_nonce(&self, target_latest_generated_nonce: MessageNonce) {
 		self.lane_state_nonces
 			.with_label_values(&["target_latest_received"])
 			.set(target_latest_generated_nonce);
 	}
 
 	/// Update latest confirmed nonce at target.
-	pub fn update_target_latest_confirmed_nonce<P: MessageLane>(&self, target_latest_confirmed_nonce: MessageNonce) {
+	pub fn update_target_latest_confirmed_nonce(&self, target_latest_confirmed_nonce: MessageNonce) {
 		self.lane_state_nonces
 			.with_label_values(&["target_latest_confirmed"])
 			.set(target_latest_confirmed_nonce);
 	}
 }
+
+impl MessageRaceMetrics {
+	/// Create race metrics. Names of all metrics are prefixed with given prefix, so different
+	/// races may register their metrics in the same registry.
+	pub fn new(prefix: &str) -> Self {
+		let gauge = |name: &str, help: &str| {
+			GaugeVec::new(Opts::new(format!("{}_{}", prefix, name), help), &["race", "lane"])
+				.expect("metric name is valid if prefix is valid; qed")
+		};
+		let counter = |name: &str, help: &str, labels: &[&str]| {
+			CounterVec::new(Opts::new(format!("{}_{}", prefix, name), help), labels)
+				.expect("metric name is valid if prefix is valid; qed")
+		};
+		let histogram = |name: &str, help: &str| {
+			HistogramVec::new(
+				HistogramOpts::new(format!("{}_{}", prefix, name), help),
+				&["race", "lane"],
+			)
+			.expect("metric name is valid if prefix is valid; qed")
+		};
+		MessageRaceMetrics {
+			best_nonce_at_source: gauge("best_nonce_at_source", "Best nonce at the race source"),
+			best_nonce_at_target: gauge("best_nonce_at_target", "Best nonce at the race target"),
+			backlog: gauge(
+				"backlog",
+				"Number of nonces that are not yet delivered to the race target",
+			),
+			proofs_generated: counter(
+				"proofs_generated_total",
+				"Number of generated proofs",
+				&["race", "lane"],
+			),
+			proofs_submitted: counter(
+				"proofs_submitted_total",
+				"Number of submitted proofs",
+				&["race", "lane"],
+			),
+			proof_failures: counter(
+				"proof_failures_total",
+				"Number of failed proof generations and submissions",
+				&["race", "lane", "kind", "class"],
+			),
+			proof_generation_duration: histogram("proof_generation_duration_seconds", "Duration of proof generations"),
+			proof_submission_duration: histogram("proof_submission_duration_seconds", "Duration of proof submissions"),
+			strategy_queued_ranges: gauge(
+				"strategy_queued_ranges",
+				"Number of nonces ranges queued by the strategy",
+			),
+			strategy_queued_nonces: gauge("strategy_queued_nonces", "Number of nonces queued by the strategy"),
+			strategy_oldest_queued_block: gauge(
+				"strategy_oldest_queued_block",
+				"Number of the source block where the oldest queued range has been seen",
+			),
+			strategy_source_nonce_gaps: gauge(
+				"strategy_source_nonce_gaps",
+				"Number of source nonces updates refused by the strategy because of the gap",
+			),
+			relayer_balance_low: gauge(
+				"relayer_balance_low",
+				"1 if the race is paused because relayer balance at the target node is low",
+			),
+			client_calls: counter(
+				"client_calls_total",
+				"Number of race client calls",
+				&["race", "lane", "client", "method"],
+			),
+			client_call_errors: counter(
+				"client_call_errors_total",
+				"Number of failed race client calls",
+				&["race", "lane", "client", "method", "class"],
+			),
+			client_call_duration: HistogramVec::new(
+				HistogramOpts::new(
+					format!("{}_client_call_duration_seconds", prefix),
+					"Duration of race client calls",
+				),
+				&["race", "lane", "client", "method"],
+			)
+			.expect("metric name is valid if prefix is valid; qed"),
+		}
+	}
+
+	/// Update best nonces of the race.
+	pub fn update_best_nonces<P: MessageRace>(
+		&self,
+		lane: &str,
+		best_nonce_at_source: MessageNonce,
+		best_nonce_at_target: MessageNonce,
+	) {
+		let race = P::race_kind();
+		let labels = [race.as_str(), lane];
+		self.best_nonce_at_source
+			.with_label_values(&labels)
+			.set(best_nonce_at_source);
+		self.best_nonce_at_target
+			.with_label_values(&labels)
+			.set(best_nonce_at_target);
+		self.backlog
+			.with_label_values(&labels)
+			.set(best_nonce_at_source.saturating_sub(best_nonce_at_target));
+	}
+
+	/// Update metrics of the race strategy. Nothing is updated if the strategy doesn't support
+	/// queue metrics.
+	pub fn update_strategy_metrics<P: MessageRace>(&self, lane: &str, strategy_metrics: &StrategyMetricsSnapshot) {
+		let race = P::race_kind();
+		let labels = [race.as_str(), lane];
+		let queued_ranges = match strategy_metrics.queued_ranges {
+			Some(queued_ranges) => queued_ranges,
+			None => return,
+		};
+		self.strategy_queued_ranges
+			.with_label_values(&labels)
+			.set(queued_ranges);
+		self.strategy_queued_nonces
+			.with_label_values(&labels)
+			.set(strategy_metrics.queued_nonces.unwrap_or(0));
+		self.strategy_oldest_queued_block
+			.with_label_values(&labels)
+			.set(strategy_metrics.oldest_queued_at.unwrap_or(0));
+		self.strategy_source_nonce_gaps
+			.with_label_values(&labels)
+			.set(strategy_metrics.source_nonce_gaps.unwrap_or(0));
+	}
+
+	/// Update metrics of completed proof generation.
+	pub fn update_proof_generation<P: MessageRace, T, E: RelayError>(
+		&self,
+		lane: &str,
+		duration: Duration,
+		result: &Result<T, E>,
+	) {
+		self.update_proof_operation::<P, T, E>(
+			lane,
+			"generation",
+			&self.proofs_generated,
+			&self.proof_generation_duration,
+			duration,
+			result,
+		)
+	}
+
+	/// Update relayer balance guard state.
+	pub fn update_relayer_balance_low<P: MessageRace>(&self, lane: &str, is_low: bool) {
+		let race = P::race_kind();
+		self.relayer_balance_low
+			.with_label_values(&[race.as_str(), lane])
+			.set(is_low as u64);
+	}
+
+	/// Update metrics of completed proof submission.
+	pub fn update_proof_submission<P: MessageRace, T, E: RelayError>(
+		&self,
+		lane: &str,
+		duration: Duration,
+		result: &Result<T, E>,
+	) {
+		self.update_proof_operation::<P, T, E>(
+			lane,
+			"submission",
+			&self.proofs_submitted,
+			&self.proof_submission_duration,
+			duration,
+			result,
+		)
+	}
+
+	/// Update metrics of completed race client call.
+	pub fn update_client_call<P: MessageRace, T, E: RelayError + MaybeFatalError>(
+		&self,
+		lane: &str,
+		client: &str,
+		method: &str,
+		duration: Duration,
+		result: &Result<T, E>,
+	) {
+		let race = P::race_kind();
+		let labels = [race.as_str(), lane, client, method];
+		self.client_calls.with_label_values(&labels).inc();
+		self.client_call_duration
+			.with_label_values(&labels)
+			.observe(duration.as_secs_f64());
+		if let Err(ref error) = result {
+			let classification = if error.is_fatal() {
+				ErrorClassification::Fatal
+			} else {
+				error.classification()
+			};
+			let class = match classification {
+				ErrorClassification::RetryImmediately => "retry_immediately",
+				ErrorClassification::RetryWithBackoff => "retry_with_backoff",
+				ErrorClassification::Reconnect => "reconnect",
+				ErrorClassification::Fatal => "fatal",
+			};
+			self.client_call_errors
+				.with_label_values(&[race.as_str(), lane, client, method, class])
+				.inc();
+		}
+	}
+
+	fn update_proof_operation<P: MessageRace, T, E: RelayError>(
+		&self,
+		lane: &str,
+		kind: &str,
+		successes: &CounterVec<U64>,
+		durations: &HistogramVec,
+		duration: Duration,
+		result: &Result<T, E>,
+	) {
+		let race = P::race_kind();
+		durations
+			.with_label_values(&[race.as_str(), lane])
+			.observe(duration.as_secs_f64());
+		match result {
+			Ok(_) => successes.with_label_values(&[race.as_str(), lane]).inc(),
+			Err(ref error) => {
+				let class = if error.classification() == ErrorClassification::Reconnect {
+					"connection"
+				} else {
+					"logical"
+				};
+				self.proof_failures
+					.with_label_values(&[race.as_str(), lane, kind, class])
+					.inc();
+			}
+		}
+	}
+}
@@ -0,0 +1,621 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Bridges Common.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Message lane clients wrapper that is limiting rate of requests to the node. Public RPC
+//! endpoints are throttling clients that are exceeding their quotas, so the relay must not
+//! send requests faster than the provider allows.
+
+use crate::message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf};
+use crate::message_lane_loop::{
+	DryRunOutcome, MessageDetailsMap, MessageProofParameters, SourceClient, SourceClientState, SubmitOptions,
+	TargetClient, TargetClientState, TargetLaneParameters,
+};
+
+use async_trait::async_trait;
+use bp_message_lane::{MessageNonce, UnrewardedRelayersState, Weight};
+use futures::{channel::oneshot, future::FutureExt};
+use parking_lot::Mutex;
+use relay_utils::{
+	AsyncStdTimer, ErrorClassification, MaybeFatalError, MaybeRejectedTransactionError, MaybeThrottledError,
+	RelayError, Timer,
+};
+use std::{
+	future::Future,
+	marker::PhantomData,
+	ops::RangeInclusive,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/// Limits of requests to the node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitParams {
+	/// Maximal number of requests that are started every second. If the client has been idle,
+	/// up to this number of requests may be started at once.
+	pub max_requests_per_second: u32,
+	/// Maximal number of requests that are in progress at the same time.
+	pub max_concurrent_requests: usize,
+}
+
+/// Error of the rate limited client.
+///
+/// Throttled requests are treated as connection errors, so the relay loop waits before sending
+/// next requests to the node, instead of retrying them immediately.
+pub struct RateLimitedClientError<E>(pub E);
+
+impl<E: std::fmt::Debug> std::fmt::Debug for RateLimitedClientError<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		// client errors are printed as is, so wrapping client doesn't change error messages
+		self.0.fmt(f)
+	}
+}
+
+impl<E: RelayError + MaybeThrottledError> RelayError for RateLimitedClientError<E> {
+	fn classification(&self) -> ErrorClassification {
+		if self.0.is_throttled() {
+			ErrorClassification::Reconnect
+		} else {
+			self.0.classification()
+		}
+	}
+}
+
+impl<E: MaybeRejectedTransactionError> MaybeRejectedTransactionError for RateLimitedClientError<E> {
+	fn is_rejected_transaction(&self) -> bool {
+		self.0.is_rejected_transaction()
+	}
+}
+
+impl<E: MaybeFatalError> MaybeFatalError for RateLimitedClientError<E> {
+	fn is_fatal(&self) -> bool {
+		self.0.is_fatal()
+	}
+}
+
+impl<E: MaybeThrottledError> MaybeThrottledError for RateLimitedClientError<E> {
+	fn is_throttled(&self) -> bool {
+		self.0.is_throttled()
+	}
+}
+
+/// Message lane client that limits rate and concurrency of requests to the wrapped client.
+///
+/// Limits are shared by all clones of the client, so if the same node is used by both races,
+/// they are respected by both races together. Transaction trackers are not limited.
+pub struct RateLimitedClient<P, C, T = AsyncStdTimer> {
+	client: C,
+	limiter: Option<Arc<RateLimiter<T>>>,
+	_phantom: PhantomData<P>,
+}
+
+impl<P, C: Clone, T> Clone for RateLimitedClient<P, C, T> {
+	fn clone(&self) -> Self {
+		RateLimitedClient {
+			client: self.client.clone(),
+			limiter: self.limiter.clone(),
+			_phantom: Default::default(),
+		}
+	}
+}
+
+impl<P, C> RateLimitedClient<P, C> {
+	/// Wrap given client. If limits are not provided, requests are only delegated to the client.
+	pub fn new(client: C, limits: Option<RateLimitParams>) -> Self {
+		Self::with_timer(client, limits, AsyncStdTimer)
+	}
+}
+
+impl<P, C, T: Timer> RateLimitedClient<P, C, T> {
+	/// Same as `new`, but delays are awaited (and time is measured) using given timer.
+	pub fn with_timer(client: C, limits: Option<RateLimitParams>, timer: T) -> Self {
+		RateLimitedClient {
+			client,
+			limiter: limits.map(|limits| Arc::new(RateLimiter::new(limits, timer))),
+			_phantom: Default::default(),
+		}
+	}
+
+	/// Wait until limits allow starting new request and then wait for the request.
+	async fn limited_call<R, E>(
+		&self,
+		request: impl Future<Output = Result<R, E>>,
+	) -> Result<R, RateLimitedClientError<E>> {
+		let _permit = match self.limiter {
+			Some(ref limiter) => Some(limiter.acquire().await),
+			None => None,
+		};
+		request.await.map_err(RateLimitedClientError)
+	}
+}
+
+#[async_trait]
+impl<P, C, T> SourceClient<P> for RateLimitedClient<P, C, T>
+where
+	P: MessageLane,
+	C: SourceClient<P>,
+	C::Error: MaybeThrottledError,
+	T: Timer,
+{
+	type Error = RateLimitedClientError<C::Error>;
+	type TransactionTracker = C::TransactionTracker;
+
+	async fn reconnect(self) -> Result<Self, Self::Error> {
+		let client = self.limited_call(self.client.clone().reconnect()).await?;
+		Ok(RateLimitedClient {
+			client,
+			limiter: self.limiter,
+			_phantom: Default::default(),
+		})
+	}
+
+	async fn state(&self) -> Result<SourceClientState<P>, Self::Error> {
+		self.limited_call(self.client.state()).await
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.limited_call(self.client.runtime_version()).await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
+	async fn latest_generated_nonce(
+		&self,
+		id: SourceHeaderIdOf<P>,
+	) -> Result<(SourceHeaderIdOf<P>, MessageNonce), Self::Error> {
+		self.limited_call(self.client.latest_generated_nonce(id)).await
+	}
+
+	async fn latest_confirmed_received_nonce(
+		&self,
+		id: SourceHeaderIdOf<P>,
+	) -> Result<(SourceHeaderIdOf<P>, MessageNonce), Self::Error> {
+		self.limited_call(self.client.latest_confirmed_received_nonce(id)).await
+	}
+
+	async fn generated_messages_details(
+		&self,
+		id: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+	) -> Result<MessageDetailsMap<P::SourceChainBalance>, Self::Error> {
+		self.limited_call(self.client.generated_messages_details(id, nonces))
+			.await
+	}
+
+	async fn prove_messages(
+		&self,
+		id: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: MessageProofParameters,
+	) -> Result<(SourceHeaderIdOf<P>, RangeInclusive<MessageNonce>, P::MessagesProof), Self::Error> {
+		self.limited_call(self.client.prove_messages(id, nonces, proof_parameters))
+			.await
+	}
+
+	async fn estimate_messages_proof_size(
+		&self,
+		id: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: MessageProofParameters,
+	) -> Result<u32, Self::Error> {
+		self.limited_call(self.client.estimate_messages_proof_size(id, nonces, proof_parameters))
+			.await
+	}
+
+	async fn submit_messages_receiving_proof(
+		&self,
+		generated_at_block: TargetHeaderIdOf<P>,
+		proof: P::MessagesReceivingProof,
+		options: SubmitOptions,
+	) -> Result<Self::TransactionTracker, Self::Error> {
+		self.limited_call(
+			self.client
+				.submit_messages_receiving_proof(generated_at_block, proof, options),
+		)
+		.await
+	}
+
+	fn require_target_header_on_source(&self, id: TargetHeaderIdOf<P>) {
+		self.client.require_target_header_on_source(id)
+	}
+}
+
+#[async_trait]
+impl<P, C, T> TargetClient<P> for RateLimitedClient<P, C, T>
+where
+	P: MessageLane,
+	C: TargetClient<P>,
+	C::Error: MaybeThrottledError,
+	T: Timer,
+{
+	type Error = RateLimitedClientError<C::Error>;
+	type TransactionTracker = C::TransactionTracker;
+
+	async fn reconnect(self) -> Result<Self, Self::Error> {
+		let client = self.limited_call(self.client.clone().reconnect()).await?;
+		Ok(RateLimitedClient {
+			client,
+			limiter: self.limiter,
+			_phantom: Default::default(),
+		})
+	}
+
+	async fn state(&self) -> Result<TargetClientState<P>, Self::Error> {
+		self.limited_call(self.client.state()).await
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.limited_call(self.client.runtime_version()).await
+	}
+
+	fn on_runtime_upgrade(&self) {
+		self.client.on_runtime_upgrade()
+	}
+
+	async fn lane_parameters(&self) -> Result<TargetLaneParameters, Self::Error> {
+		self.limited_call(self.client.lane_parameters()).await
+	}
+
+	async fn latest_received_nonce(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, MessageNonce), Self::Error> {
+		self.limited_call(self.client.latest_received_nonce(id)).await
+	}
+
+	async fn latest_confirmed_received_nonce(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, MessageNonce), Self::Error> {
+		self.limited_call(self.client.latest_confirmed_received_nonce(id)).await
+	}
+
+	async fn unrewarded_relayers_state(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, UnrewardedRelayersState), Self::Error> {
+		self.limited_call(self.client.unrewarded_relayers_state(id)).await
+	}
+
+	async fn prove_messages_receiving(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, P::MessagesReceivingProof), Self::Error> {
+		self.limited_call(self.client.prove_messages_receiving(id)).await
+	}
+
+	async fn verify_messages_proof(
+		&self,
+		generated_at_header: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<(), Self::Error> {
+		self.limited_call(self.client.verify_messages_proof(generated_at_header, nonces, proof))
+			.await
+	}
+
+	async fn dry_run_messages_proof(
+		&self,
+		generated_at_header: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		self.limited_call(self.client.dry_run_messages_proof(generated_at_header, nonces, proof))
+			.await
+	}
+
+	async fn required_source_header_at_target(
+		&self,
+		current_best: SourceHeaderIdOf<P>,
+	) -> Result<Option<SourceHeaderIdOf<P>>, Self::Error> {
+		self.limited_call(self.client.required_source_header_at_target(current_best))
+			.await
+	}
+
+	async fn submit_messages_proof(
+		&self,
+		generated_at_header: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: P::MessagesProof,
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		self.limited_call(
+			self.client
+				.submit_messages_proof(generated_at_header, nonces, proof, options),
+		)
+		.await
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<P::SourceChainBalance, Self::Error> {
+		self.limited_call(
+			self.client
+				.estimate_delivery_cost(nonces, total_dispatch_weight, total_size),
+		)
+		.await
+	}
+
+	fn require_source_header_on_target(&self, id: SourceHeaderIdOf<P>) {
+		self.client.require_source_header_on_target(id)
+	}
+}
+
+/// Requests limiter: token bucket that limits rate of requests and semaphore that limits number
+/// of concurrent requests.
+struct RateLimiter<T> {
+	timer: T,
+	/// Capacity of the token bucket.
+	max_tokens: u32,
+	/// Interval between tokens that are added to the bucket.
+	token_interval: Duration,
+	max_concurrent_requests: usize,
+	state: Mutex<RateLimiterState>,
+}
+
+/// Mutable state of the requests limiter.
+struct RateLimiterState {
+	/// Number of requests that may be started without waiting.
+	tokens: u32,
+	/// Time when the last token has been added to the bucket.
+	refilled_at: Instant,
+	/// Number of requests that are currently in progress.
+	active_requests: usize,
+	/// Requests that are waiting for active requests to complete.
+	waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Permit to have single active request. The request slot is released when permit is dropped.
+struct RateLimiterPermit<T> {
+	limiter: Arc<RateLimiter<T>>,
+}
+
+impl<T: Timer> RateLimiter<T> {
+	/// Create new limiter. Zero limits would block all requests forever, so they're treated as 1.
+	fn new(limits: RateLimitParams, timer: T) -> Self {
+		let max_tokens = std::cmp::max(limits.max_requests_per_second, 1);
+		RateLimiter {
+			max_tokens,
+			token_interval: std::cmp::max(Duration::from_secs(1) / max_tokens, Duration::from_nanos(1)),
+			max_concurrent_requests: std::cmp::max(limits.max_concurrent_requests, 1),
+			state: Mutex::new(RateLimiterState {
+				tokens: max_tokens,
+				refilled_at: timer.now(),
+				active_requests: 0,
+				waiters: Vec::new(),
+			}),
+			timer,
+		}
+	}
+
+	/// Wait until new request may be started.
+	async fn acquire(self: &Arc<Self>) -> RateLimiterPermit<T> {
+		loop {
+			let wait = {
+				let mut state = self.state.lock();
+				if state.active_requests >= self.max_concurrent_requests {
+					let (sender, receiver) = oneshot::channel();
+					state.waiters.push(sender);
+					receiver.map(|_| ()).left_future()
+				} else {
+					self.refill(&mut state);
+					if state.tokens != 0 {
+						state.tokens -= 1;
+						state.active_requests += 1;
+						return RateLimiterPermit { limiter: self.clone() };
+					}
+
+					let next_token_at = state.refilled_at + self.token_interval;
+					self.timer
+						.sleep(next_token_at.saturating_duration_since(self.timer.now()))
+						.right_future()
+				}
+			};
+			wait.await;
+		}
+	}
+
+	/// Add tokens that have been generated since last refill to the bucket.
+	fn refill(&self, state: &mut RateLimiterState) {
+		let now = self.timer.now();
+		let new_tokens = now.saturating_duration_since(state.refilled_at).as_nanos() / self.token_interval.as_nanos();
+		if new_tokens == 0 {
+			return;
+		}
+
+		if state.tokens as u128 + new_tokens >= self.max_tokens as u128 {
+			state.tokens = self.max_tokens;
+			state.refilled_at = now;
+		} else {
+			state.tokens += new_tokens as u32;
+			state.refilled_at += self.token_interval * new_tokens as u32;
+		}
+	}
+}
+
+impl<T> Drop for RateLimiterPermit<T> {
+	fn drop(&mut self) {
+		let mut state = self.limiter.state.lock();
+		state.active_requests -= 1;
+		// all waiters are woken up and compete for the released slot again. Some of them may be
+		// already cancelled, so it is simpler than choosing the single waiter
+		for waiter in state.waiters.drain(..) {
+			let _ = waiter.send(());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::testing::{TestClientData, TestError, TestSourceClient};
+	use futures::{
+		executor::LocalPool,
+		future::{join_all, BoxFuture},
+		task::LocalSpawnExt,
+	};
+	use relay_utils::MaybeConnectionError;
+
+	/// Timer that only advances when the test asks it to.
+	#[derive(Clone)]
+	struct VirtualTimer(Arc<Mutex<VirtualTimerState>>);
+
+	struct VirtualTimerState {
+		now: Instant,
+		sleepers: Vec<(Instant, oneshot::Sender<()>)>,
+	}
+
+	impl VirtualTimer {
+		fn new() -> Self {
+			VirtualTimer(Arc::new(Mutex::new(VirtualTimerState {
+				now: Instant::now(),
+				sleepers: Vec::new(),
+			})))
+		}
+
+		fn advance(&self, delay: Duration) {
+			let mut state = self.0.lock();
+			state.now += delay;
+			let now = state.now;
+			let (ready, pending) = state.sleepers.drain(..).partition(|(wake_at, _)| *wake_at <= now);
+			state.sleepers = pending;
+			for (_, sleeper) in ready {
+				let _ = sleeper.send(());
+			}
+		}
+	}
+
+	impl Timer for VirtualTimer {
+		fn now(&self) -> Instant {
+			self.0.lock().now
+		}
+
+		fn sleep(&self, delay: Duration) -> BoxFuture<'static, ()> {
+			let mut state = self.0.lock();
+			let (sender, receiver) = oneshot::channel();
+			if delay == Duration::from_secs(0) {
+				let _ = sender.send(());
+			} else {
+				let wake_at = state.now + delay;
+				state.sleepers.push((wake_at, sender));
+			}
+			receiver.map(|_| ()).boxed()
+		}
+	}
+
+	fn test_source_client(data: TestClientData) -> TestSourceClient {
+		TestSourceClient::new(Arc::new(Mutex::new(data)), Arc::new(|_: &mut TestClientData| {}))
+	}
+
+	#[test]
+	fn rate_limited_client_spreads_burst_of_requests() {
+		let timer = VirtualTimer::new();
+		let client = RateLimitedClient::with_timer(
+			test_source_client(TestClientData::default()),
+			Some(RateLimitParams {
+				max_requests_per_second: 2,
+				max_concurrent_requests: 10,
+			}),
+			timer.clone(),
+		);
+
+		let started_at = timer.now();
+		let completed_at = Arc::new(Mutex::new(Vec::new()));
+		let mut pool = LocalPool::new();
+		for _ in 0..6 {
+			let client = client.clone();
+			let timer = timer.clone();
+			let completed_at = completed_at.clone();
+			pool.spawner()
+				.spawn_local(async move {
+					SourceClient::state(&client).await.unwrap();
+					completed_at.lock().push(timer.now() - started_at);
+				})
+				.unwrap();
+		}
+
+		pool.run_until_stalled();
+		for _ in 0..4 {
+			timer.advance(Duration::from_millis(500));
+			pool.run_until_stalled();
+		}
+
+		// two requests are started at once (the bucket is full) and then requests are started
+		// every 500ms
+		assert_eq!(
+			*completed_at.lock(),
+			vec![0, 0, 500, 1000, 1500, 2000]
+				.into_iter()
+				.map(Duration::from_millis)
+				.collect::<Vec<_>>(),
+		);
+	}
+
+	#[test]
+	fn rate_limited_client_limits_concurrent_requests() {
+		let client = RateLimitedClient::new(
+			test_source_client(TestClientData {
+				source_latency: Duration::from_millis(50),
+				..Default::default()
+			}),
+			Some(RateLimitParams {
+				max_requests_per_second: 1000,
+				max_concurrent_requests: 1,
+			}),
+		);
+
+		// without the limit, all requests would complete in ~50ms
+		let started_at = Instant::now();
+		let results = async_std::task::block_on(join_all((0..3).map(|_| SourceClient::state(&client))));
+		assert!(results.iter().all(|result| result.is_ok()));
+		assert!(started_at.elapsed() >= Duration::from_millis(150));
+	}
+
+	#[test]
+	fn rate_limited_client_treats_throttled_requests_as_connection_errors() {
+		#[derive(Debug)]
+		struct ThrottledError(bool);
+
+		impl MaybeConnectionError for ThrottledError {
+			fn is_connection_error(&self) -> bool {
+				false
+			}
+		}
+
+		impl MaybeThrottledError for ThrottledError {
+			fn is_throttled(&self) -> bool {
+				self.0
+			}
+		}
+
+		assert_eq!(
+			RateLimitedClientError(ThrottledError(true)).classification(),
+			ErrorClassification::Reconnect,
+		);
+		assert_eq!(
+			RateLimitedClientError(ThrottledError(false)).classification(),
+			ErrorClassification::RetryWithBackoff,
+		);
+		assert_eq!(
+			RateLimitedClientError(TestError).classification(),
+			ErrorClassification::Reconnect,
+		);
+	}
+}
@@ -0,0 +1,589 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Bridges Common.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mock message lane clients and helpers for testing the message lane loop, clients wrappers
+//! and delivery strategies. Clients behavior is driven by the shared `TestClientData` and by
+//! tick functions that are called before serving requests.
+
+use crate::message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf};
+use crate::message_lane_loop::{
+	run, BlockRef, DeliveryLimits, DryRunOutcome, MessageDeliveryParams, MessageDetails, MessageDetailsMap,
+	MessageProofParameters, Params, SourceClient, SourceClientState, SubmitOptions, SubmitPolicy, TargetClient,
+	TargetClientState, TargetLaneParameters, TrackedTransactionStatus, TransactionTracker,
+};
+
+use async_trait::async_trait;
+use bp_message_lane::{MessageNonce, UnrewardedRelayersState, Weight};
+use parking_lot::Mutex;
+use relay_utils::{
+	HeaderId, MaybeConnectionError, MaybeFatalError, MaybeRejectedTransactionError, MaybeThrottledError,
+};
+use std::{collections::VecDeque, future::Future, ops::RangeInclusive, sync::Arc, time::Duration};
+
+/// Returns test header id with given number. Hash of the header is equal to its number.
+pub fn header_id(number: TestSourceHeaderNumber) -> TestSourceHeaderId {
+	HeaderId(number, number)
+}
+
+/// Source header id of the test lane.
+pub type TestSourceHeaderId = HeaderId<TestSourceHeaderNumber, TestSourceHeaderHash>;
+/// Target header id of the test lane.
+pub type TestTargetHeaderId = HeaderId<TestTargetHeaderNumber, TestTargetHeaderHash>;
+
+/// Messages proof of the test lane: proved nonces and optional outbound lane state (latest
+/// confirmed nonce).
+pub type TestMessagesProof = (RangeInclusive<MessageNonce>, Option<MessageNonce>);
+/// Messages receiving proof of the test lane: latest received nonce.
+pub type TestMessagesReceivingProof = MessageNonce;
+
+/// Source header number of the test lane.
+pub type TestSourceHeaderNumber = u64;
+/// Source header hash of the test lane.
+pub type TestSourceHeaderHash = u64;
+
+/// Target header number of the test lane.
+pub type TestTargetHeaderNumber = u64;
+/// Target header hash of the test lane.
+pub type TestTargetHeaderHash = u64;
+
+/// Source chain balance of the test lane.
+pub type TestSourceChainBalance = u64;
+
+/// Function that is called by the test client before serving most of requests. It may change
+/// clients data, e.g. to produce new headers or to inject failures.
+pub type TestClientTick = Arc<dyn Fn(&mut TestClientData) + Send + Sync>;
+
+/// Error of the test clients. It is always a connection error.
+#[derive(Debug)]
+pub struct TestError;
+
+impl MaybeConnectionError for TestError {
+	fn is_connection_error(&self) -> bool {
+		true
+	}
+}
+
+impl MaybeFatalError for TestError {}
+
+impl MaybeThrottledError for TestError {}
+
+impl MaybeRejectedTransactionError for TestError {
+	fn is_rejected_transaction(&self) -> bool {
+		false
+	}
+}
+
+/// Tracker of the transaction, submitted by the test client. The status is known in advance.
+#[derive(Debug)]
+pub struct TestTransactionTracker(pub TrackedTransactionStatus);
+
+#[async_trait]
+impl TransactionTracker for TestTransactionTracker {
+	async fn wait(self) -> TrackedTransactionStatus {
+		self.0
+	}
+}
+
+/// Test message lane.
+#[derive(Clone)]
+pub struct TestMessageLane;
+
+impl MessageLane for TestMessageLane {
+	const SOURCE_NAME: &'static str = "TestSource";
+	const TARGET_NAME: &'static str = "TestTarget";
+
+	type MessagesProof = TestMessagesProof;
+	type MessagesReceivingProof = TestMessagesReceivingProof;
+
+	type SourceHeaderNumber = TestSourceHeaderNumber;
+	type SourceHeaderHash = TestSourceHeaderHash;
+
+	type TargetHeaderNumber = TestTargetHeaderNumber;
+	type TargetHeaderHash = TestTargetHeaderHash;
+
+	type SourceChainBalance = TestSourceChainBalance;
+
+	fn messages_proof_size(proof: &TestMessagesProof) -> usize {
+		(proof.0.end() + 1 - proof.0.start()) as usize
+	}
+
+	fn messages_receiving_proof_size(_proof: &TestMessagesReceivingProof) -> usize {
+		1
+	}
+}
+
+/// Data of both test clients. It is shared by the clients, so they are always consistent.
+#[derive(Debug, Default, Clone)]
+pub struct TestClientData {
+	/// If true, source state and nonces requests are failing.
+	pub is_source_fails: bool,
+	/// Set to true when the source client is reconnected.
+	pub is_source_reconnected: bool,
+	/// Delay of every source client response.
+	pub source_latency: Duration,
+	/// Number of next source client requests that are failing.
+	pub source_failures: usize,
+	/// State of the source client.
+	pub source_state: SourceClientState<TestMessageLane>,
+	/// Latest generated nonce at the source node.
+	pub source_latest_generated_nonce: MessageNonce,
+	/// Scripted progression of the latest generated nonce. Every latest generated nonce request
+	/// takes the next nonce from this queue, if it isn't empty.
+	pub source_generated_nonces: VecDeque<MessageNonce>,
+	/// Latest confirmed received nonce at the source node.
+	pub source_latest_confirmed_received_nonce: MessageNonce,
+	/// All messages receiving proofs, submitted to the source node.
+	pub submitted_messages_receiving_proofs: Vec<TestMessagesReceivingProof>,
+	/// If true, target state, nonces and submission requests are failing.
+	pub is_target_fails: bool,
+	/// Set to true when the target client is reconnected.
+	pub is_target_reconnected: bool,
+	/// Delay of every target client response.
+	pub target_latency: Duration,
+	/// Number of next target client requests that are failing.
+	pub target_failures: usize,
+	/// State of the target client.
+	pub target_state: TargetClientState<TestMessageLane>,
+	/// Latest received nonce at the target node.
+	pub target_latest_received_nonce: MessageNonce,
+	/// Latest confirmed received nonce at the target node.
+	pub target_latest_confirmed_received_nonce: MessageNonce,
+	/// State of unrewarded relayers at the target node.
+	pub target_unrewarded_relayers_state: UnrewardedRelayersState,
+	/// Spec version of the target node runtime.
+	pub target_runtime_version: Option<u32>,
+	/// Parameters of the lane at the target node. If `None`, `TEST_LANE_PARAMETERS` are used.
+	pub target_lane_parameters: Option<TargetLaneParameters>,
+	/// Number of lane parameters requests.
+	pub target_lane_parameters_requests: usize,
+	/// If true, messages proofs submissions are failing.
+	pub is_target_submission_fails: bool,
+	/// All messages proofs, submitted to the target node.
+	pub submitted_messages_proofs: Vec<TestMessagesProof>,
+}
+
+/// Parameters of the lane at the test target node, matching limits of `test_params()`.
+pub const TEST_LANE_PARAMETERS: TargetLaneParameters = TargetLaneParameters {
+	max_messages_in_single_batch: MessageNonce::MAX,
+	max_messages_weight_in_single_batch: 4,
+	max_unconfirmed_messages: 4,
+};
+
+/// Test source client.
+#[derive(Clone)]
+pub struct TestSourceClient {
+	data: Arc<Mutex<TestClientData>>,
+	tick: TestClientTick,
+}
+
+impl TestSourceClient {
+	/// Create source client that is using given data and tick function.
+	pub fn new(data: Arc<Mutex<TestClientData>>, tick: TestClientTick) -> Self {
+		TestSourceClient { data, tick }
+	}
+
+	/// Wait until the response is ready and return injected failure, if any.
+	async fn begin_request(&self) -> Result<(), TestError> {
+		let latency = self.data.lock().source_latency;
+		if latency != Duration::from_secs(0) {
+			async_std::task::sleep(latency).await;
+		}
+
+		let mut data = self.data.lock();
+		if data.source_failures != 0 {
+			data.source_failures -= 1;
+			return Err(TestError);
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl SourceClient<TestMessageLane> for TestSourceClient {
+	type Error = TestError;
+	type TransactionTracker = TestTransactionTracker;
+
+	async fn reconnect(self) -> Result<Self, Self::Error> {
+		{
+			let mut data = self.data.lock();
+			(self.tick)(&mut data);
+			data.is_source_reconnected = true;
+		}
+		Ok(self)
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.begin_request().await?;
+		Ok(None)
+	}
+
+	async fn state(&self) -> Result<SourceClientState<TestMessageLane>, Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_source_fails {
+			return Err(TestError);
+		}
+		Ok(data.source_state.clone())
+	}
+
+	async fn latest_generated_nonce(
+		&self,
+		id: SourceHeaderIdOf<TestMessageLane>,
+	) -> Result<(SourceHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_source_fails {
+			return Err(TestError);
+		}
+		if let Some(source_latest_generated_nonce) = data.source_generated_nonces.pop_front() {
+			data.source_latest_generated_nonce = source_latest_generated_nonce;
+		}
+		Ok((id, data.source_latest_generated_nonce))
+	}
+
+	async fn latest_confirmed_received_nonce(
+		&self,
+		id: SourceHeaderIdOf<TestMessageLane>,
+	) -> Result<(SourceHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		Ok((id, data.source_latest_confirmed_received_nonce))
+	}
+
+	async fn generated_messages_details(
+		&self,
+		_id: SourceHeaderIdOf<TestMessageLane>,
+		nonces: RangeInclusive<MessageNonce>,
+	) -> Result<MessageDetailsMap<TestSourceChainBalance>, Self::Error> {
+		self.begin_request().await?;
+		Ok(nonces
+			.map(|nonce| {
+				(
+					nonce,
+					MessageDetails {
+						dispatch_weight: 1,
+						size: 1,
+						reward: 1,
+					},
+				)
+			})
+			.collect())
+	}
+
+	async fn prove_messages(
+		&self,
+		id: SourceHeaderIdOf<TestMessageLane>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof_parameters: MessageProofParameters,
+	) -> Result<
+		(
+			SourceHeaderIdOf<TestMessageLane>,
+			RangeInclusive<MessageNonce>,
+			TestMessagesProof,
+		),
+		Self::Error,
+	> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		Ok((
+			id,
+			nonces.clone(),
+			(
+				nonces,
+				if proof_parameters.outbound_state_proof_required {
+					Some(data.source_latest_confirmed_received_nonce)
+				} else {
+					None
+				},
+			),
+		))
+	}
+
+	async fn submit_messages_receiving_proof(
+		&self,
+		_generated_at_block: TargetHeaderIdOf<TestMessageLane>,
+		proof: TestMessagesReceivingProof,
+		_options: SubmitOptions,
+	) -> Result<TestTransactionTracker, Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		data.submitted_messages_receiving_proofs.push(proof);
+		data.source_latest_confirmed_received_nonce = proof;
+		Ok(TestTransactionTracker(TrackedTransactionStatus::Finalized))
+	}
+}
+
+/// Test target client.
+#[derive(Clone)]
+pub struct TestTargetClient {
+	data: Arc<Mutex<TestClientData>>,
+	tick: TestClientTick,
+}
+
+impl TestTargetClient {
+	/// Create target client that is using given data and tick function.
+	pub fn new(data: Arc<Mutex<TestClientData>>, tick: TestClientTick) -> Self {
+		TestTargetClient { data, tick }
+	}
+
+	/// Wait until the response is ready and return injected failure, if any.
+	async fn begin_request(&self) -> Result<(), TestError> {
+		let latency = self.data.lock().target_latency;
+		if latency != Duration::from_secs(0) {
+			async_std::task::sleep(latency).await;
+		}
+
+		let mut data = self.data.lock();
+		if data.target_failures != 0 {
+			data.target_failures -= 1;
+			return Err(TestError);
+		}
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl TargetClient<TestMessageLane> for TestTargetClient {
+	type Error = TestError;
+	type TransactionTracker = TestTransactionTracker;
+
+	async fn reconnect(self) -> Result<Self, Self::Error> {
+		{
+			let mut data = self.data.lock();
+			(self.tick)(&mut data);
+			data.is_target_reconnected = true;
+		}
+		Ok(self)
+	}
+
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		self.begin_request().await?;
+		Ok(self.data.lock().target_runtime_version)
+	}
+
+	async fn lane_parameters(&self) -> Result<TargetLaneParameters, Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		data.target_lane_parameters_requests += 1;
+		Ok(data.target_lane_parameters.unwrap_or(TEST_LANE_PARAMETERS))
+	}
+
+	async fn state(&self) -> Result<TargetClientState<TestMessageLane>, Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_target_fails {
+			return Err(TestError);
+		}
+		Ok(data.target_state.clone())
+	}
+
+	async fn latest_received_nonce(
+		&self,
+		id: TargetHeaderIdOf<TestMessageLane>,
+	) -> Result<(TargetHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_target_fails {
+			return Err(TestError);
+		}
+		Ok((id, data.target_latest_received_nonce))
+	}
+
+	async fn latest_confirmed_received_nonce(
+		&self,
+		id: TargetHeaderIdOf<TestMessageLane>,
+	) -> Result<(TargetHeaderIdOf<TestMessageLane>, MessageNonce), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_target_fails {
+			return Err(TestError);
+		}
+		Ok((id, data.target_latest_confirmed_received_nonce))
+	}
+
+	async fn unrewarded_relayers_state(
+		&self,
+		id: TargetHeaderIdOf<TestMessageLane>,
+	) -> Result<(TargetHeaderIdOf<TestMessageLane>, UnrewardedRelayersState), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_target_fails {
+			return Err(TestError);
+		}
+		Ok((id, data.target_unrewarded_relayers_state.clone()))
+	}
+
+	async fn prove_messages_receiving(
+		&self,
+		id: TargetHeaderIdOf<TestMessageLane>,
+	) -> Result<(TargetHeaderIdOf<TestMessageLane>, TestMessagesReceivingProof), Self::Error> {
+		self.begin_request().await?;
+		Ok((id, self.data.lock().target_latest_received_nonce))
+	}
+
+	async fn verify_messages_proof(
+		&self,
+		_generated_at_header: SourceHeaderIdOf<TestMessageLane>,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &TestMessagesProof,
+	) -> Result<(), Self::Error> {
+		self.begin_request().await?;
+		Ok(())
+	}
+
+	async fn dry_run_messages_proof(
+		&self,
+		_generated_at_header: SourceHeaderIdOf<TestMessageLane>,
+		_nonces: RangeInclusive<MessageNonce>,
+		_proof: &TestMessagesProof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		self.begin_request().await?;
+		Ok(DryRunOutcome::Success)
+	}
+
+	async fn required_source_header_at_target(
+		&self,
+		_current_best: SourceHeaderIdOf<TestMessageLane>,
+	) -> Result<Option<SourceHeaderIdOf<TestMessageLane>>, Self::Error> {
+		self.begin_request().await?;
+		Ok(None)
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		_nonces: RangeInclusive<MessageNonce>,
+		_total_dispatch_weight: Weight,
+		_total_size: u32,
+	) -> Result<TestSourceChainBalance, Self::Error> {
+		self.begin_request().await?;
+		Ok(0)
+	}
+
+	async fn submit_messages_proof(
+		&self,
+		_generated_at_header: SourceHeaderIdOf<TestMessageLane>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: TestMessagesProof,
+		_options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, TestTransactionTracker), Self::Error> {
+		self.begin_request().await?;
+		let mut data = self.data.lock();
+		(self.tick)(&mut data);
+		if data.is_target_fails || data.is_target_submission_fails {
+			return Err(TestError);
+		}
+		data.target_state.best_self = HeaderId(data.target_state.best_self.0 + 1, data.target_state.best_self.1 + 1);
+		data.target_latest_received_nonce = *proof.0.end();
+		if let Some(target_latest_confirmed_received_nonce) = proof.1 {
+			data.target_latest_confirmed_received_nonce = target_latest_confirmed_received_nonce;
+		}
+		data.submitted_messages_proofs.push(proof);
+		Ok((nonces, TestTransactionTracker(TrackedTransactionStatus::Finalized)))
+	}
+}
+
+/// Run message lane loop (i.e. both races) with test clients until the exit signal is received.
+/// Returns final data of test clients.
+pub fn run_race_scenario(
+	params: Params<TestSourceChainBalance>,
+	data: TestClientData,
+	source_tick: TestClientTick,
+	target_tick: TestClientTick,
+	exit_signal: impl Future<Output = ()>,
+) -> TestClientData {
+	async_std::task::block_on(async {
+		let data = Arc::new(Mutex::new(data));
+
+		let source_client = TestSourceClient::new(data.clone(), source_tick);
+		let target_client = TestTargetClient::new(data.clone(), target_tick);
+		run(params, source_client, target_client, None, exit_signal);
+		let result = data.lock().clone();
+		result
+	})
+}
+
+/// Returns message lane loop parameters that are suitable for tests: short ticks, no timeouts
+/// and small delivery limits.
+pub fn test_params() -> Params<TestSourceChainBalance> {
+	Params {
+		lane: [0, 0, 0, 0],
+		source_tick: Duration::from_millis(100),
+		target_tick: Duration::from_millis(100),
+		reconnect_delay: Duration::from_millis(0),
+		source_stall_timeout: Duration::from_millis(60 * 1000),
+		target_stall_timeout: Duration::from_millis(60 * 1000),
+		resubmit_timeout: Duration::from_millis(60 * 1000),
+		source_retry_backoff: Default::default(),
+		target_retry_backoff: Default::default(),
+		dry_run: false,
+		final_nonce: None,
+		nonces_refresh_interval: None,
+		delivery_race_state_notifier: None,
+		receiving_race_state_notifier: None,
+		verify_messages_proofs: false,
+		dry_run_messages_before_submit: false,
+		max_in_flight_submissions: 1,
+		max_proofs_per_batch: 1,
+		reselect_on_newer_target_state: false,
+		reselect_threshold: 0,
+		max_queued_nonces: None,
+		competition_delay: None,
+		proof_generation_timeout: None,
+		proof_size_limit: None,
+		rpc_timeout: Duration::from_secs(60),
+		nonces_at_target_known_header: false,
+		delivery_source_nonces_at: BlockRef::Best,
+		receiving_source_nonces_at: BlockRef::Finalized,
+		source_confirmation_depth: 0,
+		proof_at_queued_header: false,
+		max_source_header_lag: None,
+		max_consecutive_failures: None,
+		race_reconnect_attempts: 0,
+		source_rate_limit: None,
+		target_rate_limit: None,
+		delivery_condition: None,
+		balance_guard: None,
+		delivery_params: MessageDeliveryParams {
+			max_unconfirmed_nonces_at_target: 4,
+			max_unrewarded_relayer_entries_at_target: 4,
+			limits: DeliveryLimits {
+				max_nonces: None,
+				max_weight: 4,
+				max_size: 1024 * 1024,
+			},
+			redeliver_after_target_rollback: false,
+			batching: None,
+			max_messages_per_minute: None,
+			profitability: None,
+			target_cost_profitability: None,
+			batch_optimizer: None,
+			nonce_filter: None,
+			initial_target_nonce: None,
+			submit_policy: SubmitPolicy::default(),
+		},
+		lazy_confirmations: None,
+	}
+}
@@ -18,7 +18,10 @@
 
 use codec::Encode;
 use headers_relay::sync_types::SourceHeader;
-use relay_substrate_client::{Chain, ChainBase, ChainWithBalances, Client, TransactionSignScheme};
+use relay_substrate_client::{
+	transaction_tip, Chain, ChainBase, ChainWithBalances, Client, Error as SubstrateError, TransactionEraOf,
+	TransactionSignScheme,
+};
 use sp_core::{storage::StorageKey, Pair};
 use sp_runtime::{
 	generic::SignedPayload,
@@ -68,28 +71,31 @@ impl TransactionSignScheme for Millau {
 	type AccountKeyPair = sp_core::sr25519::Pair;
 	type SignedTransaction = millau_runtime::UncheckedExtrinsic;
 
-	fn sign_transaction(
+	fn sign_transaction_with_options(
 		client: &Client<Self>,
 		signer: &Self::AccountKeyPair,
 		signer_nonce: <Self::Chain as Chain>::Index,
 		call: <Self::Chain as Chain>::Call,
-	) -> Self::SignedTransaction {
+		tip: u128,
+		era: TransactionEraOf<Self>,
+	) -> Result<Self::SignedTransaction, SubstrateError> {
+		let tip = transaction_tip(tip)?;
 		let raw_payload = SignedPayload::from_raw(
 			call,
 			(
 				frame_system::CheckSpecVersion::<millau_runtime::Runtime>::new(),
 				frame_system::CheckTxVersion::<millau_runtime::Runtime>::new(),
 				frame_system::CheckGenesis::<millau_runtime::Runtime>::new(),
-				frame_system::CheckEra::<millau_runtime::Runtime>::from(sp_runtime::generic::Era::Immortal),
+				frame_system::CheckEra::<millau_runtime::Runtime>::from(era.frame_era()),
 				frame_system::CheckNonce::<millau_runtime::Runtime>::from(signer_nonce),
 				frame_system::CheckWeight::<millau_runtime::Runtime>::new(),
-				pallet_transaction_payment::ChargeTransactionPayment::<millau_runtime::Runtime>::from(0),
+				pallet_transaction_payment::ChargeTransactionPayment::<millau_runtime::Runtime>::from(tip),
 			),
 			(
 				millau_runtime::VERSION.spec_version,
 				millau_runtime::VERSION.transaction_version,
 				*client.genesis_hash(),
-				*client.genesis_hash(),
+				era.signed_payload(*client.genesis_hash()),
 				(),
 				(),
 				(),
@@ -99,7 +105,12 @@ impl TransactionSignScheme for Millau {
 		let signer: sp_runtime::MultiSigner = signer.public().into();
 		let (call, extra, _) = raw_payload.deconstruct();
 
-		millau_runtime::UncheckedExtrinsic::new_signed(call, signer.into_account(), signature.into(), extra)
+		Ok(millau_runtime::UncheckedExtrinsic::new_signed(
+			call,
+			signer.into_account(),
+			signature.into(),
+			extra,
+		))
 	}
 }
 
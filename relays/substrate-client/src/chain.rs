@@ -15,18 +15,23 @@
 // along with Parity Bridges Common.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::client::Client;
+use crate::error::{Error, Result};
 
-use bp_runtime::Chain as ChainBase;
+use bp_runtime::{BlockNumberOf, Chain as ChainBase, HashOf};
 use frame_support::Parameter;
 use jsonrpsee::common::{DeserializeOwned, Serialize};
 use num_traits::{CheckedSub, Zero};
+use relay_utils::HeaderId;
 use sp_core::{storage::StorageKey, Pair};
 use sp_runtime::{
 	generic::SignedBlock,
-	traits::{AtLeast32Bit, Dispatchable, MaybeDisplay, MaybeSerialize, MaybeSerializeDeserialize, Member},
-	Justification,
+	traits::{
+		AtLeast32Bit, AtLeast32BitUnsigned, Dispatchable, MaybeDisplay, MaybeSerialize, MaybeSerializeDeserialize,
+		Member,
+	},
+	Justification, SaturatedConversion,
 };
-use std::{fmt::Debug, time::Duration};
+use std::{convert::TryFrom, fmt::Debug, time::Duration};
 
 /// Substrate-based chain from minimal relay-client point of view.
 pub trait Chain: ChainBase {
@@ -82,13 +87,75 @@ pub trait TransactionSignScheme {
 	/// Signed transaction.
 	type SignedTransaction;
 
-	/// Create transaction for given runtime call, signed by given account.
+	/// Create immortal transaction for given runtime call, signed by given account.
 	fn sign_transaction(
 		client: &Client<Self::Chain>,
 		signer: &Self::AccountKeyPair,
 		signer_nonce: <Self::Chain as Chain>::Index,
 		call: <Self::Chain as Chain>::Call,
-	) -> Self::SignedTransaction;
+	) -> Self::SignedTransaction {
+		Self::sign_transaction_with_options(client, signer, signer_nonce, call, 0, TransactionEra::Immortal)
+			.expect("zero tip fits into any balance type; qed")
+	}
+
+	/// Create transaction for given runtime call, signed by given account. The transaction pays
+	/// given tip (in chain base units) to the block author and is only valid within given era.
+	///
+	/// Fails if the tip doesn't fit into the chain balance type.
+	fn sign_transaction_with_options(
+		client: &Client<Self::Chain>,
+		signer: &Self::AccountKeyPair,
+		signer_nonce: <Self::Chain as Chain>::Index,
+		call: <Self::Chain as Chain>::Call,
+		tip: u128,
+		era: TransactionEraOf<Self::Chain>,
+	) -> Result<Self::SignedTransaction>;
+}
+
+/// Convert transaction tip to the chain balance type.
+pub fn transaction_tip<Balance: TryFrom<u128>>(tip: u128) -> Result<Balance> {
+	Balance::try_from(tip).map_err(|_| Error::Custom(format!("Transaction tip {} is too large", tip)))
+}
+
+/// Era of the transaction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransactionEra<BlockNumber, BlockHash> {
+	/// Transaction is valid forever.
+	Immortal,
+	/// Transaction is valid for given number of blocks, starting from given header.
+	Mortal(HeaderId<BlockHash, BlockNumber>, u32),
+}
+
+/// Era of the chain transaction.
+pub type TransactionEraOf<C> = TransactionEra<BlockNumberOf<C>, HashOf<C>>;
+
+impl<BlockNumber: AtLeast32BitUnsigned + Copy, BlockHash: Copy> TransactionEra<BlockNumber, BlockHash> {
+	/// Create era of the transaction that is valid for `mortality` blocks (or forever, if `None`),
+	/// starting from given header.
+	pub fn new(best_header: HeaderId<BlockHash, BlockNumber>, mortality: Option<u32>) -> Self {
+		match mortality {
+			Some(period) => TransactionEra::Mortal(best_header, period),
+			None => TransactionEra::Immortal,
+		}
+	}
+
+	/// Returns era that is checked by the `frame_system::CheckEra` signed extension.
+	pub fn frame_era(&self) -> sp_runtime::generic::Era {
+		match *self {
+			TransactionEra::Immortal => sp_runtime::generic::Era::immortal(),
+			TransactionEra::Mortal(header_id, period) => {
+				sp_runtime::generic::Era::mortal(period as _, header_id.0.saturated_into::<u64>())
+			}
+		}
+	}
+
+	/// Returns header hash that is signed by the `frame_system::CheckEra` signed extension.
+	pub fn signed_payload(&self, genesis_hash: BlockHash) -> BlockHash {
+		match *self {
+			TransactionEra::Immortal => genesis_hash,
+			TransactionEra::Mortal(header_id, _) => header_id.1,
+		}
+	}
 }
 
 impl BlockWithJustification for () {
@@ -21,8 +21,8 @@ use crate::rpc::{Substrate, SubstrateMessageLane};
 use crate::{ConnectionParams, Error, Result};
 
 use bp_message_lane::{LaneId, MessageNonce};
-use bp_runtime::InstanceId;
-use codec::Decode;
+use bp_runtime::{HashOf, InstanceId};
+use codec::{Decode, Encode};
 use frame_system::AccountInfo;
 use jsonrpsee::common::DeserializeOwned;
 use jsonrpsee::raw::RawClient;
@@ -30,16 +30,26 @@ use jsonrpsee::transport::ws::WsTransportClient;
 use jsonrpsee::{client::Subscription, Client as RpcClient};
 use num_traits::Zero;
 use pallet_balances::AccountData;
+use pallet_transaction_payment_rpc_runtime_api::RuntimeDispatchInfo;
 use sp_core::Bytes;
+use sp_runtime::ApplyExtrinsicResult;
+use sp_transaction_pool::TransactionStatus;
 use sp_trie::StorageProof;
 use sp_version::RuntimeVersion;
 use std::ops::RangeInclusive;
 
 const SUB_API_GRANDPA_AUTHORITIES: &str = "GrandpaApi_grandpa_authorities";
+const SUB_API_TRANSACTION_PAYMENT_QUERY_INFO: &str = "TransactionPaymentApi_query_info";
 
 /// Opaque justifications subscription type.
 pub type JustificationsSubscription = Subscription<Bytes>;
 
+/// Status of the transaction, submitted to the chain.
+pub type TransactionStatusOf<C> = TransactionStatus<HashOf<C>, HashOf<C>>;
+
+/// Subscription to status updates of the transaction, submitted to the chain.
+pub type TransactionStatusSubscription<C> = Subscription<TransactionStatusOf<C>>;
+
 /// Opaque GRANDPA authorities set.
 pub type OpaqueGrandpaAuthoritiesSet = Vec<u8>;
 
@@ -188,6 +198,51 @@ impl<C: Chain> Client<C> {
 		Ok(tx_hash)
 	}
 
+	/// Submit an extrinsic for inclusion in a block and subscribe to its status updates.
+	///
+	/// Note: The given transaction does not need be SCALE encoded beforehand.
+	pub async fn submit_and_watch_extrinsic(&self, transaction: Bytes) -> Result<TransactionStatusSubscription<C>> {
+		let transaction = serde_json::to_value(transaction)
+			.map_err(|e| Error::Custom(format!("Failed to serialize transaction: {}", e)))?;
+		let subscription = self
+			.client
+			.subscribe(
+				"author_submitAndWatchExtrinsic",
+				jsonrpsee::common::Params::Array(vec![transaction]),
+				"author_unwatchExtrinsic",
+			)
+			.await?;
+		log::trace!(target: "bridge", "Sent transaction to Substrate node and subscribed to its status");
+		Ok(subscription)
+	}
+
+	/// Dry run an extrinsic at given block (or at the best block, if `None`), without including it
+	/// into the chain.
+	pub async fn dry_run_extrinsic(
+		&self,
+		transaction: Bytes,
+		at_block: Option<C::Hash>,
+	) -> Result<ApplyExtrinsicResult> {
+		let encoded_result = Substrate::<C, _, _>::system_dry_run(&self.client, transaction, at_block).await?;
+		Decode::decode(&mut &encoded_result.0[..]).map_err(Error::ResponseParseFailed)
+	}
+
+	/// Estimate fee that will be paid for given transaction, assuming that its encoded size is
+	/// `transaction_size` bytes. Fee is estimated at the best block.
+	pub async fn estimate_extrinsic_fee(&self, transaction: Bytes, transaction_size: u32) -> Result<C::NativeBalance>
+	where
+		C: ChainWithBalances,
+	{
+		let call = SUB_API_TRANSACTION_PAYMENT_QUERY_INFO.to_string();
+		let data = estimate_extrinsic_fee_call_data(transaction, transaction_size);
+
+		let encoded_response = Substrate::<C, _, _>::state_call(&self.client, call, data, None).await?;
+		let dispatch_info = RuntimeDispatchInfo::<C::NativeBalance>::decode(&mut &encoded_response.0[..])
+			.map_err(Error::ResponseParseFailed)?;
+
+		Ok(dispatch_info.partial_fee)
+	}
+
 	/// Get the GRANDPA authority set at given block.
 	pub async fn grandpa_authorities_set(&self, block: C::Hash) -> Result<OpaqueGrandpaAuthoritiesSet> {
 		let call = SUB_API_GRANDPA_AUTHORITIES.to_string();
@@ -259,3 +314,31 @@ impl<C: Chain> Client<C> {
 			.await?)
 	}
 }
+
+/// Returns encoded arguments of the `TransactionPaymentApi_query_info` runtime call.
+///
+/// The transaction is already SCALE encoded, so it is passed as is. Encoding `Bytes` would prepend
+/// one more length prefix and the runtime would fail to decode the extrinsic.
+fn estimate_extrinsic_fee_call_data(transaction: Bytes, transaction_size: u32) -> Bytes {
+	let mut data = transaction.0;
+	transaction_size.encode_to(&mut data);
+	Bytes(data)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn estimate_extrinsic_fee_call_data_is_decoded_by_runtime() {
+		let extrinsic = vec![42u8; 16];
+		let transaction = Bytes(extrinsic.encode());
+		let transaction_size = transaction.0.len() as u32;
+
+		let data = estimate_extrinsic_fee_call_data(transaction, transaction_size);
+		assert_eq!(
+			<(Vec<u8>, u32)>::decode(&mut &data.0[..]),
+			Ok((extrinsic, transaction_size)),
+		);
+	}
+}
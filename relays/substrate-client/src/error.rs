@@ -18,7 +18,17 @@
 
 use jsonrpsee::client::RequestError;
 use jsonrpsee::transport::ws::WsNewDnsError;
-use relay_utils::MaybeConnectionError;
+use relay_utils::{MaybeConnectionError, MaybeFatalError, MaybeRejectedTransactionError, MaybeThrottledError};
+use sp_runtime::transaction_validity::TransactionValidityError;
+
+/// JSON-RPC error code that Substrate node returns if transaction is invalid.
+const INVALID_TRANSACTION_ERROR_CODE: i64 = 1010;
+/// JSON-RPC error code that Substrate node returns if transaction validity can't be determined.
+const UNKNOWN_TRANSACTION_ERROR_CODE: i64 = 1011;
+/// JSON-RPC error code that is returned if the node doesn't support the method.
+const METHOD_NOT_FOUND_ERROR_CODE: i64 = -32601;
+/// JSON-RPC error code that is returned by public RPC providers when request limit is exceeded.
+const LIMIT_EXCEEDED_ERROR_CODE: i64 = -32005;
 
 /// Result type used by Substrate client.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -36,6 +46,8 @@ pub enum Error {
 	ResponseParseFailed(codec::Error),
 	/// Account does not exist on the chain.
 	AccountDoesNotExist,
+	/// The transaction has been found invalid by the runtime.
+	TransactionInvalid(TransactionValidityError),
 	/// Custom logic error.
 	Custom(String),
 }
@@ -58,6 +70,50 @@ impl MaybeConnectionError for Error {
 	}
 }
 
+impl Error {
+	/// Returns JSON-RPC error code if the node has responded with error.
+	fn rpc_error_code(&self) -> Option<i64> {
+		match *self {
+			Error::Request(RequestError::Request(ref error)) => Some(error.code.code()),
+			_ => None,
+		}
+	}
+}
+
+impl MaybeRejectedTransactionError for Error {
+	fn is_rejected_transaction(&self) -> bool {
+		matches!(*self, Error::TransactionInvalid(_))
+			|| matches!(
+				self.rpc_error_code(),
+				Some(INVALID_TRANSACTION_ERROR_CODE) | Some(UNKNOWN_TRANSACTION_ERROR_CODE)
+			)
+	}
+}
+
+impl MaybeFatalError for Error {
+	fn is_fatal(&self) -> bool {
+		match *self {
+			// the response is undecodable if the node runtime is incompatible with the relay, so
+			// the same response will be received again
+			Error::ResponseParseFailed(_) => true,
+			// the node doesn't support the RPC method
+			Error::Request(_) => self.rpc_error_code() == Some(METHOD_NOT_FOUND_ERROR_CODE),
+			Error::WsConnectionError(_)
+			| Error::AccountDoesNotExist
+			| Error::TransactionInvalid(_)
+			| Error::Custom(_) => false,
+		}
+	}
+}
+
+impl MaybeThrottledError for Error {
+	fn is_throttled(&self) -> bool {
+		// public RPC providers are responding with JSON-RPC `limit exceeded` error when they're
+		// throttling requests
+		self.rpc_error_code() == Some(LIMIT_EXCEEDED_ERROR_CODE)
+	}
+}
+
 impl From<Error> for String {
 	fn from(error: Error) -> String {
 		error.to_string()
@@ -71,6 +127,7 @@ impl ToString for Error {
 			Self::Request(e) => e.to_string(),
 			Self::ResponseParseFailed(e) => e.what().to_string(),
 			Self::AccountDoesNotExist => "Account does not exist on the chain".into(),
+			Self::TransactionInvalid(e) => format!("Transaction is invalid: {:?}", e),
 			Self::Custom(e) => e.clone(),
 		}
 	}
@@ -26,10 +26,16 @@ mod rpc;
 pub mod guard;
 pub mod headers_source;
 
-pub use crate::chain::{BlockWithJustification, Chain, ChainWithBalances, TransactionSignScheme};
-pub use crate::client::{Client, JustificationsSubscription, OpaqueGrandpaAuthoritiesSet};
+pub use crate::chain::{
+	transaction_tip, BlockWithJustification, Chain, ChainWithBalances, TransactionEra, TransactionEraOf,
+	TransactionSignScheme,
+};
+pub use crate::client::{
+	Client, JustificationsSubscription, OpaqueGrandpaAuthoritiesSet, TransactionStatusOf, TransactionStatusSubscription,
+};
 pub use crate::error::{Error, Result};
 pub use bp_runtime::{BlockNumberOf, Chain as ChainBase, HashOf, HeaderOf};
+pub use sp_transaction_pool::TransactionStatus;
 
 /// Header id used by the chain.
 pub type HeaderIdOf<C> = relay_utils::HeaderId<HashOf<C>, BlockNumberOf<C>>;
@@ -16,7 +16,7 @@
 
 //! Deal with CLI args of substrate-to-substrate relay.
 
-use bp_message_lane::LaneId;
+use bp_message_lane::{LaneId, MessageNonce};
 use sp_core::Bytes;
 use sp_finality_grandpa::SetId as GrandpaAuthoritiesSetId;
 use structopt::{clap::arg_enum, StructOpt};
@@ -89,6 +89,12 @@ pub enum Command {
 		/// Hex-encoded id of lane that should be served by relay.
 		#[structopt(long)]
 		lane: HexLaneId,
+		/// Only log proofs that would be submitted, without submitting any transactions.
+		#[structopt(long)]
+		dry_run: bool,
+		/// Deliver messages up to this nonce and exit once delivery is confirmed.
+		#[structopt(long)]
+		final_nonce: Option<MessageNonce>,
 	},
 	/// Submit message to given Millau -> Rialto lane.
 	SubmitMillauToRialtoMessage {
@@ -37,6 +37,7 @@ mod headers_initialize;
 mod headers_maintain;
 mod headers_pipeline;
 mod headers_target;
+mod messages_lane;
 mod messages_source;
 mod messages_target;
 mod millau_headers_to_rialto;
@@ -204,6 +205,8 @@ async fn run_command(command: cli::Command) -> Result<(), String> {
 			rialto_sign,
 			prometheus_params,
 			lane,
+			dry_run,
+			final_nonce,
 		} => {
 			let millau_client = MillauClient::new(ConnectionParams {
 				host: millau.millau_host,
@@ -232,6 +235,8 @@ async fn run_command(command: cli::Command) -> Result<(), String> {
 				rialto_client,
 				rialto_sign,
 				lane.into(),
+				dry_run,
+				final_nonce,
 				prometheus_params.into(),
 			);
 		}
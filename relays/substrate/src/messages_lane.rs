@@ -0,0 +1,94 @@
+// Copyright 2019-2020 Parity Technologies (UK) Ltd.
+// This file is part of Parity Bridges Common.
+
+// Parity Bridges Common is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// Parity Bridges Common is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with Parity Bridges Common.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Types that are shared by Substrate messages source and target clients.
+
+use async_trait::async_trait;
+use messages_relay::message_lane_loop::{TrackedTransactionStatus, TransactionTracker};
+use relay_substrate_client::{
+	Chain, Client, Error as SubstrateError, TransactionEra, TransactionEraOf, TransactionStatus,
+	TransactionStatusSubscription,
+};
+use relay_utils::HeaderId;
+use sp_runtime::{traits::Header as HeaderT, DeserializeOwned};
+
+/// Tracker of transactions, submitted to the Substrate node.
+///
+/// The tracker follows status updates of the transaction, reported by the node. The transaction is
+/// `Finalized` once the block that includes it is finalized. It is `Lost` if the node has dropped
+/// it from the pool (e.g. because its era has expired or it has been replaced by another
+/// transaction), or if the block that includes it hasn't been finalized in time.
+pub struct SubstrateTransactionTracker<C: Chain> {
+	subscription: TransactionStatusSubscription<C>,
+}
+
+impl<C: Chain> SubstrateTransactionTracker<C> {
+	/// Create tracker of the transaction, given subscription to its status updates.
+	pub fn new(subscription: TransactionStatusSubscription<C>) -> Self {
+		SubstrateTransactionTracker { subscription }
+	}
+}
+
+#[async_trait]
+impl<C: Chain> TransactionTracker for SubstrateTransactionTracker<C> {
+	async fn wait(mut self) -> TrackedTransactionStatus {
+		loop {
+			match self.subscription.next().await {
+				TransactionStatus::Finalized(block_hash) => {
+					log::trace!(
+						target: "bridge",
+						"{} transaction has been finalized at block {:?}",
+						C::NAME,
+						block_hash,
+					);
+					return TrackedTransactionStatus::Finalized;
+				}
+				TransactionStatus::Usurped(_)
+				| TransactionStatus::Dropped
+				| TransactionStatus::Invalid
+				| TransactionStatus::FinalityTimeout(_) => {
+					log::trace!(target: "bridge", "{} transaction has been lost", C::NAME);
+					return TrackedTransactionStatus::Lost;
+				}
+				TransactionStatus::Future
+				| TransactionStatus::Ready
+				| TransactionStatus::Broadcast(_)
+				| TransactionStatus::InBlock(_)
+				| TransactionStatus::Retracted(_) => (),
+			}
+		}
+	}
+}
+
+/// Returns era of the transaction that is valid for `mortality` blocks, starting from the best
+/// header of the chain. If `mortality` is `None`, the transaction is immortal.
+pub async fn transaction_era<C: Chain>(
+	client: &Client<C>,
+	mortality: Option<u32>,
+) -> Result<TransactionEraOf<C>, SubstrateError>
+where
+	C::Header: DeserializeOwned,
+{
+	if mortality.is_none() {
+		return Ok(TransactionEra::Immortal);
+	}
+
+	let best_header = client.best_header().await?;
+	Ok(TransactionEra::new(
+		HeaderId(*best_header.number(), best_header.hash()),
+		mortality,
+	))
+}
@@ -18,6 +18,8 @@
 //! runtime that implements `<BridgedChainName>HeaderApi` to allow bridging with
 //! <BridgedName> chain.
 
+use crate::messages_lane::{transaction_era, SubstrateTransactionTracker};
+
 use async_trait::async_trait;
 use bp_message_lane::{LaneId, MessageNonce};
 use bp_runtime::InstanceId;
@@ -25,15 +27,23 @@ use codec::{Decode, Encode};
 use frame_support::weights::Weight;
 use messages_relay::{
 	message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf},
-	message_lane_loop::{ClientState, MessageProofParameters, MessageWeightsMap, SourceClient, SourceClientState},
+	message_lane_loop::{
+		ClientState, MessageDetails, MessageDetailsMap, MessageProofParameters, SourceClient, SourceClientState,
+		SubmitOptions,
+	},
 };
-use relay_substrate_client::{Chain, Client, Error as SubstrateError, HashOf, HeaderIdOf};
+use relay_substrate_client::{Chain, Client, Error as SubstrateError, HashOf, HeaderIdOf, TransactionEraOf};
 use relay_utils::{BlockNumberBase, HeaderId};
-use sp_core::Bytes;
+use sp_core::{hashing::blake2_64, Bytes};
 use sp_runtime::{traits::Header as HeaderT, DeserializeOwned};
 use sp_trie::StorageProof;
 use std::{marker::PhantomData, ops::RangeInclusive};
 
+/// Name of the outbound message lane runtime API.
+const OUTBOUND_LANE_API_NAME: &str = "OutboundLaneApi";
+/// Version of the outbound message lane runtime API that has added the `message_details` method.
+const MESSAGE_DETAILS_API_VERSION: u32 = 2;
+
 /// Intermediate message proof returned by the source Substrate node. Includes everything
 /// required to submit to the target node: cumulative dispatch weight of bundled messages and
 /// the proof itself.
@@ -54,11 +64,13 @@ pub trait SubstrateTransactionMaker<C: Chain, P: MessageLane>: Clone + Send + Sy
 	/// Signed transaction type.
 	type SignedTransaction: Send + Sync + Encode;
 
-	/// Make messages receiving proof transaction.
+	/// Make messages receiving proof transaction that pays given tip and is valid within given era.
 	async fn make_messages_receiving_proof_transaction(
 		&self,
 		generated_at_block: TargetHeaderIdOf<P>,
 		proof: P::MessagesReceivingProof,
+		tip: u128,
+		era: TransactionEraOf<C>,
 	) -> Result<Self::SignedTransaction, SubstrateError>;
 }
 
@@ -101,9 +113,11 @@ where
 	>,
 	P::TargetHeaderNumber: Decode,
 	P::TargetHeaderHash: Decode,
+	P::SourceChainBalance: Decode,
 	M: SubstrateTransactionMaker<C, P>,
 {
 	type Error = SubstrateError;
+	type TransactionTracker = SubstrateTransactionTracker<C>;
 
 	async fn reconnect(mut self) -> Result<Self, Self::Error> {
 		let new_client = self.client.clone().reconnect().await?;
@@ -111,6 +125,10 @@ where
 		Ok(self)
 	}
 
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(Some(self.client.runtime_version().await?.spec_version))
+	}
+
 	async fn state(&self) -> Result<SourceClientState<P>, Self::Error> {
 		read_client_state::<_, P::TargetHeaderHash, P::TargetHeaderNumber>(&self.client, P::TARGET_NAME).await
 	}
@@ -151,37 +169,49 @@ where
 		Ok((id, latest_received_nonce))
 	}
 
-	async fn generated_messages_weights(
+	async fn generated_messages_details(
 		&self,
 		id: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
-	) -> Result<MessageWeightsMap, Self::Error> {
+	) -> Result<MessageDetailsMap<P::SourceChainBalance>, Self::Error> {
+		let api_version = outbound_lane_api_version(&self.client).await?;
+		if api_version < MESSAGE_DETAILS_API_VERSION {
+			return read_messages_dispatch_weight::<C, P>(&self.client, self.lane, id.1, nonces).await;
+		}
+
 		let encoded_response = self
 			.client
 			.state_call(
 				// TODO: https://github.com/paritytech/parity-bridges-common/issues/457
-				"OutboundLaneApi_messages_dispatch_weight".into(),
+				"OutboundLaneApi_message_details".into(),
 				Bytes((self.lane, nonces.start(), nonces.end()).encode()),
 				Some(id.1),
 			)
 			.await?;
-		let weights: Vec<(MessageNonce, Weight)> =
+		let details: Vec<bp_message_lane::MessageDetails<P::SourceChainBalance>> =
 			Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
 
 		let mut expected_nonce = *nonces.start();
-		let mut weights_map = MessageWeightsMap::new();
-		for (nonce, weight) in weights {
-			if nonce != expected_nonce {
+		let mut details_map = MessageDetailsMap::new();
+		for details in details {
+			if details.nonce != expected_nonce {
 				return Err(SubstrateError::Custom(format!(
-					"Unexpected nonce in messages_dispatch_weight call result. Expected {}, got {}",
-					expected_nonce, nonce
+					"Unexpected nonce in message_details call result. Expected {}, got {}",
+					expected_nonce, details.nonce
 				)));
 			}
 
-			weights_map.insert(nonce, weight);
+			details_map.insert(
+				details.nonce,
+				MessageDetails {
+					dispatch_weight: details.dispatch_weight,
+					size: details.size,
+					reward: details.delivery_and_dispatch_fee,
+				},
+			);
 			expected_nonce += 1;
 		}
-		Ok(weights_map)
+		Ok(details_map)
 	}
 
 	async fn prove_messages(
@@ -208,14 +238,73 @@ where
 		&self,
 		generated_at_block: TargetHeaderIdOf<P>,
 		proof: P::MessagesReceivingProof,
-	) -> Result<(), Self::Error> {
+		options: SubmitOptions,
+	) -> Result<Self::TransactionTracker, Self::Error> {
+		let era = transaction_era(&self.client, options.mortality).await?;
 		let tx = self
 			.tx_maker
-			.make_messages_receiving_proof_transaction(generated_at_block, proof)
+			.make_messages_receiving_proof_transaction(generated_at_block, proof, options.tip, era)
 			.await?;
-		self.client.submit_extrinsic(Bytes(tx.encode())).await?;
-		Ok(())
+		let subscription = self.client.submit_and_watch_extrinsic(Bytes(tx.encode())).await?;
+		Ok(SubstrateTransactionTracker::new(subscription))
+	}
+}
+
+/// Returns version of the outbound message lane runtime API, supported by the best runtime of
+/// the source node. Returns zero if the runtime doesn't support this API.
+async fn outbound_lane_api_version<C: Chain>(client: &Client<C>) -> Result<u32, SubstrateError> {
+	// runtime API identifier is the 8-bytes blake2 hash of the API name
+	let api_id = blake2_64(OUTBOUND_LANE_API_NAME.as_bytes());
+	let runtime_version = client.runtime_version().await?;
+	Ok(runtime_version
+		.apis
+		.iter()
+		.find(|(id, _)| *id == api_id)
+		.map(|(_, version)| *version)
+		.unwrap_or(0))
+}
+
+/// Read dispatch weights of messages using the `messages_dispatch_weight` method of the outbound
+/// message lane runtime API. This method is supported by all versions of the API, but it doesn't
+/// return size and fee of messages, so they're set to zero.
+async fn read_messages_dispatch_weight<C: Chain, P: MessageLane>(
+	client: &Client<C>,
+	lane: LaneId,
+	at_block: C::Hash,
+	nonces: RangeInclusive<MessageNonce>,
+) -> Result<MessageDetailsMap<P::SourceChainBalance>, SubstrateError> {
+	let encoded_response = client
+		.state_call(
+			// TODO: https://github.com/paritytech/parity-bridges-common/issues/457
+			"OutboundLaneApi_messages_dispatch_weight".into(),
+			Bytes((lane, nonces.start(), nonces.end()).encode()),
+			Some(at_block),
+		)
+		.await?;
+	let weights: Vec<(MessageNonce, Weight)> =
+		Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
+
+	let mut expected_nonce = *nonces.start();
+	let mut details_map = MessageDetailsMap::new();
+	for (nonce, dispatch_weight) in weights {
+		if nonce != expected_nonce {
+			return Err(SubstrateError::Custom(format!(
+				"Unexpected nonce in messages_dispatch_weight call result. Expected {}, got {}",
+				expected_nonce, nonce
+			)));
+		}
+
+		details_map.insert(
+			nonce,
+			MessageDetails {
+				dispatch_weight,
+				size: 0,
+				reward: Default::default(),
+			},
+		);
+		expected_nonce += 1;
 	}
+	Ok(details_map)
 }
 
 pub async fn read_client_state<SelfChain, BridgedHeaderHash, BridgedHeaderNumber>(
@@ -250,8 +339,10 @@ where
 		decoded_best_finalized_peer_on_self.1,
 	);
 
+	// we only follow finalized headers of this chain, so best and best finalized headers are the same
 	Ok(ClientState {
 		best_self: self_best_finalized_id,
+		best_finalized_self: self_best_finalized_id,
 		best_peer: peer_on_self_best_finalized_id,
 	})
 }
@@ -18,18 +18,21 @@
 //! runtime that implements `<BridgedChainName>HeaderApi` to allow bridging with
 //! <BridgedName> chain.
 
+use crate::messages_lane::{transaction_era, SubstrateTransactionTracker};
 use crate::messages_source::read_client_state;
 
 use async_trait::async_trait;
-use bp_message_lane::{LaneId, MessageNonce};
+use bp_message_lane::{InboundLaneParameters, LaneId, MessageNonce, UnrewardedRelayersState, Weight};
 use bp_runtime::InstanceId;
 use codec::{Decode, Encode};
 use messages_relay::{
 	message_lane::{MessageLane, SourceHeaderIdOf, TargetHeaderIdOf},
-	message_lane_loop::{TargetClient, TargetClientState},
+	message_lane_loop::{DryRunOutcome, SubmitOptions, TargetClient, TargetClientState, TargetLaneParameters},
 };
-use relay_substrate_client::{Chain, Client, Error as SubstrateError, HashOf};
-use relay_utils::BlockNumberBase;
+use relay_substrate_client::{
+	Chain, ChainWithBalances, Client, Error as SubstrateError, HashOf, TransactionEra, TransactionEraOf,
+};
+use relay_utils::{BlockNumberBase, HeaderId};
 use sp_core::Bytes;
 use sp_runtime::{traits::Header as HeaderT, DeserializeOwned};
 use sp_trie::StorageProof;
@@ -46,17 +49,31 @@ pub struct SubstrateMessagesTarget<C: Chain, P, M> {
 
 /// Substrate transactions maker.
 #[async_trait]
-pub trait SubstrateTransactionMaker<C: Chain, P: MessageLane>: Clone + Send + Sync {
+pub trait SubstrateTransactionMaker<C: ChainWithBalances, P: MessageLane>: Clone + Send + Sync {
 	/// Signed transaction type.
 	type SignedTransaction: Send + Sync + Encode;
 
-	/// Make messages delivery transaction.
+	/// Make messages delivery transaction that pays given tip and is valid within given era.
 	async fn make_messages_delivery_transaction(
 		&self,
 		generated_at_header: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
 		proof: P::MessagesProof,
+		tip: u128,
+		era: TransactionEraOf<C>,
+	) -> Result<Self::SignedTransaction, SubstrateError>;
+
+	/// Make messages delivery transaction that is only used to estimate its fee. The transaction
+	/// must have the same dispatch weight as the real delivery transaction, but its messages proof
+	/// may be empty - size of messages is accounted separately.
+	async fn make_messages_delivery_estimation_transaction(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
 	) -> Result<Self::SignedTransaction, SubstrateError>;
+
+	/// Convert fee of the delivery transaction, paid in target chain tokens, to source chain tokens.
+	fn delivery_fee_to_source_balance(&self, fee: C::NativeBalance) -> P::SourceChainBalance;
 }
 
 impl<C: Chain, P, M> SubstrateMessagesTarget<C, P, M> {
@@ -87,7 +104,7 @@ impl<C: Chain, P, M: Clone> Clone for SubstrateMessagesTarget<C, P, M> {
 #[async_trait]
 impl<C, P, M> TargetClient<P> for SubstrateMessagesTarget<C, P, M>
 where
-	C: Chain,
+	C: ChainWithBalances,
 	C::Header: DeserializeOwned,
 	C::Index: DeserializeOwned,
 	<C::Header as HeaderT>::Number: BlockNumberBase,
@@ -101,6 +118,7 @@ where
 	M: SubstrateTransactionMaker<C, P>,
 {
 	type Error = SubstrateError;
+	type TransactionTracker = SubstrateTransactionTracker<C>;
 
 	async fn reconnect(mut self) -> Result<Self, Self::Error> {
 		let new_client = self.client.clone().reconnect().await?;
@@ -108,6 +126,29 @@ where
 		Ok(self)
 	}
 
+	async fn runtime_version(&self) -> Result<Option<u32>, Self::Error> {
+		Ok(Some(self.client.runtime_version().await?.spec_version))
+	}
+
+	async fn lane_parameters(&self) -> Result<TargetLaneParameters, Self::Error> {
+		let encoded_response = self
+			.client
+			.state_call(
+				// TODO: https://github.com/paritytech/parity-bridges-common/issues/457
+				"InboundLaneApi_lane_parameters".into(),
+				Bytes(self.lane.encode()),
+				None,
+			)
+			.await?;
+		let lane_parameters: InboundLaneParameters =
+			Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
+		Ok(TargetLaneParameters {
+			max_messages_in_single_batch: lane_parameters.max_messages_in_single_batch,
+			max_messages_weight_in_single_batch: lane_parameters.max_messages_weight_in_single_batch,
+			max_unconfirmed_messages: lane_parameters.max_unconfirmed_messages,
+		})
+	}
+
 	async fn state(&self) -> Result<TargetClientState<P>, Self::Error> {
 		read_client_state::<_, P::SourceHeaderHash, P::SourceHeaderNumber>(&self.client, P::SOURCE_NAME).await
 	}
@@ -148,6 +189,24 @@ where
 		Ok((id, latest_received_nonce))
 	}
 
+	async fn unrewarded_relayers_state(
+		&self,
+		id: TargetHeaderIdOf<P>,
+	) -> Result<(TargetHeaderIdOf<P>, UnrewardedRelayersState), Self::Error> {
+		let encoded_response = self
+			.client
+			.state_call(
+				// TODO: https://github.com/paritytech/parity-bridges-common/issues/457
+				"InboundLaneApi_unrewarded_relayers_state".into(),
+				Bytes(self.lane.encode()),
+				Some(id.1),
+			)
+			.await?;
+		let unrewarded_relayers_state: UnrewardedRelayersState =
+			Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
+		Ok((id, unrewarded_relayers_state))
+	}
+
 	async fn prove_messages_receiving(
 		&self,
 		id: TargetHeaderIdOf<P>,
@@ -160,17 +219,140 @@ where
 		Ok((id, proof))
 	}
 
+	async fn verify_messages_proof(
+		&self,
+		generated_at_header: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<(), Self::Error> {
+		// the runtime has no API to verify proofs, so we're dry running the delivery transaction,
+		// which verifies the proof before dispatching messages
+		let tx = self
+			.tx_maker
+			.make_messages_delivery_transaction(
+				generated_at_header,
+				nonces.clone(),
+				proof.clone(),
+				0,
+				TransactionEra::Immortal,
+			)
+			.await?;
+		if !dry_run_delivery_transaction::<C, P>(&self.client, Bytes(tx.encode())).await? {
+			return Err(SubstrateError::Custom(format!(
+				"Proof of messages {:?} has been rejected by {}",
+				nonces,
+				P::TARGET_NAME,
+			)));
+		}
+
+		Ok(())
+	}
+
+	async fn dry_run_messages_proof(
+		&self,
+		generated_at_header: SourceHeaderIdOf<P>,
+		nonces: RangeInclusive<MessageNonce>,
+		proof: &P::MessagesProof,
+	) -> Result<DryRunOutcome, Self::Error> {
+		let encoded_response = self
+			.client
+			.state_call(
+				// TODO: https://github.com/paritytech/parity-bridges-common/issues/457
+				"InboundLaneApi_latest_received_nonce".into(),
+				Bytes(self.lane.encode()),
+				None,
+			)
+			.await?;
+		let latest_received_nonce: MessageNonce =
+			Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
+		if latest_received_nonce >= *nonces.end() {
+			return Ok(DryRunOutcome::AlreadyDelivered);
+		}
+
+		let tx = self
+			.tx_maker
+			.make_messages_delivery_transaction(generated_at_header, nonces, proof.clone(), 0, TransactionEra::Immortal)
+			.await?;
+		if dry_run_delivery_transaction::<C, P>(&self.client, Bytes(tx.encode())).await? {
+			Ok(DryRunOutcome::Success)
+		} else {
+			Ok(DryRunOutcome::Failure)
+		}
+	}
+
+	async fn required_source_header_at_target(
+		&self,
+		current_best: SourceHeaderIdOf<P>,
+	) -> Result<Option<SourceHeaderIdOf<P>>, Self::Error> {
+		// headers that are waiting for finality proofs are blocking finalization of their
+		// descendants, so the target node can't accept proofs at newer headers until the earliest
+		// of them is finalized
+		let encoded_response = self
+			.client
+			.state_call(
+				format!("{}HeaderApi_incomplete_headers", P::SOURCE_NAME),
+				Bytes(Vec::new()),
+				None,
+			)
+			.await?;
+		let incomplete_headers: Vec<(P::SourceHeaderNumber, P::SourceHeaderHash)> =
+			Decode::decode(&mut &encoded_response.0[..]).map_err(SubstrateError::ResponseParseFailed)?;
+		Ok(incomplete_headers
+			.into_iter()
+			.filter(|(number, _)| *number > current_best.0)
+			.min_by_key(|(number, _)| *number)
+			.map(|(number, hash)| HeaderId(number, hash)))
+	}
+
+	async fn estimate_delivery_cost(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+		total_size: u32,
+	) -> Result<P::SourceChainBalance, Self::Error> {
+		let tx = self
+			.tx_maker
+			.make_messages_delivery_estimation_transaction(nonces, total_dispatch_weight)
+			.await?;
+		let tx_size = (tx.encoded_size() as u32).saturating_add(total_size);
+		let fee = self.client.estimate_extrinsic_fee(Bytes(tx.encode()), tx_size).await?;
+		Ok(self.tx_maker.delivery_fee_to_source_balance(fee))
+	}
+
 	async fn submit_messages_proof(
 		&self,
 		generated_at_header: SourceHeaderIdOf<P>,
 		nonces: RangeInclusive<MessageNonce>,
 		proof: P::MessagesProof,
-	) -> Result<RangeInclusive<MessageNonce>, Self::Error> {
+		options: SubmitOptions,
+	) -> Result<(RangeInclusive<MessageNonce>, Self::TransactionTracker), Self::Error> {
+		let era = transaction_era(&self.client, options.mortality).await?;
 		let tx = self
 			.tx_maker
-			.make_messages_delivery_transaction(generated_at_header, nonces.clone(), proof)
+			.make_messages_delivery_transaction(generated_at_header, nonces.clone(), proof, options.tip, era)
 			.await?;
-		self.client.submit_extrinsic(Bytes(tx.encode())).await?;
-		Ok(nonces)
+		let subscription = self.client.submit_and_watch_extrinsic(Bytes(tx.encode())).await?;
+		Ok((nonces, SubstrateTransactionTracker::new(subscription)))
+	}
+}
+
+/// Dry run messages delivery transaction at the best target header. Returns false if the
+/// dispatch would fail and an error if the transaction itself is invalid.
+async fn dry_run_delivery_transaction<C: Chain, P: MessageLane>(
+	client: &Client<C>,
+	transaction: Bytes,
+) -> Result<bool, SubstrateError> {
+	match client.dry_run_extrinsic(transaction, None).await? {
+		Ok(Ok(())) => Ok(true),
+		Ok(Err(error)) => {
+			log::debug!(
+				target: "bridge",
+				"Messages delivery transaction would fail at {}: {:?}",
+				P::TARGET_NAME,
+				error,
+			);
+			Ok(false)
+		}
+		Err(error) => Err(SubstrateError::TransactionInvalid(error)),
 	}
 }
@@ -23,15 +23,16 @@ use crate::{MillauClient, RialtoClient};
 use async_trait::async_trait;
 use bp_message_lane::{LaneId, MessageNonce};
 use bp_runtime::{MILLAU_BRIDGE_INSTANCE, RIALTO_BRIDGE_INSTANCE};
+use codec::Encode;
 use frame_support::weights::Weight;
 use messages_relay::message_lane::MessageLane;
 use relay_millau_client::{HeaderId as MillauHeaderId, Millau, SigningParams as MillauSigningParams};
 use relay_rialto_client::{HeaderId as RialtoHeaderId, Rialto, SigningParams as RialtoSigningParams};
-use relay_substrate_client::{BlockNumberOf, Error as SubstrateError, HashOf, TransactionSignScheme};
+use relay_substrate_client::{BlockNumberOf, Error as SubstrateError, HashOf, TransactionEraOf, TransactionSignScheme};
 use relay_utils::metrics::MetricsParams;
 use sp_core::Pair;
 use sp_trie::StorageProof;
-use std::{ops::RangeInclusive, time::Duration};
+use std::{convert::TryInto, ops::RangeInclusive, time::Duration};
 
 /// Millau -> Rialto messages proof:
 ///
@@ -60,6 +61,16 @@ impl MessageLane for MillauMessagesToRialto {
 
 	type TargetHeaderNumber = BlockNumberOf<Rialto>;
 	type TargetHeaderHash = HashOf<Rialto>;
+
+	type SourceChainBalance = bp_millau::Balance;
+
+	fn messages_proof_size(proof: &FromMillauMessagesProof) -> usize {
+		proof.1.encoded_size()
+	}
+
+	fn messages_receiving_proof_size(proof: &FromRialtoMessagesReceivingProof) -> usize {
+		proof.encoded_size()
+	}
 }
 
 /// Millau node as messages source.
@@ -80,11 +91,14 @@ impl SubstrateSourceTransactionMaker<Millau, MillauMessagesToRialto> for MillauT
 		&self,
 		_generated_at_block: RialtoHeaderId,
 		proof: FromRialtoMessagesReceivingProof,
+		tip: u128,
+		era: TransactionEraOf<Millau>,
 	) -> Result<Self::SignedTransaction, SubstrateError> {
 		let account_id = self.sign.signer.public().as_array_ref().clone().into();
 		let nonce = self.client.next_account_index(account_id).await?;
 		let call = millau_runtime::MessageLaneCall::receive_messages_delivery_proof(proof).into();
-		let transaction = Millau::sign_transaction(&self.client, &self.sign.signer, nonce, call);
+		let transaction =
+			Millau::sign_transaction_with_options(&self.client, &self.sign.signer, nonce, call, tip, era)?;
 		Ok(transaction)
 	}
 }
@@ -109,6 +123,8 @@ impl SubstrateTargetTransactionMaker<Rialto, MillauMessagesToRialto> for RialtoT
 		_generated_at_header: MillauHeaderId,
 		_nonces: RangeInclusive<MessageNonce>,
 		proof: FromMillauMessagesProof,
+		tip: u128,
+		era: TransactionEraOf<Rialto>,
 	) -> Result<Self::SignedTransaction, SubstrateError> {
 		let (dispatch_weight, proof) = proof;
 		let account_id = self.sign.signer.public().as_array_ref().clone().into();
@@ -116,9 +132,38 @@ impl SubstrateTargetTransactionMaker<Rialto, MillauMessagesToRialto> for RialtoT
 		let call =
 			rialto_runtime::MessageLaneCall::receive_messages_proof(self.relayer_id.clone(), proof, dispatch_weight)
 				.into();
-		let transaction = Rialto::sign_transaction(&self.client, &self.sign.signer, nonce, call);
+		let transaction =
+			Rialto::sign_transaction_with_options(&self.client, &self.sign.signer, nonce, call, tip, era)?;
+		Ok(transaction)
+	}
+
+	async fn make_messages_delivery_estimation_transaction(
+		&self,
+		nonces: RangeInclusive<MessageNonce>,
+		total_dispatch_weight: Weight,
+	) -> Result<Self::SignedTransaction, SubstrateError> {
+		// neither account nonce, nor proof contents affect the transaction fee
+		let proof = (
+			Default::default(),
+			StorageProof::new(Vec::new()),
+			Default::default(),
+			*nonces.start(),
+			*nonces.end(),
+		);
+		let call = rialto_runtime::MessageLaneCall::receive_messages_proof(
+			self.relayer_id.clone(),
+			proof,
+			total_dispatch_weight,
+		)
+		.into();
+		let transaction = Rialto::sign_transaction(&self.client, &self.sign.signer, Default::default(), call);
 		Ok(transaction)
 	}
+
+	fn delivery_fee_to_source_balance(&self, fee: bp_rialto::Balance) -> bp_millau::Balance {
+		// Millau and Rialto tokens are considered to have the same value
+		fee.try_into().unwrap_or(bp_millau::Balance::MAX)
+	}
 }
 
 /// Run Millau-to-Rialto messages sync.
@@ -128,12 +173,17 @@ pub fn run(
 	rialto_client: RialtoClient,
 	rialto_sign: RialtoSigningParams,
 	lane: LaneId,
+	dry_run: bool,
+	final_nonce: Option<MessageNonce>,
 	metrics_params: Option<MetricsParams>,
 ) {
 	let millau_tick = Duration::from_secs(5);
 	let rialto_tick = Duration::from_secs(5);
 	let reconnect_delay = Duration::from_secs(10);
 	let stall_timeout = Duration::from_secs(5 * 60);
+	let resubmit_timeout = Duration::from_secs(2 * 60);
+	let nonces_refresh_interval = Duration::from_secs(60);
+	let rpc_timeout = Duration::from_secs(60);
 	let relayer_id = millau_sign.signer.public().as_array_ref().clone().into();
 
 	messages_relay::message_lane_loop::run(
@@ -142,13 +192,62 @@ pub fn run(
 			source_tick: millau_tick,
 			target_tick: rialto_tick,
 			reconnect_delay,
-			stall_timeout,
+			source_stall_timeout: stall_timeout,
+			target_stall_timeout: stall_timeout,
+			resubmit_timeout,
+			source_retry_backoff: Default::default(),
+			target_retry_backoff: Default::default(),
+			dry_run,
+			final_nonce,
+			nonces_refresh_interval: Some(nonces_refresh_interval),
+			delivery_race_state_notifier: None,
+			receiving_race_state_notifier: None,
+			verify_messages_proofs: false,
+			dry_run_messages_before_submit: false,
+			max_in_flight_submissions: 1,
+			max_proofs_per_batch: 1,
+			reselect_on_newer_target_state: false,
+			reselect_threshold: 0,
+			max_queued_nonces: None,
+			competition_delay: None,
+			proof_generation_timeout: None,
+			proof_size_limit: None,
+			rpc_timeout,
+			nonces_at_target_known_header: false,
+			delivery_source_nonces_at: messages_relay::message_lane_loop::BlockRef::Best,
+			// confirmations at non-finalized Rialto headers may be reverted
+			receiving_source_nonces_at: messages_relay::message_lane_loop::BlockRef::Finalized,
+			source_confirmation_depth: 0,
+			proof_at_queued_header: false,
+			max_source_header_lag: None,
+			max_consecutive_failures: None,
+			race_reconnect_attempts: 0,
+			source_rate_limit: None,
+			target_rate_limit: None,
+			delivery_condition: None,
+			balance_guard: None,
 			delivery_params: messages_relay::message_lane_loop::MessageDeliveryParams {
 				max_unconfirmed_nonces_at_target: bp_rialto::MAX_UNCONFIRMED_MESSAGES_AT_INBOUND_LANE,
+				// Rialto inbound lane limits number of unrewarded relayer entries with the same constant
+				max_unrewarded_relayer_entries_at_target: bp_rialto::MAX_UNCONFIRMED_MESSAGES_AT_INBOUND_LANE,
 				// TODO: subtract base weight of delivery from this when it'll be known
 				// https://github.com/paritytech/parity-bridges-common/issues/78
-				max_messages_weight_in_single_batch: bp_rialto::MAXIMUM_EXTRINSIC_WEIGHT,
+				limits: messages_relay::message_lane_loop::DeliveryLimits {
+					max_nonces: None,
+					max_weight: bp_rialto::MAXIMUM_EXTRINSIC_WEIGHT,
+					max_size: bp_rialto::MAXIMUM_EXTRINSIC_SIZE,
+				},
+				redeliver_after_target_rollback: false,
+				batching: None,
+				max_messages_per_minute: None,
+				profitability: None,
+				target_cost_profitability: None,
+				batch_optimizer: None,
+				nonce_filter: None,
+				initial_target_nonce: None,
+				submit_policy: Default::default(),
 			},
+			lazy_confirmations: None,
 		},
 		MillauSourceClient::new(
 			millau_client.clone(),
@@ -17,8 +17,11 @@
 //! Utilities used by different relays.
 
 use backoff::{backoff::Backoff, ExponentialBackoff};
-use futures::future::FutureExt;
-use std::time::Duration;
+use futures::{
+	future::{BoxFuture, Either, FutureExt},
+	stream::{BoxStream, StreamExt},
+};
+use std::time::{Duration, Instant};
 
 /// Max delay after connection-unrelated error happened before we'll try the
 /// same request again.
@@ -99,6 +102,7 @@ macro_rules! bail_on_arg_error {
 
 /// Ethereum header Id.
 #[derive(Debug, Default, Clone, Copy, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HeaderId<Hash, Number>(pub Number, pub Hash);
 
 /// Error type that can signal connection errors.
@@ -107,6 +111,125 @@ pub trait MaybeConnectionError {
 	fn is_connection_error(&self) -> bool;
 }
 
+/// Classification of the client error. It defines how the relay reacts to the error.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorClassification {
+	/// The request may be retried immediately (e.g. if the node has been temporarily busy).
+	RetryImmediately,
+	/// The request may be retried after the retry backoff.
+	RetryWithBackoff,
+	/// Connection to the node is (probably) lost, so the client must be reconnected.
+	Reconnect,
+	/// Retrying the request would never succeed, so the relay must be stopped.
+	Fatal,
+}
+
+impl ErrorClassification {
+	/// Classify error that is only able to tell whether it is connection error: connection errors
+	/// require reconnect and other errors are retried after backoff.
+	pub fn from_maybe_connection_error(error: &impl MaybeConnectionError) -> Self {
+		if error.is_connection_error() {
+			ErrorClassification::Reconnect
+		} else {
+			ErrorClassification::RetryWithBackoff
+		}
+	}
+}
+
+/// Error type that is able to classify itself.
+///
+/// The trait is implemented for all `MaybeConnectionError` types: connection errors require
+/// reconnect and other errors are retried after backoff. Errors that need other classifications
+/// should implement this trait directly, instead of `MaybeConnectionError`. Fatal errors may also
+/// be reported by `MaybeFatalError`.
+pub trait RelayError {
+	/// Returns classification of the error.
+	fn classification(&self) -> ErrorClassification;
+}
+
+impl<E: MaybeConnectionError> RelayError for E {
+	fn classification(&self) -> ErrorClassification {
+		ErrorClassification::from_maybe_connection_error(self)
+	}
+}
+
+/// Error type that can signal that the submitted transaction has been rejected.
+pub trait MaybeRejectedTransactionError {
+	/// Returns true if error represents rejection of the submitted transaction by the node. It
+	/// means that the transaction is invalid at the current state of the chain (e.g. if it
+	/// contains outdated proof), so there's no sense in submitting the same transaction again.
+	/// By default, errors are not rejections.
+	fn is_rejected_transaction(&self) -> bool {
+		false
+	}
+}
+
+/// Error type that can signal that the error is permanent.
+pub trait MaybeFatalError {
+	/// Returns true if error is permanent, i.e. if retrying the failed request would never succeed
+	/// (e.g. if the lane is unknown to the runtime). By default, errors are not fatal.
+	fn is_fatal(&self) -> bool {
+		false
+	}
+}
+
+/// Error type that can signal that the request has been throttled by the node.
+pub trait MaybeThrottledError {
+	/// Returns true if the node (or RPC provider) has refused to serve the request, because the
+	/// client has exceeded its requests quota (e.g. HTTP 429). By default, errors are not
+	/// throttling errors.
+	fn is_throttled(&self) -> bool {
+		false
+	}
+}
+
+/// Error of the request that must complete before deadline.
+#[derive(PartialEq)]
+pub enum TimeoutError<E> {
+	/// The request has not completed in time.
+	Timeout(Duration),
+	/// The request has failed.
+	Failed(E),
+}
+
+impl<E: std::fmt::Debug> std::fmt::Debug for TimeoutError<E> {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		// request errors are printed as is, so wrapping request doesn't change error messages
+		match *self {
+			TimeoutError::Timeout(timeout) => write!(f, "Request has timed out after {:?}", timeout),
+			TimeoutError::Failed(ref error) => error.fmt(f),
+		}
+	}
+}
+
+impl<E: RelayError> RelayError for TimeoutError<E> {
+	fn classification(&self) -> ErrorClassification {
+		match *self {
+			// the node is (probably) not responding, so we treat it as connection error
+			TimeoutError::Timeout(_) => ErrorClassification::Reconnect,
+			TimeoutError::Failed(ref error) => error.classification(),
+		}
+	}
+}
+
+impl<E: MaybeFatalError> MaybeFatalError for TimeoutError<E> {
+	fn is_fatal(&self) -> bool {
+		match *self {
+			TimeoutError::Timeout(_) => false,
+			TimeoutError::Failed(ref error) => error.is_fatal(),
+		}
+	}
+}
+
+impl<E: MaybeThrottledError> MaybeThrottledError for TimeoutError<E> {
+	fn is_throttled(&self) -> bool {
+		match *self {
+			TimeoutError::Timeout(_) => false,
+			TimeoutError::Failed(ref error) => error.is_throttled(),
+		}
+	}
+}
+
 /// Stringified error that may be either connection-related or not.
 #[derive(Debug)]
 pub enum StringifiedMaybeConnectionError {
@@ -154,6 +277,48 @@ pub fn retry_backoff() -> ExponentialBackoff {
 	backoff
 }
 
+/// Parameters of exponential backoff for connection-unrelated errors retries.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryBackoffParams {
+	/// Delay before the first retry.
+	pub initial: Duration,
+	/// Max delay between retries.
+	pub max: Duration,
+	/// Every next delay is the previous delay multiplied by this value.
+	pub multiplier: f64,
+	/// Every delay is randomly selected from the `[delay * (1 - factor); delay * (1 + factor)]`
+	/// range.
+	pub randomization_factor: f64,
+}
+
+impl Default for RetryBackoffParams {
+	fn default() -> Self {
+		let backoff = retry_backoff();
+		RetryBackoffParams {
+			initial: backoff.initial_interval,
+			max: backoff.max_interval,
+			multiplier: backoff.multiplier,
+			randomization_factor: backoff.randomization_factor,
+		}
+	}
+}
+
+impl RetryBackoffParams {
+	/// Create exponential backoff with these parameters.
+	///
+	/// The `max` is the upper bound of the delay before randomization, so the actual delay may be
+	/// up to `max * (1 + randomization_factor)`.
+	pub fn backoff(&self) -> ExponentialBackoff {
+		let mut backoff = retry_backoff();
+		backoff.current_interval = self.initial;
+		backoff.initial_interval = self.initial;
+		backoff.max_interval = self.max;
+		backoff.multiplier = self.multiplier;
+		backoff.randomization_factor = self.randomization_factor;
+		backoff
+	}
+}
+
 /// Compact format of IDs vector.
 pub fn format_ids<Id: std::fmt::Debug>(mut ids: impl ExactSizeIterator<Item = Id>) -> String {
 	const NTH_PROOF: &str = "we have checked len; qed";
@@ -181,6 +346,59 @@ pub fn interval(timeout: Duration) -> impl futures::Stream<Item = ()> {
 	})
 }
 
+/// Timer that is used by relay loops to wait. It allows running loops on any async executor.
+pub trait Timer: Clone + Send + Sync + 'static {
+	/// Returns current time. By default, this is the system time.
+	fn now(&self) -> Instant {
+		Instant::now()
+	}
+
+	/// Returns future that resolves after given delay.
+	fn sleep(&self, delay: Duration) -> BoxFuture<'static, ()>;
+
+	/// Stream that emits item every `period`.
+	fn interval(&self, period: Duration) -> BoxStream<'static, ()> {
+		let timer = self.clone();
+		futures::stream::unfold((), move |_| timer.sleep(period).map(|_| Some(((), ())))).boxed()
+	}
+}
+
+/// Timer of the `async-std` executor. This is the default timer of relay loops.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdTimer;
+
+impl Timer for AsyncStdTimer {
+	fn sleep(&self, delay: Duration) -> BoxFuture<'static, ()> {
+		async_std::task::sleep(delay).boxed()
+	}
+}
+
+/// Timer of the `tokio` executor. It may only be used from within the tokio runtime with the
+/// time driver enabled.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+	fn sleep(&self, delay: Duration) -> BoxFuture<'static, ()> {
+		tokio::time::delay_for(delay).boxed()
+	}
+}
+
+/// Wait for the request to complete, but no longer than `timeout`.
+pub async fn with_timeout<T, E>(
+	timer: &impl Timer,
+	timeout: Duration,
+	request: impl std::future::Future<Output = Result<T, E>>,
+) -> Result<T, TimeoutError<E>> {
+	futures::pin_mut!(request);
+	match futures::future::select(request, timer.sleep(timeout)).await {
+		Either::Left((result, _)) => result.map_err(TimeoutError::Failed),
+		Either::Right(_) => Err(TimeoutError::Timeout(timeout)),
+	}
+}
+
 /// Which client has caused error.
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum FailedClient {
@@ -202,6 +420,8 @@ pub enum ProcessFutureResult {
 	Failed,
 	/// Future has failed with connection error.
 	ConnectionFailed,
+	/// Future has failed with fatal error.
+	Fatal,
 }
 
 impl ProcessFutureResult {
@@ -209,23 +429,27 @@ impl ProcessFutureResult {
 	pub fn is_ok(self) -> bool {
 		match self {
 			ProcessFutureResult::Success => true,
-			ProcessFutureResult::Failed | ProcessFutureResult::ConnectionFailed => false,
+			ProcessFutureResult::Failed | ProcessFutureResult::ConnectionFailed | ProcessFutureResult::Fatal => false,
 		}
 	}
 
 	/// Returns Ok(true) if future has succeeded.
 	/// Returns Ok(false) if future has failed with non-connection error.
-	/// Returns Err if future is `ConnectionFailed`.
+	/// Returns Err if future is `ConnectionFailed` or `Fatal`.
 	pub fn fail_if_connection_error(self, failed_client: FailedClient) -> Result<bool, FailedClient> {
 		match self {
 			ProcessFutureResult::Success => Ok(true),
 			ProcessFutureResult::Failed => Ok(false),
-			ProcessFutureResult::ConnectionFailed => Err(failed_client),
+			ProcessFutureResult::ConnectionFailed | ProcessFutureResult::Fatal => Err(failed_client),
 		}
 	}
 }
 
 /// Process result of the future from a client.
+///
+/// Errors are handled according to their classification: the client is retried immediately or
+/// after backoff, or the future is reported as failed with connection (or fatal) error, so that
+/// the caller could reconnect the client (or stop).
 pub fn process_future_result<TResult, TError, TGoOfflineFuture>(
 	result: Result<TResult, TError>,
 	retry_backoff: &mut ExponentialBackoff,
@@ -235,7 +459,7 @@ pub fn process_future_result<TResult, TError, TGoOfflineFuture>(
 	error_pattern: impl FnOnce() -> String,
 ) -> ProcessFutureResult
 where
-	TError: std::fmt::Debug + MaybeConnectionError,
+	TError: std::fmt::Debug + RelayError,
 	TGoOfflineFuture: FutureExt,
 {
 	match result {
@@ -244,30 +468,223 @@ where
 			retry_backoff.reset();
 			ProcessFutureResult::Success
 		}
-		Err(error) if error.is_connection_error() => {
-			log::error!(
-				target: "bridge",
-				"{}: {:?}. Going to restart",
-				error_pattern(),
-				error,
-			);
+		Err(error) => match error.classification() {
+			ErrorClassification::RetryImmediately => {
+				log::debug!(
+					target: "bridge",
+					"{}: {:?}. Retrying immediately",
+					error_pattern(),
+					error,
+				);
 
-			retry_backoff.reset();
-			go_offline_future.set(go_offline(CONNECTION_ERROR_DELAY).fuse());
-			ProcessFutureResult::ConnectionFailed
+				go_offline_future.set(go_offline(Duration::from_secs(0)).fuse());
+				ProcessFutureResult::Failed
+			}
+			ErrorClassification::RetryWithBackoff => {
+				let retry_delay = retry_backoff.next_backoff().unwrap_or(CONNECTION_ERROR_DELAY);
+				log::error!(
+					target: "bridge",
+					"{}: {:?}. Retrying in {}",
+					error_pattern(),
+					error,
+					retry_delay.as_secs_f64(),
+				);
+
+				go_offline_future.set(go_offline(retry_delay).fuse());
+				ProcessFutureResult::Failed
+			}
+			ErrorClassification::Reconnect => {
+				log::error!(
+					target: "bridge",
+					"{}: {:?}. Going to restart",
+					error_pattern(),
+					error,
+				);
+
+				retry_backoff.reset();
+				go_offline_future.set(go_offline(CONNECTION_ERROR_DELAY).fuse());
+				ProcessFutureResult::ConnectionFailed
+			}
+			ErrorClassification::Fatal => {
+				log::error!(
+					target: "bridge",
+					"{}: {:?}. Giving up",
+					error_pattern(),
+					error,
+				);
+
+				ProcessFutureResult::Fatal
+			}
+		},
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn retry_backoff_respects_configured_max_delay() {
+		let params = RetryBackoffParams {
+			initial: Duration::from_secs(1),
+			max: Duration::from_secs(10),
+			multiplier: 2.0,
+			randomization_factor: 0.0,
+		};
+		let mut backoff = params.backoff();
+		let delays = (0..10).map(|_| backoff.next_backoff().unwrap()).collect::<Vec<_>>();
+		assert_eq!(
+			delays,
+			[1, 2, 4, 8, 10, 10, 10, 10, 10, 10]
+				.iter()
+				.map(|secs| Duration::from_secs(*secs))
+				.collect::<Vec<_>>(),
+		);
+
+		backoff.reset();
+		assert_eq!(backoff.next_backoff(), Some(params.initial));
+	}
+
+	#[derive(Debug, PartialEq)]
+	struct TestError;
+
+	impl MaybeConnectionError for TestError {
+		fn is_connection_error(&self) -> bool {
+			false
+		}
+	}
+
+	#[test]
+	fn with_timeout_returns_request_result_if_it_completes_in_time() {
+		let timeout = Duration::from_secs(60);
+		assert_eq!(
+			async_std::task::block_on(with_timeout(
+				&AsyncStdTimer,
+				timeout,
+				futures::future::ok::<_, TestError>(42)
+			)),
+			Ok(42),
+		);
+		assert_eq!(
+			async_std::task::block_on(with_timeout(
+				&AsyncStdTimer,
+				timeout,
+				futures::future::err::<u32, _>(TestError)
+			)),
+			Err(TimeoutError::Failed(TestError)),
+		);
+		assert_eq!(
+			TimeoutError::Failed(TestError).classification(),
+			ErrorClassification::RetryWithBackoff,
+		);
+	}
+
+	#[test]
+	fn with_timeout_fails_with_connection_error_if_request_never_completes() {
+		let timeout = Duration::from_millis(10);
+		let result = async_std::task::block_on(with_timeout(
+			&AsyncStdTimer,
+			timeout,
+			futures::future::pending::<Result<u32, TestError>>(),
+		));
+		assert_eq!(result, Err(TimeoutError::Timeout(timeout)));
+		assert_eq!(result.unwrap_err().classification(), ErrorClassification::Reconnect);
+	}
+
+	#[derive(Debug)]
+	struct TestRelayError(ErrorClassification);
+
+	impl RelayError for TestRelayError {
+		fn classification(&self) -> ErrorClassification {
+			self.0
+		}
+	}
+
+	/// Process failed future result with given error classification. Returns process result, along
+	/// with the delay before the client goes online again (if it is going to).
+	fn process_failed_future_result(
+		retry_backoff: &mut ExponentialBackoff,
+		classification: ErrorClassification,
+	) -> (ProcessFutureResult, Option<Duration>) {
+		let go_offline_future = futures::future::Fuse::terminated();
+		futures::pin_mut!(go_offline_future);
+		let result = process_future_result(
+			Err::<(), _>(TestRelayError(classification)),
+			retry_backoff,
+			|_| unreachable!("future has failed"),
+			&mut go_offline_future,
+			futures::future::ready,
+			|| "Test error".into(),
+		);
+		(result, go_offline_future.now_or_never())
+	}
+
+	fn test_retry_backoff() -> ExponentialBackoff {
+		RetryBackoffParams {
+			initial: Duration::from_secs(1),
+			max: Duration::from_secs(10),
+			multiplier: 2.0,
+			randomization_factor: 0.0,
 		}
-		Err(error) => {
-			let retry_delay = retry_backoff.next_backoff().unwrap_or(CONNECTION_ERROR_DELAY);
-			log::error!(
-				target: "bridge",
-				"{}: {:?}. Retrying in {}",
-				error_pattern(),
-				error,
-				retry_delay.as_secs_f64(),
-			);
-
-			go_offline_future.set(go_offline(retry_delay).fuse());
-			ProcessFutureResult::Failed
+		.backoff()
+	}
+
+	#[test]
+	fn maybe_connection_errors_are_classified() {
+		struct ConnectionError(bool);
+
+		impl MaybeConnectionError for ConnectionError {
+			fn is_connection_error(&self) -> bool {
+				self.0
+			}
 		}
+
+		assert_eq!(ConnectionError(true).classification(), ErrorClassification::Reconnect);
+		assert_eq!(
+			ConnectionError(false).classification(),
+			ErrorClassification::RetryWithBackoff
+		);
+	}
+
+	#[test]
+	fn process_future_result_retries_immediately_without_backoff() {
+		let mut retry_backoff = test_retry_backoff();
+		let (result, delay) = process_failed_future_result(&mut retry_backoff, ErrorClassification::RetryImmediately);
+		assert!(matches!(result, ProcessFutureResult::Failed));
+		assert_eq!(delay, Some(Duration::from_secs(0)));
+		assert_eq!(retry_backoff.next_backoff(), Some(Duration::from_secs(1)));
+	}
+
+	#[test]
+	fn process_future_result_retries_with_backoff() {
+		let mut retry_backoff = test_retry_backoff();
+		let (result, delay) = process_failed_future_result(&mut retry_backoff, ErrorClassification::RetryWithBackoff);
+		assert!(matches!(result, ProcessFutureResult::Failed));
+		assert_eq!(delay, Some(Duration::from_secs(1)));
+		assert_eq!(retry_backoff.next_backoff(), Some(Duration::from_secs(2)));
+	}
+
+	#[test]
+	fn process_future_result_reports_failed_client_if_reconnect_is_required() {
+		let mut retry_backoff = test_retry_backoff();
+		let (result, delay) = process_failed_future_result(&mut retry_backoff, ErrorClassification::Reconnect);
+		assert_eq!(
+			result.fail_if_connection_error(FailedClient::Source),
+			Err(FailedClient::Source)
+		);
+		assert_eq!(delay, Some(CONNECTION_ERROR_DELAY));
+	}
+
+	#[test]
+	fn process_future_result_reports_failed_client_if_error_is_fatal() {
+		let mut retry_backoff = test_retry_backoff();
+		let (result, delay) = process_failed_future_result(&mut retry_backoff, ErrorClassification::Fatal);
+		assert!(matches!(result, ProcessFutureResult::Fatal));
+		assert_eq!(
+			result.fail_if_connection_error(FailedClient::Target),
+			Err(FailedClient::Target)
+		);
+		// the client never goes online again
+		assert_eq!(delay, None);
 	}
 }